@@ -1,16 +1,1288 @@
-export function actix(): ActixApp;
-export default actix;
+/* tslint:disable */
+/* eslint-disable */
 
-export class ActixApp {
-  hostname?: string;
-  port?: number;
-
-  get(path: string, callback: (req: Request) => void): void;
+/* auto-generated by NAPI-RS */
 
-  listen(port: number, callback?: (server: ActixApp) => void): Promise<void>;
-  listen(
-    port: number,
-    hostname?: string,
-    callback?: (server: ActixApp) => void,
-  ): Promise<void>;
+/** Chooses the responses archived with `app.archive()`. */
+export interface ArchiveOptions {
+  /** Share of the matching responses archived, from 0 to 1. Defaults to 1. */
+  sampleRatio?: number
+  /** Only archives requests whose path starts with one of these prefixes. */
+  paths?: Array<string>
+  /** Only archives responses with one of these statuses. */
+  statuses?: Array<number>
+}
+/** How an archive kept up with its sink. */
+export interface ArchiveSnapshot {
+  /** Records dropped because the sink fell behind. */
+  dropped: number
+}
+/** Options accepted by `app.fetch()`, mirroring a subset of `RequestInit`. */
+export interface FetchOptions {
+  method?: string
+  headers?: Record<string, string>
+  /**
+   * A string, a buffer, or an async iterable of strings and byte arrays
+   * streamed as the upstream reads it.
+   */
+  body?: string | Buffer | AsyncIterable<string | Uint8Array> | Iterable<string | Uint8Array>
+  /** Milliseconds to wait for the response headers. Unlimited by default. */
+  timeout?: number
+}
+/** How outbound connections are made, set with `app.configureClient()`. */
+export interface ClientOptions {
+  /**
+   * Milliseconds to wait on a connection attempt before racing the next
+   * address, alternating IPv6 and IPv4, when a host resolves to both
+   * (Happy Eyeballs, RFC 8305). `0` tries addresses one after another.
+   * Defaults to 300.
+   */
+  happyEyeballsTimeout?: number
+  /**
+   * Local addresses outbound sockets are bound to: at most one IPv4 and
+   * one IPv6 address, each used for upstreams of its family.
+   */
+  localAddresses?: Array<string>
+  /** Milliseconds a connection attempt may take. Unlimited by default. */
+  connectTimeout?: number
+  /** Caches resolved host names in process. Off by default. */
+  dnsCache?: DnsCacheOptions
+  /** Fixed addresses for host names, used instead of resolving them. */
+  hosts?: Record<string, Array<string>>
+  /**
+   * Starts each connection at the next address of a host name resolving
+   * to several, spreading connections over its records instead of always
+   * trying the first one. Off by default.
+   */
+  rotateAddresses?: boolean
+}
+/** Configures client hints with `app.clientHints()`. */
+export interface ClientHintsOptions {
+  /** Hints to ask for, such as `"Sec-CH-DPR"`. */
+  accept: Array<string>
+  /**
+   * Hints the response needs, for which the browser retries the request.
+   * They are asked for too, and responses vary on them.
+   */
+  critical?: Array<string>
+}
+/** Configures the DNS cache of the outbound client. */
+export interface DnsCacheOptions {
+  /** Milliseconds resolved addresses are reused for. Defaults to 30000. */
+  ttl?: number
+  /**
+   * Milliseconds a failed lookup is remembered for. Defaults to 5000;
+   * `0` retries failed names every time.
+   */
+  negativeTtl?: number
+  /** Most names kept at once. Defaults to 1024. */
+  maxEntries?: number
+}
+/** Configures `app.expectContinue()`. */
+export interface ExpectContinueOptions {
+  /** Largest `Content-Length`, in bytes, of a body the client may send. */
+  maxLength?: number
+  /**
+   * Called with `{ method, path, headers }` before the client sends the
+   * body. `true` lets it, `false` answers `417` and a number answers with
+   * that status.
+   */
+  check?: (request: import('./types').ExpectContinueRequest) => boolean | number | Promise<boolean | number>
+}
+/** Wraps an Express-style `(req, res, next)` middleware into a handler. */
+export declare function express(middleware: import('./types').ExpressMiddleware): import('./types').Handler<import('./types').AnyActixRequest>
+/** A constant response registered with `app.getStatic()`. */
+export interface StaticResponse {
+  /** Defaults to `200`. */
+  status?: number
+  /**
+   * Text is sent as `text/plain` and bytes as `application/octet-stream`,
+   * unless `headers` sets a content type.
+   */
+  body?: string | Buffer
+  headers?: Record<string, string>
+  /** Credentials required before the response is served. */
+  guard?: GuardOptions
+  /** Rewrites the body once, when it is HTML. */
+  rewriteHtml?: HtmlRewriteOptions
+}
+/** The `guard` of a static response or a route. */
+export interface GuardOptions {
+  /** Accepts an `Authorization: Bearer` JWT signed with this HS256 secret. */
+  jwt?: JwtOptions
+  /** Accepts one of these API keys. */
+  apiKey?: ApiKeyOptions
+  /**
+   * Called with the request's method, path, `Authorization` header and
+   * the identity a provider found: a token's claims, or `{ sub }` for an
+   * API key. Only answering `true` lets the request through.
+   */
+  authorize?: (attempt: import('./types').GuardAttempt) => boolean | Promise<boolean>
+}
+export interface JwtOptions {
+  secret: string
+  /** Required value of the `iss` claim. */
+  issuer?: string
+  /** Required value, or member, of the `aud` claim. */
+  audience?: string
+}
+export interface ApiKeyOptions {
+  /** Header carrying the key. Defaults to `x-api-key`. */
+  header?: string
+  /** The accepted keys, each mapped to the `sub` it identifies. */
+  keys: Record<string, string>
+}
+/** Configures the probes with `app.health()`. */
+export interface HealthOptions {
+  /** Path of the liveness probe, such as `/healthz`. */
+  liveness?: string
+  /** Path of the readiness probe, such as `/readyz`. */
+  readiness?: string
+  /**
+   * Called by the readiness probe. Returning or resolving to `false`,
+   * throwing or rejecting marks the app as not ready.
+   */
+  check?: () => boolean | void | Promise<boolean | void>
+  /** Milliseconds `check` gets to answer. Defaults to 1000. */
+  timeout?: number
+}
+/** Rewrites HTML responses as they are sent. */
+export interface HtmlRewriteOptions {
+  /** Markup inserted right before `</head>`. */
+  head?: string
+  /** Markup inserted right before `</body>`. */
+  body?: string
+  /**
+   * URL prefixes replaced in `href`, `src` and `action` attributes, such as
+   * `{ "http://localhost:4000/": "/api/" }`. The longest one matching
+   * applies.
+   */
+  links?: Record<string, string>
+}
+/** A histogram as seen from JS. */
+export interface HistogramSnapshot {
+  /** Upper bound of each bucket, inclusive. */
+  bounds: Array<number>
+  /**
+   * How many values fell in each bucket. The last count, with no bound,
+   * holds the values above every bound.
+   */
+  counts: Array<number>
+  count: number
+  sum: number
+}
+/** How connections closed so far were used, to tune keep-alive. */
+export interface ConnectionMetricsSnapshot {
+  /** Requests served by each connection. */
+  requestsPerConnection: HistogramSnapshot
+  /** How long each connection stayed open, in seconds. */
+  connectionDuration: HistogramSnapshot
+  transfer: TransferSnapshot
+}
+/** How request bodies arrived and how responses were written. */
+export interface TransferSnapshot {
+  /** Requests with a body sent with `Transfer-Encoding: chunked`. */
+  chunkedRequests: number
+  /** Requests with a body sent with a `Content-Length`. */
+  contentLengthRequests: number
+  /**
+   * Pieces request bodies were received in, and their average size in
+   * bytes.
+   */
+  chunks: number
+  averageChunkSize: number
+  /** Writes to and flushes of the sockets of closed connections. */
+  writes: number
+  flushes: number
+}
+/** Options of `app.normalize()`. */
+export interface NormalizeOptions {
+  /**
+   * `Host` given to requests without one, when their target doesn't name
+   * the host either.
+   */
+  defaultHost?: string
+  /** Treats `;` in queries as `&`. Defaults to `true`. */
+  semicolons?: boolean
+  /**
+   * Percent-encodes characters queries may not hold as they are. Defaults
+   * to `true`.
+   */
+  encodeQuery?: boolean
+}
+/** How many requests each fix was applied to. */
+export interface NormalizationSnapshot {
+  missingHost: number
+  semicolonQueries: number
+  encodedQueries: number
+}
+/** How responses are written to the socket, set with `app.writeStrategy()`. */
+export interface WriteOptions {
+  /**
+   * `"flatten"` copies headers and body into one buffer so small responses
+   * go out in a single write, `"queue"` uses vectored writes instead of
+   * copying, and `"auto"` (the default) picks based on the transport.
+   */
+  strategy?: 'auto' | 'flatten' | 'queue'
+  /** Aggregates flushes of pipelined responses. */
+  pipelineFlush?: boolean
+  /**
+   * Maximum size of the read and write buffers of a connection, in bytes.
+   * At least 8192.
+   */
+  maxBufSize?: number
+}
+/**
+ * Every HTTP/1 knob of the server, set with `app.configure()`.
+ *
+ * Unset options keep hyper's defaults. Changes take effect on the next
+ * `listen()`.
+ */
+export interface ServerOptions {
+  /** Reuses connections for several requests. Defaults to `true`. */
+  keepAlive?: boolean
+  /**
+   * Finishes the response when the client half-closes its side of the
+   * connection. Defaults to `false`.
+   */
+  halfClose?: boolean
+  /**
+   * Maximum size of the read and write buffers of a connection, in bytes.
+   * At least 8192.
+   */
+  maxBufSize?: number
+  /** Aggregates flushes of pipelined responses. Defaults to `false`. */
+  pipelineFlush?: boolean
+  /** Forces vectored writes on or off; unset picks based on the transport. */
+  writev?: boolean
+  /** Writes header names in title case. Defaults to `false`. */
+  titleCaseHeaders?: boolean
+  /** Keeps the case of header names as received. Defaults to `false`. */
+  preserveHeaderCase?: boolean
+  /** Only accepts HTTP/1 connections. Defaults to `false`. */
+  http1Only?: boolean
+  /**
+   * Milliseconds a keep-alive connection may wait for its next request
+   * before it is closed gracefully. The timer only runs between requests,
+   * like Node's `server.keepAliveTimeout`. `0` (the default) disables the
+   * timeout.
+   */
+  keepAliveTimeout?: number
+  /**
+   * Milliseconds a client gets to send the headers of a request, counted
+   * from when the server starts waiting for it. `0` (the default) disables
+   * the timeout. Node calls it `server.headersTimeout`.
+   */
+  headerReadTimeout?: number
+  /**
+   * Milliseconds a request gets to be received and answered, up to the
+   * response headers; late ones get a `408` and their connection is
+   * closed. `0` (the default) disables the timeout.
+   */
+  requestTimeout?: number
+  /**
+   * Milliseconds a connection may go without reading or writing anything
+   * before it is dropped, like Node's `server.timeout`. Unlike
+   * `keepAliveTimeout`, the timer also runs while a request is in flight,
+   * and the connection is dropped mid-response rather than closed
+   * gracefully. `0` (the default) disables the timeout.
+   */
+  idleTimeout?: number
+  /** Maximum number of connections open at once. Unlimited by default. */
+  maxConnections?: number
+  /**
+   * What to do with new connections once `maxConnections` is reached:
+   * `"pause"` (the default) stops accepting until one closes, `"reject"`
+   * answers them with a `503`.
+   */
+  saturation?: 'pause' | 'reject'
+  /**
+   * Runs connections on a runtime of their own with this many worker
+   * threads. Unset shares the addon's runtime.
+   */
+  maxWorkers?: number
+  /**
+   * Milliseconds idle blocking threads of that runtime are kept. Defaults
+   * to 10 seconds.
+   */
+  workerKeepAlive?: number
+  /**
+   * Lets apps in other worker threads of the process listen on the same
+   * address: the first one accepts connections and hands requests to each
+   * of them in turn. Defaults to `false`.
+   */
+  shareListener?: boolean
+  /**
+   * Sets `SO_REUSEPORT`, so several processes can listen on the same port
+   * with the kernel spreading connections between them. Unix only.
+   * Defaults to `true` in cluster workers.
+   */
+  reusePort?: boolean
+  /** Sets `SO_REUSEADDR`. Defaults to `true` on Unix. */
+  reuseAddress?: boolean
+  /** Disables Nagle's algorithm on accepted connections. */
+  noDelay?: boolean
+  /**
+   * Maximum number of connections waiting to be accepted. Defaults to
+   * 1024.
+   */
+  backlog?: number
+  /**
+   * Milliseconds a connection stays idle before TCP keep-alive probes are
+   * sent. Setting any `tcpKeepAlive*` option enables the probes.
+   */
+  tcpKeepAliveIdle?: number
+  /** Milliseconds between TCP keep-alive probes. */
+  tcpKeepAliveInterval?: number
+  /** Unanswered TCP keep-alive probes before the connection is dropped. */
+  tcpKeepAliveRetries?: number
+}
+/** One socket served by `app.listen([...])`. */
+export interface ListenerOptions {
+  port: number
+  /** Defaults to `127.0.0.1`. */
+  hostname?: string
+  /** Overrides the options set with `app.configure()` for this listener. */
+  options?: ServerOptions
+}
+/** Options of `app.listenUnix()`. */
+export interface UnixListenOptions {
+  /** Permissions of the socket file, such as `0o660`. */
+  mode?: number
+  /** Overrides the options set with `app.configure()` for this listener. */
+  options?: ServerOptions
+}
+/** Where the server is bound, as returned by `app.address()`. */
+export interface ListenAddress {
+  address: string
+  /** `"IPv4"` or `"IPv6"`. */
+  family: 'IPv4' | 'IPv6'
+  port: number
+}
+/** An upstream of `app.proxy()`, with its share of the requests. */
+export interface UpstreamOptions {
+  /**
+   * An absolute `http:` or `https:` URL, or `unix:/path/to.sock` with an
+   * optional `:/base/path`.
+   */
+  url: string
+  /** Relative share of the requests, from 1 to 1000. Defaults to 1. */
+  weight?: number
+}
+/** When upstreams are left out for failing. */
+export interface OutlierOptions {
+  /** Failed requests in a row that eject an upstream. Defaults to 5. */
+  consecutiveFailures?: number
+  /**
+   * Milliseconds after which a response counts as a failure. Unlimited by
+   * default.
+   */
+  maxLatency?: number
+  /** Milliseconds an ejected upstream is left out. Defaults to 30000. */
+  cooldown?: number
+}
+/** Keeps clients on the upstream that first answered them, with a cookie. */
+export interface AffinityOptions {
+  /** Name of the cookie. Defaults to `"upstream"`. */
+  cookie?: string
+  /** Seconds the cookie lasts. By default it ends with the browser session. */
+  maxAge?: number
+}
+/** When idempotent requests are sent a second time. */
+export interface HedgeOptions {
+  /**
+   * Percentile of the proxy's recent response times after which a `GET`
+   * or `HEAD` without an answer is sent to another upstream as well, above
+   * 0 and below 100. Defaults to 95.
+   */
+  percentile?: number
+}
+/** How an upstream has fared, as returned by `app.upstreamMetrics()`. */
+export interface UpstreamSnapshot {
+  /** The prefix of the proxy. */
+  prefix: string
+  url: string
+  requests: number
+  failures: number
+  /** Average time to the response headers, in milliseconds. */
+  averageLatency: number
+  ejections: number
+  /** Requests sent to the upstream as hedges of slow ones. */
+  hedges: number
+  /** Whether the upstream is currently left out. */
+  ejected: boolean
+}
+/**
+ * What `balance: "hash"` hashes. The path, unless a header or a cookie is
+ * named.
+ */
+export interface HashOnOptions {
+  header?: string
+  cookie?: string
+}
+/** Options for `app.proxy()`. */
+export interface ProxyOptions {
+  /**
+   * Replaces the prefix in forwarded paths; `""` strips it. By default
+   * paths are forwarded whole.
+   */
+  rewrite?: string
+  /**
+   * Forwards the client's `Host` header rather than the upstream's.
+   * Defaults to `false`.
+   */
+  preserveHost?: boolean
+  /** Adds the `X-Forwarded-*` headers. Defaults to `true`. */
+  xForwarded?: boolean
+  /**
+   * Milliseconds to wait for the upstream's response headers. Unlimited
+   * by default.
+   */
+  timeout?: number
+  /**
+   * How requests are spread over several upstreams: `"round-robin"`,
+   * `"random"` or `"hash"`. Defaults to `"round-robin"`.
+   */
+  balance?: 'round-robin' | 'random' | 'hash'
+  /** The key of `"hash"`. */
+  hashOn?: HashOnOptions
+  /** Leaves out upstreams that keep failing. Off by default. */
+  outlierDetection?: OutlierOptions
+  /** Sends slow idempotent requests to a second upstream. Off by default. */
+  hedge?: HedgeOptions
+  /**
+   * Sends requests with the affinity cookie to the upstream it names, and
+   * sets it on responses to those without it. Off by default.
+   */
+  affinity?: AffinityOptions
+  /** Rewrites HTML responses as they stream through. */
+  rewriteHtml?: HtmlRewriteOptions
+}
+/** Quota settings, accepted by `app.quota()` and the `quota` route option. */
+export interface QuotaOptions {
+  /** Header carrying the API key. Defaults to `x-api-key`. */
+  header?: string
+  /**
+   * Query string parameter carrying the API key, checked when the header is
+   * absent.
+   */
+  query?: string
+  /** Requests allowed per key and hour. */
+  hourly?: number
+  /** Requests allowed per key and day. */
+  daily?: number
+  /**
+   * Rejects requests without an API key with `401`. Otherwise they are not
+   * counted. Defaults to `false`.
+   */
+  required?: boolean
+  /**
+   * Sends the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+   * headers of the IETF draft with every metered response. Defaults to
+   * `true`.
+   */
+  headers?: boolean
+  /**
+   * Also sends the `X-RateLimit-*` headers older clients look for, with the
+   * reset as a Unix timestamp. Defaults to `true`.
+   */
+  legacyHeaders?: boolean
+}
+/** Options of `app.watch()`. */
+export interface WatchOptions {
+  /** Milliseconds between two looks at the files. Defaults to 500. */
+  interval?: number
+}
+/**
+ * Options controlling how request paths are matched, set with
+ * `app.routerOptions()`.
+ */
+export interface RouterOptions {
+  /**
+   * When `false`, `/users/` also matches `/users` and vice versa.
+   * Defaults to `true`.
+   */
+  strictSlash?: boolean
+  /**
+   * When `false`, static route segments are matched case-insensitively.
+   * Parameter values are kept as they were sent. Defaults to `true`.
+   */
+  caseSensitive?: boolean
+  /** Collapses repeated slashes (`//a///b` → `/a/b`). Defaults to `false`. */
+  mergeSlashes?: boolean
+  /**
+   * Answers non-canonical paths with a `301` to the canonical form instead
+   * of serving them directly. Defaults to `false`.
+   */
+  redirect?: boolean
+}
+/** Per-route options, passed as the last argument when registering a route. */
+export interface RouteOptions {
+  /** Name used to build URLs for the route with `app.urlFor()`. */
+  name?: string
+  /** Quota applied to this route, on top of the app's. */
+  quota?: QuotaOptions
+  /** Accepts requests without a signature even when the app verifies them. */
+  unsigned?: boolean
+  /**
+   * Calls the handler with an array of requests, to be answered with an
+   * array of statuses in the same order. Takes no middleware.
+   */
+  batch?: boolean
+  /** Most requests handed to a batched handler at once. Defaults to 64. */
+  maxBatch?: number
+  /**
+   * Header values and query parameters requests must also have. Several
+   * handlers can share a method and path as long as their conditions
+   * differ.
+   */
+  when?: MatchOptions
+  /**
+   * Largest request body accepted, in bytes. A tenant's lower limit
+   * still applies.
+   */
+  maxBodySize?: number
+  /**
+   * Milliseconds requests get to be received and answered, in place of the
+   * server's `requestTimeout`. `0` lifts the timeout.
+   */
+  timeout?: number
+  /**
+   * Credentials requests must carry, checked before the body is read and
+   * the handler called.
+   */
+  guard?: GuardOptions
+}
+/** A route as listed by `app.routes()`. */
+export interface RegisteredRoute {
+  /**
+   * What answers the route: a handler, a response set with
+   * `app.getStatic()` or `app.favicon()`, an upload endpoint or a proxy.
+   */
+  kind: 'route' | 'static' | 'upload' | 'proxy'
+  /** `*` for proxies, which forward every method. */
+  method: string
+  /** The template, as it was registered; the prefix of a proxy. */
+  path: string
+  /** The conditions of the route, if it was registered with some. */
+  when?: MatchOptions
+  name?: string
+}
+/** Conditions of a route besides its path. */
+export interface MatchOptions {
+  /**
+   * Values headers must have. A value matches the whole header or its
+   * media type, before any `;` parameter, ignoring case. `*` only requires
+   * the header.
+   */
+  headers?: Record<string, string>
+  /** Values query parameters must have. `*` only requires the parameter. */
+  query?: Record<string, string>
+}
+/** Options of `app.handleSignals()`. */
+export interface ShutdownOptions {
+  /**
+   * Signals that stop the server, among `SIGINT`, `SIGTERM`, `SIGHUP` and
+   * `SIGQUIT`. Defaults to `SIGINT` and `SIGTERM`.
+   */
+  signals?: Array<import('./types').Signal>
+  /**
+   * Milliseconds open connections get to finish before being closed.
+   * Defaults to 30 seconds.
+   */
+  timeout?: number
+}
+/** Options for `app.verifySignatures()`. */
+export interface SignatureOptions {
+  /**
+   * Headers every signature must cover. Defaults to `["host", "x-date"]`;
+   * `x-date` is always required.
+   */
+  headers?: Array<string>
+  /**
+   * Seconds the `x-date` of a request may differ from the server clock.
+   * Defaults to 300.
+   */
+  maxSkew?: number
+}
+/** Options for `app.signResponses()`. */
+export interface ResponseSigningOptions {
+  /** Sent as the `Credential` so clients know which secret to check with. */
+  keyId: string
+  secret: string
+  /**
+   * Response headers covered by the signature, besides
+   * `x-content-sha256`. Defaults to `["content-type"]`.
+   */
+  headers?: Array<string>
+  /**
+   * Answers `500` in place of responses that can't be signed, rather than
+   * sending them unsigned. Defaults to `false`.
+   */
+  rejectUnsigned?: boolean
+}
+/** Configures signed URLs with `app.signedUrls()`. */
+export interface SignedUrlOptions {
+  /** Key of the HMAC; anyone holding it can mint URLs. */
+  secret: string
+  /** Paths under these prefixes are only served with a valid signature. */
+  prefixes: Array<string>
+}
+/** A status code with an optional reason phrase, as returned by `status()`. */
+export interface Status {
+  status: number
+  reason?: string
+}
+/**
+ * Builds a `{ status, reason }` object for a handler to return, checking
+ * that the code is between 100 and 999 and that the reason is a valid
+ * reason phrase. Without a reason, the standard phrase of the code is used.
+ */
+export declare function status(code: number, reason?: string | undefined | null): Status
+/**
+ * Streams the items of `iterable` as newline-delimited JSON, for a handler
+ * to return. Items are pulled as the client reads.
+ */
+export declare function ndjson(iterable: AsyncIterable<unknown> | Iterable<unknown> | AsyncIterator<unknown> | Iterator<unknown>): import('./types').StreamedBody
+/** Options of `csv()`. */
+export interface CsvOptions {
+  /**
+   * Compresses the body with gzip. Defaults to `false`; check the
+   * request's `Accept-Encoding` first.
+   */
+  gzip?: boolean
+}
+/**
+ * Streams the items of `iterable` as CSV, for a handler to return. Each
+ * item is an array of fields in column order, or an object whose fields
+ * are looked up by column name.
+ */
+export declare function csv(columns: Array<string>, iterable: AsyncIterable<Array<unknown> | Record<string, unknown>> | Iterable<Array<unknown> | Record<string, unknown>>, options?: CsvOptions | undefined | null): import('./types').StreamedBody
+/**
+ * Writes a structured field from its JS form, as returned by
+ * `req.getStructuredHeader()`. Items may also be given as bare values,
+ * without `params`.
+ */
+export declare function serializeStructuredHeader(value: import('./types').StructuredBareItem | import('./types').StructuredField | Array<import('./types').StructuredBareItem | import('./types').StructuredMember> | Record<string, import('./types').StructuredBareItem | import('./types').StructuredMember>, kind: import('./types').StructuredFieldType): string
+/** Declares a tenant with `app.tenant()`. */
+export interface TenantOptions {
+  name: string
+  /** Matches requests whose path is this prefix or continues it with `/`. */
+  prefix?: string
+  /** Matches requests carrying this header with `value`. */
+  header?: string
+  value?: string
+  /** Quota counted for this tenant only. */
+  quota?: QuotaOptions
+  /** Largest request body accepted, in bytes. */
+  maxBodySize?: number
+  /**
+   * Labels describing the tenant's requests, available as
+   * `req.tenant.labels`.
+   */
+  labels?: Record<string, string>
+}
+/** The timings of a route, as returned by `app.routeTimings()`. */
+export interface RouteTiming {
+  method: string
+  path: string
+  /** The conditions of the route, if it was registered with some. */
+  when?: MatchOptions
+  calls: number
+  /** Average wall time per request, in milliseconds. */
+  averageMs: number
+  maxMs: number
+  /** Average time spent in synchronous calls per request, in milliseconds. */
+  averageSyncMs: number
+}
+/** Configures tracing with `app.tracing()`. */
+export interface TracingOptions {
+  /**
+   * URL of the collector's OTLP/HTTP endpoint, such as
+   * `http://localhost:4318`. `/v1/traces` is appended unless the URL
+   * already ends with it.
+   */
+  endpoint: string
+  serviceName: string
+  /**
+   * Share of new traces that are recorded, from 0 to 1. Requests joining a
+   * trace follow its sampling decision. Defaults to 1.
+   */
+  sampleRatio?: number
+  /** Largest number of spans sent at once. Defaults to 512. */
+  batchSize?: number
+  /** Milliseconds between exports. Defaults to 5000. */
+  exportInterval?: number
+}
+/** Sets up an upload endpoint with `app.tus()`. */
+export interface TusOptions {
+  /**
+   * Directory uploads are written to, one file per upload named after its
+   * id. Created if missing.
+   */
+  directory: string
+  /** Largest upload accepted, in bytes. Unlimited by default. */
+  maxSize?: number
+  /**
+   * Milliseconds an upload is kept after it was last written to. Expired
+   * unfinished uploads are deleted; finished ones are only forgotten, their
+   * files left to the app. Unset keeps uploads until the server stops.
+   */
+  expiration?: number
+  /**
+   * Called with `{ id, length, metadata, path }` once an upload is
+   * complete.
+   */
+  onComplete?: (upload: import('./types').CompletedUpload) => void
+}
+/** A request synthesized by `app.warmup()`. */
+export interface WarmupRequest {
+  /** Defaults to `GET`. */
+  method?: string
+  /** Path and query, such as `/users?page=1`. */
+  path: string
+  headers?: Record<string, string>
+  body?: string
+}
+/** Options of `writer()`. */
+export interface WriterOptions {
+  /**
+   * Milliseconds of silence after which an event stream gets a comment
+   * line. Defaults to 15000; `0` turns heartbeats off.
+   */
+  heartbeat?: number
+  /** Bytes queued before `write()` returns `false`. Defaults to 16384. */
+  highWaterMark?: number
+}
+/** Wraps a Node-style `(req, res)` handler into a handler. */
+export declare function writer<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(handler: (req: Req, res: ResponseWriter) => void | Promise<void>, options?: WriterOptions | undefined | null): import('./types').Handler<Req>
+export declare function actix(): ActixApp
+/**
+ * The `res` of an Express-style middleware. Status and headers can be
+ * changed until the response is ended; the body is buffered until then.
+ */
+export class ExpressResponse {
+  get statusCode(): number
+  set statusCode(code: number)
+  /**
+   * Whether `writeHead()` was called, by the middleware or by the first
+   * `write()` or `end()`. Nothing is actually sent before the response is
+   * ended.
+   */
+  get headersSent(): boolean
+  get writableEnded(): boolean
+  get finished(): boolean
+  status(this: this, code: number): this
+  /** Replaces a header. Arrays set one header per item. */
+  setHeader(name: string, value: string | number | Array<string>): this
+  /** Express' `res.set()`, the same as `setHeader()`. */
+  set(name: string, value: string | number | Array<string>): this
+  /** Adds a header, keeping the values already set. */
+  append(name: string, value: string | number | Array<string>): this
+  /** The values of a header joined with `, `. */
+  getHeader(name: string): string | null
+  /** Express' `res.get()`, the same as `getHeader()`. */
+  get(name: string): string | null
+  hasHeader(name: string): boolean
+  removeHeader(name: string): void
+  /**
+   * Node's `res.writeHead(status[, statusMessage][, headers])`. `headers`
+   * is an object, or an array of names and values one after the other;
+   * they replace the headers of the same name. The status message is
+   * ignored: responses get the standard reason phrase.
+   *
+   * `write()` and `end()` call `res.writeHead(res.statusCode)` first if it
+   * wasn't, so middleware wrapping it, such as `on-headers`, sees the head
+   * before it's sent.
+   */
+  writeHead(status: number, messageOrHeaders?: string | import('./types').OutgoingHeaders | undefined | null, headers?: import('./types').OutgoingHeaders | undefined | null): this
+  /**
+   * Node's `res.writeEarlyHints()`. The server can't send `103` responses,
+   * so the `link` values are added to the final response instead, where
+   * browsers still preload them.
+   */
+  writeEarlyHints(hints: { link?: string | Array<string> }): void
+  /** Buffers a chunk of the body. */
+  write(this: this, chunk: string | Buffer): boolean
+  /** Ends the response, with a last chunk of the body. */
+  end(this: this, chunk?: string | Buffer | undefined | null): this
+  /**
+   * Sends a body: strings as HTML, buffers as bytes, anything else as
+   * JSON, unless `Content-Type` is already set.
+   */
+  send(body?: unknown): this
+  /** Sends `value` as JSON. */
+  json(this: this, value: unknown): this
+  /** Sends the status with its reason phrase as the body. */
+  sendStatus(this: this, code: number): this
+  /**
+   * Redirects to `url`, with `302` unless a status is given first, as in
+   * `res.redirect(301, url)`.
+   */
+  redirect(target: number | string, url?: string | undefined | null): this
+  /**
+   * Listens for `finish` or `close`, both emitted once the response is
+   * handed to the server. Other events never happen.
+   */
+  on(event: string, listener: () => void): this
+  /** The same as `on()`: `finish` and `close` are only emitted once. */
+  once(event: string, listener: () => void): this
+}
+/**
+ * The headers of a request, read on demand. Names are case-insensitive,
+ * and repeated headers are joined with `, `, as `Headers` does.
+ */
+export class RequestHeaders {
+  get(name: string): string | null
+  has(name: string): boolean
+  /** Every header as a `[name, value]` pair, sorted by name. */
+  entries(): Array<[string, string]>
+}
+/**
+ * A request as seen by handlers once `app.nativeRequests(true)` is set.
+ * Mirrors the parts of `Request` handlers use, reading the Rust request
+ * lazily.
+ */
+export class NodeRequest {
+  get method(): string
+  /**
+   * The absolute URL, built from the `Host` header. The server does not
+   * terminate TLS, so the scheme is always `http`.
+   */
+  get url(): string
+  get path(): string
+  /**
+   * Parameters captured by the route template, such as `id` in
+   * `/users/:id`.
+   */
+  get params(): Record<string, string>
+  /** The query string, decoded like an urlencoded form. */
+  get query(): { [key: string]: import('./types').FormValue }
+  /**
+   * A copy of the body, or `null` when there is none. The bytes read from
+   * the connection are shared with the server, and JS may write to the
+   * `Buffer` it gets.
+   */
+  get body(): Buffer | null
+  /** The body decoded as UTF-8. */
+  text(): string
+  /** The body parsed as JSON. Throws if it is not valid JSON. */
+  json(): unknown
+  /**
+   * Header `name` parsed as a structured field (RFC 8941), or `null` if it
+   * is missing or malformed. `kind` can be left out for registered fields.
+   */
+  getStructuredHeader(name: string, kind?: import('./types').StructuredFieldType | undefined | null): import('./types').StructuredField | null
+}
+/** A response for a handler to return. */
+export class NodeResponse {
+  /** A `200` response with a text or binary body, or none. */
+  constructor(body?: string | Buffer | undefined | null)
+  /** A `200` response with `value` serialized as JSON. */
+  static json(value: unknown): NodeResponse
+  /** A `200` response with a UTF-8 text body. */
+  static text(text: string): NodeResponse
+  /**
+   * A `200` response streaming the strings and buffers of a sync or async
+   * iterable, such as a Node.js `Readable`, as the client reads them.
+   */
+  static stream(iterable: Iterable<string | Buffer> | AsyncIterable<string | Buffer>): NodeResponse
+  /** Sets the status code, between 100 and 999. */
+  status(this: this, code: number): this
+  /**
+   * Adds a header. Setting `Content-Type` replaces the one implied by the
+   * body; other headers can be repeated.
+   */
+  header(this: this, name: string, value: string): this
+}
+/** The `res` of a `writer()` handler, mirroring Node's `ServerResponse`. */
+export class ResponseWriter {
+  get statusCode(): number
+  set statusCode(code: number)
+  get headersSent(): boolean
+  get writableEnded(): boolean
+  /** Bytes written that the server hasn't taken yet. */
+  get writableLength(): number
+  get writableHighWaterMark(): number
+  /** Whether `write()` returned `false` and `drain` wasn't emitted yet. */
+  get writableNeedDrain(): boolean
+  /** Replaces a header. Arrays set one header per item. */
+  setHeader(name: string, value: string | number | Array<string>): this
+  /** Adds a header, keeping the values already set. */
+  appendHeader(name: string, value: string | number | Array<string>): this
+  /** The values of a header joined with `, `. */
+  getHeader(name: string): string | null
+  hasHeader(name: string): boolean
+  removeHeader(name: string): void
+  /**
+   * Sends the status and headers, adding `headers` to those already set.
+   * `headers` is an object, or an array of names and values one after the
+   * other; they replace the headers of the same name. A status message
+   * may come before the headers, as in Node, but the standard reason
+   * phrase is always sent.
+   */
+  writeHead(code: number, messageOrHeaders?: string | import('./types').OutgoingHeaders | undefined | null, headers?: import('./types').OutgoingHeaders | undefined | null): this
+  /** Sends the status and headers set so far. */
+  flushHeaders(this: this): void
+  /**
+   * Sends a chunk of the body, and the status and headers first if they
+   * weren't yet. Returns `false` once the queue is full, or the client
+   * went away; wait for `drain` before writing more.
+   */
+  write(this: this, chunk: string | Buffer): boolean
+  /** Ends the response, with a last chunk of the body. */
+  end(this: this, chunk?: string | Buffer | undefined | null): this
+  /**
+   * Listens for `event`. The writer itself emits `drain`; `res.closed`
+   * tells when the response is over.
+   */
+  on(event: string, listener: (...args: any[]) => void): this
+  /** Listens for the next `event` only. */
+  once(event: string, listener: (...args: any[]) => void): this
+  /** Removes the last `listener` added for `event`. */
+  removeListener(event: string, listener: (...args: any[]) => void): this
+  /** The same as `removeListener()`. */
+  off(event: string, listener: (...args: any[]) => void): this
+  /**
+   * Calls the listeners of `event` with `arg`. Returns `false` if there
+   * were none.
+   */
+  emit(event: string, arg?: unknown): boolean
+}
+export class ActixApp {
+  hostname?: string
+  port?: number
+  get<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  post<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  put<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  patch<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  delete<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  /** Overrides the automatic `HEAD` handling, which runs the `GET` handler. */
+  head<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  /** Overrides the automatic `OPTIONS` answer listing the allowed methods. */
+  options<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null): void
+  /**
+   * Removes the handlers for `method`, or every handler of the route when
+   * no method is given. With `when`, only the handlers registered with
+   * these conditions are removed. Returns whether anything was removed.
+   */
+  remove(path: string, method?: string | undefined | null, when?: MatchOptions | undefined | null): boolean
+  /**
+   * Builds the URL of a named route. Parameters missing from the route
+   * template are appended as the query string.
+   */
+  urlFor(name: string, params?: Record<string, string | number> | undefined | null): string
+  /**
+   * Configures how request paths are matched against routes.
+   *
+   * Call this before registering routes: with `caseSensitive: false` route
+   * templates are lowercased as they are inserted.
+   */
+  routerOptions(options: RouterOptions): void
+  /**
+   * Limits how many requests each API key can make to the app's routes
+   * and proxies.
+   * Passing `null` removes the quota.
+   */
+  quota(options?: QuotaOptions | undefined | null): void
+  /**
+   * Rejects requests without a valid HMAC signature, looking the secret
+   * of each key up with `lookup`. Passing `null` turns verification off.
+   *
+   * Routes registered with `unsigned: true` are not checked.
+   */
+  verifySignatures(lookup: import('./types').KeyLookup | null, options?: SignatureOptions | undefined | null): void
+  /**
+   * Signs every response with a shared secret, adding the
+   * `x-content-sha256` and `x-signature` headers. Streamed responses and
+   * ones over 16 MiB can't be signed: each is logged, and sent unsigned
+   * unless `rejectUnsigned` is set. Passing `null` turns signing off.
+   */
+  signResponses(options?: ResponseSigningOptions | undefined | null): void
+  /**
+   * Only serves paths under `options.prefixes` to URLs minted with
+   * `app.signUrl()` that have not expired; others get `403` before any
+   * fast path or handler runs. Passing `null` turns the check off.
+   */
+  signedUrls(options?: SignedUrlOptions | undefined | null): void
+  /**
+   * Asks browsers for client hints with `Accept-CH` and `Critical-CH` on
+   * routed responses, and hands the hints requests carry to handlers as
+   * `req.clientHints`. Passing `null` turns both off.
+   */
+  clientHints(options?: ClientHintsOptions | undefined | null): void
+  /**
+   * Decides whether clients sending `Expect: 100-continue` may upload
+   * their body, before it is read: bodies over `options.maxLength` get
+   * `413`, and `options.check` can refuse others. Passing `null` turns the
+   * policy off, leaving the `100 Continue` to be sent when the body is read.
+   */
+  expectContinue(options?: ExpectContinueOptions | undefined | null): void
+  /**
+   * Signs `url`, a path with an optional query string, so it is served for
+   * the next `ttl` seconds under the prefixes of `app.signedUrls()`. The
+   * URL must be percent-encoded the way clients will send it.
+   */
+  signUrl(url: string, ttl: number): string
+  /**
+   * Declares a tenant, recognized by a path prefix or a header value, with
+   * its own quota and body size limit. Declaring a tenant again under the
+   * same name replaces it.
+   */
+  tenant(options: TenantOptions): void
+  /**
+   * Serves requests for `host` from the routes of another app. The host may
+   * start with `*.` to match every subdomain.
+   *
+   * Routes added to `app` later are served too. Its own virtual hosts and
+   * settings such as `parseJson` are not used.
+   */
+  vhost(host: string, app: ActixApp): void
+  /**
+   * Swaps in the routes, middleware, upload endpoints, proxies and static
+   * responses of `app`, built while this one keeps serving, in one step.
+   * Connections stay open and requests already routed finish on the old
+   * routes. Settings of this app are kept.
+   */
+  reload(app: ActixApp): void
+  /**
+   * Calls `rebuild` whenever a file under `paths` changes, and reloads the
+   * app it returns, or its promise resolves to. Meant for development.
+   * Returns a function that stops watching.
+   */
+  watch(paths: Array<string>, rebuild: () => ActixApp | void | Promise<ActixApp | void>, options?: WatchOptions | undefined | null): () => void
+  /**
+   * Serves a constant response on `path`, for `GET` and `HEAD`, straight
+   * from memory: the JS thread is never involved. Registering the same
+   * path again replaces the response. Static paths are matched before the
+   * router, so they shadow routes on the same path. A `guard` is checked
+   * before the response is served.
+   */
+  getStatic(path: string, response: StaticResponse): void
+  /** Serves `/robots.txt` from memory, without calling into JS. */
+  robotsTxt(content: string): void
+  /** Serves `/favicon.ico` from memory, without calling into JS. */
+  favicon(icon: Buffer, contentType?: string | undefined | null): void
+  /**
+   * Parses `application/json` bodies before calling the handler, exposing
+   * the result as `req.jsonBody`. With `strict`, malformed bodies are
+   * rejected with `400` instead of reaching the handler.
+   */
+  parseJson(enabled: boolean, strict?: boolean | undefined | null): void
+  /**
+   * Makes `req.headers` a native object with `get()`, `has()` and
+   * `entries()` that converts a header to a string only when it is read,
+   * instead of a `Headers` filled with every header up front. Takes effect
+   * immediately, even on a running server.
+   */
+  lazyHeaders(enabled: boolean): void
+  /**
+   * Hands requests to handlers as `NodeRequest`s, which read the method,
+   * URL, headers and body from the native request when they are used and
+   * add `params`, `path` and `query`, instead of building a WHATWG
+   * `Request` for every request. Takes effect immediately, even on a
+   * running server.
+   */
+  nativeRequests(enabled: boolean): void
+  /**
+   * Gives requests a `signal` that is aborted when the client disconnects
+   * before the response is ready, so long-running work can be cancelled.
+   * Takes effect immediately, even on a running server.
+   */
+  abortSignals(enabled: boolean): void
+  /**
+   * Believes the `Forwarded` or `X-Forwarded-*` headers of peers within
+   * `ranges`, CIDR blocks or addresses, when setting `req.ip`,
+   * `req.protocol` and `req.hostname`. `loopback`, `linklocal` and
+   * `uniquelocal` name the blocks of those names. Passing `null` trusts
+   * no proxy.
+   */
+  trustProxy(ranges?: Array<string> | undefined | null): void
+  /**
+   * Fixes requests of legacy clients rather than rejecting them: fills in
+   * a missing `Host`, reads `;` in queries as `&` and percent-encodes
+   * characters queries may not hold as they are. Passing `null` stops.
+   */
+  normalize(options?: NormalizeOptions | undefined | null): void
+  /** How many requests each fix of `app.normalize()` was applied to. */
+  normalizationMetrics(): NormalizationSnapshot
+  /**
+   * Answers Kubernetes-style probes before routing. The `liveness` path
+   * is served without calling into JS, so it answers even while the event
+   * loop is busy. The `readiness` path answers `503` while the server
+   * drains, or when `check` returns `false`, throws or takes longer than
+   * `timeout` milliseconds. Passing `null` removes both.
+   */
+  health(options?: HealthOptions | undefined | null): void
+  /**
+   * Serves resumable uploads with the tus protocol at `path`: `POST` to it
+   * creates an upload, and `HEAD`, `PATCH` and `DELETE` on `path/<id>`
+   * query, append to and cancel it. Bodies are written to
+   * `options.directory` without going through JS. Passing `null` removes
+   * the endpoint.
+   */
+  tus(path: string, options?: TusOptions | undefined | null): void
+  /**
+   * Forwards requests under `prefix` to `target`, an `http:`, `https:` or
+   * `unix:` URL or a list of weighted upstreams, with the client configured
+   * at the time. Bodies are streamed both ways without going through JS. Passing
+   * `null` removes the proxy.
+   *
+   * A request under several prefixes goes to the longest one's proxy. The
+   * app's and tenants' quotas apply to proxied requests, but body size
+   * limits and signature checks don't.
+   */
+  proxy(prefix: string, target?: string | Array<UpstreamOptions> | undefined | null, options?: ProxyOptions | undefined | null): void
+  /**
+   * Runs `requests` through the routes, middleware and handlers every time
+   * the server starts, before it accepts connections, so the first clients
+   * don't pay for cold code paths. Handlers see them with `req.warmup` set.
+   * Takes effect on the next `listen()`.
+   */
+  warmup(requests: Array<WarmupRequest>): void
+  /**
+   * How long a stopping server waits for the promises passed to
+   * `req.waitUntil()` before `listen()` resolves anyway, in milliseconds.
+   * Defaults to 30 seconds. Takes effect on the next `listen()`.
+   */
+  backgroundTimeout(ms: number): void
+  /**
+   * Stops the server on `SIGINT` or `SIGTERM`, or the signals listed in
+   * `options`: it stops accepting, emits `shutdown`, and gives open
+   * connections `options.timeout` to finish before closing them, after
+   * which `listen()` resolves. Takes effect on the next `listen()`. Once
+   * the server stops, the signals end the process again, as they do by
+   * default.
+   */
+  handleSignals(enabled: boolean, options?: ShutdownOptions | undefined | null): void
+  /**
+   * Chooses the body of the responses to requests rejected before reaching
+   * a handler, such as malformed or oversized bodies. `format` receives the
+   * error and returns a string, a `Buffer`, a value sent as JSON, or nothing
+   * for an empty body. The status code is not up to the formatter.
+   *
+   * Passing `null` restores empty bodies.
+   */
+  errorFormatter(format: import('./types').ErrorFormatter | null): void
+  /**
+   * Logs every request once its response is ready. `format` is
+   * `"common"`, `"combined"`, `"dev"`, `"tiny"` or a template of tokens
+   * such as `:method :url :status :response-time ms`. Lines go to `output`,
+   * a function or a writable stream, or to stdout without one. Passing
+   * `null` turns logging off.
+   */
+  accessLog(format: 'common' | 'combined' | 'dev' | 'tiny' | (string & {}) | null, output?: import('./types').LogOutput | undefined | null): void
+  /**
+   * Copies selected responses to `output` as they are sent, for auditing
+   * or offline analysis: a file path, appended to, or a function called
+   * with each record. Bodies are copied chunk by chunk as they stream out;
+   * records are dropped, rather than held, when the output falls 1024
+   * records behind. Passing `null` turns archiving off.
+   */
+  archive(options: ArchiveOptions | null, output?: string | ((record: import('./types').ArchiveRecord) => void) | undefined | null): void
+  /**
+   * How many records `app.archive()` dropped because its output fell
+   * behind.
+   */
+  archiveMetrics(): ArchiveSnapshot
+  /**
+   * Records a span for every request and exports them to an OpenTelemetry
+   * collector over OTLP/HTTP. Requests carrying a `traceparent` header join
+   * its trace; handlers get their span as `req.traceparent`, to pass on to
+   * the requests they make. Passing `null` turns tracing off.
+   */
+  tracing(options?: TracingOptions | undefined | null): void
+  /**
+   * Runs `handler` alongside the handler of an already registered route,
+   * with the same request, once the primary handler has answered. Its
+   * response is dropped; when it differs from the primary one, the
+   * difference is reported to `app.onShadowMismatch()`. Passing `null`
+   * removes the shadow handler.
+   *
+   * Of the handlers registered for `method` and `path` with `when`, the
+   * shadowed one is the one with the same conditions; without `when`, the
+   * one registered without any.
+   */
+  shadow<Req extends import('./types').AnyActixRequest = import('./types').ActixRequest>(method: string, path: string, handler: import('./types').Handler<Req> | null, when?: MatchOptions | undefined | null): void
+  /**
+   * Calls `callback` with every response of a shadow handler that differs
+   * from the primary handler's in status, headers or body. Without a
+   * callback, mismatches are logged.
+   */
+  onShadowMismatch(callback: ((mismatch: import('./types').ShadowMismatch) => void) | null): void
+  /**
+   * Tunes how responses are written to the socket. Takes effect on the next
+   * `listen()`.
+   */
+  writeStrategy(options: WriteOptions): void
+  /**
+   * Sets HTTP/1 options of the server, keeping the ones not given. Takes
+   * effect on the next `listen()`.
+   */
+  configure(options: ServerOptions): void
+  /**
+   * Calls `listener` on server events: `listening` once every listener is
+   * bound, `connection` for each accepted connection, `error` when a
+   * listener fails (with the error's `code`, such as `EADDRINUSE`) or a
+   * request handler panics, and `close` once the server stopped.
+   */
+  on<E extends keyof import('./types').AppEvents>(event: E, listener: import('./types').AppEvents[E]): void
+  /** Removes the listeners of `event`, or of every event. */
+  removeAllListeners(event?: keyof import('./types').AppEvents | undefined | null): void
+  /**
+   * The address the server is bound to, like Node's `server.address()`.
+   * `null` until the server is bound, and for Unix sockets. With several
+   * listeners, this is the first one's.
+   */
+  address(): ListenAddress | null
+  /** Time spent in the JS middleware and handlers of each route. */
+  routeTimings(): Array<RouteTiming>
+  /**
+   * Everything requests are answered by: upload endpoints, proxies,
+   * static responses and routes with their conditions and names, in the
+   * order requests are matched against them. Routes come in the order
+   * their templates were first registered.
+   */
+  routes(): Array<RegisteredRoute>
+  /**
+   * Routes whose requests take `thresholdMs` milliseconds or more on
+   * average in JS, slowest first.
+   */
+  slowRoutes(thresholdMs: number): Array<RouteTiming>
+  /**
+   * Histograms of the requests served per connection and of how long
+   * connections stayed open, over every connection closed so far.
+   */
+  connectionMetrics(): ConnectionMetricsSnapshot
+  /** Requests, failures, latency and ejections of every proxy upstream. */
+  upstreamMetrics(): Array<UpstreamSnapshot>
+  /**
+   * Replaces the client used by `app.fetch()` and by proxies set up
+   * afterwards with one connecting and resolving host names as described by
+   * `options`. Connections pooled by the previous client are not reused.
+   */
+  configureClient(options: ClientOptions): void
+  /** Forgets the host names cached by the client's DNS cache, if enabled. */
+  flushDnsCache(): void
+  /** Sends an outbound request through the app's pooled client. */
+  fetch(url: string, options?: FetchOptions | undefined | null): Promise<Response>
+  /**
+   * Starts serving. `port` is either a port, or a list of listeners served
+   * at once with the same routes, each with its own host and server
+   * options. Port `0` picks a free port, available from `address()` once
+   * the callback runs.
+   */
+  listen(port: number | Array<ListenerOptions>, hostname?: string | import('./types').ListenCallback | undefined | null, callback?: import('./types').ListenCallback | undefined | null): Promise<void>
+  /**
+   * Starts serving on a Unix domain socket at `path`. The socket file is
+   * replaced if it already exists and removed once the server stops.
+   */
+  listenUnix(path: string, options?: UnixListenOptions | undefined | null, callback?: import('./types').ListenCallback | undefined | null): Promise<void>
+  /**
+   * Starts serving on sockets that are already listening. `fd` defaults to
+   * every socket passed by systemd socket activation (`LISTEN_FDS`), which
+   * are only handed out to the first call. A descriptor is only served
+   * once, and only if it is a listening stream socket.
+   */
+  listenFd(fd?: number | undefined | null, options?: ServerOptions | undefined | null, callback?: import('./types').ListenCallback | undefined | null): Promise<void>
 }
@@ -295,8 +295,33 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { actix, ActixApp } = nativeBinding
+const {
+  actix,
+  ActixApp,
+  status,
+  ndjson,
+  csv,
+  express,
+  writer,
+  serializeStructuredHeader,
+  RequestHeaders,
+  NodeRequest,
+  NodeResponse,
+  ExpressResponse,
+  ResponseWriter,
+} = nativeBinding
 
 module.exports.actix = actix
-module.exports.actix = actix
+module.exports.default = actix
 module.exports.ActixApp = ActixApp
+module.exports.status = status
+module.exports.ndjson = ndjson
+module.exports.csv = csv
+module.exports.express = express
+module.exports.writer = writer
+module.exports.serializeStructuredHeader = serializeStructuredHeader
+module.exports.RequestHeaders = RequestHeaders
+module.exports.NodeRequest = NodeRequest
+module.exports.NodeResponse = NodeResponse
+module.exports.ExpressResponse = ExpressResponse
+module.exports.ResponseWriter = ResponseWriter
@@ -0,0 +1,82 @@
+//! `req.signal`, aborted when the client goes away before it is answered.
+//!
+//! hyper drops the future answering a request once its connection closes.
+//! With `app.abortSignals(true)`, each request gets a [`Disconnect`] guard
+//! living in that future: dropped before the response is ready, it aborts
+//! the `AbortController` behind every `req.signal` handed out for the
+//! request, so handlers can stop work nobody will receive. Once the
+//! response is ready, signals are never aborted, even if the client leaves
+//! while the body is sent.
+
+use std::sync::{Arc, Mutex};
+
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, Error, JsFunction, JsObject, JsUnknown, Result};
+
+use crate::stream;
+
+type Abort = ThreadsafeFunction<(), ErrorStrategy::Fatal>;
+
+#[derive(Default)]
+struct State {
+  answered: bool,
+  aborted: bool,
+  /// `abort()` of each controller handed out, while the request is pending.
+  aborts: Vec<Abort>,
+}
+
+/// Aborts the signals of a request if dropped before [`Disconnect::answered`].
+pub struct Disconnect(Arc<Mutex<State>>);
+
+/// Hands out signals for a request, kept in its extensions.
+#[derive(Clone)]
+pub struct Signals(Arc<Mutex<State>>);
+
+impl Disconnect {
+  pub fn new() -> (Disconnect, Signals) {
+    let state = Arc::new(Mutex::new(State::default()));
+    (Disconnect(state.clone()), Signals(state))
+  }
+
+  /// The response is ready: the signals will not be aborted.
+  pub fn answered(self) {
+    let mut state = self.0.lock().unwrap();
+    state.answered = true;
+    state.aborts.clear();
+  }
+}
+
+impl Drop for Disconnect {
+  fn drop(&mut self) {
+    let mut state = self.0.lock().unwrap();
+    if state.answered {
+      return;
+    }
+    state.aborted = true;
+    for abort in state.aborts.drain(..) {
+      abort.call((), ThreadsafeFunctionCallMode::NonBlocking);
+    }
+  }
+}
+
+impl Signals {
+  /// A new `AbortSignal` for the request.
+  pub fn signal(&self, env: &Env) -> Result<JsObject> {
+    let constructor: JsFunction = env.get_global()?.get_named_property("AbortController")?;
+    let controller = constructor.new_instance::<JsUnknown>(&[])?;
+    let signal = controller.get_named_property("signal")?;
+
+    let mut state = self.0.lock().unwrap();
+    if state.aborted {
+      let abort: JsFunction = controller.get_named_property("abort")?;
+      abort.call_without_args(Some(&controller))?;
+    } else if !state.answered {
+      let mut abort = stream::bound(env, &controller, "abort")?
+        .ok_or_else(|| Error::from_reason("`AbortController` has no `abort()` method"))?;
+      // Pending signals shouldn't keep the process alive.
+      abort.unref(env)?;
+      state.aborts.push(abort);
+    }
+    Ok(signal)
+  }
+}
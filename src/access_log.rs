@@ -0,0 +1,295 @@
+//! Access logging, configured with `app.accessLog()`.
+//!
+//! Lines are formatted on the runtime threads once the response is ready,
+//! from a template of `:token`s in the style of morgan, and written to
+//! stdout or handed to a JS stream.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderName};
+use hyper::{HeaderMap, Method, Uri, Version};
+use napi::bindgen_prelude::Either;
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, JsFunction, JsObject, Result};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{ConnectionInfo, Request, Response};
+
+const COMMON: &str =
+  r#":remote-addr - - [:date[clf]] ":method :url HTTP/:http-version" :status :res[content-length]"#;
+const COMBINED: &str = r#":remote-addr - - [:date[clf]] ":method :url HTTP/:http-version" :status :res[content-length] ":referrer" ":user-agent""#;
+const DEV: &str = ":method :url :status :response-time ms - :res[content-length]";
+const TINY: &str = ":method :url :status :res[content-length] - :response-time ms";
+
+#[derive(Clone, Debug)]
+enum Token {
+  Literal(String),
+  RemoteAddr,
+  Date,
+  Method,
+  Url,
+  HttpVersion,
+  Status,
+  ResponseTime,
+  Referrer,
+  UserAgent,
+  Request(HeaderName),
+  Response(HeaderName),
+}
+
+/// A parsed log line template.
+#[derive(Clone, Debug)]
+struct Format(Vec<Token>);
+
+impl Format {
+  /// Parses one of the named formats (`common`, `combined`, `dev`, `tiny`)
+  /// or a custom template.
+  fn parse(format: &str) -> std::result::Result<Self, ActixError> {
+    let template = match format {
+      "common" => COMMON,
+      "combined" => COMBINED,
+      "dev" => DEV,
+      "tiny" => TINY,
+      template => template,
+    };
+
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(idx) = rest.find(':') {
+      literal.push_str(&rest[..idx]);
+      rest = &rest[idx + 1..];
+
+      let len = rest
+        .find(|c: char| !(c.is_ascii_lowercase() || c == '-'))
+        .unwrap_or(rest.len());
+      if len == 0 {
+        literal.push(':');
+        continue;
+      }
+      let name = &rest[..len];
+      rest = &rest[len..];
+
+      let arg = match rest.strip_prefix('[').and_then(|arg| arg.split_once(']')) {
+        Some((arg, after)) => {
+          rest = after;
+          Some(arg)
+        }
+        None => None,
+      };
+
+      if !literal.is_empty() {
+        tokens.push(Token::Literal(std::mem::take(&mut literal)));
+      }
+      tokens.push(token(name, arg)?);
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+      tokens.push(Token::Literal(literal));
+    }
+
+    Ok(Format(tokens))
+  }
+}
+
+fn token(name: &str, arg: Option<&str>) -> std::result::Result<Token, ActixError> {
+  let invalid = |message: String| ActixError::new(ErrorCode::InvalidOption, message);
+  let header = |arg: Option<&str>| {
+    let arg = arg.ok_or_else(|| invalid(format!("`:{name}` needs a header name")))?;
+    HeaderName::try_from(arg).map_err(|err| invalid(err.to_string()))
+  };
+
+  Ok(match name {
+    "remote-addr" => Token::RemoteAddr,
+    // Only the common log format date is supported.
+    "date" if matches!(arg, None | Some("clf")) => Token::Date,
+    "method" => Token::Method,
+    "url" => Token::Url,
+    "http-version" => Token::HttpVersion,
+    "status" => Token::Status,
+    "response-time" => Token::ResponseTime,
+    "referrer" | "referer" => Token::Referrer,
+    "user-agent" => Token::UserAgent,
+    "req" => Token::Request(header(arg)?),
+    "res" => Token::Response(header(arg)?),
+    _ => return Err(invalid(format!("unknown access log token `:{name}`"))),
+  })
+}
+
+/// What is kept of a request until its response is ready.
+pub struct Pending {
+  started: Instant,
+  remote: Option<SocketAddr>,
+  method: Method,
+  uri: Uri,
+  version: Version,
+  headers: HeaderMap,
+}
+
+impl Pending {
+  pub fn start(req: &Request) -> Self {
+    Pending {
+      started: Instant::now(),
+      remote: req
+        .extensions()
+        .get::<ConnectionInfo>()
+        .and_then(ConnectionInfo::peer_addr),
+      method: req.method().clone(),
+      uri: req.uri().clone(),
+      version: req.version(),
+      headers: req.headers().clone(),
+    }
+  }
+}
+
+enum Output {
+  Stdout,
+  Js(ThreadsafeFunction<String, ErrorStrategy::Fatal>),
+}
+
+/// Formats and writes access log lines.
+pub struct AccessLog {
+  format: Format,
+  output: Output,
+}
+
+impl AccessLog {
+  /// `output` is a function called with each line, or a writable stream.
+  /// Lines go to stdout without one.
+  pub fn new(
+    env: &Env,
+    format: &str,
+    output: Option<Either<JsFunction, JsObject>>,
+  ) -> Result<Self> {
+    let write = match output {
+      None => None,
+      Some(Either::A(write)) => Some(write),
+      Some(Either::B(stream)) => {
+        // `stream.write` needs the stream as `this`.
+        let write = stream
+          .get_named_property::<JsFunction>("write")?
+          .coerce_to_object()?;
+        let bind: JsFunction = write.get_named_property("bind")?;
+        let bound = bind.call(Some(&write), &[stream])?;
+        Some(bound.try_into()?)
+      }
+    };
+
+    let output = match write {
+      Some(write) => {
+        let mut write =
+          write.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<String>| {
+            ctx.env.create_string(&ctx.value).map(|line| vec![line])
+          })?;
+        // Logging doesn't keep the process alive on its own.
+        write.unref(env)?;
+        Output::Js(write)
+      }
+      None => Output::Stdout,
+    };
+
+    Ok(AccessLog {
+      format: Format::parse(format).map_err(|err| err.into_js(env))?,
+      output,
+    })
+  }
+
+  pub fn log(&self, pending: Pending, res: &Response) {
+    let line = self.line(&pending, res, pending.started.elapsed());
+    match &self.output {
+      Output::Stdout => println!("{line}"),
+      Output::Js(write) => {
+        write.call(line + "\n", ThreadsafeFunctionCallMode::NonBlocking);
+      }
+    }
+  }
+
+  fn line(&self, req: &Pending, res: &Response, elapsed: Duration) -> String {
+    let header = |headers: &HeaderMap, name: &HeaderName| {
+      headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+    };
+
+    let mut line = String::new();
+    for token in &self.format.0 {
+      let value = match token {
+        Token::Literal(literal) => {
+          line.push_str(literal);
+          continue;
+        }
+        Token::RemoteAddr => req.remote.map(|addr| addr.ip().to_string()),
+        Token::Date => Some(clf_date(SystemTime::now())),
+        Token::Method => Some(req.method.to_string()),
+        Token::Url => Some(req.uri.to_string()),
+        Token::HttpVersion => Some(
+          match req.version {
+            Version::HTTP_09 => "0.9",
+            Version::HTTP_10 => "1.0",
+            Version::HTTP_2 => "2.0",
+            Version::HTTP_3 => "3.0",
+            _ => "1.1",
+          }
+          .to_owned(),
+        ),
+        Token::Status => Some(res.status().as_u16().to_string()),
+        Token::ResponseTime => Some(format!("{:.3}", elapsed.as_secs_f64() * 1000.0)),
+        Token::Referrer => header(&req.headers, &header::REFERER),
+        Token::UserAgent => header(&req.headers, &header::USER_AGENT),
+        Token::Request(name) => header(&req.headers, name),
+        // Bodies are complete by now, so their exact size is known even
+        // without a `Content-Length` header.
+        Token::Response(name) if name == header::CONTENT_LENGTH => header(res.headers(), name)
+          .or_else(|| {
+            HttpBody::size_hint(res.body())
+              .exact()
+              .map(|len| len.to_string())
+          }),
+        Token::Response(name) => header(res.headers(), name),
+      };
+
+      line.push_str(value.as_deref().unwrap_or("-"));
+    }
+    line
+  }
+}
+
+/// Formats a time like `10/Oct/2000:13:55:36 +0000`.
+fn clf_date(time: SystemTime) -> String {
+  const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+  ];
+
+  let secs = time
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |elapsed| elapsed.as_secs()) as i64;
+  let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+  // Proleptic Gregorian date of a number of days since the epoch.
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + i64::from(month <= 2);
+
+  let mut date = String::with_capacity(26);
+  let _ = write!(
+    date,
+    "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+    MONTHS[month as usize - 1],
+    secs / 3600,
+    secs % 3600 / 60,
+    secs % 60
+  );
+  date
+}
@@ -0,0 +1,417 @@
+//! Copies of outgoing responses, configured with `app.archive()`.
+//!
+//! Selected responses are teed as their body streams to the client: each
+//! chunk is handed to the sink as it is sent, so the body is never buffered
+//! a second time. Since responses are sent concurrently, the sink receives
+//! a stream of records tagged with the id of their response:
+//!
+//! - `start`, with the request method and path, the status and headers,
+//! - `chunk`, with a piece of the body,
+//! - `end`, with `aborted` set if the client went away first.
+//!
+//! A JS sink gets the records as objects. A file sink gets each record as a
+//! line of JSON, with a `chunk` record giving the length of the body bytes
+//! that follow its line.
+//!
+//! Records wait for the sink in a queue of [`QUEUE`] records. When a slow
+//! sink lets it fill up, records are dropped and counted rather than held:
+//! a response whose `start` is dropped isn't archived, and one that loses
+//! a chunk stops sending chunks and ends with `truncated` set.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderValue};
+use hyper::{HeaderMap, Method, StatusCode};
+use napi::bindgen_prelude::{Buffer, Either};
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, JsFunction, JsObject, Result, Status};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Request, Response};
+
+/// Records waiting for the sink at most.
+const QUEUE: usize = 1024;
+
+/// Chooses the responses archived with `app.archive()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveOptions {
+  /// Share of the matching responses archived, from 0 to 1. Defaults to 1.
+  pub sample_ratio: Option<f64>,
+  /// Only archives requests whose path starts with one of these prefixes.
+  pub paths: Option<Vec<String>>,
+  /// Only archives responses with one of these statuses.
+  pub statuses: Option<Vec<u16>>,
+}
+
+/// One piece of an archived response.
+enum Record {
+  Start {
+    id: u64,
+    method: Method,
+    path: String,
+    status: StatusCode,
+    headers: HeaderMap,
+  },
+  Chunk {
+    id: u64,
+    data: Bytes,
+  },
+  End {
+    id: u64,
+    aborted: bool,
+    /// Chunks were dropped since the sink fell behind.
+    truncated: bool,
+  },
+}
+
+impl Record {
+  fn to_js(&self, env: &Env) -> Result<JsObject> {
+    let mut record = env.create_object()?;
+    match self {
+      Record::Start {
+        id,
+        method,
+        path,
+        status,
+        headers,
+      } => {
+        record.set_named_property("id", env.create_double(*id as f64)?)?;
+        record.set_named_property("type", env.create_string("start")?)?;
+        record.set_named_property("method", env.create_string(method.as_str())?)?;
+        record.set_named_property("path", env.create_string(path)?)?;
+        record.set_named_property("status", env.create_uint32(status.as_u16().into())?)?;
+        record.set_named_property("headers", env.to_js_value(&header_map(headers))?)?;
+      }
+      Record::Chunk { id, data } => {
+        record.set_named_property("id", env.create_double(*id as f64)?)?;
+        record.set_named_property("type", env.create_string("chunk")?)?;
+        record.set_named_property("data", Buffer::from(data.to_vec()))?;
+      }
+      Record::End {
+        id,
+        aborted,
+        truncated,
+      } => {
+        record.set_named_property("id", env.create_double(*id as f64)?)?;
+        record.set_named_property("type", env.create_string("end")?)?;
+        record.set_named_property("aborted", env.get_boolean(*aborted)?)?;
+        record.set_named_property("truncated", env.get_boolean(*truncated)?)?;
+      }
+    }
+    Ok(record)
+  }
+
+  /// The record as written to a file sink.
+  fn to_bytes(&self) -> Vec<u8> {
+    let line = match self {
+      Record::Start {
+        id,
+        method,
+        path,
+        status,
+        headers,
+      } => json!({
+        "id": id,
+        "type": "start",
+        "method": method.as_str(),
+        "path": path,
+        "status": status.as_u16(),
+        "headers": header_map(headers),
+      }),
+      Record::Chunk { id, data } => json!({ "id": id, "type": "chunk", "length": data.len() }),
+      Record::End {
+        id,
+        aborted,
+        truncated,
+      } => json!({ "id": id, "type": "end", "aborted": aborted, "truncated": truncated }),
+    };
+
+    let mut bytes = line.to_string().into_bytes();
+    bytes.push(b'\n');
+    if let Record::Chunk { data, .. } = self {
+      bytes.extend_from_slice(data);
+      bytes.push(b'\n');
+    }
+    bytes
+  }
+}
+
+/// Headers as an object, with repeated headers joined by `, `.
+fn header_map(headers: &HeaderMap) -> HashMap<&str, String> {
+  let mut map: HashMap<&str, String> = HashMap::new();
+  for (name, value) in headers {
+    let value = String::from_utf8_lossy(value.as_bytes());
+    map
+      .entry(name.as_str())
+      .and_modify(|joined| {
+        joined.push_str(", ");
+        joined.push_str(&value);
+      })
+      .or_insert_with(|| value.into_owned());
+  }
+  map
+}
+
+#[derive(Clone)]
+enum Output {
+  Js(ThreadsafeFunction<Record, ErrorStrategy::Fatal>),
+  File(mpsc::Sender<Record>),
+}
+
+#[derive(Clone)]
+struct Sink {
+  output: Output,
+  /// Records dropped because the queue was full.
+  dropped: Arc<AtomicU64>,
+}
+
+impl Sink {
+  /// Queues `record`, or drops it if the queue is full. Returns whether it
+  /// was queued.
+  fn send(&self, record: Record) -> bool {
+    let queued = match &self.output {
+      Output::Js(callback) => {
+        callback.call(record, ThreadsafeFunctionCallMode::NonBlocking) == Status::Ok
+      }
+      Output::File(records) => records.try_send(record).is_ok(),
+    };
+    if !queued {
+      self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+    queued
+  }
+}
+
+/// How an archive kept up with its sink.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveSnapshot {
+  /// Records dropped because the sink fell behind.
+  pub dropped: f64,
+}
+
+/// Appends records to a file until every sender is gone.
+async fn write_file(mut file: tokio::fs::File, mut records: mpsc::Receiver<Record>) {
+  while let Some(record) = records.recv().await {
+    if let Err(err) = file.write_all(&record.to_bytes()).await {
+      eprintln!("Error writing response archive: {err}");
+      return;
+    }
+  }
+}
+
+/// A request whose response will be archived, if its status matches.
+pub struct Selected {
+  method: Method,
+  path: String,
+}
+
+pub struct Archive {
+  sink: Sink,
+  sample_ratio: f64,
+  paths: Vec<String>,
+  statuses: Vec<u16>,
+  /// Matching responses seen so far, for sampling.
+  seen: AtomicU64,
+  next_id: AtomicU64,
+}
+
+impl Archive {
+  /// Archives to `output`: a file path, appended to, or a function called
+  /// with each record.
+  pub fn new(
+    env: &Env,
+    options: ArchiveOptions,
+    output: Either<String, JsFunction>,
+  ) -> Result<Self> {
+    let sample_ratio = options.sample_ratio.unwrap_or(1.0);
+    if !(0.0..=1.0).contains(&sample_ratio) {
+      return Err(
+        ActixError::new(
+          ErrorCode::InvalidOption,
+          format!("`sampleRatio` must be between 0 and 1, got {sample_ratio}"),
+        )
+        .into_js(env),
+      );
+    }
+
+    let output = match output {
+      Either::A(path) => {
+        let file = std::fs::OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(&path)
+          .map_err(|err| {
+            ActixError::new(
+              ErrorCode::InvalidOption,
+              format!("cannot open `{path}`: {err}"),
+            )
+            .caused_by_io(&err)
+            .into_js(env)
+          })?;
+        let (records, queue) = mpsc::channel(QUEUE);
+        napi::bindgen_prelude::spawn(write_file(tokio::fs::File::from_std(file), queue));
+        Output::File(records)
+      }
+      Either::B(callback) => {
+        let mut callback = callback
+          .create_threadsafe_function(QUEUE, |ctx: ThreadSafeCallContext<Record>| {
+            ctx.value.to_js(&ctx.env).map(|record| vec![record])
+          })?;
+        // Archiving doesn't keep the process alive on its own.
+        callback.unref(env)?;
+        Output::Js(callback)
+      }
+    };
+    let sink = Sink {
+      output,
+      dropped: Arc::new(AtomicU64::new(0)),
+    };
+
+    Ok(Archive {
+      sink,
+      sample_ratio,
+      paths: options.paths.unwrap_or_default(),
+      statuses: options.statuses.unwrap_or_default(),
+      seen: AtomicU64::new(0),
+      next_id: AtomicU64::new(0),
+    })
+  }
+
+  /// Remembers what the response needs from the request, if its path is
+  /// archived.
+  pub fn select(&self, req: &Request) -> Option<Selected> {
+    let path = req.uri().path();
+    let matches = self.paths.is_empty()
+      || self
+        .paths
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()));
+
+    matches.then(|| Selected {
+      method: req.method().clone(),
+      path: path.to_owned(),
+    })
+  }
+
+  /// Tees the response to the sink if it is sampled. Sampling is spread
+  /// evenly: with a ratio of 0.25, every fourth matching response is kept.
+  pub fn tee(&self, selected: Selected, mut res: Response) -> Response {
+    let status = res.status();
+    if !self.statuses.is_empty() && !self.statuses.contains(&status.as_u16()) {
+      return res;
+    }
+
+    let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+    if ((seen + 1.0) * self.sample_ratio).floor() == (seen * self.sample_ratio).floor() {
+      return res;
+    }
+
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let started = self.sink.send(Record::Start {
+      id,
+      method: selected.method,
+      path: selected.path,
+      status,
+      headers: res.headers().clone(),
+    });
+    if !started {
+      return res;
+    }
+
+    if res.body().0.is_end_stream() {
+      self.sink.send(Record::End {
+        id,
+        aborted: false,
+        truncated: false,
+      });
+      return res;
+    }
+
+    // The wrapped body no longer knows its length, so keep it in the
+    // headers rather than switching to chunked encoding.
+    if let Some(length) = HttpBody::size_hint(&res.body().0).exact() {
+      res
+        .headers_mut()
+        .entry(header::CONTENT_LENGTH)
+        .or_insert_with(|| HeaderValue::from(length));
+    }
+
+    res.map(|body| {
+      Body(hyper::Body::wrap_stream(Tee {
+        body,
+        sink: self.sink.clone(),
+        id,
+        done: false,
+        truncated: false,
+      }))
+    })
+  }
+
+  pub fn snapshot(&self) -> ArchiveSnapshot {
+    ArchiveSnapshot {
+      dropped: self.sink.dropped.load(Ordering::Relaxed) as f64,
+    }
+  }
+}
+
+/// A response body that hands a copy of each chunk to the sink.
+struct Tee {
+  body: Body,
+  sink: Sink,
+  id: u64,
+  done: bool,
+  /// A chunk was dropped, so no more are sent.
+  truncated: bool,
+}
+
+impl Stream for Tee {
+  type Item = std::result::Result<Bytes, hyper::Error>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let polled = Pin::new(&mut self.body).poll_data(cx);
+    match &polled {
+      Poll::Ready(Some(Ok(data))) if !self.truncated => {
+        let queued = self.sink.send(Record::Chunk {
+          id: self.id,
+          data: data.clone(),
+        });
+        self.truncated = !queued;
+      }
+      Poll::Ready(Some(Err(_))) | Poll::Ready(None) if !self.done => {
+        self.done = true;
+        self.sink.send(Record::End {
+          id: self.id,
+          aborted: matches!(polled, Poll::Ready(Some(Err(_)))),
+          truncated: self.truncated,
+        });
+      }
+      _ => {}
+    }
+    polled
+  }
+}
+
+impl Drop for Tee {
+  fn drop(&mut self) {
+    if !self.done {
+      self.sink.send(Record::End {
+        id: self.id,
+        aborted: true,
+        truncated: self.truncated,
+      });
+    }
+  }
+}
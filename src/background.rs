@@ -0,0 +1,79 @@
+//! Work that outlives its response, registered with `req.waitUntil()`.
+//!
+//! The response is sent without waiting for it, but a server that stops
+//! waits for tracked work to finish, up to a timeout, before `listen()`
+//! resolves.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use napi::bindgen_prelude::FromNapiValue;
+use napi::{sys, Result};
+use tokio::sync::Notify;
+
+/// How long a stopping server waits for background work by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tracks background promises. Shared by the app and its servers, and
+/// carried by requests in their extensions.
+#[derive(Debug, Default)]
+pub struct Background {
+  pending: AtomicUsize,
+  idle: Notify,
+}
+
+impl Background {
+  /// Number of promises still running.
+  pub fn pending(&self) -> usize {
+    self.pending.load(Ordering::Acquire)
+  }
+
+  /// Runs `work` in the background until it settles. Rejections are only
+  /// logged: the response is long gone.
+  pub fn track<F>(self: &Arc<Self>, work: F)
+  where
+    F: Future<Output = Result<Settled>> + Send + 'static,
+  {
+    self.pending.fetch_add(1, Ordering::AcqRel);
+    let background = self.clone();
+
+    napi::bindgen_prelude::spawn(async move {
+      if let Err(err) = work.await {
+        eprintln!("Error in background work: {err}");
+      }
+      if background.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+        background.idle.notify_waiters();
+      }
+    });
+  }
+
+  /// Waits until no work is pending, for at most `timeout`. Returns the
+  /// number of promises abandoned.
+  pub async fn settle(&self, timeout: Duration) -> usize {
+    let idle = async {
+      loop {
+        // Created before checking, so a notification sent in between isn't
+        // missed.
+        let notified = self.idle.notified();
+        if self.pending() == 0 {
+          return;
+        }
+        notified.await;
+      }
+    };
+
+    let _ = tokio::time::timeout(timeout, idle).await;
+    self.pending()
+  }
+}
+
+/// What a background promise resolved to, which nobody reads.
+pub struct Settled;
+
+impl FromNapiValue for Settled {
+  unsafe fn from_napi_value(_: sys::napi_env, _: sys::napi_value) -> Result<Self> {
+    Ok(Settled)
+  }
+}
@@ -0,0 +1,57 @@
+//! Base64 as the protocols handled here spell it.
+
+/// The characters standing for 62 and 63.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alphabet {
+  /// `+` and `/` (RFC 4648 §4).
+  Standard,
+  /// `-` and `_` (RFC 4648 §5), as in JWTs.
+  UrlSafe,
+  /// Either, for senders known to mix them up.
+  Any,
+}
+
+/// Decodes `text` in `alphabet`, with or without padding.
+pub fn decode(text: &str, alphabet: Alphabet) -> Option<Vec<u8>> {
+  let text = text.trim_end_matches('=');
+  let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+  let (mut acc, mut bits) = (0u32, 0);
+
+  for byte in text.bytes() {
+    let sextet = match (byte, alphabet) {
+      (b'A'..=b'Z', _) => byte - b'A',
+      (b'a'..=b'z', _) => byte - b'a' + 26,
+      (b'0'..=b'9', _) => byte - b'0' + 52,
+      (b'+', Alphabet::Standard | Alphabet::Any) | (b'-', Alphabet::UrlSafe | Alphabet::Any) => 62,
+      (b'/', Alphabet::Standard | Alphabet::Any) | (b'_', Alphabet::UrlSafe | Alphabet::Any) => 63,
+      _ => return None,
+    };
+    acc = (acc << 6 | u32::from(sextet)) & 0xffff;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      decoded.push((acc >> bits) as u8);
+    }
+  }
+  Some(decoded)
+}
+
+/// Appends `bytes` to `out` in the standard alphabet, padded.
+pub fn encode(out: &mut String, bytes: &[u8]) {
+  const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+  for chunk in bytes.chunks(3) {
+    let group = chunk.iter().enumerate().fold(0u32, |group, (idx, byte)| {
+      group | u32::from(*byte) << (16 - 8 * idx)
+    });
+    for idx in 0..4 {
+      if idx <= chunk.len() {
+        out.push(char::from(
+          ALPHABET[(group >> (18 - 6 * idx) & 0x3f) as usize],
+        ));
+      } else {
+        out.push('=');
+      }
+    }
+  }
+}
@@ -0,0 +1,103 @@
+//! Routes whose handler receives requests in batches, to cross into JS once
+//! for many requests.
+//!
+//! Requests queue up while the JS thread is busy. As soon as the handler
+//! has returned for one batch, everything queued in the meantime is handed
+//! over as the next one, so under load each call carries the requests of a
+//! whole event loop tick, and a lone request still goes through at once.
+
+use std::sync::Arc;
+
+use napi::{Error, Result};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::dispatch::{Outcome, Returned};
+use crate::{BatchNode, MyRequest};
+
+/// Requests per batch unless the route sets `maxBatch`.
+pub const DEFAULT_MAX_BATCH: usize = 64;
+
+struct Queued {
+  req: Arc<MyRequest>,
+  reply: oneshot::Sender<Result<Outcome>>,
+}
+
+/// Queues the requests of a batched route for its handler.
+pub(crate) struct Batcher {
+  queue: mpsc::UnboundedSender<Queued>,
+}
+
+impl Batcher {
+  /// Starts feeding `handler`. The batches stop once the batcher is
+  /// dropped, along with its route.
+  pub fn new(handler: BatchNode, max_batch: usize) -> Self {
+    let (queue, queued) = mpsc::unbounded_channel();
+    napi::bindgen_prelude::spawn(feed(handler, queued, max_batch.max(1)));
+
+    Batcher { queue }
+  }
+
+  /// Waits for the handler to answer a request, as part of some batch.
+  pub async fn call(&self, req: Arc<MyRequest>) -> Result<Outcome> {
+    let (reply, replied) = oneshot::channel();
+    self
+      .queue
+      .send(Queued { req, reply })
+      .map_err(|_| Error::from_reason("the batched route was removed"))?;
+
+    replied
+      .await
+      .map_err(|_| Error::from_reason("the batched route was removed"))?
+  }
+}
+
+async fn feed(handler: BatchNode, mut queued: mpsc::UnboundedReceiver<Queued>, max_batch: usize) {
+  while let Some(first) = queued.recv().await {
+    let mut batch = vec![first];
+    while batch.len() < max_batch {
+      match queued.try_recv() {
+        Ok(next) => batch.push(next),
+        Err(_) => break,
+      }
+    }
+
+    let (reqs, replies): (Vec<_>, Vec<_>) = batch
+      .into_iter()
+      .map(|Queued { req, reply }| (req, reply))
+      .unzip();
+    let returned = handler.call_async::<Returned<Vec<Outcome>>>(reqs).await;
+
+    // The handler has returned, so the JS thread is free for the next
+    // batch; its promise settles on the side.
+    tokio::spawn(async move {
+      let outcomes = match returned {
+        Ok(returned) => returned.settle().await,
+        Err(err) => Err(err),
+      };
+      answer(replies, outcomes);
+    });
+  }
+}
+
+/// Hands each request of a batch its outcome, matched by position.
+fn answer(replies: Vec<oneshot::Sender<Result<Outcome>>>, outcomes: Result<Vec<Outcome>>) {
+  match outcomes {
+    Ok(outcomes) => {
+      let (requests, answered) = (replies.len(), outcomes.len());
+      let mut outcomes = outcomes.into_iter();
+      for reply in replies {
+        let outcome = outcomes.next().ok_or_else(|| {
+          Error::from_reason(format!(
+            "batch handler answered {answered} of {requests} requests"
+          ))
+        });
+        let _ = reply.send(outcome);
+      }
+    }
+    Err(err) => {
+      for reply in replies {
+        let _ = reply.send(Err(Error::new(err.status, err.reason.clone())));
+      }
+    }
+  }
+}
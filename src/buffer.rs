@@ -0,0 +1,37 @@
+//! Bodies handed between hyper and JS without copying.
+//!
+//! A body going to JS becomes an external `Buffer` over the memory hyper read
+//! it into, and the `Bytes` owning that memory is only dropped once the
+//! garbage collector has collected the `Buffer`. A body coming back wraps the
+//! JS `Buffer`, which stays referenced until hyper is done writing it.
+//!
+//! Runtimes that forbid external buffers get a copy instead.
+
+use hyper::body::Bytes;
+use napi::bindgen_prelude::Buffer;
+use napi::{Env, JsBuffer, Result};
+
+/// A `Buffer` viewing `bytes`.
+///
+/// The memory may be shared with other `Bytes` still read on the Rust side,
+/// so the `Buffer` must not be written to, and never reach user code. Only
+/// hand it to the WHATWG `Request` and `Response` constructors, which copy
+/// what they are given.
+pub fn to_js(env: &Env, bytes: Bytes) -> Result<JsBuffer> {
+  if bytes.is_empty() {
+    return env.create_buffer(0).map(|buffer| buffer.into_raw());
+  }
+
+  let data = bytes.as_ptr() as *mut u8;
+  let length = bytes.len();
+  // SAFETY: `bytes` is moved into the finalizer, so `data` stays valid for
+  // as long as the `Buffer` can be reached.
+  unsafe { env.create_buffer_with_borrowed_data(data, length, bytes, |bytes, _| drop(bytes)) }
+    .map(|buffer| buffer.into_raw())
+}
+
+/// The bytes of a `Buffer` returned from JS, referencing it rather than
+/// copying it. JS must not modify it until it has been sent.
+pub fn from_js(buffer: Buffer) -> Bytes {
+  Bytes::from_owner(buffer)
+}
@@ -0,0 +1,480 @@
+//! Outbound HTTP client.
+//!
+//! Requests made through [`Client`] run on the same tokio runtime as the
+//! server and share a single connection pool, so service-to-service calls
+//! from handlers don't need to go through undici.
+//!
+//! Bodies are streamed both ways: a request body given as an async
+//! iterable, such as a `ReadableStream` or a Node.js `Readable`, is pulled
+//! as the upstream reads it, and the response body is a `ReadableStream`
+//! reading from the connection as JS pulls it.
+//!
+//! Besides `http:` and `https:` URLs, the client accepts
+//! `unix:/path/to.sock:/request/path` to talk to local daemons over a Unix
+//! domain socket. The request path defaults to `/` when omitted.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::{self, HeaderValue};
+use hyper::{HeaderMap, Method, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use napi::bindgen_prelude::*;
+use napi::{JsFunction, JsObject, JsUnknown, ValueType};
+use tokio::sync::{Mutex, Notify};
+
+use crate::buffer;
+use crate::dns::{DnsCache, DnsCacheOptions, Resolver};
+use crate::error::{ActixError, ErrorCode};
+use crate::stream::Streamed;
+
+/// Options accepted by `app.fetch()`, mirroring a subset of `RequestInit`.
+#[napi(object)]
+pub struct FetchOptions {
+  pub method: Option<String>,
+  pub headers: Option<HashMap<String, String>>,
+  /// A string, a buffer, or an async iterable of strings and byte arrays
+  /// streamed as the upstream reads it.
+  #[napi(
+    ts_type = "string | Buffer | AsyncIterable<string | Uint8Array> | Iterable<string | Uint8Array>"
+  )]
+  pub body: Option<Either3<String, Buffer, JsObject>>,
+  /// Milliseconds to wait for the response headers. Unlimited by default.
+  pub timeout: Option<u32>,
+}
+
+/// A request built from `fetch()` arguments, ready to send.
+pub struct Outgoing {
+  request: hyper::Request<hyper::Body>,
+  /// Feeds the body of `request` from a JS iterable.
+  upload: Option<(Streamed, hyper::body::Sender)>,
+  timeout: Option<Duration>,
+}
+
+/// A response whose headers arrived, waiting to be converted into a JS
+/// `Response`.
+pub struct FetchResponse {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: hyper::Body,
+}
+
+/// Socket a request is sent over instead of TCP, set for `unix:` URLs.
+#[derive(Clone, Debug)]
+pub struct UnixSocket(pub PathBuf);
+
+/// How outbound connections are made, set with `app.configureClient()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+  /// Milliseconds to wait on a connection attempt before racing the next
+  /// address, alternating IPv6 and IPv4, when a host resolves to both
+  /// (Happy Eyeballs, RFC 8305). `0` tries addresses one after another.
+  /// Defaults to 300.
+  pub happy_eyeballs_timeout: Option<u32>,
+  /// Local addresses outbound sockets are bound to: at most one IPv4 and
+  /// one IPv6 address, each used for upstreams of its family.
+  pub local_addresses: Option<Vec<String>>,
+  /// Milliseconds a connection attempt may take. Unlimited by default.
+  pub connect_timeout: Option<u32>,
+  /// Caches resolved host names in process. Off by default.
+  pub dns_cache: Option<DnsCacheOptions>,
+  /// Fixed addresses for host names, used instead of resolving them.
+  pub hosts: Option<HashMap<String, Vec<String>>>,
+  /// Starts each connection at the next address of a host name resolving
+  /// to several, spreading connections over its records instead of always
+  /// trying the first one. Off by default.
+  pub rotate_addresses: Option<bool>,
+}
+
+/// A pooled HTTP/HTTPS client.
+#[derive(Clone)]
+pub struct Client {
+  inner: hyper::Client<HttpsConnector<HttpConnector<Resolver>>>,
+  dns_cache: Option<Arc<DnsCache>>,
+}
+
+impl Default for Client {
+  fn default() -> Self {
+    Client::with_connector(HttpConnector::new_with_resolver(Resolver::default()), None)
+  }
+}
+
+impl Client {
+  pub fn new(options: &ClientOptions) -> std::result::Result<Self, ActixError> {
+    let dns_cache = options
+      .dns_cache
+      .as_ref()
+      .map(|options| Arc::new(DnsCache::new(options)));
+    let mut hosts = HashMap::new();
+    for (host, addresses) in options.hosts.iter().flatten() {
+      let addresses = addresses
+        .iter()
+        .map(|address| {
+          let ip = address.parse::<IpAddr>().map_err(|_| {
+            ActixError::new(
+              ErrorCode::InvalidOption,
+              format!("invalid address `{address}` for host `{host}`"),
+            )
+          })?;
+          Ok(SocketAddr::new(ip, 0))
+        })
+        .collect::<std::result::Result<Arc<[SocketAddr]>, ActixError>>()?;
+      if addresses.is_empty() {
+        return Err(ActixError::new(
+          ErrorCode::InvalidOption,
+          format!("host `{host}` needs at least one address"),
+        ));
+      }
+      hosts.insert(host.to_ascii_lowercase(), addresses);
+    }
+    let resolver = Resolver::new(
+      dns_cache.clone(),
+      hosts,
+      options.rotate_addresses.unwrap_or(false),
+    );
+    let mut http = HttpConnector::new_with_resolver(resolver);
+
+    if let Some(timeout) = options.happy_eyeballs_timeout {
+      http.set_happy_eyeballs_timeout((timeout > 0).then(|| Duration::from_millis(timeout.into())));
+    }
+    if let Some(timeout) = options.connect_timeout {
+      http.set_connect_timeout(Some(Duration::from_millis(timeout.into())));
+    }
+
+    let (mut v4, mut v6) = (None, None);
+    for address in options.local_addresses.iter().flatten() {
+      let parsed = address.parse::<IpAddr>().map_err(|_| {
+        ActixError::new(
+          ErrorCode::InvalidOption,
+          format!("invalid local address `{address}`"),
+        )
+      })?;
+      let slot = match parsed {
+        IpAddr::V4(_) => &mut v4,
+        IpAddr::V6(_) => &mut v6,
+      };
+      if slot.replace(parsed).is_some() {
+        return Err(ActixError::new(
+          ErrorCode::InvalidOption,
+          "`localAddresses` takes at most one IPv4 and one IPv6 address",
+        ));
+      }
+    }
+    match (v4, v6) {
+      (Some(IpAddr::V4(v4)), Some(IpAddr::V6(v6))) => http.set_local_addresses(v4, v6),
+      (v4, v6) => http.set_local_address(v4.or(v6)),
+    }
+
+    Ok(Client::with_connector(http, dns_cache))
+  }
+
+  fn with_connector(mut http: HttpConnector<Resolver>, dns_cache: Option<Arc<DnsCache>>) -> Self {
+    // Lets `https:` URLs through to the TLS layer.
+    http.enforce_http(false);
+    let connector = HttpsConnectorBuilder::new()
+      .with_webpki_roots()
+      .https_or_http()
+      .enable_http1()
+      .wrap_connector(http);
+
+    Client {
+      inner: hyper::Client::builder().build(connector),
+      dns_cache,
+    }
+  }
+
+  /// The cache of resolved host names, if enabled.
+  pub fn dns_cache(&self) -> Option<&DnsCache> {
+    self.dns_cache.as_deref()
+  }
+
+  /// Sends a request built by [`build_request`], resolving once the
+  /// response headers arrive.
+  pub async fn fetch(&self, outgoing: Outgoing) -> std::result::Result<FetchResponse, ActixError> {
+    let Outgoing {
+      request,
+      upload,
+      timeout,
+    } = outgoing;
+    if let Some((streamed, sender)) = upload {
+      tokio::spawn(streamed.pump(sender));
+    }
+
+    let sent = self.send(request);
+    let response = match timeout {
+      Some(timeout) => tokio::time::timeout(timeout, sent).await.map_err(|_| {
+        ActixError::new(
+          ErrorCode::FetchFailed,
+          format!("no response after {} ms", timeout.as_millis()),
+        )
+      })??,
+      None => sent.await?,
+    };
+
+    let (parts, body) = response.into_parts();
+    Ok(FetchResponse {
+      status: parts.status,
+      headers: parts.headers,
+      body,
+    })
+  }
+
+  /// Sends a request, routing `unix:` targets over their socket.
+  pub async fn send(
+    &self,
+    request: hyper::Request<hyper::Body>,
+  ) -> std::result::Result<hyper::Response<hyper::Body>, ActixError> {
+    match request.extensions().get::<UnixSocket>().cloned() {
+      Some(UnixSocket(path)) => send_unix(path, request).await,
+      None => self.inner.request(request).await.map_err(failed),
+    }
+  }
+}
+
+/// Unix socket connections are not pooled; each request gets its own
+/// connection, which is cheap for local sockets.
+#[cfg(unix)]
+async fn send_unix(
+  path: PathBuf,
+  mut request: hyper::Request<hyper::Body>,
+) -> std::result::Result<hyper::Response<hyper::Body>, ActixError> {
+  let stream = tokio::net::UnixStream::connect(&path)
+    .await
+    .map_err(|err| {
+      ActixError::new(ErrorCode::FetchFailed, format!("{}: {err}", path.display()))
+        .caused_by_io(&err)
+    })?;
+  let (mut sender, conn) = hyper::client::conn::handshake(stream)
+    .await
+    .map_err(failed)?;
+
+  tokio::spawn(async move {
+    if let Err(err) = conn.await {
+      eprintln!("Error on connection: {err}");
+    }
+  });
+
+  if !request.headers().contains_key(header::HOST) {
+    request
+      .headers_mut()
+      .insert(header::HOST, HeaderValue::from_static("localhost"));
+  }
+
+  sender.send_request(request).await.map_err(failed)
+}
+
+#[cfg(not(unix))]
+async fn send_unix(
+  _: PathBuf,
+  _: hyper::Request<hyper::Body>,
+) -> std::result::Result<hyper::Response<hyper::Body>, ActixError> {
+  Err(ActixError::new(
+    ErrorCode::FetchFailed,
+    "Unix domain sockets are not supported on this platform",
+  ))
+}
+
+/// Why a request sent by the client got no response.
+fn failed(err: hyper::Error) -> ActixError {
+  ActixError::new(ErrorCode::FetchFailed, err.to_string())
+}
+
+/// Splits `unix:/path/to.sock:/request/path` into the socket path and the
+/// request path.
+pub(crate) fn unix_target(url: &str) -> Option<(PathBuf, &str)> {
+  let target = url.strip_prefix("unix:")?;
+
+  match target.find(":/") {
+    Some(idx) => Some((PathBuf::from(&target[..idx]), &target[idx + 1..])),
+    None => Some((PathBuf::from(target), "/")),
+  }
+}
+
+/// Builds the outgoing request from `fetch(url, options)` arguments.
+pub fn build_request(env: &Env, url: String, options: Option<FetchOptions>) -> Result<Outgoing> {
+  let FetchOptions {
+    method,
+    headers,
+    body,
+    timeout,
+  } = options.unwrap_or(FetchOptions {
+    method: None,
+    headers: None,
+    body: None,
+    timeout: None,
+  });
+
+  let method = match method {
+    Some(method) => crate::parse_method(&method).map_err(|err| err.into_js(env))?,
+    None => Method::GET,
+  };
+
+  let mut builder = hyper::Request::builder().method(method);
+  builder = match unix_target(&url) {
+    Some((socket, path)) => builder.uri(path).extension(UnixSocket(socket)),
+    None => builder.uri(url),
+  };
+  for (name, value) in headers.into_iter().flatten() {
+    builder = builder.header(name, value);
+  }
+
+  let mut upload = None;
+  let body = match body {
+    Some(Either3::A(text)) => hyper::Body::from(text),
+    Some(Either3::B(bytes)) => hyper::Body::from(buffer::from_js(bytes)),
+    Some(Either3::C(iterable)) => {
+      let (sender, body) = hyper::Body::channel();
+      upload = Some((Streamed::raw(env, iterable)?, sender));
+      body
+    }
+    None => hyper::Body::empty(),
+  };
+
+  let request = builder
+    .body(body)
+    .map_err(|err| ActixError::new(ErrorCode::InvalidValue, err.to_string()).into_js(env))?;
+  Ok(Outgoing {
+    request,
+    upload,
+    timeout: timeout.map(|ms| Duration::from_millis(ms.into())),
+  })
+}
+
+impl FetchResponse {
+  /// Builds a WHATWG `Response` through the global constructor.
+  pub fn into_js(self, env: &Env) -> Result<JsObject> {
+    let ctor = env
+      .get_global()?
+      .get_named_property::<JsFunction>("Response")?;
+
+    let mut init = env.create_object()?;
+    init.set_named_property("status", env.create_uint32(self.status.as_u16() as u32)?)?;
+    if let Some(reason) = self.status.canonical_reason() {
+      init.set_named_property("statusText", env.create_string(reason)?)?;
+    }
+
+    // A list of pairs keeps repeated headers such as `set-cookie` intact.
+    let mut headers = env.create_array_with_length(self.headers.len())?;
+    for (idx, (name, value)) in self.headers.iter().enumerate() {
+      let mut pair = env.create_array_with_length(2)?;
+      pair.set_element(0, env.create_string(name.as_str())?)?;
+      pair.set_element(
+        1,
+        env.create_string(&String::from_utf8_lossy(value.as_bytes()))?,
+      )?;
+      headers.set_element(idx as u32, pair)?;
+    }
+    init.set_named_property("headers", headers)?;
+
+    // Null-body statuses (204, 304, ...) throw when given any body at all.
+    let body = if self.body.is_end_stream() {
+      env.get_null()?.into_unknown()
+    } else {
+      readable(env, self.body)?.into_unknown()
+    };
+
+    ctor.new_instance(&[body, init.into_unknown()])
+  }
+}
+
+/// The body a `ReadableStream` reads.
+struct Source {
+  body: Mutex<hyper::Body>,
+  cancelled: AtomicBool,
+  /// Wakes the pull in progress, if any, when the stream is cancelled.
+  cancel: Notify,
+}
+
+/// A `ReadableStream` reading `body` as it is pulled.
+fn readable(env: &Env, body: hyper::Body) -> Result<JsObject> {
+  let source = Arc::new(Source {
+    body: Mutex::new(body),
+    cancelled: AtomicBool::new(false),
+    cancel: Notify::new(),
+  });
+
+  let read = source.clone();
+  let pull = env.create_function_from_closure("pull", move |ctx| {
+    let controller: JsObject = ctx.get(0)?;
+    let source = read.clone();
+    let chunk = ctx.env.execute_tokio_future(
+      async move {
+        // Created first, so that it's woken by a cancel from now on.
+        let cancelled = source.cancel.notified();
+        let mut body = source.body.lock().await;
+        let chunk = if source.cancelled.load(Ordering::SeqCst) {
+          None
+        } else {
+          tokio::select! {
+            chunk = body.data() => chunk,
+            _ = cancelled => None,
+          }
+        };
+        if source.cancelled.load(Ordering::SeqCst) {
+          // Releases the connection.
+          *body = hyper::Body::empty();
+          return Ok(Ok(None));
+        }
+        Ok(chunk.transpose().map_err(failed))
+      },
+      // A copy: the stream hands its chunks to user code, which may write
+      // to them.
+      |env, chunk| match chunk {
+        Ok(Some(chunk)) => env
+          .create_buffer_with_data(Vec::from(chunk))
+          .map(|chunk| chunk.into_raw().into_unknown()),
+        Ok(None) => env.get_null().map(|null| null.into_unknown()),
+        Err(err) => Err(err.into_js(env)),
+      },
+    )?;
+
+    // `enqueue` is called on the controller once the chunk is read.
+    let enqueue = ctx
+      .env
+      .create_function_from_closure("enqueue", |ctx| {
+        let controller: JsObject = ctx.this()?;
+        let chunk: JsUnknown = ctx.get(0)?;
+        if chunk.get_type()? == ValueType::Null {
+          let close: JsFunction = controller.get_named_property("close")?;
+          close.call_without_args(Some(&controller))?;
+        } else {
+          let enqueue: JsFunction = controller.get_named_property("enqueue")?;
+          enqueue.call(Some(&controller), &[chunk])?;
+        }
+        ctx.env.get_undefined()
+      })?
+      .coerce_to_object()?;
+    let bind: JsFunction = enqueue.get_named_property("bind")?;
+    let enqueue: JsFunction = bind.call(Some(&enqueue), &[controller])?.try_into()?;
+
+    let then: JsFunction = chunk.get_named_property("then")?;
+    then.call(Some(&chunk), &[enqueue])
+  })?;
+
+  // Releases the connection when the body is no longer wanted, here or
+  // in the pull in progress.
+  let cancel = env.create_function_from_closure("cancel", move |ctx| {
+    source.cancelled.store(true, Ordering::SeqCst);
+    source.cancel.notify_waiters();
+    if let Ok(mut body) = source.body.try_lock() {
+      *body = hyper::Body::empty();
+    }
+    ctx.env.get_undefined()
+  })?;
+
+  let mut source = env.create_object()?;
+  source.set_named_property("pull", pull)?;
+  source.set_named_property("cancel", cancel)?;
+
+  let ctor = env
+    .get_global()?
+    .get_named_property::<JsFunction>("ReadableStream")?;
+  ctor.new_instance(&[source])
+}
@@ -0,0 +1,236 @@
+//! Client hints, for services that adapt images and content to the device.
+//!
+//! With `app.clientHints()`, responses ask browsers for hints with
+//! `Accept-CH`, and for the ones the response can't do without with
+//! `Critical-CH`, which makes the browser retry once it has them. The hints
+//! a request carries are parsed from their structured header form and
+//! handed to handlers as `req.clientHints`:
+//!
+//! ```text
+//! Sec-CH-UA: "Chromium";v="124", "Not-A.Brand";v="99"
+//! Sec-CH-UA-Mobile: ?0
+//! Sec-CH-DPR: 2
+//! ```
+//!
+//! becomes `{ brands: [{ brand: "Chromium", version: "124" }, ...],
+//! mobile: false, dpr: 2 }`. Hints that are missing or malformed are left
+//! out. The legacy unprefixed headers, such as `DPR` and `Viewport-Width`,
+//! are read when the prefixed ones are absent.
+
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use napi::{Env, JsObject, Result};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::structured::{self, BareItem, Item, Member};
+
+/// Configures client hints with `app.clientHints()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ClientHintsOptions {
+  /// Hints to ask for, such as `"Sec-CH-DPR"`.
+  pub accept: Vec<String>,
+  /// Hints the response needs, for which the browser retries the request.
+  /// They are asked for too, and responses vary on them.
+  pub critical: Option<Vec<String>>,
+}
+
+/// The headers advertising client hints, added to every routed response.
+pub struct ClientHints {
+  accept: HeaderValue,
+  critical: Option<HeaderValue>,
+}
+
+impl ClientHints {
+  pub fn new(options: ClientHintsOptions) -> std::result::Result<Self, ActixError> {
+    let parse = |names: Vec<String>| {
+      names
+        .into_iter()
+        .map(|name| {
+          HeaderName::try_from(name.as_str()).map_err(|_| {
+            ActixError::new(
+              ErrorCode::InvalidHeader,
+              format!("invalid client hint `{name}`"),
+            )
+          })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()
+    };
+
+    let critical = parse(options.critical.unwrap_or_default())?;
+    let mut accept = parse(options.accept)?;
+    for name in &critical {
+      if !accept.contains(name) {
+        accept.push(name.clone());
+      }
+    }
+    if accept.is_empty() {
+      return Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        "client hints need at least one hint",
+      ));
+    }
+
+    Ok(ClientHints {
+      accept: join(&accept),
+      critical: (!critical.is_empty()).then(|| join(&critical)),
+    })
+  }
+
+  pub fn set_headers(&self, headers: &mut HeaderMap) {
+    headers.insert(ACCEPT_CH, self.accept.clone());
+    if let Some(critical) = &self.critical {
+      headers.insert(CRITICAL_CH, critical.clone());
+      headers.append(header::VARY, critical.clone());
+    }
+  }
+}
+
+const ACCEPT_CH: HeaderName = HeaderName::from_static("accept-ch");
+const CRITICAL_CH: HeaderName = HeaderName::from_static("critical-ch");
+
+fn join(names: &[HeaderName]) -> HeaderValue {
+  let names: Vec<_> = names.iter().map(HeaderName::as_str).collect();
+  // Header names are valid header values.
+  HeaderValue::try_from(names.join(", ")).unwrap()
+}
+
+/// The client hints of a request.
+#[derive(Clone, Debug, Default)]
+pub struct Hints {
+  brands: Option<Vec<Brand>>,
+  full_version_list: Option<Vec<Brand>>,
+  mobile: Option<bool>,
+  platform: Option<String>,
+  platform_version: Option<String>,
+  model: Option<String>,
+  arch: Option<String>,
+  bitness: Option<String>,
+  dpr: Option<f64>,
+  viewport_width: Option<f64>,
+  width: Option<f64>,
+  device_memory: Option<f64>,
+  downlink: Option<f64>,
+  rtt: Option<f64>,
+  ect: Option<String>,
+  save_data: Option<bool>,
+  prefers_color_scheme: Option<String>,
+  prefers_reduced_motion: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Brand {
+  brand: String,
+  version: Option<String>,
+}
+
+impl Hints {
+  pub fn parse(headers: &HeaderMap) -> Hints {
+    let item = |names: &[&str]| {
+      names
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|value| structured::parse_item(value.as_bytes()))
+        .map(|item| item.bare)
+    };
+    let text = |names: &[&str]| item(names).and_then(BareItem::into_text);
+    let number = |names: &[&str]| item(names).and_then(|item| item.number());
+    let brands = |name: &str| {
+      let list = structured::parse_list(headers.get(name)?.as_bytes())?;
+      let brands = list.into_iter().filter_map(|member| {
+        let Member::Item(Item { bare, params }) = member else {
+          return None;
+        };
+        Some(Brand {
+          brand: bare.into_text()?,
+          version: params
+            .into_iter()
+            .find(|(key, _)| key == "v")
+            .and_then(|(_, version)| version.into_text()),
+        })
+      });
+      Some(brands.collect())
+    };
+
+    Hints {
+      brands: brands("sec-ch-ua"),
+      full_version_list: brands("sec-ch-ua-full-version-list"),
+      mobile: item(&["sec-ch-ua-mobile"]).and_then(|item| item.flag()),
+      platform: text(&["sec-ch-ua-platform"]),
+      platform_version: text(&["sec-ch-ua-platform-version"]),
+      model: text(&["sec-ch-ua-model"]),
+      arch: text(&["sec-ch-ua-arch"]),
+      bitness: text(&["sec-ch-ua-bitness"]),
+      dpr: number(&["sec-ch-dpr", "dpr"]),
+      viewport_width: number(&["sec-ch-viewport-width", "viewport-width"]),
+      width: number(&["sec-ch-width", "width"]),
+      device_memory: number(&["sec-ch-device-memory", "device-memory"]),
+      downlink: number(&["downlink"]),
+      rtt: number(&["rtt"]),
+      ect: text(&["ect"]),
+      save_data: headers
+        .get("save-data")
+        .map(|value| value.as_bytes().eq_ignore_ascii_case(b"on")),
+      prefers_color_scheme: text(&["sec-ch-prefers-color-scheme"]),
+      prefers_reduced_motion: text(&["sec-ch-prefers-reduced-motion"]),
+    }
+  }
+
+  pub fn to_js(&self, env: &Env) -> Result<JsObject> {
+    let mut hints = env.create_object()?;
+
+    for (name, brands) in [
+      ("brands", &self.brands),
+      ("fullVersionList", &self.full_version_list),
+    ] {
+      let Some(brands) = brands else {
+        continue;
+      };
+      let mut array = env.create_array_with_length(brands.len())?;
+      for (idx, Brand { brand, version }) in brands.iter().enumerate() {
+        let mut entry = env.create_object()?;
+        entry.set_named_property("brand", env.create_string(brand)?)?;
+        if let Some(version) = version {
+          entry.set_named_property("version", env.create_string(version)?)?;
+        }
+        array.set_element(idx as u32, entry)?;
+      }
+      hints.set_named_property(name, array)?;
+    }
+
+    for (name, flag) in [("mobile", self.mobile), ("saveData", self.save_data)] {
+      if let Some(flag) = flag {
+        hints.set_named_property(name, env.get_boolean(flag)?)?;
+      }
+    }
+
+    for (name, text) in [
+      ("platform", &self.platform),
+      ("platformVersion", &self.platform_version),
+      ("model", &self.model),
+      ("arch", &self.arch),
+      ("bitness", &self.bitness),
+      ("ect", &self.ect),
+      ("prefersColorScheme", &self.prefers_color_scheme),
+      ("prefersReducedMotion", &self.prefers_reduced_motion),
+    ] {
+      if let Some(text) = text {
+        hints.set_named_property(name, env.create_string(text)?)?;
+      }
+    }
+
+    for (name, number) in [
+      ("dpr", self.dpr),
+      ("viewportWidth", self.viewport_width),
+      ("width", self.width),
+      ("deviceMemory", self.device_memory),
+      ("downlink", self.downlink),
+      ("rtt", self.rtt),
+    ] {
+      if let Some(number) = number {
+        hints.set_named_property(name, env.create_double(number)?)?;
+      }
+    }
+
+    Ok(hints)
+  }
+}
@@ -0,0 +1,698 @@
+//! Dispatch of incoming requests to the registered JS handlers.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use hyper::body::{Bytes, HttpBody};
+use hyper::ext::ReasonPhrase;
+use hyper::header::{self, HeaderValue};
+use hyper::{HeaderMap, StatusCode};
+use napi::bindgen_prelude::{FromNapiValue, Promise};
+use napi::{sys, Env, JsNumber, JsObject, JsUnknown, NapiRaw, ValueType};
+use tokio::sync::oneshot;
+
+use crate::abort::Disconnect;
+use crate::access_log;
+use crate::background::Background;
+use crate::client_hints::Hints;
+use crate::headers::LazyHeaders;
+use crate::http::metrics::TransferCounters;
+use crate::http::{
+  Body, ConnectionInfo, ConnectionMetrics, Deadline, Drain, Request, Response, ResponseBuilder,
+  ResponseFuture, Service,
+};
+use crate::json::{self, JsonBody, ParseJson};
+use crate::patch;
+use crate::quota::{Quota, Usage, Verdict};
+use crate::rejection::{self, Rejection};
+use crate::request::{NativeRequests, SharedObject};
+use crate::response::{self, NodeResponse};
+use crate::routes::{Lookup, Route};
+use crate::shadow::{self, Answer, Mismatch, Reporter};
+use crate::status::{self, Status};
+use crate::stream::Streamed;
+use crate::tenant;
+use crate::trust::Origin;
+use crate::vhost::{self, Hosts, Site};
+use crate::warmup::Warmups;
+use crate::{content_type, MyRequest, RouterNode};
+
+/// Routes requests to JS handlers.
+///
+/// The routes are read through an [`ArcSwap`] snapshot, so dispatching a
+/// request never takes a lock, no matter how many connections are served
+/// concurrently.
+#[derive(Clone)]
+pub(crate) struct Dispatcher {
+  site: Site,
+  hosts: Arc<ArcSwap<Hosts>>,
+  parse_json: ParseJson,
+  drain: Drain,
+  metrics: Arc<ConnectionMetrics>,
+  background: Arc<Background>,
+}
+
+impl Dispatcher {
+  pub fn new(
+    site: Site,
+    hosts: Arc<ArcSwap<Hosts>>,
+    parse_json: ParseJson,
+    drain: Drain,
+    metrics: Arc<ConnectionMetrics>,
+    background: Arc<Background>,
+  ) -> Self {
+    Dispatcher {
+      site,
+      hosts,
+      parse_json,
+      drain,
+      metrics,
+      background,
+    }
+  }
+
+  /// Runs the warmup requests one after another, reporting the ones that
+  /// fail with a server error.
+  pub async fn warm_up(&self, warmups: &Warmups) {
+    for req in warmups.requests() {
+      let (method, path) = (req.method().clone(), req.uri().clone());
+      let res = self.clone().dispatch(req).await;
+      if res.status().is_server_error() {
+        eprintln!(
+          "Warmup request {method} {path} failed with {}",
+          res.status()
+        );
+      }
+    }
+  }
+
+  async fn dispatch(self, mut req: Request) -> Response {
+    if let Some(normalizer) = self.site.routes.load().normalizer() {
+      normalizer.apply(&mut req);
+    }
+    req.extensions_mut().insert(self.background.clone());
+    let site = match vhost::find(&self.hosts.load(), &req) {
+      Some(site) => site.clone(),
+      None => self.site.clone(),
+    };
+
+    let (signer, access_log, archive, tracer) = {
+      let routes = site.routes.load();
+      (
+        routes.signer().cloned(),
+        routes.access_log().cloned(),
+        routes.archive().cloned(),
+        routes.tracer().cloned(),
+      )
+    };
+    let pending = access_log
+      .as_ref()
+      .map(|_| access_log::Pending::start(&req));
+    let span = tracer.as_ref().map(|tracer| tracer.start(&mut req));
+    let selected = archive.as_ref().and_then(|archive| archive.select(&req));
+
+    let res = self.respond(site, req).await;
+    let res = match signer {
+      Some(signer) => signer.sign(res).await,
+      None => res,
+    };
+
+    if let (Some(access_log), Some(pending)) = (access_log, pending) {
+      access_log.log(pending, &res);
+    }
+    let res = match (archive, selected) {
+      (Some(archive), Some(selected)) => archive.tee(selected, res),
+      _ => res,
+    };
+    if let (Some(tracer), Some(span)) = (tracer, span) {
+      tracer.finish(span, res.status());
+    }
+    shadow::hash_primary(res)
+  }
+
+  async fn respond(&self, site: Site, req: Request) -> Response {
+    // A snapshot of the routes, held until the response is ready.
+    let routes = site.routes.load_full();
+
+    if let Some(health) = routes.health() {
+      if let Some(probe) = health.probe(req.method(), req.uri().path()) {
+        return health.respond(probe, req.method(), &self.drain).await;
+      }
+    }
+
+    if let Some(res) = routes
+      .signed_urls()
+      .and_then(|urls| urls.check(&req, &routes))
+    {
+      return res;
+    }
+
+    let path = req.uri().path();
+    let upload = routes
+      .uploads()
+      .iter()
+      .find_map(|tus| Some((tus, tus.matches(path)?.map(str::to_owned))));
+    if let Some((tus, id)) = upload {
+      return tus.respond(req, id).await;
+    }
+
+    // The longest prefix wins, whatever order the proxies were set in.
+    let path = req.uri().path();
+    let proxy = routes
+      .proxies()
+      .iter()
+      .filter(|proxy| proxy.matches(path))
+      .max_by_key(|proxy| proxy.prefix().len());
+    if let Some(proxy) = proxy {
+      let tenant = tenant::find(routes.tenants(), &req);
+      let quotas = routes
+        .quota()
+        .map(Arc::as_ref)
+        .into_iter()
+        .chain(tenant.and_then(|tenant| tenant.quota()));
+      let metered = match meter(quotas, &req) {
+        Ok(metered) => metered,
+        Err(refusal) => return refusal.respond(),
+      };
+      let mut res = proxy.forward(req).await;
+      if let Some((quota, usage)) = metered {
+        quota.set_headers(res.headers_mut(), usage);
+      }
+      return res;
+    }
+
+    let fast_paths = site.fast_paths.load_full();
+    if let Some(fixed) = fast_paths.get(req.uri().path()) {
+      if let Some(res) = fixed.respond(req.method(), req.headers()) {
+        if let Some(guard) = fixed.guard() {
+          if let Some(denied) = guard.check(&req).await {
+            return denied;
+          }
+        }
+        return res;
+      }
+    }
+
+    let lookup = routes.lookup(
+      req.method(),
+      req.uri().path(),
+      req.headers(),
+      req.uri().query(),
+    );
+    let (route, params) = match lookup {
+      Lookup::Found(route, params) => (route, params),
+      Lookup::Options(allow) => {
+        return ResponseBuilder::new()
+          .status(StatusCode::NO_CONTENT)
+          .header(header::ALLOW, allow)
+          .body(Body::empty())
+          .unwrap()
+      }
+      Lookup::MethodNotAllowed(allow) => {
+        return ResponseBuilder::new()
+          .status(StatusCode::METHOD_NOT_ALLOWED)
+          .header(header::ALLOW, allow)
+          .body(Body::empty())
+          .unwrap()
+      }
+      Lookup::Redirect(path) => return redirect(&req, path),
+      Lookup::NotFound => return status(StatusCode::NOT_FOUND),
+    };
+    if let (Some(timeout), Some(deadline)) = (route.timeout, req.extensions().get::<Deadline>()) {
+      deadline.set(timeout);
+    }
+    let tenant = tenant::find(routes.tenants(), &req);
+    let formatter = routes.formatter();
+
+    // Whatever answers here spares the client from sending the body.
+    if let Some(guard) = &route.guard {
+      if let Some(denied) = guard.check(&req).await {
+        return denied;
+      }
+    }
+    if let Some(expect_continue) = routes.expect_continue() {
+      if let Some(refused) = expect_continue.check(&req).await {
+        return refused;
+      }
+    }
+
+    let (parts, body) = req.into_parts();
+    if is_chunked(&parts.headers) {
+      self.metrics.transfer().request(true);
+    } else if parts.headers.contains_key(header::CONTENT_LENGTH) {
+      self.metrics.transfer().request(false);
+    }
+    let limit = match (
+      route.max_body_size,
+      tenant.and_then(|tenant| tenant.max_body_size()),
+    ) {
+      (Some(route), Some(tenant)) => Some(route.min(tenant)),
+      (route, tenant) => route.or(tenant),
+    };
+    let body = match read_body(body, limit, self.metrics.transfer()).await {
+      Ok(body) => body,
+      Err(rejection) => return rejection::render(formatter, rejection).await,
+    };
+
+    let mut req = MyRequest::from_parts(parts, body);
+    if let Some(tenant) = tenant {
+      req.extensions_mut().insert(tenant.clone());
+    }
+    if routes.lazy_headers() {
+      req.extensions_mut().insert(LazyHeaders);
+    }
+    if routes.native_requests() {
+      req.extensions_mut().insert(NativeRequests);
+    }
+    if !params.0.is_empty() {
+      req.extensions_mut().insert(params);
+    }
+    let origin = Origin::of(&req, routes.trust_proxy());
+    req.extensions_mut().insert(origin);
+    if routes.client_hints().is_some() {
+      let hints = Hints::parse(req.headers());
+      req.extensions_mut().insert(hints);
+    }
+    let disconnect = routes.abort_signals().then(|| {
+      let (disconnect, signals) = Disconnect::new();
+      req.extensions_mut().insert(signals);
+      disconnect
+    });
+
+    if let Some(verifier) = routes.verifier().filter(|_| !route.unsigned) {
+      if !verifier.verify(&req).await {
+        return ResponseBuilder::new()
+          .status(StatusCode::UNAUTHORIZED)
+          .header(header::WWW_AUTHENTICATE, "HMAC-SHA256")
+          .body(Body::empty())
+          .unwrap();
+      }
+    }
+
+    let patch = content_type(req.headers()).filter(|value| patch::is_patch(value));
+    if let Some(content_type) = patch.filter(|_| !req.body().is_empty()) {
+      match patch::parse(content_type, req.body()) {
+        Ok(patch) => {
+          req.extensions_mut().insert(patch);
+        }
+        Err(rejection) => return rejection::render(formatter, rejection).await,
+      }
+    }
+
+    let parse_json = self.parse_json != ParseJson::Off && !req.body().is_empty();
+    if parse_json && content_type(req.headers()).is_some_and(json::is_json) {
+      match json::parse(req.body()) {
+        Ok(value) => {
+          req.extensions_mut().insert(JsonBody(value));
+        }
+        Err(err) if self.parse_json == ParseJson::Strict => {
+          return rejection::render(formatter, Rejection::Json(err)).await;
+        }
+        Err(_) => {}
+      }
+    }
+
+    // Only requests the route accepts count against its quotas.
+    let quotas = routes
+      .quota()
+      .map(Arc::as_ref)
+      .into_iter()
+      .chain(tenant.and_then(|tenant| tenant.quota()))
+      .chain(route.quota.as_deref());
+    let metered = match meter(quotas, &req) {
+      Ok(metered) => metered,
+      Err(refusal) => return refusal.respond(),
+    };
+
+    if route.pipeline.len() > 1 {
+      req.extensions_mut().insert(SharedObject);
+    }
+
+    let mismatches = routes.mismatches();
+    let mut res = self.run(route, mismatches, Arc::new(req)).await;
+    if let Some(disconnect) = disconnect {
+      disconnect.answered();
+    }
+    if let Some((quota, usage)) = metered {
+      quota.set_headers(res.headers_mut(), usage);
+    }
+    if let Some(client_hints) = routes.client_hints() {
+      client_hints.set_headers(res.headers_mut());
+    }
+    res
+  }
+
+  /// Runs the middleware of a route and then its handler.
+  ///
+  /// A middleware ends the chain early by returning (or resolving to) an
+  /// HTTP status code, which becomes the response. The shadow handler, if
+  /// any, only runs when the primary handler did.
+  async fn run(
+    &self,
+    route: &Route,
+    mismatches: Option<&Arc<Reporter>>,
+    req: Arc<MyRequest>,
+  ) -> Response {
+    let started = Instant::now();
+    let mut sync = Duration::ZERO;
+    let record = |sync| route.timing.record(started.elapsed(), sync);
+
+    if let Some(batcher) = &route.batch {
+      let res = match batcher.call(req).await {
+        Ok(outcome) => outcome.into_response(),
+        Err(err) => self.failed(err),
+      };
+      // Time spent waiting for the batch to be picked up counts as wall
+      // time; the JS thread is shared by the whole batch.
+      record(Duration::ZERO);
+      return res;
+    }
+
+    let Some((handler, middleware)) = route.pipeline.split_last() else {
+      return status(StatusCode::NOT_FOUND);
+    };
+
+    for callback in middleware {
+      match self.call(callback, req.clone(), &mut sync).await {
+        Ok(Outcome::Continue) => {}
+        Ok(outcome @ (Outcome::Status(..) | Outcome::Stream(_) | Outcome::Response(_))) => {
+          record(sync);
+          return outcome.into_response();
+        }
+        Err(res) => {
+          record(sync);
+          return res;
+        }
+      }
+    }
+
+    let mut res = match self.call(handler, req.clone(), &mut sync).await {
+      Ok(outcome) => outcome.into_response(),
+      Err(res) => res,
+    };
+    record(sync);
+
+    if let Some(shadow) = &route.shadow {
+      let primary = Answer::observe(&mut res);
+      self.shadow(shadow.clone(), mismatches.cloned(), req, primary);
+    }
+    res
+  }
+
+  /// Runs a shadow handler in the background and reports it if it answers
+  /// differently than the primary handler did.
+  fn shadow(
+    &self,
+    shadow: Arc<RouterNode>,
+    mismatches: Option<Arc<Reporter>>,
+    req: Arc<MyRequest>,
+    primary: oneshot::Receiver<Answer>,
+  ) {
+    let dispatcher = self.clone();
+    tokio::spawn(async move {
+      // Shadow handlers are not part of the route's timings.
+      let mut sync = Duration::ZERO;
+      let answered = match dispatcher.call(&shadow, req.clone(), &mut sync).await {
+        Ok(outcome) => outcome.into_response(),
+        Err(res) => res,
+      };
+      let answered = Answer::read(answered).await;
+      // The primary body was dropped before its end, such as when the
+      // client went away: there's nothing to compare against.
+      let Ok(primary) = primary.await else {
+        return;
+      };
+
+      if let Some(mismatch) = Mismatch::compare(req.method(), req.uri().path(), primary, answered) {
+        shadow::report(mismatches.as_deref(), mismatch);
+      }
+    });
+  }
+
+  /// Calls a JS function, waiting for the promise it returns, if any. The
+  /// time until the function returns is added to `sync`.
+  ///
+  /// Failures are turned into the response to send.
+  async fn call(
+    &self,
+    callback: &RouterNode,
+    req: Arc<MyRequest>,
+    sync: &mut Duration,
+  ) -> std::result::Result<Outcome, Response> {
+    let called = Instant::now();
+    let returned = callback.call_async::<Returned<Outcome>>(req).await;
+    *sync += called.elapsed();
+
+    let returned = match returned {
+      Ok(returned) => returned,
+      Err(err) => return Err(self.failed(err)),
+    };
+
+    returned.settle().await.map_err(|err| {
+      eprintln!("Handler rejected: {err}");
+      status(StatusCode::INTERNAL_SERVER_ERROR)
+    })
+  }
+
+  /// The response when a JS function could not be called.
+  fn failed(&self, err: napi::Error) -> Response {
+    // The Node environment is shutting down: stop taking connections and
+    // tell the client not to reuse this one.
+    if err.status == napi::Status::Closing {
+      self.drain.start();
+      return ResponseBuilder::new()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::CONNECTION, "close")
+        .body(Body::empty())
+        .unwrap();
+    }
+
+    eprintln!("Error calling handler: {err}");
+    status(StatusCode::INTERNAL_SERVER_ERROR)
+  }
+}
+
+impl Service for Dispatcher {
+  fn call(&self, mut req: Request, info: ConnectionInfo) -> ResponseFuture {
+    req.extensions_mut().insert(info);
+    Box::pin(self.clone().dispatch(req))
+  }
+
+  fn needs_deadline(&self) -> bool {
+    self.site.routes.load().timeouts()
+      || self
+        .hosts
+        .load()
+        .values()
+        .any(|site| site.routes.load().timeouts())
+  }
+}
+
+/// What a middleware or handler returned, once any promise has settled.
+pub(crate) enum Outcome {
+  Continue,
+  Status(StatusCode, Option<ReasonPhrase>),
+  /// A body streamed from a JS iterator, as returned by `ndjson()`.
+  Stream(Streamed),
+  /// A `NodeResponse` or WHATWG `Response`, sent as built.
+  Response(Response),
+}
+
+impl Outcome {
+  /// The response when the outcome comes from a handler.
+  fn into_response(self) -> Response {
+    match self {
+      Outcome::Status(code, reason) => status::respond(code, reason),
+      Outcome::Stream(streamed) => streamed.into_response(),
+      Outcome::Response(res) => res,
+      Outcome::Continue => status(StatusCode::FOUND),
+    }
+  }
+}
+
+impl FromNapiValue for Outcome {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> napi::Result<Self> {
+    let value = JsUnknown::from_napi_value(env, value)?;
+    match value.get_type()? {
+      ValueType::Number => {
+        let code = value.cast::<JsNumber>().get_double()?;
+        let status = (code.fract() == 0.0 && (100.0..1000.0).contains(&code))
+          .then(|| StatusCode::from_u16(code as u16).ok())
+          .flatten();
+
+        Ok(status.map_or(Outcome::Continue, |status| Outcome::Status(status, None)))
+      }
+      // `{ status, reason }`, as built by `status()`.
+      ValueType::Object => {
+        let object = value.cast::<JsObject>();
+        if let Some(streamed) = Streamed::take(env, &object)? {
+          return Ok(Outcome::Stream(streamed));
+        }
+        // Checked before `status`, which both kinds of response have.
+        if let Some(res) = NodeResponse::take(env, object.raw())? {
+          return Ok(Outcome::Response(res));
+        }
+        if let Some(res) = response::from_fetch(env, &object)? {
+          return Ok(Outcome::Response(res));
+        }
+        if !object.has_named_property("status")? {
+          return Ok(Outcome::Continue);
+        }
+
+        let Status { status, reason } = Status::from_napi_value(env, object.raw())?;
+        let (status, reason) =
+          status::parse(status, reason).map_err(|err| err.into_js(&Env::from_raw(env)))?;
+        Ok(Outcome::Status(status, reason))
+      }
+      _ => Ok(Outcome::Continue),
+    }
+  }
+}
+
+/// The raw return value of a JS call, which may be a pending promise.
+pub(crate) enum Returned<T: FromNapiValue> {
+  Ready(T),
+  Pending(Promise<T>),
+}
+
+impl<T: FromNapiValue + Send + 'static> Returned<T> {
+  /// Waits for the promise, if any.
+  pub async fn settle(self) -> napi::Result<T> {
+    match self {
+      Returned::Ready(value) => Ok(value),
+      Returned::Pending(promise) => promise.await,
+    }
+  }
+}
+
+impl<T: FromNapiValue + Send + 'static> FromNapiValue for Returned<T> {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> napi::Result<Self> {
+    let mut is_promise = false;
+    napi::check_status!(sys::napi_is_promise(env, value, &mut is_promise))?;
+
+    if is_promise {
+      Promise::from_napi_value(env, value).map(Returned::Pending)
+    } else {
+      T::from_napi_value(env, value).map(Returned::Ready)
+    }
+  }
+}
+
+fn status(status: StatusCode) -> Response {
+  ResponseBuilder::new()
+    .status(status)
+    .body(Body::empty())
+    .unwrap()
+}
+
+/// Buffers a request body, rejecting it once it grows past `limit` bytes.
+async fn read_body(
+  mut body: Body,
+  limit: Option<u64>,
+  counters: &TransferCounters,
+) -> std::result::Result<Bytes, Rejection> {
+  let bad_request = |err: hyper::Error| {
+    eprintln!("Error reading body: {err}");
+    Rejection::BodyRead {
+      message: err.to_string(),
+    }
+  };
+
+  let limit = limit.unwrap_or(u64::MAX);
+  if HttpBody::size_hint(&body).lower() > limit {
+    return Err(Rejection::BodyTooLarge { limit });
+  }
+
+  let mut chunks = Vec::new();
+  let mut len = 0;
+  while let Some(chunk) = body.data().await {
+    let chunk = chunk.map_err(bad_request)?;
+    counters.chunk(chunk.len());
+    len += chunk.len();
+    if len as u64 > limit {
+      return Err(Rejection::BodyTooLarge { limit });
+    }
+    chunks.push(chunk);
+  }
+
+  // Bodies received in one piece are handed over without copying.
+  match chunks.len() {
+    0 => Ok(Bytes::new()),
+    1 => Ok(chunks.remove(0)),
+    _ => Ok(Bytes::from(chunks.concat())),
+  }
+}
+
+fn is_chunked(headers: &HeaderMap) -> bool {
+  headers
+    .get_all(header::TRANSFER_ENCODING)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// Why a request was refused by a quota.
+enum Refusal<'a> {
+  Exceeded(&'a Quota, Usage),
+  MissingKey,
+}
+
+impl Refusal<'_> {
+  fn respond(self) -> Response {
+    match self {
+      Refusal::Exceeded(quota, usage) => too_many_requests(quota, usage),
+      Refusal::MissingKey => status(StatusCode::UNAUTHORIZED),
+    }
+  }
+}
+
+/// Counts `req` against `quotas`. Returns the usage of the one closest to
+/// running out, the one reported to the client.
+fn meter<'a, B>(
+  quotas: impl Iterator<Item = &'a Quota>,
+  req: &hyper::Request<B>,
+) -> std::result::Result<Option<(&'a Quota, Usage)>, Refusal<'a>> {
+  let mut metered: Option<(&Quota, Usage)> = None;
+  for quota in quotas {
+    match quota.check(req) {
+      Verdict::Unmetered => {}
+      Verdict::Allowed(usage) => {
+        if metered.is_none_or(|(_, closest)| usage.remaining < closest.remaining) {
+          metered = Some((quota, usage));
+        }
+      }
+      Verdict::Exceeded(usage) => return Err(Refusal::Exceeded(quota, usage)),
+      Verdict::MissingKey => return Err(Refusal::MissingKey),
+    }
+  }
+  Ok(metered)
+}
+
+/// A `429` telling the client when the exhausted limit resets.
+fn too_many_requests(quota: &Quota, usage: Usage) -> Response {
+  let mut res = ResponseBuilder::new()
+    .status(StatusCode::TOO_MANY_REQUESTS)
+    .header(header::RETRY_AFTER, usage.reset)
+    .body(Body::empty())
+    .unwrap();
+  quota.set_headers(res.headers_mut(), usage);
+  res
+}
+
+fn redirect(req: &Request, path: String) -> Response {
+  let location = match req.uri().query() {
+    Some(query) => format!("{path}?{query}"),
+    None => path,
+  };
+
+  match HeaderValue::try_from(location) {
+    Ok(location) => ResponseBuilder::new()
+      .status(StatusCode::MOVED_PERMANENTLY)
+      .header(header::LOCATION, location)
+      .body(Body::empty())
+      .unwrap(),
+    Err(_) => status(StatusCode::BAD_REQUEST),
+  }
+}
@@ -0,0 +1,214 @@
+//! Host name resolution for the outbound [`Client`](crate::client::Client),
+//! with an optional in-process cache set up by `app.configureClient()`.
+//!
+//! Lookups go through the system resolver, which doesn't report record
+//! TTLs, so entries live for a configured time instead. Failed lookups are
+//! cached too, for a shorter time, and concurrent lookups of the same name
+//! share a single query, so a burst of upstream calls makes one request to
+//! the resolver.
+//!
+//! Names given fixed addresses with `hosts` are never looked up. The system
+//! resolver runs on tokio's blocking threads, never on the runtime's. With
+//! `rotateAddresses`, each connection starts at the next address of a name,
+//! so connections spread over all of its records rather than piling up on
+//! the first one. This applies to `app.fetch()` and `app.proxy()` alike.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::service::Service;
+
+/// Configures the DNS cache of the outbound client.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct DnsCacheOptions {
+  /// Milliseconds resolved addresses are reused for. Defaults to 30000.
+  pub ttl: Option<u32>,
+  /// Milliseconds a failed lookup is remembered for. Defaults to 5000;
+  /// `0` retries failed names every time.
+  pub negative_ttl: Option<u32>,
+  /// Most names kept at once. Defaults to 1024.
+  pub max_entries: Option<u32>,
+}
+
+type Lookup = Shared<BoxFuture<'static, Result<Arc<[SocketAddr]>, Arc<io::Error>>>>;
+
+enum Entry {
+  /// A query in flight, shared by everyone asking for the name meanwhile.
+  Pending(Lookup),
+  Resolved(Arc<[SocketAddr]>, Instant),
+  Failed(Arc<io::Error>, Instant),
+}
+
+pub struct DnsCache {
+  entries: Mutex<HashMap<Name, Entry>>,
+  ttl: Duration,
+  negative_ttl: Duration,
+  max_entries: usize,
+}
+
+impl DnsCache {
+  pub fn new(options: &DnsCacheOptions) -> Self {
+    let millis =
+      |ms: Option<u32>, default: u64| Duration::from_millis(ms.map_or(default, u64::from));
+
+    DnsCache {
+      entries: Mutex::default(),
+      ttl: millis(options.ttl, 30_000),
+      negative_ttl: millis(options.negative_ttl, 5_000),
+      max_entries: options.max_entries.map_or(1024, |max| max as usize).max(1),
+    }
+  }
+
+  /// Forgets every cached name.
+  pub fn flush(&self) {
+    self.entries.lock().unwrap().clear();
+  }
+
+  fn lookup(self: &Arc<Self>, name: Name) -> Lookup {
+    let mut entries = self.entries.lock().unwrap();
+    let now = Instant::now();
+
+    match entries.get(&name) {
+      Some(Entry::Pending(lookup)) => return lookup.clone(),
+      Some(Entry::Resolved(addrs, expires)) if *expires > now => {
+        let addrs = addrs.clone();
+        return async move { Ok(addrs) }.boxed().shared();
+      }
+      Some(Entry::Failed(err, expires)) if *expires > now => {
+        let err = err.clone();
+        return async move { Err(err) }.boxed().shared();
+      }
+      _ => {}
+    }
+
+    if entries.len() >= self.max_entries {
+      entries.retain(|_, entry| match entry {
+        Entry::Pending(_) => true,
+        Entry::Resolved(_, expires) | Entry::Failed(_, expires) => *expires > now,
+      });
+    }
+
+    let cache = self.clone();
+    let query = name.clone();
+    let lookup = async move {
+      let resolved = GaiResolver::new()
+        .call(query.clone())
+        .await
+        .map(|addrs| addrs.collect::<Arc<[SocketAddr]>>())
+        .map_err(Arc::new);
+      cache.store(query, &resolved);
+      resolved
+    }
+    .boxed()
+    .shared();
+
+    // Still full of live entries: resolve without caching.
+    if entries.len() < self.max_entries {
+      entries.insert(name, Entry::Pending(lookup.clone()));
+    }
+    lookup
+  }
+
+  fn store(&self, name: Name, resolved: &Result<Arc<[SocketAddr]>, Arc<io::Error>>) {
+    let mut entries = self.entries.lock().unwrap();
+    // Flushed while the query was in flight.
+    if !matches!(entries.get(&name), Some(Entry::Pending(_))) {
+      return;
+    }
+
+    let now = Instant::now();
+    match resolved {
+      Ok(addrs) => {
+        entries.insert(name, Entry::Resolved(addrs.clone(), now + self.ttl));
+      }
+      Err(err) if !self.negative_ttl.is_zero() => {
+        entries.insert(name, Entry::Failed(err.clone(), now + self.negative_ttl));
+      }
+      Err(_) => {
+        entries.remove(&name);
+      }
+    }
+  }
+}
+
+/// The resolver of the outbound client: fixed addresses for the names that
+/// have them, else the system resolver, behind the cache when there is one.
+#[derive(Clone, Default)]
+pub struct Resolver {
+  cache: Option<Arc<DnsCache>>,
+  /// Fixed addresses, by lowercase host name.
+  hosts: Arc<HashMap<String, Arc<[SocketAddr]>>>,
+  /// How many lookups were made, to rotate addresses by, when rotating.
+  next: Option<Arc<AtomicUsize>>,
+}
+
+impl Resolver {
+  pub fn new(
+    cache: Option<Arc<DnsCache>>,
+    hosts: HashMap<String, Arc<[SocketAddr]>>,
+    rotate: bool,
+  ) -> Self {
+    Resolver {
+      cache,
+      hosts: Arc::new(hosts),
+      next: rotate.then(Arc::default),
+    }
+  }
+}
+
+impl Service<Name> for Resolver {
+  type Response = std::vec::IntoIter<SocketAddr>;
+  type Error = io::Error;
+  type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+  fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, name: Name) -> Self::Future {
+    let skip = self
+      .next
+      .as_ref()
+      .map(|next| next.fetch_add(1, Ordering::Relaxed));
+    let order = move |mut addrs: Vec<SocketAddr>| {
+      if let Some(skip) = skip.filter(|_| !addrs.is_empty()) {
+        let len = addrs.len();
+        addrs.rotate_left(skip % len);
+      }
+      addrs.into_iter()
+    };
+
+    if !self.hosts.is_empty() {
+      if let Some(addrs) = self.hosts.get(&name.as_str().to_ascii_lowercase()) {
+        let addrs = order(addrs.to_vec());
+        return Box::pin(async move { Ok(addrs) });
+      }
+    }
+
+    match &self.cache {
+      Some(cache) => {
+        let lookup = cache.lookup(name);
+        Box::pin(async move {
+          match lookup.await {
+            Ok(addrs) => Ok(order(addrs.to_vec())),
+            Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+          }
+        })
+      }
+      None => {
+        let mut resolver = GaiResolver::new();
+        Box::pin(async move { Ok(order(resolver.call(name).await?.collect())) })
+      }
+    }
+  }
+}
@@ -0,0 +1,177 @@
+//! Errors thrown to JS with a `code` to branch on.
+//!
+//! JS sees an [`ActixError`] as an `Error` named `ActixError`, with `code`
+//! and `message` and, when an underlying error caused it, `cause`. Errors
+//! raised by napi or thrown by JS callbacks keep reaching JS as they are.
+
+use std::fmt;
+use std::io;
+
+use napi::{Env, Error, JsObject, Result};
+
+/// What went wrong, as `err.code`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+  /// A route clashes with one already registered.
+  RouteConflict,
+  /// A route template or handler list is malformed.
+  InvalidRoute,
+  /// An option is out of range or of an unknown kind.
+  InvalidOption,
+  /// A listener couldn't take its address.
+  BindFailed,
+  /// A server stopped on an error after it started.
+  ServerFailed,
+  BodyReadFailed,
+  BodyTooLarge,
+  InvalidJson,
+  InvalidPatch,
+  /// A header name or value is malformed.
+  InvalidHeader,
+  /// A status code is out of range, or its reason phrase malformed.
+  InvalidStatus,
+  /// An argument can't be used as given, such as an unknown method, a
+  /// missing URL parameter or a value that can't be sent as JSON.
+  InvalidValue,
+  /// A response was written to after it ended.
+  WriteAfterEnd,
+  /// Headers were changed after they were sent.
+  HeadersSent,
+  /// A response, body or stream was already used.
+  AlreadyUsed,
+  /// No route has the name, or the method and path, asked for.
+  RouteNotFound,
+  /// A feature was used before being set up.
+  NotConfigured,
+  /// An outgoing request failed, or got no response in time.
+  FetchFailed,
+}
+
+impl ErrorCode {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ErrorCode::RouteConflict => "ROUTE_CONFLICT",
+      ErrorCode::InvalidRoute => "INVALID_ROUTE",
+      ErrorCode::InvalidOption => "INVALID_OPTION",
+      ErrorCode::BindFailed => "BIND_FAILED",
+      ErrorCode::ServerFailed => "SERVER_FAILED",
+      ErrorCode::BodyReadFailed => "BODY_READ_FAILED",
+      ErrorCode::BodyTooLarge => "BODY_TOO_LARGE",
+      ErrorCode::InvalidJson => "INVALID_JSON",
+      ErrorCode::InvalidPatch => "INVALID_PATCH",
+      ErrorCode::InvalidHeader => "INVALID_HEADER",
+      ErrorCode::InvalidStatus => "INVALID_STATUS",
+      ErrorCode::InvalidValue => "INVALID_VALUE",
+      ErrorCode::WriteAfterEnd => "WRITE_AFTER_END",
+      ErrorCode::HeadersSent => "HEADERS_SENT",
+      ErrorCode::AlreadyUsed => "ALREADY_USED",
+      ErrorCode::RouteNotFound => "ROUTE_NOT_FOUND",
+      ErrorCode::NotConfigured => "NOT_CONFIGURED",
+      ErrorCode::FetchFailed => "FETCH_FAILED",
+    }
+  }
+}
+
+/// The error an [`ActixError`] wraps, as `err.cause`.
+#[derive(Clone, Debug)]
+struct Cause {
+  message: String,
+  /// A system error code such as `EADDRINUSE`.
+  code: Option<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ActixError {
+  code: ErrorCode,
+  message: String,
+  cause: Option<Cause>,
+  /// Extra properties of the JS error, such as the `path` of a route.
+  details: Vec<(&'static str, String)>,
+}
+
+impl ActixError {
+  pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+    ActixError {
+      code,
+      message: message.into(),
+      cause: None,
+      details: Vec::new(),
+    }
+  }
+
+  pub fn with_detail(mut self, name: &'static str, value: impl Into<String>) -> Self {
+    self.details.push((name, value.into()));
+    self
+  }
+
+  pub fn caused_by(mut self, cause: impl fmt::Display) -> Self {
+    self.cause = Some(Cause {
+      message: cause.to_string(),
+      code: None,
+    });
+    self
+  }
+
+  /// Like [`Self::caused_by`], keeping the system error code of `err`.
+  pub fn caused_by_io(mut self, err: &io::Error) -> Self {
+    self.cause = Some(Cause {
+      message: err.to_string(),
+      code: io_code(err),
+    });
+    self
+  }
+
+  /// Why a listener failed: binding if the OS refused the address,
+  /// serving otherwise.
+  pub fn listen(err: &io::Error) -> Self {
+    let code = match io_code(err) {
+      Some(_) => ErrorCode::BindFailed,
+      None => ErrorCode::ServerFailed,
+    };
+    ActixError::new(code, err.to_string()).caused_by_io(err)
+  }
+
+  /// The error to throw to JS, or to reject a promise with.
+  pub fn into_js(self, env: &Env) -> Error {
+    match self.to_object(env) {
+      Ok(error) => Error::from(error.into_unknown()),
+      Err(err) => err,
+    }
+  }
+
+  fn to_object(&self, env: &Env) -> Result<JsObject> {
+    let mut error = env.create_error(Error::from_reason(self.message.as_str()))?;
+    error.set_named_property("name", env.create_string("ActixError")?)?;
+    error.set_named_property("code", env.create_string(self.code.as_str())?)?;
+    for (name, value) in &self.details {
+      error.set_named_property(name, env.create_string(value)?)?;
+    }
+    if let Some(cause) = &self.cause {
+      let mut inner = env.create_error(Error::from_reason(cause.message.as_str()))?;
+      if let Some(code) = cause.code {
+        inner.set_named_property("code", env.create_string(code)?)?;
+      }
+      error.set_named_property("cause", inner)?;
+    }
+    Ok(error)
+  }
+}
+
+impl fmt::Display for ActixError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.code.as_str(), self.message)
+  }
+}
+
+impl std::error::Error for ActixError {}
+
+/// The code Node gives `err`, for the errors listeners commonly hit.
+pub fn io_code(err: &io::Error) -> Option<&'static str> {
+  match err.kind() {
+    io::ErrorKind::AddrInUse => Some("EADDRINUSE"),
+    io::ErrorKind::AddrNotAvailable => Some("EADDRNOTAVAIL"),
+    io::ErrorKind::PermissionDenied => Some("EACCES"),
+    io::ErrorKind::NotFound => Some("ENOENT"),
+    _ => None,
+  }
+}
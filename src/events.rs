@@ -0,0 +1,202 @@
+//! Server lifecycle events, listened to with `app.on()`.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, Error, JsFunction, JsUnknown, Result};
+
+use crate::error::{io_code, ActixError, ErrorCode};
+use crate::options::ListenAddress;
+
+/// Something that happened to the server.
+#[derive(Clone, Debug)]
+pub enum Event {
+  /// Every listener is bound; holds the first TCP address, if any.
+  Listening(Option<SocketAddr>),
+  /// A connection was accepted.
+  Connection { remote: Option<SocketAddr> },
+  /// A listener failed, such as when its address is already in use.
+  Error {
+    message: String,
+    code: Option<&'static str>,
+  },
+  /// Every listener stopped.
+  Close,
+  /// A proxy left out an upstream that kept failing.
+  Ejection {
+    prefix: String,
+    upstream: String,
+    reason: &'static str,
+    cooldown: Duration,
+  },
+  /// A signal stopped the server; open connections get `timeout` to finish.
+  Shutdown {
+    signal: &'static str,
+    timeout: Duration,
+  },
+}
+
+/// The events that can be listened to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+  Listening,
+  Connection,
+  Error,
+  Close,
+  Ejection,
+  Shutdown,
+}
+
+impl EventKind {
+  pub fn parse(name: &str) -> std::result::Result<Self, ActixError> {
+    match name {
+      "listening" => Ok(EventKind::Listening),
+      "connection" => Ok(EventKind::Connection),
+      "error" => Ok(EventKind::Error),
+      "close" => Ok(EventKind::Close),
+      "ejection" => Ok(EventKind::Ejection),
+      "shutdown" => Ok(EventKind::Shutdown),
+      other => Err(ActixError::new(ErrorCode::InvalidOption, format!(
+        "unknown event `{other}`, expected \"listening\", \"connection\", \"error\", \"close\", \"ejection\" or \"shutdown\""
+      ))),
+    }
+  }
+}
+
+impl Event {
+  fn kind(&self) -> EventKind {
+    match self {
+      Event::Listening(_) => EventKind::Listening,
+      Event::Connection { .. } => EventKind::Connection,
+      Event::Error { .. } => EventKind::Error,
+      Event::Close => EventKind::Close,
+      Event::Ejection { .. } => EventKind::Ejection,
+      Event::Shutdown { .. } => EventKind::Shutdown,
+    }
+  }
+
+  /// The error event for a listener that failed.
+  pub fn error(err: &io::Error) -> Self {
+    Event::Error {
+      message: err.to_string(),
+      code: io_code(err),
+    }
+  }
+
+  /// The arguments the listeners are called with.
+  fn to_js(&self, env: &Env) -> Result<Vec<JsUnknown>> {
+    match self {
+      Event::Listening(addr) => match addr {
+        Some(addr) => {
+          let ListenAddress {
+            address,
+            family,
+            port,
+          } = ListenAddress::from(*addr);
+
+          let mut listening = env.create_object()?;
+          listening.set_named_property("address", env.create_string(&address)?)?;
+          listening.set_named_property("family", env.create_string(&family)?)?;
+          listening.set_named_property("port", env.create_uint32(port.into())?)?;
+          Ok(vec![listening.into_unknown()])
+        }
+        None => Ok(vec![env.get_null()?.into_unknown()]),
+      },
+      Event::Connection { remote } => {
+        let mut connection = env.create_object()?;
+        if let Some(addr) = remote {
+          connection
+            .set_named_property("remoteAddress", env.create_string(&addr.ip().to_string())?)?;
+          connection.set_named_property("remotePort", env.create_uint32(addr.port().into())?)?;
+        }
+        Ok(vec![connection.into_unknown()])
+      }
+      Event::Error { message, code } => {
+        let mut error = env.create_error(Error::from_reason(message.as_str()))?;
+        if let Some(code) = code {
+          error.set_named_property("code", env.create_string(code)?)?;
+        }
+        Ok(vec![error.into_unknown()])
+      }
+      Event::Close => Ok(vec![]),
+      Event::Ejection {
+        prefix,
+        upstream,
+        reason,
+        cooldown,
+      } => {
+        let mut ejection = env.create_object()?;
+        ejection.set_named_property("prefix", env.create_string(prefix)?)?;
+        ejection.set_named_property("upstream", env.create_string(upstream)?)?;
+        ejection.set_named_property("reason", env.create_string(reason)?)?;
+        ejection.set_named_property("cooldown", env.create_double(cooldown.as_millis() as f64)?)?;
+        Ok(vec![ejection.into_unknown()])
+      }
+      Event::Shutdown { signal, timeout } => {
+        let mut shutdown = env.create_object()?;
+        shutdown.set_named_property("signal", env.create_string(signal)?)?;
+        shutdown.set_named_property("timeout", env.create_double(timeout.as_millis() as f64)?)?;
+        Ok(vec![shutdown.into_unknown()])
+      }
+    }
+  }
+}
+
+type Listener = ThreadsafeFunction<Event, ErrorStrategy::Fatal>;
+
+/// The listeners registered with `app.on()`, shared with running servers.
+#[derive(Default)]
+pub struct Events {
+  listeners: ArcSwap<Vec<(EventKind, Listener)>>,
+}
+
+impl Events {
+  pub fn on(&self, env: &Env, kind: EventKind, callback: JsFunction) -> Result<()> {
+    let mut listener = callback
+      .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Event>| {
+        ctx.value.to_js(&ctx.env)
+      })?;
+    // Listening for events doesn't keep the process alive on its own.
+    listener.unref(env)?;
+
+    self.listeners.rcu(|listeners| {
+      let mut listeners = Vec::clone(listeners);
+      listeners.push((kind, listener.clone()));
+      listeners
+    });
+    Ok(())
+  }
+
+  /// Removes the listeners of `kind`, or every listener.
+  pub fn remove_all(&self, kind: Option<EventKind>) {
+    self.listeners.rcu(|listeners| {
+      let mut listeners = Vec::clone(listeners);
+      listeners.retain(|(registered, _)| kind.is_some_and(|kind| kind != *registered));
+      listeners
+    });
+  }
+
+  /// Returns `true` if anything listens to `kind`, so events that are
+  /// costly to build can be skipped.
+  pub fn has(&self, kind: EventKind) -> bool {
+    self
+      .listeners
+      .load()
+      .iter()
+      .any(|(registered, _)| *registered == kind)
+  }
+
+  pub fn emit(&self, event: Event) {
+    let kind = event.kind();
+    for (registered, listener) in self.listeners.load().iter() {
+      if *registered == kind {
+        listener.call(event.clone(), ThreadsafeFunctionCallMode::NonBlocking);
+      }
+    }
+  }
+}
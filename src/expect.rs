@@ -0,0 +1,175 @@
+//! `Expect: 100-continue`, decided before the body is sent.
+//!
+//! A client sending `Expect: 100-continue` waits for a `100 Continue`
+//! before uploading the body. hyper sends it as soon as the body is first
+//! read, so a request answered before its body is read never makes the
+//! client upload it. Routing, quotas and body size limits already answer
+//! before reading; `app.expectContinue()` adds a policy on top:
+//!
+//! - a `Content-Length` over `maxLength` gets `413`,
+//! - `check` sees the method, path and headers, and answers `true` to let
+//!   the body come, `false` for `417`, or another status code,
+//! - an expectation other than `100-continue` gets `417` (RFC 9110).
+//!
+//! Requests without `Expect` are not checked.
+
+use hyper::header::{self, HeaderMap};
+use hyper::StatusCode;
+use napi::bindgen_prelude::FromNapiValue;
+use napi::threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction};
+use napi::{sys, Env, Error, JsFunction, JsUnknown, Result, ValueType};
+
+use crate::dispatch::Returned;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Request, Response, ResponseBuilder};
+
+/// Configures `app.expectContinue()`.
+#[napi(object)]
+pub struct ExpectContinueOptions {
+  /// Largest `Content-Length`, in bytes, of a body the client may send.
+  pub max_length: Option<u32>,
+  /// Called with `{ method, path, headers }` before the client sends the
+  /// body. `true` lets it, `false` answers `417` and a number answers with
+  /// that status.
+  #[napi(
+    ts_type = "(request: import('./types').ExpectContinueRequest) => boolean | number | Promise<boolean | number>"
+  )]
+  pub check: Option<JsFunction>,
+}
+
+/// What `check` is asked about.
+struct Expectation {
+  method: String,
+  path: String,
+  headers: HeaderMap,
+}
+
+pub struct ExpectContinue {
+  max_length: Option<u64>,
+  check: Option<ThreadsafeFunction<Expectation, ErrorStrategy::Fatal>>,
+}
+
+impl ExpectContinue {
+  pub fn new(env: &Env, options: ExpectContinueOptions) -> Result<Self> {
+    let check = options
+      .check
+      .map(|check| {
+        let mut check =
+          check.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Expectation>| {
+            let Expectation {
+              method,
+              path,
+              headers,
+            } = ctx.value;
+            let mut expectation = ctx.env.create_object()?;
+            expectation.set_named_property("method", ctx.env.create_string(&method)?)?;
+            expectation.set_named_property("path", ctx.env.create_string(&path)?)?;
+            let mut object = ctx.env.create_object()?;
+            for name in headers.keys() {
+              let mut values = headers.get_all(name).iter();
+              let Some(first) = values.next() else {
+                continue;
+              };
+              let joined = values.fold(first.as_bytes().to_vec(), |mut joined, value| {
+                joined.extend_from_slice(b", ");
+                joined.extend_from_slice(value.as_bytes());
+                joined
+              });
+              let value = ctx
+                .env
+                .create_string_from_std(String::from_utf8_lossy(&joined).into_owned())?;
+              object.set_named_property(name.as_str(), value)?;
+            }
+            expectation.set_named_property("headers", object)?;
+            Ok(vec![expectation])
+          })?;
+        // The policy alone shouldn't keep the process alive.
+        check.unref(env)?;
+        Ok::<_, Error>(check)
+      })
+      .transpose()?;
+
+    Ok(ExpectContinue {
+      max_length: options.max_length.map(u64::from),
+      check,
+    })
+  }
+
+  /// The response refusing the body of `req`, or `None` if the client may
+  /// send it.
+  pub async fn check(&self, req: &Request) -> Option<Response> {
+    let expect = req.headers().get(header::EXPECT)?;
+    if !expect.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+      return Some(status(StatusCode::EXPECTATION_FAILED));
+    }
+
+    if let Some(max_length) = self.max_length {
+      let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+      if length.is_some_and(|length| length > max_length) {
+        return Some(status(StatusCode::PAYLOAD_TOO_LARGE));
+      }
+    }
+
+    let check = self.check.as_ref()?;
+    let expectation = Expectation {
+      method: req.method().as_str().to_owned(),
+      path: req.uri().path().to_owned(),
+      headers: req.headers().clone(),
+    };
+    let answer = match check.call_async::<Returned<Answer>>(expectation).await {
+      Ok(returned) => returned.settle().await,
+      Err(err) => Err(err),
+    };
+
+    match answer {
+      Ok(Answer::Continue) => None,
+      Ok(Answer::Status(code)) => Some(status(code)),
+      Err(err) => {
+        eprintln!("Expect check failed: {err}");
+        Some(status(StatusCode::EXPECTATION_FAILED))
+      }
+    }
+  }
+}
+
+/// What `check` answered.
+enum Answer {
+  Continue,
+  Status(StatusCode),
+}
+
+impl FromNapiValue for Answer {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let unknown = JsUnknown::from_napi_value(env, value)?;
+    match unknown.get_type()? {
+      ValueType::Boolean if bool::from_napi_value(env, value)? => Ok(Answer::Continue),
+      ValueType::Number => {
+        let code = u32::from_napi_value(env, value)?;
+        u16::try_from(code)
+          .ok()
+          .and_then(|code| StatusCode::from_u16(code).ok())
+          .filter(|code| !code.is_informational())
+          .map(Answer::Status)
+          .ok_or_else(|| {
+            ActixError::new(
+              ErrorCode::InvalidStatus,
+              format!("invalid status code {code}"),
+            )
+            .into_js(&Env::from_raw(env))
+          })
+      }
+      _ => Ok(Answer::Status(StatusCode::EXPECTATION_FAILED)),
+    }
+  }
+}
+
+fn status(status: StatusCode) -> Response {
+  ResponseBuilder::new()
+    .status(status)
+    .body(Body::empty())
+    .unwrap()
+}
@@ -0,0 +1,609 @@
+//! Express-style middleware on top of the native server.
+//!
+//! `express(middleware)` wraps a `(req, res, next)` function, such as an
+//! Express app or a middleware like `morgan`, into a handler:
+//!
+//! - `req` is the request handed to handlers, given the `url`,
+//!   `originalUrl`, `path`, `query` and plain-object `headers` Express
+//!   middleware reads,
+//! - `res` is an [`ExpressResponse`], which buffers what is written and
+//!   answers the request on `end()`, `send()`, `json()` or `redirect()`,
+//! - `next()` moves on to the next middleware or handler, and `next(err)`
+//!   fails the request with a `500`.
+//!
+//! The middleware of a route share one `req` and one `res`, so properties
+//! set on `req`, such as `req.user`, and headers set on `res` are seen by
+//! the ones after it. Handlers after an Express-style middleware see the
+//! `url` it was given, the path and query, rather than the absolute URL.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::StatusCode;
+use napi::bindgen_prelude::{Buffer, Either, FromNapiRef, FromNapiValue, This};
+use napi::{
+  Env, Error, JsDeferred, JsFunction, JsObject, JsString, JsUnknown, NapiRaw, Property, Result,
+  ValueType,
+};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::form;
+use crate::outgoing::{self, chunk_bytes, head_headers, header_values, parse_status};
+use crate::response::{self, NodeResponse};
+
+/// Property of `res` holding the `finish` and `close` listeners.
+const LISTENERS_KEY: &str = "__actixListeners";
+
+/// Property of `req` holding the `res` shared by the middleware of a route.
+const RESPONSE_KEY: &str = "__actixResponse";
+
+type Settle =
+  JsDeferred<Option<NodeResponse>, Box<dyn FnOnce(Env) -> Result<Option<NodeResponse>>>>;
+
+/// Settles the promise the handler returns, once: with `null` when the
+/// middleware calls `next()`, or with the response once it is ended.
+#[derive(Clone)]
+struct Settler(Rc<RefCell<Pending>>);
+
+struct Pending(Option<Settle>);
+
+impl Settler {
+  fn resolve(&self, response: Option<NodeResponse>) {
+    if let Some(settle) = self.0.borrow_mut().0.take() {
+      settle.resolve(Box::new(move |_| Ok(response)));
+    }
+  }
+
+  fn reject(&self, err: Error) {
+    if let Some(settle) = self.0.borrow_mut().0.take() {
+      settle.reject(err);
+    }
+  }
+}
+
+impl Drop for Pending {
+  /// Fails requests whose `res` and `next` were collected unused, rather
+  /// than leaving them hanging.
+  fn drop(&mut self) {
+    if let Some(settle) = self.0.take() {
+      settle.reject(Error::from_reason(
+        "the middleware neither answered nor called `next()`",
+      ));
+    }
+  }
+}
+
+/// Wraps an Express-style `(req, res, next)` middleware into a handler.
+#[napi(
+  js_name = "express",
+  ts_args_type = "middleware: import('./types').ExpressMiddleware",
+  ts_return_type = "import('./types').Handler<import('./types').AnyActixRequest>"
+)]
+pub fn create_express(env: Env, middleware: JsFunction) -> Result<JsFunction> {
+  let run = env.create_function_from_closure("express", |ctx| {
+    let middleware: JsFunction = ctx.get(0)?;
+    let req: JsObject = ctx.get(1)?;
+    run(ctx.env, middleware, req)
+  })?;
+
+  // The handler is `run` with the middleware as its first argument.
+  let run = run.coerce_to_object()?;
+  let bind: JsFunction = run.get_named_property("bind")?;
+  let args = [env.get_null()?.into_unknown(), middleware.into_unknown()];
+  bind.call(Some(&run), &args)?.try_into()
+}
+
+fn run(env: &Env, middleware: JsFunction, mut req: JsObject) -> Result<JsObject> {
+  let (settle, promise) = env.create_deferred()?;
+  let settler = Settler(Rc::new(RefCell::new(Pending(Some(settle)))));
+
+  let res = match shared_response(env, &req)? {
+    Some(res) => {
+      // SAFETY: `shared_response` checked it is an `ExpressResponse`.
+      let response = unsafe { ExpressResponse::from_napi_ref(env.raw(), res.raw())? };
+      response.0.borrow_mut().settler = settler.clone();
+      res
+    }
+    None => {
+      prepare(env, &mut req)?;
+      let res = ExpressResponse(RefCell::new(State {
+        status: StatusCode::OK,
+        headers: HeaderMap::new(),
+        body: Vec::new(),
+        settler: settler.clone(),
+        head_written: false,
+        ended: false,
+      }))
+      .into_instance(*env)?;
+      // SAFETY: the instance was just created in this scope.
+      let mut res = unsafe { JsObject::from_napi_value(env.raw(), res.raw())? };
+      res.set_named_property("locals", env.create_object()?)?;
+      // Hidden, so that it isn't copied along with `req`.
+      req.define_properties(&[Property::new(RESPONSE_KEY)?.with_value(&res)])?;
+      res
+    }
+  };
+
+  let next = {
+    let settler = settler.clone();
+    env.create_function_from_closure("next", move |ctx| {
+      let err = match ctx.length {
+        0 => None,
+        _ => Some(ctx.get::<JsUnknown>(0)?),
+      };
+      match err.map(failure).transpose()?.flatten() {
+        Some(err) => settler.reject(err),
+        None => settler.resolve(None),
+      }
+      ctx.env.get_undefined()
+    })?
+  };
+
+  let args = [req.into_unknown(), res.into_unknown(), next.into_unknown()];
+  if let Err(err) = middleware.call(None, &args) {
+    settler.reject(err);
+  }
+  Ok(promise)
+}
+
+/// The `res` an earlier middleware of the same request was given, if any.
+fn shared_response(env: &Env, req: &JsObject) -> Result<Option<JsObject>> {
+  let res = req.get_named_property::<JsUnknown>(RESPONSE_KEY)?;
+  if res.get_type()? != ValueType::Object || !ExpressResponse::instance_of(*env, &res)? {
+    return Ok(None);
+  }
+  Ok(Some(res.coerce_to_object()?))
+}
+
+/// The error passed to `next()`, if any. `next('route')` and
+/// `next('router')` only skip ahead, which here is the same as `next()`.
+fn failure(err: JsUnknown) -> Result<Option<Error>> {
+  match err.get_type()? {
+    ValueType::Undefined | ValueType::Null => Ok(None),
+    ValueType::String => {
+      let text = err.coerce_to_string()?.into_utf8()?.into_owned()?;
+      match text.as_str() {
+        "route" | "router" => Ok(None),
+        _ => Ok(Some(Error::from_reason(text))),
+      }
+    }
+    ValueType::Object => {
+      let err = err.coerce_to_object()?;
+      let message: JsUnknown = err.get_named_property("message")?;
+      let message = message.coerce_to_string()?.into_utf8()?.into_owned()?;
+      Ok(Some(Error::from_reason(message)))
+    }
+    _ => {
+      let text = err.coerce_to_string()?.into_utf8()?.into_owned()?;
+      Ok(Some(Error::from_reason(text)))
+    }
+  }
+}
+
+/// Gives `req` the properties Express middleware expects of Node's
+/// `IncomingMessage` and Express' own request.
+fn prepare(env: &Env, req: &mut JsObject) -> Result<()> {
+  let url: JsString = req.get_named_property("url")?;
+  let url = url.into_utf8()?.into_owned()?;
+  // `url` is absolute; Express' is the path and query.
+  let target = url
+    .split_once("://")
+    .and_then(|(_, rest)| rest.find('/').map(|idx| &rest[idx..]))
+    .unwrap_or("/");
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+  let headers: JsObject = req.get_named_property("headers")?;
+  let entries: JsFunction = headers.get_named_property("entries")?;
+  let entries: JsObject = entries.call_without_args(Some(&headers))?.try_into()?;
+  let headers = response::header_map(env, entries)?;
+
+  let mut plain = env.create_object()?;
+  for name in headers.keys() {
+    // Node keeps `Set-Cookie` as an array and joins the others.
+    if name == header::SET_COOKIE {
+      let values = headers
+        .get_all(name)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+      plain.set_named_property(name.as_str(), values.collect::<Vec<_>>())?;
+    } else {
+      let joined = outgoing::joined(&headers, name).unwrap_or_default();
+      plain.set_named_property(name.as_str(), env.create_string(&joined)?)?;
+    }
+  }
+
+  let target = env.create_string(target)?;
+  // `url` and `headers` are getters of `Request.prototype`; own properties
+  // shadow them.
+  req.define_properties(&[
+    Property::new("url")?.with_value(&target),
+    Property::new("originalUrl")?.with_value(&target),
+    Property::new("path")?.with_value(&env.create_string(path)?),
+    Property::new("query")?.with_value(&form::parse(query.as_bytes(), None).into_js(env)?),
+    Property::new("headers")?.with_value(&plain),
+  ])
+}
+
+/// The `res` of an Express-style middleware. Status and headers can be
+/// changed until the response is ended; the body is buffered until then.
+// The state is never borrowed while JS runs: the `writeHead` and `finish`
+// hooks `res` calls may use it themselves.
+#[napi]
+pub struct ExpressResponse(RefCell<State>);
+
+struct State {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Vec<u8>,
+  /// Settles the call of the middleware currently holding `res`.
+  settler: Settler,
+  head_written: bool,
+  ended: bool,
+}
+
+#[napi]
+impl ExpressResponse {
+  #[napi(getter, js_name = "statusCode")]
+  pub fn status_code(&self) -> u16 {
+    self.0.borrow().status.as_u16()
+  }
+
+  #[napi(setter, js_name = "statusCode")]
+  pub fn set_status_code(&self, env: Env, code: u32) -> Result<()> {
+    self.0.borrow_mut().status = parse_status(&env, code)?;
+    Ok(())
+  }
+
+  /// Whether `writeHead()` was called, by the middleware or by the first
+  /// `write()` or `end()`. Nothing is actually sent before the response is
+  /// ended.
+  #[napi(getter)]
+  pub fn headers_sent(&self) -> bool {
+    let state = self.0.borrow();
+    state.head_written || state.ended
+  }
+
+  #[napi(getter)]
+  pub fn writable_ended(&self) -> bool {
+    self.0.borrow().ended
+  }
+
+  #[napi(getter)]
+  pub fn finished(&self) -> bool {
+    self.0.borrow().ended
+  }
+
+  #[napi]
+  pub fn status(&self, env: Env, this: This, code: u32) -> Result<This> {
+    self.0.borrow_mut().status = parse_status(&env, code)?;
+    Ok(this)
+  }
+
+  /// Replaces a header. Arrays set one header per item.
+  #[napi(ts_args_type = "name: string, value: string | number | Array<string>")]
+  pub fn set_header(&self, env: Env, this: This, name: String, value: JsUnknown) -> Result<This> {
+    let name = outgoing::parse_name(&env, name)?;
+    let values = header_values(&env, value)?;
+    let mut state = self.0.borrow_mut();
+    state.headers.remove(&name);
+    for value in values {
+      state.headers.append(name.clone(), value);
+    }
+    Ok(this)
+  }
+
+  /// Express' `res.set()`, the same as `setHeader()`.
+  #[napi(ts_args_type = "name: string, value: string | number | Array<string>")]
+  pub fn set(&self, env: Env, this: This, name: String, value: JsUnknown) -> Result<This> {
+    self.set_header(env, this, name, value)
+  }
+
+  /// Adds a header, keeping the values already set.
+  #[napi(ts_args_type = "name: string, value: string | number | Array<string>")]
+  pub fn append(&self, env: Env, this: This, name: String, value: JsUnknown) -> Result<This> {
+    let name = outgoing::parse_name(&env, name)?;
+    let values = header_values(&env, value)?;
+    let mut state = self.0.borrow_mut();
+    for value in values {
+      state.headers.append(name.clone(), value);
+    }
+    Ok(this)
+  }
+
+  /// The values of a header joined with `, `.
+  #[napi]
+  pub fn get_header(&self, name: String) -> Option<String> {
+    outgoing::joined(&self.0.borrow().headers, name.as_str())
+  }
+
+  /// Express' `res.get()`, the same as `getHeader()`.
+  #[napi]
+  pub fn get(&self, name: String) -> Option<String> {
+    self.get_header(name)
+  }
+
+  #[napi]
+  pub fn has_header(&self, name: String) -> bool {
+    self.0.borrow().headers.contains_key(name.as_str())
+  }
+
+  #[napi]
+  pub fn remove_header(&self, name: String) {
+    self.0.borrow_mut().headers.remove(name.as_str());
+  }
+
+  /// Node's `res.writeHead(status[, statusMessage][, headers])`. `headers`
+  /// is an object, or an array of names and values one after the other;
+  /// they replace the headers of the same name. The status message is
+  /// ignored: responses get the standard reason phrase.
+  ///
+  /// `write()` and `end()` call `res.writeHead(res.statusCode)` first if it
+  /// wasn't, so middleware wrapping it, such as `on-headers`, sees the head
+  /// before it's sent.
+  #[napi(
+    ts_args_type = "status: number, messageOrHeaders?: string | import('./types').OutgoingHeaders | undefined | null, headers?: import('./types').OutgoingHeaders | undefined | null"
+  )]
+  pub fn write_head(
+    &self,
+    env: Env,
+    this: This,
+    status: u32,
+    message_or_headers: Option<Either<String, JsObject>>,
+    headers: Option<JsObject>,
+  ) -> Result<This> {
+    let status = parse_status(&env, status)?;
+    let headers = match (message_or_headers, headers) {
+      (Some(Either::B(headers)), _) | (_, Some(headers)) => head_headers(&env, headers)?,
+      _ => Vec::new(),
+    };
+
+    let mut state = self.0.borrow_mut();
+    if state.ended {
+      return Ok(this);
+    }
+    state.status = status;
+    for (name, _) in &headers {
+      state.headers.remove(name);
+    }
+    for (name, value) in headers {
+      state.headers.append(name, value);
+    }
+    state.head_written = true;
+    Ok(this)
+  }
+
+  /// Node's `res.writeEarlyHints()`. The server can't send `103` responses,
+  /// so the `link` values are added to the final response instead, where
+  /// browsers still preload them.
+  #[napi(ts_args_type = "hints: { link?: string | Array<string> }")]
+  pub fn write_early_hints(&self, env: Env, hints: JsObject) -> Result<()> {
+    if self.0.borrow().ended || !hints.has_named_property("link")? {
+      return Ok(());
+    }
+    let values = header_values(&env, hints.get_named_property("link")?)?;
+    let mut state = self.0.borrow_mut();
+    for value in values {
+      state.headers.append(header::LINK, value);
+    }
+    Ok(())
+  }
+
+  /// Buffers a chunk of the body.
+  #[napi]
+  pub fn write(&self, env: Env, this: This, chunk: Either<String, Buffer>) -> Result<bool> {
+    if self.0.borrow().ended {
+      return Err(ActixError::new(ErrorCode::WriteAfterEnd, "write after end").into_js(&env));
+    }
+    self.write_head_once(env, &this)?;
+    self
+      .0
+      .borrow_mut()
+      .body
+      .extend_from_slice(&chunk_bytes(chunk));
+    Ok(true)
+  }
+
+  /// Ends the response, with a last chunk of the body.
+  #[napi]
+  pub fn end(&self, env: Env, this: This, chunk: Option<Either<String, Buffer>>) -> Result<This> {
+    if let Some(chunk) = chunk.filter(|_| !self.0.borrow().ended) {
+      self
+        .0
+        .borrow_mut()
+        .body
+        .extend_from_slice(&chunk_bytes(chunk));
+    }
+    self.finish(env, &this)?;
+    Ok(this)
+  }
+
+  /// Sends a body: strings as HTML, buffers as bytes, anything else as
+  /// JSON, unless `Content-Type` is already set.
+  #[napi(ts_args_type = "body?: unknown")]
+  pub fn send(&self, env: Env, this: This, body: JsUnknown) -> Result<This> {
+    match body.get_type()? {
+      ValueType::Undefined | ValueType::Null => {}
+      ValueType::String => {
+        let text: JsString = body.try_into()?;
+        let text = text.into_utf8()?.into_owned()?;
+        self.set_body("text/html; charset=utf-8", text.into_bytes());
+      }
+      _ if body.is_buffer()? => {
+        // SAFETY: `body` was just checked to be a buffer.
+        let bytes = unsafe { Buffer::from_napi_value(env.raw(), body.raw())? };
+        self.set_body("application/octet-stream", Vec::from(bytes));
+      }
+      _ => return self.json(env, this, body),
+    }
+    self.finish(env, &this)?;
+    Ok(this)
+  }
+
+  /// Sends `value` as JSON.
+  #[napi]
+  pub fn json(&self, env: Env, this: This, value: JsUnknown) -> Result<This> {
+    let value: serde_json::Value = env.from_js_value(value)?;
+    let body = serde_json::to_vec(&value).map_err(|err| {
+      ActixError::new(ErrorCode::InvalidValue, "the value can't be sent as JSON")
+        .caused_by(err)
+        .into_js(&env)
+    })?;
+    self.set_body("application/json; charset=utf-8", body);
+    self.finish(env, &this)?;
+    Ok(this)
+  }
+
+  /// Sends the status with its reason phrase as the body.
+  #[napi]
+  pub fn send_status(&self, env: Env, this: This, code: u32) -> Result<This> {
+    let status = parse_status(&env, code)?;
+    let reason = status
+      .canonical_reason()
+      .map_or_else(|| status.as_str().to_owned(), str::to_owned);
+    self.0.borrow_mut().status = status;
+    self.set_body("text/plain; charset=utf-8", reason.into_bytes());
+    self.finish(env, &this)?;
+    Ok(this)
+  }
+
+  /// Redirects to `url`, with `302` unless a status is given first, as in
+  /// `res.redirect(301, url)`.
+  #[napi(ts_args_type = "target: number | string, url?: string | undefined | null")]
+  pub fn redirect(
+    &self,
+    env: Env,
+    this: This,
+    target: Either<u32, String>,
+    url: Option<String>,
+  ) -> Result<This> {
+    let (status, url) = match (target, url) {
+      (Either::A(code), Some(url)) => (parse_status(&env, code)?, url),
+      (Either::B(url), _) => (StatusCode::FOUND, url),
+      (Either::A(_), None) => {
+        return Err(ActixError::new(ErrorCode::InvalidValue, "redirect needs a URL").into_js(&env))
+      }
+    };
+    let location = HeaderValue::try_from(url.as_str()).map_err(|_| {
+      ActixError::new(
+        ErrorCode::InvalidHeader,
+        format!("invalid redirect URL `{url}`"),
+      )
+      .into_js(&env)
+    })?;
+
+    {
+      let mut state = self.0.borrow_mut();
+      state.status = status;
+      state.headers.insert(header::LOCATION, location);
+    }
+    self.finish(env, &this)?;
+    Ok(this)
+  }
+
+  /// Listens for `finish` or `close`, both emitted once the response is
+  /// handed to the server. Other events never happen.
+  #[napi(ts_args_type = "event: string, listener: () => void")]
+  pub fn on(&self, env: Env, mut this: This, event: String, listener: JsFunction) -> Result<This> {
+    if event != "finish" && event != "close" {
+      return Ok(this);
+    }
+
+    let mut listeners = match this
+      .get_named_property::<JsUnknown>(LISTENERS_KEY)?
+      .get_type()?
+    {
+      ValueType::Object => this.get_named_property::<JsObject>(LISTENERS_KEY)?,
+      _ => {
+        this.set_named_property(LISTENERS_KEY, env.create_object()?)?;
+        this.get_named_property::<JsObject>(LISTENERS_KEY)?
+      }
+    };
+    let mut added = match listeners
+      .get_named_property::<JsUnknown>(&event)?
+      .get_type()?
+    {
+      ValueType::Object => listeners.get_named_property::<JsObject>(&event)?,
+      _ => {
+        listeners.set_named_property(&event, env.create_empty_array()?)?;
+        listeners.get_named_property::<JsObject>(&event)?
+      }
+    };
+    added.set_element(added.get_array_length()?, listener)?;
+    Ok(this)
+  }
+
+  /// The same as `on()`: `finish` and `close` are only emitted once.
+  #[napi(ts_args_type = "event: string, listener: () => void")]
+  pub fn once(&self, env: Env, this: This, event: String, listener: JsFunction) -> Result<This> {
+    self.on(env, this, event, listener)
+  }
+}
+
+impl ExpressResponse {
+  /// Sets the body, and `Content-Type` unless it is already set.
+  fn set_body(&self, content_type: &'static str, body: Vec<u8>) {
+    let mut state = self.0.borrow_mut();
+    if !state.headers.contains_key(header::CONTENT_TYPE) {
+      state
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+    state.body = body;
+  }
+
+  /// Calls `res.writeHead()` through JS, where middleware may have wrapped
+  /// it, unless it was already called.
+  fn write_head_once(&self, env: Env, this: &This) -> Result<()> {
+    let status = {
+      let state = self.0.borrow();
+      if state.head_written || state.ended {
+        return Ok(());
+      }
+      state.status.as_u16()
+    };
+    let write_head: JsFunction = this.get_named_property("writeHead")?;
+    write_head.call(Some(this), &[env.create_uint32(status.into())?])?;
+    // A wrapper that didn't call the original still wrote the head.
+    self.0.borrow_mut().head_written = true;
+    Ok(())
+  }
+
+  /// Answers the request, unless it was already, and tells the listeners.
+  fn finish(&self, env: Env, this: &This) -> Result<()> {
+    self.write_head_once(env, this)?;
+    let (status, headers, body, settler) = {
+      let mut state = self.0.borrow_mut();
+      if state.ended {
+        return Ok(());
+      }
+      state.ended = true;
+      (
+        state.status,
+        std::mem::take(&mut state.headers),
+        Bytes::from(std::mem::take(&mut state.body)),
+        state.settler.clone(),
+      )
+    };
+    settler.resolve(Some(NodeResponse::from_parts(status, headers, body)));
+
+    let listeners = this.get_named_property::<JsUnknown>(LISTENERS_KEY)?;
+    if listeners.get_type()? != ValueType::Object {
+      return Ok(());
+    }
+    let listeners = listeners.coerce_to_object()?;
+    for event in ["finish", "close"] {
+      let added = listeners.get_named_property::<JsUnknown>(event)?;
+      if added.get_type()? != ValueType::Object {
+        continue;
+      }
+      let added = added.coerce_to_object()?;
+      for idx in 0..added.get_array_length()? {
+        let listener: JsFunction = added.get_element(idx)?;
+        listener.call(Some(this), &[] as &[JsUnknown])?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,275 @@
+//! Responses served straight from memory, without calling into JS.
+//!
+//! These are matched on the exact request path before the router runs, so
+//! junk traffic like `/favicon.ico` and `/robots.txt` never reaches the JS
+//! thread. Their bodies are held whole, so byte `Range` requests are served
+//! from them too, as slices of the same buffer.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method, StatusCode};
+use napi::bindgen_prelude::{Buffer, Either};
+use napi::{Env, Result};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::guard::{Guard, GuardOptions};
+use crate::html::{HtmlRewrite, HtmlRewriteOptions};
+use crate::http::{Body, Response};
+
+/// Fixed responses keyed by request path.
+pub type FastPaths = HashMap<String, FixedResponse>;
+
+/// A constant response registered with `app.getStatic()`.
+#[napi(object)]
+pub struct StaticResponse {
+  /// Defaults to `200`.
+  pub status: Option<u16>,
+  /// Text is sent as `text/plain` and bytes as `application/octet-stream`,
+  /// unless `headers` sets a content type.
+  pub body: Option<Either<String, Buffer>>,
+  pub headers: Option<HashMap<String, String>>,
+  /// Credentials required before the response is served.
+  pub guard: Option<GuardOptions>,
+  /// Rewrites the body once, when it is HTML.
+  pub rewrite_html: Option<HtmlRewriteOptions>,
+}
+
+/// A response fully described up front.
+#[derive(Clone)]
+pub struct FixedResponse {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Bytes,
+  guard: Option<Arc<Guard>>,
+}
+
+impl FixedResponse {
+  pub fn new(status: StatusCode, headers: HeaderMap, body: impl Into<Bytes>) -> Self {
+    FixedResponse {
+      status,
+      headers,
+      body: body.into(),
+      guard: None,
+    }
+  }
+
+  /// A `200 OK` with the given content type, cacheable for a day.
+  pub fn asset(content_type: HeaderValue, body: impl Into<Bytes>) -> Self {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(
+      header::CACHE_CONTROL,
+      HeaderValue::from_static("public, max-age=86400"),
+    );
+
+    FixedResponse::new(StatusCode::OK, headers, body)
+  }
+
+  /// Checks a response registered from JS.
+  pub fn from_static(env: &Env, response: StaticResponse) -> Result<Self> {
+    let invalid = |code: ErrorCode, err: &dyn std::fmt::Display| {
+      ActixError::new(code, err.to_string()).into_js(env)
+    };
+
+    let status = StatusCode::from_u16(response.status.unwrap_or(200))
+      .map_err(|err| invalid(ErrorCode::InvalidStatus, &err))?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in response.headers.into_iter().flatten() {
+      headers.append(
+        HeaderName::try_from(name).map_err(|err| invalid(ErrorCode::InvalidHeader, &err))?,
+        HeaderValue::try_from(value).map_err(|err| invalid(ErrorCode::InvalidHeader, &err))?,
+      );
+    }
+
+    let (content_type, body) = match response.body {
+      Some(Either::A(text)) => ("text/plain; charset=utf-8", Bytes::from(text)),
+      Some(Either::B(bytes)) => ("application/octet-stream", Bytes::from(Vec::from(bytes))),
+      None => ("text/plain; charset=utf-8", Bytes::new()),
+    };
+    if !body.is_empty() && !headers.contains_key(header::CONTENT_TYPE) {
+      headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+    let body = match response.rewrite_html {
+      Some(options) if HtmlRewrite::applies(&headers) => {
+        Bytes::from(Arc::new(HtmlRewrite::new(options)).document(&body))
+      }
+      _ => body,
+    };
+
+    let guard = response
+      .guard
+      .map(|guard| Guard::new(env, guard).map(Arc::new))
+      .transpose()?;
+
+    Ok(FixedResponse {
+      guard,
+      ..FixedResponse::new(status, headers, body)
+    })
+  }
+
+  /// The guard checked before the response is served, if any.
+  pub fn guard(&self) -> Option<&Guard> {
+    self.guard.as_deref()
+  }
+
+  /// Builds the response for a request, or `None` if the method is anything
+  /// other than `GET` or `HEAD`. `200` bodies are also served in parts, to
+  /// requests with a single byte `Range`.
+  pub fn respond(&self, method: &Method, headers: &HeaderMap) -> Option<Response> {
+    if method != Method::GET && method != Method::HEAD {
+      return None;
+    }
+
+    let len = self.body.len();
+    let slice = match headers.get(header::RANGE) {
+      Some(range) if self.status == StatusCode::OK && self.is_current(headers) => range
+        .to_str()
+        .map_or(Slice::Full, |range| Slice::parse(range, len)),
+      _ => Slice::Full,
+    };
+
+    let (status, body, content_range) = match slice {
+      Slice::Full => (self.status, self.body.clone(), None),
+      Slice::Part(range) => (
+        StatusCode::PARTIAL_CONTENT,
+        self.body.slice(range.clone()),
+        Some(format!("bytes {}-{}/{len}", range.start, range.end - 1)),
+      ),
+      Slice::Unsatisfiable => {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+        res.headers_mut().insert(
+          header::CONTENT_RANGE,
+          HeaderValue::try_from(format!("bytes */{len}")).unwrap(),
+        );
+        return Some(res);
+      }
+    };
+
+    let content_length = HeaderValue::from(body.len());
+    let mut res = Response::new(match *method {
+      Method::HEAD => Body::empty(),
+      _ => Body::new(body),
+    });
+    *res.status_mut() = status;
+    *res.headers_mut() = self.headers.clone();
+    if self.status == StatusCode::OK {
+      res
+        .headers_mut()
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    }
+    if let Some(content_range) = content_range {
+      res.headers_mut().insert(
+        header::CONTENT_RANGE,
+        HeaderValue::try_from(content_range).unwrap(),
+      );
+    }
+    res
+      .headers_mut()
+      .insert(header::CONTENT_LENGTH, content_length);
+
+    Some(res)
+  }
+
+  /// Whether a `Range` applies: with `If-Range`, only if it names the
+  /// response's own `ETag`.
+  fn is_current(&self, headers: &HeaderMap) -> bool {
+    match headers.get(header::IF_RANGE) {
+      Some(if_range) => self
+        .headers
+        .get(header::ETAG)
+        .is_some_and(|etag| etag == if_range && !etag.as_bytes().starts_with(b"W/")),
+      None => true,
+    }
+  }
+}
+
+/// The part of a body a `Range` header asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum Slice {
+  /// The whole body, also sent for ranges that can't be honoured as one
+  /// part, such as multiple ranges.
+  Full,
+  Part(Range<usize>),
+  Unsatisfiable,
+}
+
+impl Slice {
+  fn parse(range: &str, len: usize) -> Slice {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+      return Slice::Full;
+    };
+    if spec.contains(',') {
+      return Slice::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+      return Slice::Full;
+    };
+
+    let bound = |text: &str| text.trim().parse::<usize>().ok();
+    let range = match (first.trim().is_empty(), bound(first), bound(last)) {
+      // `bytes=-n`: the last `n` bytes.
+      (true, _, Some(suffix)) if suffix > 0 && len > 0 => len.saturating_sub(suffix)..len,
+      (true, _, Some(_)) => return Slice::Unsatisfiable,
+      (false, Some(start), None) if last.trim().is_empty() => start..len,
+      (false, Some(start), Some(end)) if start <= end => start..len.min(end.saturating_add(1)),
+      _ => return Slice::Full,
+    };
+
+    if range.start >= len || range.is_empty() {
+      Slice::Unsatisfiable
+    } else {
+      Slice::Part(range)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Slice;
+
+  #[test]
+  fn parses_closed_ranges() {
+    assert_eq!(Slice::parse("bytes=0-4", 10), Slice::Part(0..5));
+    assert_eq!(Slice::parse("bytes=2-2", 10), Slice::Part(2..3));
+    assert_eq!(Slice::parse("bytes=5-100", 10), Slice::Part(5..10));
+  }
+
+  #[test]
+  fn parses_open_and_suffix_ranges() {
+    assert_eq!(Slice::parse("bytes=7-", 10), Slice::Part(7..10));
+    assert_eq!(Slice::parse("bytes=-3", 10), Slice::Part(7..10));
+    assert_eq!(Slice::parse("bytes=-30", 10), Slice::Part(0..10));
+  }
+
+  #[test]
+  fn clamps_the_largest_end() {
+    assert_eq!(
+      Slice::parse("bytes=0-18446744073709551615", 10),
+      Slice::Part(0..10)
+    );
+  }
+
+  #[test]
+  fn rejects_ranges_past_the_end() {
+    assert_eq!(Slice::parse("bytes=10-", 10), Slice::Unsatisfiable);
+    assert_eq!(Slice::parse("bytes=10-20", 10), Slice::Unsatisfiable);
+    assert_eq!(Slice::parse("bytes=-0", 10), Slice::Unsatisfiable);
+    assert_eq!(Slice::parse("bytes=0-0", 0), Slice::Unsatisfiable);
+    assert_eq!(Slice::parse("bytes=-5", 0), Slice::Unsatisfiable);
+  }
+
+  #[test]
+  fn serves_the_whole_body_otherwise() {
+    assert_eq!(Slice::parse("items=0-4", 10), Slice::Full);
+    assert_eq!(Slice::parse("bytes=0-1,4-5", 10), Slice::Full);
+    assert_eq!(Slice::parse("bytes=5-2", 10), Slice::Full);
+    assert_eq!(Slice::parse("bytes=a-b", 10), Slice::Full);
+    assert_eq!(Slice::parse("bytes=4", 10), Slice::Full);
+  }
+}
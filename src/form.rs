@@ -0,0 +1,284 @@
+//! Parsing of `application/x-www-form-urlencoded` bodies.
+//!
+//! Keys using bracket notation are expanded into nested values, so
+//! `a[b]=c&a[d][]=1&a[d][]=2` becomes `{ a: { b: "c", d: ["1", "2"] } }`.
+//! Repeating a plain key (`x=1&x=2`) collects the values into a list.
+
+use encoding_rs::{Encoding, UTF_8};
+use napi::{Env, JsUnknown, Result};
+use percent_encoding::percent_decode;
+
+/// A decoded form value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormValue {
+  Text(String),
+  List(Vec<FormValue>),
+  Map(Vec<(String, FormValue)>),
+}
+
+type Entries = Vec<(String, FormValue)>;
+
+/// Returns the value stored under `key`, inserting `default` if missing.
+fn slot<'a>(entries: &'a mut Entries, key: &str, default: FormValue) -> &'a mut FormValue {
+  let idx = match entries.iter().position(|(name, _)| name == key) {
+    Some(idx) => idx,
+    None => {
+      entries.push((key.to_owned(), default));
+      entries.len() - 1
+    }
+  };
+  &mut entries[idx].1
+}
+
+fn insert(entries: &mut Entries, path: &[&str], value: String) {
+  let Some((key, rest)) = path.split_first() else {
+    return;
+  };
+
+  match rest {
+    [] => {
+      let Some((_, slot)) = entries.iter_mut().find(|(name, _)| name == key) else {
+        entries.push((key.to_string(), FormValue::Text(value)));
+        return;
+      };
+      match slot {
+        FormValue::List(items) => items.push(FormValue::Text(value)),
+        other => {
+          let prev = std::mem::replace(other, FormValue::List(Vec::new()));
+          *other = FormValue::List(vec![prev, FormValue::Text(value)]);
+        }
+      }
+    }
+    ["", rest @ ..] => {
+      let slot = slot(entries, key, FormValue::List(Vec::new()));
+      if !matches!(slot, FormValue::List(_)) {
+        let prev = std::mem::replace(slot, FormValue::List(Vec::new()));
+        *slot = FormValue::List(vec![prev]);
+      }
+      let FormValue::List(items) = slot else {
+        unreachable!()
+      };
+
+      if rest.is_empty() {
+        items.push(FormValue::Text(value));
+      } else {
+        let mut nested = Entries::new();
+        insert(&mut nested, rest, value);
+        items.push(FormValue::Map(nested));
+      }
+    }
+    _ => {
+      let slot = slot(entries, key, FormValue::Map(Entries::new()));
+      if !matches!(slot, FormValue::Map(_)) {
+        *slot = FormValue::Map(Entries::new());
+      }
+      let FormValue::Map(nested) = slot else {
+        unreachable!()
+      };
+      insert(nested, rest, value);
+    }
+  }
+}
+
+impl FormValue {
+  /// Converts the value into a plain JS string, array or object.
+  pub fn into_js(self, env: &Env) -> Result<JsUnknown> {
+    match self {
+      FormValue::Text(text) => env.create_string(&text).map(|v| v.into_unknown()),
+      FormValue::List(items) => {
+        let mut array = env.create_array_with_length(items.len())?;
+        for (idx, item) in items.into_iter().enumerate() {
+          array.set_element(idx as u32, item.into_js(env)?)?;
+        }
+        Ok(array.into_unknown())
+      }
+      FormValue::Map(entries) => {
+        let mut object = env.create_object()?;
+        for (name, value) in entries {
+          object.set_named_property(&name, value.into_js(env)?)?;
+        }
+        Ok(object.into_unknown())
+      }
+    }
+  }
+}
+
+/// Returns `true` if the `Content-Type` names an urlencoded form.
+pub fn is_form(content_type: &str) -> bool {
+  content_type.split(';').next().is_some_and(|mime| {
+    mime
+      .trim()
+      .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+  })
+}
+
+/// Extracts the `charset` parameter of a `Content-Type` header value.
+pub fn charset(content_type: &str) -> Option<&str> {
+  content_type.split(';').skip(1).find_map(|param| {
+    let (name, value) = param.split_once('=')?;
+    name
+      .trim()
+      .eq_ignore_ascii_case("charset")
+      .then(|| value.trim().trim_matches('"'))
+  })
+}
+
+/// Parses an urlencoded body, decoding it with the given charset.
+///
+/// Unknown charsets fall back to UTF-8, as browsers do.
+pub fn parse(body: &[u8], charset: Option<&str>) -> FormValue {
+  let encoding = charset
+    .and_then(|label| Encoding::for_label(label.as_bytes()))
+    .unwrap_or(UTF_8);
+  let mut form = Entries::new();
+
+  for pair in body.split(|b| *b == b'&').filter(|pair| !pair.is_empty()) {
+    let (name, value) = match pair.iter().position(|b| *b == b'=') {
+      Some(idx) => (&pair[..idx], &pair[idx + 1..]),
+      None => (pair, &[][..]),
+    };
+    let name = decode(name, encoding);
+    let value = decode(value, encoding);
+
+    insert(&mut form, &key_path(&name), value);
+  }
+
+  FormValue::Map(form)
+}
+
+fn decode(raw: &[u8], encoding: &'static Encoding) -> String {
+  let raw: Vec<u8> = raw
+    .iter()
+    .map(|b| if *b == b'+' { b' ' } else { *b })
+    .collect();
+  let bytes: Vec<u8> = percent_decode(&raw).collect();
+  let (text, _, _) = encoding.decode(&bytes);
+  text.into_owned()
+}
+
+/// Splits `a[b][]` into `["a", "b", ""]`.
+///
+/// Malformed brackets are kept verbatim as part of the key.
+fn key_path(name: &str) -> Vec<&str> {
+  let Some(open) = name.find('[').filter(|idx| *idx > 0) else {
+    return vec![name];
+  };
+
+  let mut path = vec![&name[..open]];
+  let mut rest = &name[open..];
+  while let Some(inner) = rest.strip_prefix('[') {
+    let Some(close) = inner.find(']') else {
+      return vec![name];
+    };
+    path.push(&inner[..close]);
+    rest = &inner[close + 1..];
+  }
+
+  if rest.is_empty() {
+    path
+  } else {
+    vec![name]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{charset, is_form, key_path, parse, FormValue};
+
+  fn text(value: &str) -> FormValue {
+    FormValue::Text(value.into())
+  }
+
+  fn map(entries: &[(&str, FormValue)]) -> FormValue {
+    FormValue::Map(
+      entries
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn splits_bracketed_keys() {
+    assert_eq!(key_path("a"), ["a"]);
+    assert_eq!(key_path("a[b][]"), ["a", "b", ""]);
+    assert_eq!(key_path("a[]"), ["a", ""]);
+  }
+
+  #[test]
+  fn keeps_malformed_brackets_in_the_key() {
+    assert_eq!(key_path("[a]"), ["[a]"]);
+    assert_eq!(key_path("a[b"), ["a[b"]);
+    assert_eq!(key_path("a[b]c"), ["a[b]c"]);
+    assert_eq!(key_path("a]b"), ["a]b"]);
+  }
+
+  #[test]
+  fn expands_nested_keys() {
+    assert_eq!(
+      parse(b"a[b]=c&a[d][]=1&a[d][]=2", None),
+      map(&[(
+        "a",
+        map(&[
+          ("b", text("c")),
+          ("d", FormValue::List(vec![text("1"), text("2")]))
+        ])
+      )])
+    );
+    assert_eq!(
+      parse(b"x[][y]=1", None),
+      map(&[("x", FormValue::List(vec![map(&[("y", text("1"))])]))])
+    );
+  }
+
+  #[test]
+  fn collects_repeated_keys_into_lists() {
+    assert_eq!(
+      parse(b"x=1&x=2&x=3", None),
+      map(&[("x", FormValue::List(vec![text("1"), text("2"), text("3")]))])
+    );
+    assert_eq!(
+      parse(b"x=1&x[]=2", None),
+      map(&[("x", FormValue::List(vec![text("1"), text("2")]))])
+    );
+  }
+
+  #[test]
+  fn decodes_plus_and_percent_escapes() {
+    assert_eq!(
+      parse(b"q=a+b%2Bc&%C3%A9=%E2%9C%93", None),
+      map(&[("q", text("a b+c")), ("é", text("✓"))])
+    );
+  }
+
+  #[test]
+  fn skips_empty_pairs_and_defaults_missing_values() {
+    assert_eq!(
+      parse(b"&&flag&a=&=b", None),
+      map(&[("flag", text("")), ("a", text("")), ("", text("b"))])
+    );
+  }
+
+  #[test]
+  fn decodes_with_the_given_charset() {
+    assert_eq!(
+      parse(b"n=%E9", Some("iso-8859-1")),
+      map(&[("n", text("é"))])
+    );
+    assert_eq!(
+      parse(b"n=%C3%A9", Some("nonsense")),
+      map(&[("n", text("é"))])
+    );
+  }
+
+  #[test]
+  fn reads_the_content_type() {
+    assert!(is_form("Application/X-WWW-Form-Urlencoded; charset=utf-8"));
+    assert!(!is_form("multipart/form-data"));
+    assert_eq!(
+      charset("application/x-www-form-urlencoded; Charset=\"latin1\""),
+      Some("latin1")
+    );
+    assert_eq!(charset("application/x-www-form-urlencoded"), None);
+  }
+}
@@ -0,0 +1,384 @@
+//! Authentication guards for static responses and routes.
+//!
+//! A response registered with `app.getStatic()`, or a route registered with
+//! a `guard` option, can require credentials checked in Rust, an
+//! `authorize` callback's approval, or both. Guards run before the response
+//! is served, so a request without credentials gets its `401` without the
+//! asset leaving memory, or the body being read and the handler called:
+//!
+//! - no provider authenticating the request: `401`, with `WWW-Authenticate`,
+//! - `authorize` answering anything but `true`, throwing or rejecting: `403`.
+//!
+//! Credentials are checked by [`AuthProvider`]s, tried in order until one
+//! authenticates the request, so common schemes never cross into JS. The
+//! built-in ones take a bearer JWT, where only HS256 is accepted, `exp` and
+//! `nbf` are enforced when present, and `iss` and `aud` when configured, or
+//! an API key looked up by its SHA-256 digest. Other providers can be
+//! added from Rust; `authorize` is the fallback for everything else, and
+//! sees the identity a provider found.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use napi::bindgen_prelude::FromNapiValue;
+use napi::threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction};
+use napi::{sys, Env, Error, JsFunction, JsUnknown, Result, ValueType};
+use ring::{digest, hmac};
+use serde_json::{json, Value};
+
+use crate::base64::{self, Alphabet};
+use crate::dispatch::Returned;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Request, Response, ResponseBuilder};
+
+/// The `guard` of a static response or a route.
+#[napi(object)]
+pub struct GuardOptions {
+  /// Accepts an `Authorization: Bearer` JWT signed with this HS256 secret.
+  pub jwt: Option<JwtOptions>,
+  /// Accepts one of these API keys.
+  pub api_key: Option<ApiKeyOptions>,
+  /// Called with the request's method, path, `Authorization` header and
+  /// the identity a provider found: a token's claims, or `{ sub }` for an
+  /// API key. Only answering `true` lets the request through.
+  #[napi(ts_type = "(attempt: import('./types').GuardAttempt) => boolean | Promise<boolean>")]
+  pub authorize: Option<JsFunction>,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct JwtOptions {
+  pub secret: String,
+  /// Required value of the `iss` claim.
+  pub issuer: Option<String>,
+  /// Required value, or member, of the `aud` claim.
+  pub audience: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ApiKeyOptions {
+  /// Header carrying the key. Defaults to `x-api-key`.
+  pub header: Option<String>,
+  /// The accepted keys, each mapped to the `sub` it identifies.
+  pub keys: HashMap<String, String>,
+}
+
+/// Checks the credentials of requests, without calling into JS.
+pub trait AuthProvider: Send + Sync {
+  fn authenticate(&self, req: &Request) -> Auth;
+}
+
+/// What an [`AuthProvider`] made of a request.
+pub enum Auth {
+  /// Authenticated, with the identity handed to `authorize` as `claims`.
+  Identity(Value),
+  /// The request has no credentials for the provider. The challenge is
+  /// sent with the `401` if no other provider authenticates it.
+  Missing(HeaderValue),
+  /// The request has credentials for the provider, but they are invalid.
+  /// Its challenge is preferred over those of missing credentials.
+  Invalid(HeaderValue),
+}
+
+/// What `authorize` is asked about.
+struct Attempt {
+  method: String,
+  path: String,
+  authorization: Option<String>,
+  claims: Option<Value>,
+}
+
+pub struct Guard {
+  providers: Vec<Box<dyn AuthProvider>>,
+  authorize: Option<ThreadsafeFunction<Attempt, ErrorStrategy::Fatal>>,
+}
+
+struct Jwt {
+  key: hmac::Key,
+  issuer: Option<String>,
+  audience: Option<String>,
+}
+
+struct ApiKeys {
+  header: HeaderName,
+  /// The `sub` of each key, by the key's SHA-256 digest, so looking a key
+  /// up takes no longer for a near miss.
+  keys: HashMap<Vec<u8>, String>,
+}
+
+impl Guard {
+  pub fn new(env: &Env, options: GuardOptions) -> Result<Self> {
+    Guard::with_providers(env, options, Vec::new())
+  }
+
+  /// A guard also trying `providers`, after those set in `options`.
+  pub fn with_providers(
+    env: &Env,
+    options: GuardOptions,
+    extra: Vec<Box<dyn AuthProvider>>,
+  ) -> Result<Self> {
+    let invalid = |message: String| ActixError::new(ErrorCode::InvalidOption, message);
+
+    let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+    if let Some(jwt) = options.jwt {
+      if jwt.secret.is_empty() {
+        return Err(invalid("`jwt` needs a non-empty `secret`".to_owned()).into_js(env));
+      }
+      providers.push(Box::new(Jwt {
+        key: hmac::Key::new(hmac::HMAC_SHA256, jwt.secret.as_bytes()),
+        issuer: jwt.issuer,
+        audience: jwt.audience,
+      }));
+    }
+    if let Some(api_key) = options.api_key {
+      providers.push(Box::new(
+        ApiKeys::new(api_key).map_err(|err| err.into_js(env))?,
+      ));
+    }
+    providers.extend(extra);
+
+    if providers.is_empty() && options.authorize.is_none() {
+      return Err(
+        invalid("a guard needs `jwt`, `apiKey`, `authorize` or a provider".to_owned()).into_js(env),
+      );
+    }
+
+    let authorize = options
+      .authorize
+      .map(|authorize| {
+        let mut authorize =
+          authorize.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Attempt>| {
+            let Attempt {
+              method,
+              path,
+              authorization,
+              claims,
+            } = ctx.value;
+            let mut attempt = ctx.env.create_object()?;
+            attempt.set_named_property("method", ctx.env.create_string(&method)?)?;
+            attempt.set_named_property("path", ctx.env.create_string(&path)?)?;
+            match authorization {
+              Some(authorization) => attempt
+                .set_named_property("authorization", ctx.env.create_string(&authorization)?)?,
+              None => attempt.set_named_property("authorization", ctx.env.get_null()?)?,
+            }
+            match claims {
+              Some(claims) => {
+                attempt.set_named_property("claims", ctx.env.to_js_value(&claims)?)?
+              }
+              None => attempt.set_named_property("claims", ctx.env.get_null()?)?,
+            }
+            Ok(vec![attempt])
+          })?;
+        // Guards alone shouldn't keep the process alive.
+        authorize.unref(env)?;
+        Ok::<_, Error>(authorize)
+      })
+      .transpose()?;
+
+    Ok(Guard {
+      providers,
+      authorize,
+    })
+  }
+
+  /// The response denying the request, or `None` if it may be served.
+  pub async fn check(&self, req: &Request) -> Option<Response> {
+    let authorization = req
+      .headers()
+      .get(header::AUTHORIZATION)
+      .and_then(|value| value.to_str().ok());
+
+    let mut claims = None;
+    let mut challenge = None;
+    let mut invalid = false;
+    for provider in &self.providers {
+      match provider.authenticate(req) {
+        Auth::Identity(identity) => {
+          claims = Some(identity);
+          break;
+        }
+        Auth::Invalid(found) if !invalid => {
+          challenge = Some(found);
+          invalid = true;
+        }
+        Auth::Missing(found) => {
+          challenge.get_or_insert(found);
+        }
+        Auth::Invalid(_) => {}
+      }
+    }
+    if let Some(challenge) = challenge.filter(|_| claims.is_none()) {
+      return Some(unauthorized(challenge));
+    }
+
+    let Some(authorize) = &self.authorize else {
+      return None;
+    };
+    let attempt = Attempt {
+      method: req.method().as_str().to_owned(),
+      path: req.uri().path().to_owned(),
+      authorization: authorization.map(str::to_owned),
+      claims,
+    };
+    let answer = match authorize.call_async::<Returned<Allowed>>(attempt).await {
+      Ok(returned) => returned.settle().await,
+      Err(err) => Err(err),
+    };
+
+    match answer {
+      Ok(Allowed(true)) => None,
+      Ok(Allowed(false)) => Some(status(StatusCode::FORBIDDEN)),
+      Err(err) => {
+        eprintln!("Guard failed: {err}");
+        Some(status(StatusCode::FORBIDDEN))
+      }
+    }
+  }
+}
+
+impl AuthProvider for Jwt {
+  fn authenticate(&self, req: &Request) -> Auth {
+    let token = req
+      .headers()
+      .get(header::AUTHORIZATION)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| {
+        let (scheme, token) = value.split_once(' ')?;
+        scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+      });
+    let Some(token) = token else {
+      return Auth::Missing(HeaderValue::from_static("Bearer"));
+    };
+    match self.verify(token) {
+      Some(claims) => Auth::Identity(claims),
+      None => Auth::Invalid(HeaderValue::from_static("Bearer error=\"invalid_token\"")),
+    }
+  }
+}
+
+impl Jwt {
+  /// The claims of `token`, if it is a valid HS256 JWT for this key and
+  /// is currently usable.
+  fn verify(&self, token: &str) -> Option<Value> {
+    let (signed, signature) = token.rsplit_once('.')?;
+    let (header, claims) = signed.split_once('.')?;
+
+    let header: Value = serde_json::from_slice(&base64::decode(header, Alphabet::UrlSafe)?).ok()?;
+    if header.get("alg")?.as_str()? != "HS256" {
+      return None;
+    }
+    hmac::verify(
+      &self.key,
+      signed.as_bytes(),
+      &base64::decode(signature, Alphabet::UrlSafe)?,
+    )
+    .ok()?;
+
+    let claims: Value = serde_json::from_slice(&base64::decode(claims, Alphabet::UrlSafe)?).ok()?;
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0.0, |now| now.as_secs_f64());
+    let time = |name: &str| claims.get(name).map(Value::as_f64);
+    if time("exp").is_some_and(|exp| exp.is_none_or(|exp| now >= exp)) {
+      return None;
+    }
+    if time("nbf").is_some_and(|nbf| nbf.is_none_or(|nbf| now < nbf)) {
+      return None;
+    }
+
+    if let Some(issuer) = &self.issuer {
+      if claims.get("iss")?.as_str()? != issuer {
+        return None;
+      }
+    }
+    if let Some(audience) = &self.audience {
+      let matches = match claims.get("aud")? {
+        Value::String(aud) => aud == audience,
+        Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+        _ => false,
+      };
+      if !matches {
+        return None;
+      }
+    }
+
+    Some(claims)
+  }
+}
+
+impl ApiKeys {
+  fn new(options: ApiKeyOptions) -> std::result::Result<Self, ActixError> {
+    let header = match options.header {
+      Some(name) => HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+        ActixError::new(
+          ErrorCode::InvalidHeader,
+          format!("invalid header name `{name}`"),
+        )
+      })?,
+      None => HeaderName::from_static("x-api-key"),
+    };
+    let keys = options
+      .keys
+      .into_iter()
+      .map(|(key, sub)| {
+        (
+          digest::digest(&digest::SHA256, key.as_bytes())
+            .as_ref()
+            .to_vec(),
+          sub,
+        )
+      })
+      .collect();
+    Ok(ApiKeys { header, keys })
+  }
+}
+
+impl AuthProvider for ApiKeys {
+  fn authenticate(&self, req: &Request) -> Auth {
+    // No scheme is registered for API keys; this one names the header.
+    let challenge = || {
+      HeaderValue::try_from(format!("ApiKey header=\"{}\"", self.header))
+        .unwrap_or(HeaderValue::from_static("ApiKey"))
+    };
+    let Some(key) = req.headers().get(&self.header) else {
+      return Auth::Missing(challenge());
+    };
+    let digest = digest::digest(&digest::SHA256, key.as_bytes());
+    match self.keys.get(digest.as_ref()) {
+      Some(sub) => Auth::Identity(json!({ "sub": sub })),
+      None => Auth::Invalid(challenge()),
+    }
+  }
+}
+
+/// Whether `authorize` lets a request through. Only `true` does.
+struct Allowed(bool);
+
+impl FromNapiValue for Allowed {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let unknown = JsUnknown::from_napi_value(env, value)?;
+    match unknown.get_type()? {
+      ValueType::Boolean => bool::from_napi_value(env, value).map(Allowed),
+      _ => Ok(Allowed(false)),
+    }
+  }
+}
+
+fn unauthorized(challenge: HeaderValue) -> Response {
+  ResponseBuilder::new()
+    .status(StatusCode::UNAUTHORIZED)
+    .header(header::WWW_AUTHENTICATE, challenge)
+    .body(Body::empty())
+    .unwrap()
+}
+
+fn status(status: StatusCode) -> Response {
+  ResponseBuilder::new()
+    .status(status)
+    .body(Body::empty())
+    .unwrap()
+}
@@ -0,0 +1,79 @@
+//! Lazy `req.headers`.
+//!
+//! Handing headers to the WHATWG `Request` costs two JS strings per header
+//! on every request, though most handlers read one or two of them. With
+//! `app.lazyHeaders(true)`, `req.headers` is a native object reading the
+//! request's header map instead, and a value only becomes a JS string when
+//! it is asked for.
+
+use std::sync::Arc;
+
+use hyper::header::HeaderMap;
+use napi::bindgen_prelude::ClassInstance;
+use napi::{Env, JsObject, Property, Result};
+
+use crate::MyRequest;
+
+/// Marks requests whose headers are handed to JS lazily.
+#[derive(Clone, Copy, Debug)]
+pub struct LazyHeaders;
+
+/// The headers of a request, read on demand. Names are case-insensitive,
+/// and repeated headers are joined with `, `, as `Headers` does.
+#[napi]
+pub struct RequestHeaders {
+  req: Arc<MyRequest>,
+}
+
+#[napi]
+impl RequestHeaders {
+  #[napi]
+  pub fn get(&self, name: String) -> Option<String> {
+    join(self.headers(), &name)
+  }
+
+  #[napi]
+  pub fn has(&self, name: String) -> bool {
+    self.headers().contains_key(name.as_str())
+  }
+
+  /// Every header as a `[name, value]` pair, sorted by name.
+  #[napi(ts_return_type = "Array<[string, string]>")]
+  pub fn entries(&self) -> Vec<Vec<String>> {
+    let headers = self.headers();
+    let mut names: Vec<_> = headers.keys().map(|name| name.as_str()).collect();
+    names.sort_unstable();
+
+    names
+      .into_iter()
+      .filter_map(|name| Some(vec![name.to_owned(), join(headers, name)?]))
+      .collect()
+  }
+}
+
+impl RequestHeaders {
+  fn headers(&self) -> &HeaderMap {
+    self.req.headers()
+  }
+
+  /// Replaces `req.headers` of a JS request with a lazy view of `req`'s
+  /// headers.
+  pub fn attach(env: Env, jsreq: &mut JsObject, req: Arc<MyRequest>) -> Result<()> {
+    let headers: ClassInstance<RequestHeaders> = RequestHeaders { req }.into_instance(env)?;
+    // `headers` is a getter of `Request.prototype`, so assigning it would
+    // be ignored; an own property shadows it.
+    jsreq.define_properties(&[Property::new("headers")?.with_value(&headers)])
+  }
+}
+
+/// The values of a header joined with `, `, or `None` if it is absent.
+fn join(headers: &HeaderMap, name: &str) -> Option<String> {
+  let mut values = headers.get_all(name).iter();
+  let first = String::from_utf8_lossy(values.next()?.as_bytes()).into_owned();
+
+  Some(values.fold(first, |mut joined, value| {
+    joined.push_str(", ");
+    joined.push_str(&String::from_utf8_lossy(value.as_bytes()));
+    joined
+  }))
+}
@@ -0,0 +1,165 @@
+//! Liveness and readiness probes, set with `app.health()`.
+//!
+//! Both are answered before routing. The liveness probe never touches the
+//! JS thread, so it keeps answering while the event loop is busy. The
+//! readiness probe reports a draining server as not ready and can ask a JS
+//! callback, which counts as failing if it doesn't answer in time.
+
+use std::time::Duration;
+
+use hyper::header::{self, HeaderValue};
+use hyper::{Method, StatusCode};
+use napi::bindgen_prelude::FromNapiValue;
+use napi::threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction};
+use napi::{sys, Env, Error, JsFunction, JsUnknown, Result, ValueType};
+
+use crate::dispatch::Returned;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Drain, Response, ResponseBuilder};
+
+/// How long the readiness callback gets by default, in milliseconds.
+const DEFAULT_TIMEOUT: u32 = 1000;
+
+/// Configures the probes with `app.health()`.
+#[napi(object)]
+pub struct HealthOptions {
+  /// Path of the liveness probe, such as `/healthz`.
+  pub liveness: Option<String>,
+  /// Path of the readiness probe, such as `/readyz`.
+  pub readiness: Option<String>,
+  /// Called by the readiness probe. Returning or resolving to `false`,
+  /// throwing or rejecting marks the app as not ready.
+  #[napi(ts_type = "() => boolean | void | Promise<boolean | void>")]
+  pub check: Option<JsFunction>,
+  /// Milliseconds `check` gets to answer. Defaults to 1000.
+  pub timeout: Option<u32>,
+}
+
+/// Which probe a request is for.
+#[derive(Clone, Copy, Debug)]
+pub enum Probe {
+  Liveness,
+  Readiness,
+}
+
+pub struct Health {
+  liveness: Option<String>,
+  readiness: Option<String>,
+  check: Option<ThreadsafeFunction<(), ErrorStrategy::Fatal>>,
+  timeout: Duration,
+}
+
+impl Health {
+  pub fn new(env: &Env, options: HealthOptions) -> Result<Self> {
+    for path in options.liveness.iter().chain(&options.readiness) {
+      if !path.starts_with('/') {
+        return Err(
+          ActixError::new(
+            ErrorCode::InvalidOption,
+            format!("health check path `{path}` must start with `/`"),
+          )
+          .into_js(env),
+        );
+      }
+    }
+
+    let check = options
+      .check
+      .map(|check| {
+        let mut check = check.create_threadsafe_function(0, |_: ThreadSafeCallContext<()>| {
+          Ok(Vec::<JsUnknown>::new())
+        })?;
+        // Probes alone shouldn't keep the process alive.
+        check.unref(env)?;
+        Ok::<_, Error>(check)
+      })
+      .transpose()?;
+
+    Ok(Health {
+      liveness: options.liveness,
+      readiness: options.readiness,
+      check,
+      timeout: Duration::from_millis(options.timeout.unwrap_or(DEFAULT_TIMEOUT).into()),
+    })
+  }
+
+  /// The probe a request is for, if any. Only `GET` and `HEAD` requests are
+  /// probes; others go on to the router.
+  pub fn probe(&self, method: &Method, path: &str) -> Option<Probe> {
+    if method != Method::GET && method != Method::HEAD {
+      return None;
+    }
+
+    if self.liveness.as_deref() == Some(path) {
+      Some(Probe::Liveness)
+    } else if self.readiness.as_deref() == Some(path) {
+      Some(Probe::Readiness)
+    } else {
+      None
+    }
+  }
+
+  pub async fn respond(&self, probe: Probe, method: &Method, drain: &Drain) -> Response {
+    let ready = match probe {
+      Probe::Liveness => true,
+      Probe::Readiness => !drain.is_draining() && self.check().await,
+    };
+
+    let (status, body) = if ready {
+      (StatusCode::OK, "ok")
+    } else {
+      (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    };
+    let body = if method == Method::HEAD {
+      Body::empty()
+    } else {
+      Body::new(body)
+    };
+
+    ResponseBuilder::new()
+      .status(status)
+      .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+      .header(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))
+      .body(body)
+      .unwrap()
+  }
+
+  /// Asks the readiness callback, if there is one.
+  async fn check(&self) -> bool {
+    let Some(check) = &self.check else {
+      return true;
+    };
+
+    let answer = async {
+      match check.call_async::<Returned<Ready>>(()).await {
+        Ok(returned) => returned.settle().await,
+        Err(err) => Err(err),
+      }
+    };
+
+    match tokio::time::timeout(self.timeout, answer).await {
+      Ok(Ok(Ready(ready))) => ready,
+      Ok(Err(err)) => {
+        eprintln!("Readiness check failed: {err}");
+        false
+      }
+      Err(_) => {
+        eprintln!("Readiness check timed out after {:?}", self.timeout);
+        false
+      }
+    }
+  }
+}
+
+/// What the readiness callback answered: anything but `false` means ready.
+struct Ready(bool);
+
+impl FromNapiValue for Ready {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let unknown = JsUnknown::from_napi_value(env, value)?;
+    match unknown.get_type()? {
+      ValueType::Boolean => bool::from_napi_value(env, value).map(Ready),
+      _ => Ok(Ready(true)),
+    }
+  }
+}
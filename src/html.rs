@@ -0,0 +1,397 @@
+//! Streaming rewrites of HTML responses, for proxies and static paths.
+//!
+//! Markup can be inserted right before `</head>` and `</body>`, such as
+//! scripts or meta tags, and URL prefixes replaced in `href`, `src` and
+//! `action` attributes. Documents are rewritten chunk by chunk as they are
+//! sent: only a tag split across two chunks is held back until its end
+//! arrives, so whole documents are never buffered.
+//!
+//! This is a tag scanner, not an HTML parser. Comments are skipped, but
+//! tags are looked for in the text of `<script>` and `<style>` elements
+//! too. Compressed responses are left alone.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderMap};
+
+/// Tags longer than this are passed through unchanged rather than held back
+/// until they end.
+const MAX_TAG: usize = 16 * 1024;
+
+/// Attributes whose URLs `links` rewrites.
+const LINK_ATTRIBUTES: [&[u8]; 3] = [b"href", b"src", b"action"];
+
+/// Rewrites HTML responses as they are sent.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct HtmlRewriteOptions {
+  /// Markup inserted right before `</head>`.
+  pub head: Option<String>,
+  /// Markup inserted right before `</body>`.
+  pub body: Option<String>,
+  /// URL prefixes replaced in `href`, `src` and `action` attributes, such as
+  /// `{ "http://localhost:4000/": "/api/" }`. The longest one matching
+  /// applies.
+  pub links: Option<HashMap<String, String>>,
+}
+
+/// What HTML responses get rewritten with.
+pub struct HtmlRewrite {
+  head: Option<Bytes>,
+  body: Option<Bytes>,
+  /// Longest prefixes first.
+  links: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl HtmlRewrite {
+  pub fn new(options: HtmlRewriteOptions) -> Self {
+    let mut links: Vec<_> = options
+      .links
+      .into_iter()
+      .flatten()
+      .filter(|(from, _)| !from.is_empty())
+      .map(|(from, to)| (from.into_bytes(), to.into_bytes()))
+      .collect();
+    links.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+    HtmlRewrite {
+      head: options.head.map(Bytes::from),
+      body: options.body.map(Bytes::from),
+      links,
+    }
+  }
+
+  /// Whether a response with `headers` can be rewritten: HTML, and not
+  /// compressed.
+  pub fn applies(headers: &HeaderMap) -> bool {
+    let html = headers
+      .get(header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.split(';').next())
+      .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/html"));
+    let encoded = headers
+      .get(header::CONTENT_ENCODING)
+      .is_some_and(|value| value != "identity");
+    html && !encoded
+  }
+
+  /// Rewrites a whole document at once.
+  pub fn document(self: &Arc<Self>, html: &[u8]) -> Vec<u8> {
+    let mut rewriter = Rewriter::new(self.clone());
+    let mut out = rewriter.push(html);
+    out.extend(rewriter.finish());
+    out
+  }
+
+  /// Rewrites a body as it streams. The length changes, so the caller must
+  /// drop `Content-Length`.
+  pub fn stream(self: &Arc<Self>, body: hyper::Body) -> hyper::Body {
+    let rewriter = Rewriter::new(self.clone());
+    let chunks = futures::stream::unfold(Some((body, rewriter)), |state| async move {
+      let (mut body, mut rewriter) = state?;
+      match body.data().await {
+        Some(Ok(chunk)) => {
+          let chunk = Bytes::from(rewriter.push(&chunk));
+          Some((Ok(chunk), Some((body, rewriter))))
+        }
+        Some(Err(err)) => Some((Err(err), None)),
+        None => Some((Ok(Bytes::from(rewriter.finish())), None)),
+      }
+    });
+    // An empty chunk would end a chunked body early.
+    let chunks = futures::StreamExt::filter(chunks, |chunk| {
+      std::future::ready(!matches!(chunk, Ok(chunk) if chunk.is_empty()))
+    });
+    hyper::Body::wrap_stream(chunks)
+  }
+}
+
+/// The state of one document being rewritten.
+struct Rewriter {
+  rewrite: Arc<HtmlRewrite>,
+  /// The start of a tag whose end hasn't arrived yet.
+  pending: Vec<u8>,
+  head_done: bool,
+  body_done: bool,
+}
+
+impl Rewriter {
+  fn new(rewrite: Arc<HtmlRewrite>) -> Self {
+    Rewriter {
+      rewrite,
+      pending: Vec::new(),
+      head_done: false,
+      body_done: false,
+    }
+  }
+
+  /// Rewrites the next chunk, holding back a tag it ends in the middle of.
+  fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+    let buf = if self.pending.is_empty() {
+      chunk.to_vec()
+    } else {
+      let mut buf = std::mem::take(&mut self.pending);
+      buf.extend_from_slice(chunk);
+      buf
+    };
+
+    let mut out = Vec::with_capacity(buf.len());
+    let mut at = 0;
+    while let Some(lt) = buf[at..]
+      .iter()
+      .position(|&b| b == b'<')
+      .map(|idx| at + idx)
+    {
+      out.extend_from_slice(&buf[at..lt]);
+      let rest = &buf[lt..];
+      let Some(len) = tag_len(rest) else {
+        if rest.len() > MAX_TAG {
+          out.extend_from_slice(rest);
+        } else {
+          self.pending = rest.to_vec();
+        }
+        return out;
+      };
+      self.tag(&rest[..len], &mut out);
+      at = lt + len;
+    }
+    out.extend_from_slice(&buf[at..]);
+    out
+  }
+
+  /// What was held back, at the end of the document.
+  fn finish(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.pending)
+  }
+
+  fn tag(&mut self, tag: &[u8], out: &mut Vec<u8>) {
+    let closing = tag.get(1) == Some(&b'/');
+    let name = tag_name(&tag[if closing { 2 } else { 1 }..]);
+
+    if closing {
+      let insert = if name.eq_ignore_ascii_case(b"head") && !self.head_done {
+        self.head_done = true;
+        self.rewrite.head.as_ref()
+      } else if name.eq_ignore_ascii_case(b"body") && !self.body_done {
+        self.body_done = true;
+        self.rewrite.body.as_ref()
+      } else {
+        None
+      };
+      if let Some(insert) = insert {
+        out.extend_from_slice(insert);
+      }
+    } else if !self.rewrite.links.is_empty() && !name.is_empty() {
+      self.links(tag, out);
+      return;
+    }
+    out.extend_from_slice(tag);
+  }
+
+  /// Writes `tag` with the prefixes of its link attributes replaced.
+  fn links(&self, tag: &[u8], out: &mut Vec<u8>) {
+    let mut copied = 0;
+    for (start, end) in link_values(tag) {
+      let value = &tag[start..end];
+      let Some((from, to)) = self
+        .rewrite
+        .links
+        .iter()
+        .find(|(from, _)| value.starts_with(from))
+      else {
+        continue;
+      };
+      out.extend_from_slice(&tag[copied..start]);
+      out.extend_from_slice(to);
+      copied = start + from.len();
+    }
+    out.extend_from_slice(&tag[copied..]);
+  }
+}
+
+/// The length of the tag or comment `html` starts with, or `None` if it
+/// doesn't end in `html`. A `<` that can't start a tag is a tag of its own.
+fn tag_len(html: &[u8]) -> Option<usize> {
+  if html.starts_with(b"<!--") {
+    return html[4..]
+      .windows(3)
+      .position(|window| window == b"-->")
+      .map(|idx| idx + 7);
+  }
+  if "<!--".as_bytes().starts_with(html) {
+    return None;
+  }
+  match html.get(1) {
+    None => return None,
+    Some(b) if !(b.is_ascii_alphabetic() || matches!(b, b'/' | b'!' | b'?')) => return Some(1),
+    Some(_) => {}
+  }
+
+  let mut quote = None;
+  for (idx, &b) in html.iter().enumerate().skip(1) {
+    match (quote, b) {
+      (None, b'"' | b'\'') => quote = Some(b),
+      (None, b'>') => return Some(idx + 1),
+      (Some(q), _) if q == b => quote = None,
+      _ => {}
+    }
+  }
+  None
+}
+
+/// The name a tag starts with, after its `<` or `</`.
+fn tag_name(tag: &[u8]) -> &[u8] {
+  let end = tag
+    .iter()
+    .position(|b| !b.is_ascii_alphanumeric() && *b != b'-')
+    .unwrap_or(tag.len());
+  &tag[..end]
+}
+
+/// Where the values of the link attributes of `tag` are.
+fn link_values(tag: &[u8]) -> Vec<(usize, usize)> {
+  let mut values = Vec::new();
+  let mut at = 1 + tag_name(&tag[1..]).len();
+  let skip_space = |at: &mut usize| {
+    while tag.get(*at).is_some_and(u8::is_ascii_whitespace) {
+      *at += 1;
+    }
+  };
+
+  loop {
+    while tag
+      .get(at)
+      .is_some_and(|b| b.is_ascii_whitespace() || *b == b'/')
+    {
+      at += 1;
+    }
+    let name_start = at;
+    while tag
+      .get(at)
+      .is_some_and(|b| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/'))
+    {
+      at += 1;
+    }
+    if at == name_start {
+      return values;
+    }
+    let name = &tag[name_start..at];
+
+    skip_space(&mut at);
+    if tag.get(at) != Some(&b'=') {
+      continue;
+    }
+    at += 1;
+    skip_space(&mut at);
+
+    let (start, end) = match tag.get(at) {
+      Some(&quote @ (b'"' | b'\'')) => {
+        let start = at + 1;
+        let len = tag[start..]
+          .iter()
+          .position(|&b| b == quote)
+          .unwrap_or(tag.len() - start);
+        at = start + len + 1;
+        (start, start + len)
+      }
+      _ => {
+        let start = at;
+        while tag
+          .get(at)
+          .is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>')
+        {
+          at += 1;
+        }
+        (start, at)
+      }
+    };
+    if LINK_ATTRIBUTES
+      .iter()
+      .any(|attribute| name.eq_ignore_ascii_case(attribute))
+    {
+      values.push((start, end));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::{HtmlRewrite, HtmlRewriteOptions, Rewriter};
+
+  fn rewrite() -> Arc<HtmlRewrite> {
+    Arc::new(HtmlRewrite::new(HtmlRewriteOptions {
+      head: Some("<script src=\"/a.js\"></script>".into()),
+      body: Some("<footer></footer>".into()),
+      links: Some(
+        [
+          ("http://up/".to_owned(), "/api/".to_owned()),
+          ("http://up/static/".to_owned(), "/s/".to_owned()),
+        ]
+        .into(),
+      ),
+    }))
+  }
+
+  fn chunked(chunks: &[&str]) -> String {
+    let mut rewriter = Rewriter::new(rewrite());
+    let mut out = Vec::new();
+    for chunk in chunks {
+      out.extend(rewriter.push(chunk.as_bytes()));
+    }
+    out.extend(rewriter.finish());
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn inserts_before_closing_head_and_body() {
+    assert_eq!(
+      chunked(&["<html><head><title>x</title></HEAD><body>hi</body></html>"]),
+      "<html><head><title>x</title><script src=\"/a.js\"></script></HEAD>\
+       <body>hi<footer></footer></body></html>"
+    );
+  }
+
+  #[test]
+  fn holds_back_tags_split_across_chunks() {
+    assert_eq!(
+      chunked(&["<p>a</he", "ad><a hr", "ef='http://up/x'>b</a>"]),
+      "<p>a<script src=\"/a.js\"></script></head><a href='/api/x'>b</a>"
+    );
+  }
+
+  #[test]
+  fn rewrites_the_longest_prefix_of_link_attributes() {
+    assert_eq!(
+      chunked(&[
+        "<img SRC=http://up/static/i.png alt=\"http://up/\">",
+        "<form action = \"http://up/f\" data-x=\"a>b\">",
+      ]),
+      "<img SRC=/s/i.png alt=\"http://up/\"><form action = \"/api/f\" data-x=\"a>b\">"
+    );
+  }
+
+  #[test]
+  fn skips_comments_and_stray_angle_brackets() {
+    assert_eq!(
+      chunked(&["1 < 2 <!-- </head> <a href=\"http://up/\"> -", "-></head>"]),
+      "1 < 2 <!-- </head> <a href=\"http://up/\"> --><script src=\"/a.js\"></script></head>"
+    );
+  }
+
+  #[test]
+  fn inserts_only_once() {
+    assert_eq!(
+      chunked(&["</head></head>"]),
+      "<script src=\"/a.js\"></script></head></head>"
+    );
+  }
+
+  #[test]
+  fn passes_unterminated_tags_through_at_the_end() {
+    assert_eq!(chunked(&["a<b", "c"]), "a<bc");
+  }
+}
@@ -210,6 +210,14 @@ impl HttpBody for Body {
   ) -> Poll<Result<Option<hyper::HeaderMap>, Self::Error>> {
     Pin::new(&mut self.0).poll_trailers(cx)
   }
+
+  fn is_end_stream(&self) -> bool {
+    HttpBody::is_end_stream(&self.0)
+  }
+
+  fn size_hint(&self) -> hyper::body::SizeHint {
+    HttpBody::size_hint(&self.0)
+  }
 }
 
 struct ReaderStream<R> {
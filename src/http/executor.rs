@@ -0,0 +1,97 @@
+//! Where a [`Server`](super::Server) runs its connections.
+
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::Notify;
+
+/// Spawns connection tasks, either on the runtime the server runs on or on
+/// a multi-threaded runtime of its own.
+pub(crate) struct Executor {
+  runtime: Option<Runtime>,
+  tasks: Arc<Tasks>,
+}
+
+/// Connection tasks still running.
+#[derive(Default)]
+struct Tasks {
+  open: AtomicUsize,
+  idle: Notify,
+}
+
+impl Executor {
+  /// With `max_workers`, connections run on a runtime of their own with
+  /// that many worker threads. `worker_keep_alive` is how long its idle
+  /// blocking threads are kept around.
+  pub fn new(max_workers: Option<usize>, worker_keep_alive: Option<Duration>) -> io::Result<Self> {
+    let runtime = match max_workers {
+      Some(workers) => {
+        let mut builder = Builder::new_multi_thread();
+        builder
+          .worker_threads(workers.max(1))
+          .thread_name("node-actix-worker")
+          .enable_all();
+        if let Some(keep_alive) = worker_keep_alive {
+          builder.thread_keep_alive(keep_alive);
+        }
+        Some(builder.build()?)
+      }
+      None => None,
+    };
+
+    Ok(Executor {
+      runtime,
+      tasks: Arc::default(),
+    })
+  }
+
+  pub fn spawn<F>(&self, task: F)
+  where
+    F: Future<Output = ()> + Send + 'static,
+  {
+    self.tasks.open.fetch_add(1, Ordering::AcqRel);
+    let tasks = self.tasks.clone();
+    let task = async move {
+      task.await;
+      if tasks.open.fetch_sub(1, Ordering::AcqRel) == 1 {
+        tasks.idle.notify_waiters();
+      }
+    };
+    match &self.runtime {
+      Some(runtime) => {
+        runtime.spawn(task);
+      }
+      None => {
+        tokio::task::spawn(task);
+      }
+    }
+  }
+
+  /// Waits for the connections to close, then stops the server's own
+  /// runtime, if any.
+  pub async fn shutdown(mut self) {
+    loop {
+      let idle = self.tasks.idle.notified();
+      if self.tasks.open.load(Ordering::Acquire) == 0 {
+        break;
+      }
+      idle.await;
+    }
+    // Dropping a runtime blocks, which isn't allowed in async code.
+    if let Some(runtime) = self.runtime.take() {
+      runtime.shutdown_background();
+    }
+  }
+}
+
+impl Drop for Executor {
+  fn drop(&mut self) {
+    if let Some(runtime) = self.runtime.take() {
+      runtime.shutdown_background();
+    }
+  }
+}
@@ -0,0 +1,193 @@
+//! Detection of idle keep-alive connections.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// When a connection last read or wrote, and how many of its requests are
+/// still being handled.
+#[derive(Debug)]
+pub(crate) struct Activity {
+  opened: Instant,
+  /// Milliseconds since `opened`.
+  last: AtomicU64,
+  in_flight: AtomicUsize,
+  /// Notified when the last request in flight is done.
+  settled: Notify,
+  requests: AtomicU64,
+  writes: AtomicU64,
+  flushes: AtomicU64,
+}
+
+impl Activity {
+  pub fn new() -> Arc<Self> {
+    Arc::new(Activity {
+      opened: Instant::now(),
+      last: AtomicU64::new(0),
+      in_flight: AtomicUsize::new(0),
+      settled: Notify::new(),
+      requests: AtomicU64::new(0),
+      writes: AtomicU64::new(0),
+      flushes: AtomicU64::new(0),
+    })
+  }
+
+  fn touch(&self) {
+    let elapsed = self.opened.elapsed().as_millis() as u64;
+    self.last.store(elapsed, Ordering::Relaxed);
+  }
+
+  /// When the connection becomes idle if nothing happens until then.
+  pub fn deadline(&self, timeout: Duration) -> Instant {
+    self.opened + Duration::from_millis(self.last.load(Ordering::Relaxed)) + timeout
+  }
+
+  /// Returns `true` if no request is being handled and the connection has
+  /// been quiet for `timeout`.
+  pub fn is_idle(&self, timeout: Duration) -> bool {
+    self.in_flight.load(Ordering::Acquire) == 0 && self.is_quiet(timeout)
+  }
+
+  /// Resolves once no request has been handled and nothing read or written
+  /// for `timeout`. The timer only runs while no request is in flight, so
+  /// slow handlers don't wake the caller over and over.
+  pub async fn idle(&self, timeout: Duration) {
+    loop {
+      let settled = self.settled.notified();
+      if self.in_flight.load(Ordering::Acquire) > 0 {
+        settled.await;
+        continue;
+      }
+      tokio::time::sleep_until(self.deadline(timeout)).await;
+      if self.is_idle(timeout) {
+        return;
+      }
+    }
+  }
+
+  /// Returns `true` if the connection has neither read nor written anything
+  /// for `timeout`, requests in flight or not.
+  pub fn is_quiet(&self, timeout: Duration) -> bool {
+    self.deadline(timeout) <= Instant::now()
+  }
+
+  /// How long the connection has been open.
+  pub fn age(&self) -> Duration {
+    self.opened.elapsed()
+  }
+
+  /// How many requests the connection has received.
+  pub fn requests(&self) -> u64 {
+    self.requests.load(Ordering::Relaxed)
+  }
+
+  /// How many writes and flushes went to the transport.
+  pub fn io(&self) -> (u64, u64) {
+    (
+      self.writes.load(Ordering::Relaxed),
+      self.flushes.load(Ordering::Relaxed),
+    )
+  }
+
+  /// Marks a request as being handled until the guard is dropped.
+  pub fn request(self: &Arc<Self>) -> InFlight {
+    self.requests.fetch_add(1, Ordering::Relaxed);
+    self.in_flight.fetch_add(1, Ordering::AcqRel);
+    self.touch();
+    InFlight(self.clone())
+  }
+}
+
+/// Counts a request as in flight while alive.
+pub(crate) struct InFlight(Arc<Activity>);
+
+impl Drop for InFlight {
+  fn drop(&mut self) {
+    self.0.touch();
+    if self.0.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+      self.0.settled.notify_waiters();
+    }
+  }
+}
+
+/// A transport that records its reads and writes in an [`Activity`].
+pub(crate) struct Tracked<T> {
+  io: T,
+  activity: Arc<Activity>,
+}
+
+impl<T> Tracked<T> {
+  pub fn new(io: T, activity: Arc<Activity>) -> Self {
+    Tracked { io, activity }
+  }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<T> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let filled = buf.filled().len();
+    let poll = Pin::new(&mut self.io).poll_read(cx, buf);
+    if buf.filled().len() > filled {
+      self.activity.touch();
+    }
+    poll
+  }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<T> {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    let poll = Pin::new(&mut self.io).poll_write(cx, buf);
+    if let Poll::Ready(Ok(n)) = poll {
+      if n > 0 {
+        self.activity.touch();
+        self.activity.writes.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    poll
+  }
+
+  fn poll_write_vectored(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    bufs: &[io::IoSlice<'_>],
+  ) -> Poll<io::Result<usize>> {
+    let poll = Pin::new(&mut self.io).poll_write_vectored(cx, bufs);
+    if let Poll::Ready(Ok(n)) = poll {
+      if n > 0 {
+        self.activity.touch();
+        self.activity.writes.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    poll
+  }
+
+  fn is_write_vectored(&self) -> bool {
+    self.io.is_write_vectored()
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let poll = Pin::new(&mut self.io).poll_flush(cx);
+    if poll.is_ready() {
+      self.activity.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+    poll
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.io).poll_shutdown(cx)
+  }
+}
@@ -0,0 +1,389 @@
+//! Sockets a [`Server`](super::Server) can accept connections on.
+
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Where a server listens.
+#[derive(Clone, Debug)]
+pub enum Bind {
+  Tcp(SocketAddr),
+  /// A Unix domain socket, with the permissions to give the socket file.
+  Unix {
+    path: PathBuf,
+    mode: Option<u32>,
+  },
+  /// A socket already listening, inherited from the parent process, such as
+  /// one passed by systemd socket activation. Unix only.
+  Fd(i32),
+}
+
+/// First descriptor passed by systemd socket activation.
+const LISTEN_FDS_START: i32 = 3;
+
+/// Descriptors a server took ownership of and still listens on, so none is
+/// taken twice.
+static TAKEN: Mutex<Option<HashSet<i32>>> = Mutex::new(None);
+
+/// The sockets passed by systemd socket activation, as announced by the
+/// `LISTEN_PID` and `LISTEN_FDS` environment variables.
+///
+/// The variables are read by the first call only, which the sockets are
+/// handed out to. They are left in the environment, since changing it isn't
+/// safe once other threads run; child processes ignore them anyway, as
+/// `LISTEN_PID` names this process.
+pub fn listen_fds() -> Vec<i32> {
+  static HANDED_OUT: AtomicBool = AtomicBool::new(false);
+  if HANDED_OUT.swap(true, Ordering::AcqRel) {
+    return Vec::new();
+  }
+  let pid = std::env::var("LISTEN_PID").ok();
+  let count = std::env::var("LISTEN_FDS").ok();
+
+  let for_us = pid
+    .and_then(|pid| pid.parse::<u32>().ok())
+    .is_some_and(|pid| pid == std::process::id());
+  if !for_us {
+    return Vec::new();
+  }
+
+  let count = count
+    .and_then(|count| count.parse::<i32>().ok())
+    .unwrap_or(0);
+
+  (LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count)).collect()
+}
+
+/// Keeps a descriptor in [`TAKEN`] until dropped, after the listener that
+/// closes it.
+pub(crate) struct Taken(i32);
+
+impl Drop for Taken {
+  fn drop(&mut self) {
+    if let Some(taken) = TAKEN.lock().unwrap().as_mut() {
+      taken.remove(&self.0);
+    }
+  }
+}
+
+/// Takes ownership of `fd`, once it is known to be a listening stream
+/// socket no other server took.
+#[cfg(unix)]
+fn take_fd(fd: i32) -> io::Result<(Socket, Taken)> {
+  use std::os::unix::io::{BorrowedFd, FromRawFd};
+
+  let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+  let mut taken = TAKEN.lock().unwrap();
+  let taken = taken.get_or_insert_with(HashSet::new);
+  if taken.contains(&fd) {
+    return Err(invalid(format!(
+      "file descriptor {fd} is already being listened on"
+    )));
+  }
+
+  {
+    // SAFETY: the descriptor is only borrowed to inspect it; it is either
+    // open, or the calls below fail with `EBADF`.
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&borrowed);
+    let is_stream = socket
+      .r#type()
+      .map_err(|err| invalid(format!("file descriptor {fd} is not a socket: {err}")))?
+      == Type::STREAM;
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+    let is_stream = is_stream && socket.is_listener()?;
+    if !is_stream {
+      return Err(invalid(format!(
+        "file descriptor {fd} is not a listening stream socket"
+      )));
+    }
+  }
+
+  taken.insert(fd);
+  // SAFETY: the descriptor is a socket nothing else in the process took,
+  // handed over to be listened on.
+  let socket = unsafe { Socket::from_raw_fd(fd) };
+  Ok((socket, Taken(fd)))
+}
+
+/// Low-level options of TCP sockets. Unset options keep the system's
+/// defaults.
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+  /// Lets several sockets bind the same address, with the kernel spreading
+  /// connections between them. Only available on Unix.
+  pub reuse_port: Option<bool>,
+  /// Allows binding an address still in `TIME_WAIT`. Enabled by default on
+  /// Unix.
+  pub reuse_address: Option<bool>,
+  /// Disables Nagle's algorithm on accepted connections.
+  pub nodelay: Option<bool>,
+  /// Maximum number of connections waiting to be accepted. Defaults to
+  /// 1024.
+  pub backlog: Option<u32>,
+  /// Sends TCP keep-alive probes on accepted connections.
+  pub keepalive: Option<Keepalive>,
+}
+
+/// When TCP keep-alive probes are sent.
+#[derive(Clone, Debug, Default)]
+pub struct Keepalive {
+  /// How long a connection stays idle before the first probe.
+  pub idle: Option<Duration>,
+  /// Time between probes.
+  pub interval: Option<Duration>,
+  /// Unanswered probes before the connection is dropped.
+  pub retries: Option<u32>,
+}
+
+impl SocketOptions {
+  fn bind(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    // Matches what `TcpListener::bind` does.
+    socket.set_reuse_address(self.reuse_address.unwrap_or(cfg!(unix)))?;
+    if let Some(reuse_port) = self.reuse_port {
+      #[cfg(unix)]
+      socket.set_reuse_port(reuse_port)?;
+      #[cfg(not(unix))]
+      if reuse_port {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          "SO_REUSEPORT is not supported on this platform",
+        ));
+      }
+    }
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    let backlog = self.backlog.unwrap_or(1024).min(i32::MAX as u32);
+    socket.listen(backlog as i32)?;
+
+    TcpListener::from_std(socket.into())
+  }
+
+  fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+    if let Some(nodelay) = self.nodelay {
+      stream.set_nodelay(nodelay)?;
+    }
+
+    if let Some(keepalive) = &self.keepalive {
+      let mut params = TcpKeepalive::new();
+      if let Some(idle) = keepalive.idle {
+        params = params.with_time(idle);
+      }
+      if let Some(interval) = keepalive.interval {
+        params = params.with_interval(interval);
+      }
+      #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "macos",
+      ))]
+      if let Some(retries) = keepalive.retries {
+        params = params.with_retries(retries);
+      }
+      SockRef::from(stream).set_tcp_keepalive(&params)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// A bound socket.
+pub(crate) enum Listener {
+  Tcp {
+    listener: TcpListener,
+    options: SocketOptions,
+    /// The descriptor the socket was taken from, if inherited.
+    _taken: Option<Taken>,
+  },
+  #[cfg(unix)]
+  Unix(UnixSocket),
+}
+
+impl Listener {
+  pub async fn bind(bind: &Bind, options: &SocketOptions) -> io::Result<Listener> {
+    match bind {
+      Bind::Tcp(addr) => options.bind(*addr).map(|listener| Listener::Tcp {
+        listener,
+        options: options.clone(),
+        _taken: None,
+      }),
+      #[cfg(unix)]
+      Bind::Unix { path, mode } => {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // A socket file left behind by a previous run would make the bind
+        // fail. Anything that isn't a socket is left alone.
+        if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+          std::fs::remove_file(path)?;
+        }
+
+        let socket = UnixSocket {
+          listener: UnixListener::bind(path)?,
+          path: Some(path.clone()),
+          _taken: None,
+        };
+        if let Some(mode) = mode {
+          std::fs::set_permissions(path, std::fs::Permissions::from_mode(*mode))?;
+        }
+
+        Ok(Listener::Unix(socket))
+      }
+      #[cfg(unix)]
+      Bind::Fd(fd) => {
+        let (socket, taken) = take_fd(*fd)?;
+        socket.set_nonblocking(true)?;
+
+        if socket.local_addr()?.domain() == Domain::UNIX {
+          // The file belongs to whoever created the socket, so it is not
+          // removed when the server stops.
+          Ok(Listener::Unix(UnixSocket {
+            listener: UnixListener::from_std(socket.into())?,
+            path: None,
+            _taken: Some(taken),
+          }))
+        } else {
+          let listener = TcpListener::from_std(socket.into())?;
+          Ok(Listener::Tcp {
+            listener,
+            options: options.clone(),
+            _taken: Some(taken),
+          })
+        }
+      }
+      #[cfg(not(unix))]
+      Bind::Unix { .. } => Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported on this platform",
+      )),
+      #[cfg(not(unix))]
+      Bind::Fd(_) => Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "inheriting sockets is not supported on this platform",
+      )),
+    }
+  }
+
+  /// The address the socket is bound to, unless it is a Unix socket.
+  pub fn local_addr(&self) -> Option<SocketAddr> {
+    match self {
+      Listener::Tcp { listener, .. } => listener.local_addr().ok(),
+      #[cfg(unix)]
+      Listener::Unix(_) => None,
+    }
+  }
+
+  /// Accepts a connection, along with the address of the peer if it has
+  /// one.
+  pub async fn accept(&self) -> io::Result<(Stream, Option<SocketAddr>)> {
+    match self {
+      Listener::Tcp {
+        listener, options, ..
+      } => {
+        let (stream, addr) = listener.accept().await?;
+        if let Err(err) = options.configure(&stream) {
+          eprintln!("Error configuring connection: {err}");
+        }
+        Ok((Stream::Tcp(stream), Some(addr)))
+      }
+      #[cfg(unix)]
+      Listener::Unix(socket) => {
+        let (stream, _) = socket.listener.accept().await?;
+        Ok((Stream::Unix(stream), None))
+      }
+    }
+  }
+}
+
+/// A listening Unix socket, whose file is removed once it is dropped.
+#[cfg(unix)]
+pub(crate) struct UnixSocket {
+  listener: UnixListener,
+  /// The socket file, if it was created by this server.
+  path: Option<PathBuf>,
+  /// The descriptor the socket was taken from, if inherited.
+  _taken: Option<Taken>,
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+  fn drop(&mut self) {
+    if let Some(path) = &self.path {
+      let _ = std::fs::remove_file(path);
+    }
+  }
+}
+
+/// An accepted connection.
+pub(crate) enum Stream {
+  Tcp(TcpStream),
+  #[cfg(unix)]
+  Unix(UnixStream),
+}
+
+macro_rules! delegate {
+  ($self:ident, $stream:ident => $body:expr) => {
+    match $self.get_mut() {
+      Stream::Tcp($stream) => $body,
+      #[cfg(unix)]
+      Stream::Unix($stream) => $body,
+    }
+  };
+}
+
+impl AsyncRead for Stream {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    delegate!(self, stream => Pin::new(stream).poll_read(cx, buf))
+  }
+}
+
+impl AsyncWrite for Stream {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    delegate!(self, stream => Pin::new(stream).poll_write(cx, buf))
+  }
+
+  fn poll_write_vectored(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    bufs: &[io::IoSlice<'_>],
+  ) -> Poll<io::Result<usize>> {
+    delegate!(self, stream => Pin::new(stream).poll_write_vectored(cx, bufs))
+  }
+
+  fn is_write_vectored(&self) -> bool {
+    match self {
+      Stream::Tcp(stream) => stream.is_write_vectored(),
+      #[cfg(unix)]
+      Stream::Unix(stream) => stream.is_write_vectored(),
+    }
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    delegate!(self, stream => Pin::new(stream).poll_flush(cx))
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    delegate!(self, stream => Pin::new(stream).poll_shutdown(cx))
+  }
+}
@@ -0,0 +1,166 @@
+//! Histograms describing how connections are used, to tune keep-alive.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the buckets counting requests served per connection.
+const REQUEST_BOUNDS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 1000.0];
+
+/// Upper bounds, in seconds, of the buckets counting connection lifetimes.
+const DURATION_BOUNDS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+/// A histogram with fixed buckets that can be updated concurrently.
+#[derive(Debug)]
+pub struct Histogram {
+  bounds: &'static [f64],
+  /// One count per bound, plus one for values above the last bound.
+  counts: Vec<AtomicU64>,
+  /// Sum of the observed values, as the bits of an `f64`.
+  sum: AtomicU64,
+}
+
+impl Histogram {
+  fn new(bounds: &'static [f64]) -> Self {
+    Histogram {
+      bounds,
+      counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+      sum: AtomicU64::new(0),
+    }
+  }
+
+  fn observe(&self, value: f64) {
+    let bucket = self.bounds.partition_point(|bound| *bound < value);
+    self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+
+    let _ = self
+      .sum
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+        Some((f64::from_bits(sum) + value).to_bits())
+      });
+  }
+
+  /// Upper bound of each bucket but the last, which has none.
+  pub fn bounds(&self) -> &[f64] {
+    self.bounds
+  }
+
+  /// How many values fell in each bucket, not cumulative.
+  pub fn counts(&self) -> Vec<u64> {
+    self
+      .counts
+      .iter()
+      .map(|count| count.load(Ordering::Relaxed))
+      .collect()
+  }
+
+  pub fn sum(&self) -> f64 {
+    f64::from_bits(self.sum.load(Ordering::Relaxed))
+  }
+}
+
+/// Counters describing how request bodies arrive and how responses are
+/// written, to tune buffer sizes.
+#[derive(Debug, Default)]
+pub struct TransferCounters {
+  chunked_requests: AtomicU64,
+  sized_requests: AtomicU64,
+  chunks: AtomicU64,
+  chunk_bytes: AtomicU64,
+  writes: AtomicU64,
+  flushes: AtomicU64,
+}
+
+/// A copy of the [`TransferCounters`] at one point in time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Transfer {
+  /// Requests whose body was sent with `Transfer-Encoding: chunked`.
+  pub chunked_requests: u64,
+  /// Requests whose body was sent with a `Content-Length`.
+  pub sized_requests: u64,
+  /// Pieces request bodies were received in.
+  pub chunks: u64,
+  pub chunk_bytes: u64,
+  /// Writes and flushes of closed connections.
+  pub writes: u64,
+  pub flushes: u64,
+}
+
+impl TransferCounters {
+  /// Counts a request body by how its length was announced.
+  pub fn request(&self, chunked: bool) {
+    let counter = if chunked {
+      &self.chunked_requests
+    } else {
+      &self.sized_requests
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Counts a piece of a request body as it is received.
+  pub fn chunk(&self, len: usize) {
+    self.chunks.fetch_add(1, Ordering::Relaxed);
+    self.chunk_bytes.fetch_add(len as u64, Ordering::Relaxed);
+  }
+
+  pub(crate) fn io(&self, writes: u64, flushes: u64) {
+    self.writes.fetch_add(writes, Ordering::Relaxed);
+    self.flushes.fetch_add(flushes, Ordering::Relaxed);
+  }
+
+  pub fn snapshot(&self) -> Transfer {
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    Transfer {
+      chunked_requests: load(&self.chunked_requests),
+      sized_requests: load(&self.sized_requests),
+      chunks: load(&self.chunks),
+      chunk_bytes: load(&self.chunk_bytes),
+      writes: load(&self.writes),
+      flushes: load(&self.flushes),
+    }
+  }
+}
+
+/// Requests served per connection and connection lifetimes, recorded when
+/// each connection closes.
+#[derive(Debug)]
+pub struct ConnectionMetrics {
+  requests: Histogram,
+  duration: Histogram,
+  transfer: TransferCounters,
+}
+
+impl ConnectionMetrics {
+  pub fn new() -> Self {
+    ConnectionMetrics {
+      requests: Histogram::new(REQUEST_BOUNDS),
+      duration: Histogram::new(DURATION_BOUNDS),
+      transfer: TransferCounters::default(),
+    }
+  }
+
+  pub(crate) fn record(&self, requests: u64, duration: Duration) {
+    self.requests.observe(requests as f64);
+    self.duration.observe(duration.as_secs_f64());
+  }
+
+  /// Requests served by each closed connection.
+  pub fn requests(&self) -> &Histogram {
+    &self.requests
+  }
+
+  /// How long each closed connection stayed open, in seconds.
+  pub fn duration(&self) -> &Histogram {
+    &self.duration
+  }
+
+  pub fn transfer(&self) -> &TransferCounters {
+    &self.transfer
+  }
+}
+
+impl Default for ConnectionMetrics {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -1,7 +1,16 @@
 pub mod body;
+mod executor;
 pub mod http;
+mod idle;
+mod listener;
+pub mod metrics;
 pub mod server;
+mod shared;
 
 pub use body::ResponseBuilder;
 pub use http::{Body, Request, Response};
-pub use server::{ConnectionInfo, Server};
+pub use listener::{listen_fds, Keepalive, SocketOptions};
+pub use metrics::ConnectionMetrics;
+pub use server::{
+  ConnectionInfo, Deadline, Drain, ResponseFuture, Saturation, Server, Service, MIN_BUF_SIZE,
+};
@@ -1,15 +1,20 @@
+use super::executor::Executor;
+use super::idle::{Activity, Tracked};
+use super::listener::{Bind, Listener, SocketOptions, Stream};
+use super::metrics::ConnectionMetrics;
+use super::shared::Membership;
 use super::{Body, Request, Response};
 
-use hyper::server::conn::Http;
+use futures::future::BoxFuture;
+use hyper::server::conn::{Connection, Http};
 use std::{
-  convert::Infallible,
-  future::Future,
-  io,
-  net::{SocketAddr, ToSocketAddrs},
-  pin::Pin,
-  sync::Arc,
+  convert::Infallible, future::Future, io, net::SocketAddr, path::PathBuf, sync::Arc,
+  time::Duration,
 };
-use tokio::net::TcpListener;
+use tokio::io::AsyncWriteExt;
+use tokio::net::ToSocketAddrs;
+use tokio::sync::{watch, Semaphore};
+use tokio::time::Instant;
 
 /// An HTTP server.
 ///
@@ -26,7 +31,7 @@ use tokio::net::TcpListener;
 ///
 /// See the [crate-level documentation](crate#how-does-it-work) for details.
 pub struct Server {
-  addr: SocketAddr,
+  addr: Bind,
   http1_keep_alive: Option<bool>,
   http1_half_close: Option<bool>,
   http1_max_buf_size: Option<usize>,
@@ -35,6 +40,161 @@ pub struct Server {
   http1_title_case_headers: Option<bool>,
   http1_preserve_header_case: Option<bool>,
   http1_only: Option<bool>,
+  http1_header_read_timeout: Option<Duration>,
+  http1_keep_alive_timeout: Option<Duration>,
+  request_timeout: Option<Duration>,
+  idle_timeout: Option<Duration>,
+  max_connections: Option<usize>,
+  saturation: Saturation,
+  max_workers: Option<usize>,
+  worker_keep_alive: Option<Duration>,
+  share_listener: bool,
+  drain: Drain,
+  metrics: Option<Arc<ConnectionMetrics>>,
+  socket: SocketOptions,
+  on_bind: Option<OnBind>,
+  on_connection: Option<OnConnection>,
+  on_panic: Option<OnPanic>,
+}
+
+/// Smallest buffer hyper accepts; it panics on anything lower.
+pub const MIN_BUF_SIZE: usize = 8192;
+
+/// Called with the address of the socket once it is bound.
+type OnBind = Box<dyn FnOnce(Option<SocketAddr>) + Send>;
+
+/// Called for every connection accepted.
+type OnConnection = Arc<dyn Fn(&ConnectionInfo) + Send + Sync>;
+
+/// Called with the message of a panic while handling a request.
+type OnPanic = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// What a [`Server`] does once it has as many open connections as allowed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Saturation {
+  /// Stops accepting; new connections wait in the listen backlog until a
+  /// connection closes.
+  #[default]
+  Pause,
+  /// Keeps accepting, but answers new connections with a `503` and closes
+  /// them right away.
+  Reject,
+}
+
+/// A handle that makes a running [`Server`] stop accepting connections.
+///
+/// Connections that are already open are closed once their in-flight
+/// response is sent, or right away by [`Drain::force`].
+#[derive(Clone, Debug)]
+pub struct Drain(Arc<watch::Sender<Stage>>);
+
+/// How far a [`Drain`] has gone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+  Serving,
+  Draining,
+  Closing,
+}
+
+impl Drain {
+  pub fn new() -> Drain {
+    Drain(Arc::new(watch::channel(Stage::Serving).0))
+  }
+
+  /// Stops the server from accepting new connections.
+  pub fn start(&self) {
+    self.0.send_if_modified(|stage| {
+      let started = *stage == Stage::Serving;
+      if started {
+        *stage = Stage::Draining;
+      }
+      started
+    });
+  }
+
+  /// Stops accepting and closes the open connections, whatever they are
+  /// doing.
+  pub fn force(&self) {
+    self.0.send_replace(Stage::Closing);
+  }
+
+  /// Returns `true` once [`Self::start`] has been called.
+  pub fn is_draining(&self) -> bool {
+    *self.0.borrow() >= Stage::Draining
+  }
+
+  /// Resolves once draining has started.
+  pub async fn wait(&self) {
+    self.reach(Stage::Draining).await;
+  }
+
+  /// Resolves once [`Self::force`] has been called.
+  pub async fn forced(&self) {
+    self.reach(Stage::Closing).await;
+  }
+
+  async fn reach(&self, stage: Stage) {
+    let mut current = self.0.subscribe();
+    // The sender lives as long as `self`, so this can't fail.
+    let _ = current.wait_for(|current| *current >= stage).await;
+  }
+}
+
+impl Default for Drain {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// When a request has to be answered by, kept in its extensions. A
+/// [`Service`] may move it, such as for a route that needs longer. Requests
+/// only have one with a request timeout, or when the service
+/// [needs one](Service::needs_deadline).
+#[derive(Clone, Debug)]
+pub struct Deadline {
+  arrived: Instant,
+  at: Arc<watch::Sender<Option<Instant>>>,
+}
+
+impl Deadline {
+  fn new(timeout: Option<Duration>) -> Self {
+    let arrived = Instant::now();
+    Deadline {
+      arrived,
+      at: Arc::new(watch::channel(timeout.map(|timeout| arrived + timeout)).0),
+    }
+  }
+
+  /// Gives the request `timeout` from when it arrived; `None` lifts the
+  /// deadline.
+  pub fn set(&self, timeout: Option<Duration>) {
+    self
+      .at
+      .send_replace(timeout.map(|timeout| self.arrived + timeout));
+  }
+
+  /// Runs `future` to completion, or until the deadline passes.
+  async fn enforce<F: Future>(&self, future: F) -> Option<F::Output> {
+    tokio::pin!(future);
+    let mut at = self.at.subscribe();
+
+    loop {
+      let current = *at.borrow_and_update();
+      let expired = async {
+        match current {
+          Some(at) => tokio::time::sleep_until(at).await,
+          None => std::future::pending().await,
+        }
+      };
+
+      tokio::select! {
+        output = &mut future => return Some(output),
+        _ = expired => return None,
+        // The sender lives as long as `self`, so this can't fail.
+        _ = at.changed() => {}
+      }
+    }
+  }
 }
 
 /// HTTP connection information.
@@ -50,14 +210,17 @@ impl ConnectionInfo {
   }
 }
 
+/// The future returned by [`Service::call`].
+pub type ResponseFuture = BoxFuture<'static, Response>;
+
 /// A service capable of responding to an HTTP request.
 ///
 /// This trait is automatically implemented for functions
 /// from a [`Request`] to a [`Response`], but implementing
-/// it manually allows for stateful services:
+/// it manually allows for stateful and asynchronous services:
 ///
 /// ```no_run
-/// use astra::{Request, Response, Server, Service, Body, ConnectionInfo};
+/// use astra::{Request, Response, ResponseFuture, Server, Service, Body, ConnectionInfo};
 /// use std::sync::Mutex;
 ///
 /// struct MyService {
@@ -65,11 +228,11 @@ impl ConnectionInfo {
 /// }
 ///
 /// impl Service for MyService {
-///     fn call(&self, request: Request, _info: ConnectionInfo) -> Response {
+///     fn call(&self, request: Request, _info: ConnectionInfo) -> ResponseFuture {
 ///         let mut count = self.count.lock().unwrap();
 ///         *count += 1;
 ///         println!("request #{}", *count);
-///         Response::new(Body::new("Hello world"))
+///         Box::pin(async { Response::new(Body::new("Hello world")) })
 ///     }
 /// }
 ///
@@ -82,7 +245,7 @@ impl ConnectionInfo {
 /// If your service is already cheaply cloneable, you can instead use `serve_clone` and avoid an extra `Arc` wrapper:
 ///
 /// ```no_run
-/// use astra::{Request, Response, Server, Service, Body, ConnectionInfo};
+/// use astra::{Request, Response, ResponseFuture, Server, Service, Body, ConnectionInfo};
 /// use std::sync::{Arc, Mutex};
 ///
 /// #[derive(Clone)]
@@ -91,11 +254,11 @@ impl ConnectionInfo {
 /// }
 ///
 /// impl Service for MyService {
-///     fn call(&self, request: Request, _info: ConnectionInfo) -> Response {
+///     fn call(&self, request: Request, _info: ConnectionInfo) -> ResponseFuture {
 ///         let mut count = self.count.lock().unwrap();
 ///         *count += 1;
 ///         println!("request #{}", *count);
-///         Response::new(Body::new("Hello world"))
+///         Box::pin(async { Response::new(Body::new("Hello world")) })
 ///     }
 /// }
 ///
@@ -104,15 +267,21 @@ impl ConnectionInfo {
 ///     .expect("failed to start server");
 /// ```
 pub trait Service: Send + 'static {
-  fn call(&self, request: Request, info: ConnectionInfo) -> Response;
+  fn call(&self, request: Request, info: ConnectionInfo) -> ResponseFuture;
+
+  /// Whether requests need a [`Deadline`] for the service to move, even
+  /// without a request timeout. Defaults to `false`.
+  fn needs_deadline(&self) -> bool {
+    false
+  }
 }
 
 impl<F> Service for F
 where
   F: Fn(Request, ConnectionInfo) -> Response + Send + 'static,
 {
-  fn call(&self, request: Request, info: ConnectionInfo) -> Response {
-    (self)(request, info)
+  fn call(&self, request: Request, info: ConnectionInfo) -> ResponseFuture {
+    Box::pin(std::future::ready((self)(request, info)))
   }
 }
 
@@ -120,28 +289,65 @@ impl<S> Service for Arc<S>
 where
   S: Service + Sync,
 {
-  fn call(&self, request: Request, info: ConnectionInfo) -> Response {
+  fn call(&self, request: Request, info: ConnectionInfo) -> ResponseFuture {
     (**self).call(request, info)
   }
+
+  fn needs_deadline(&self) -> bool {
+    (**self).needs_deadline()
+  }
 }
 
 impl Server {
-  /// Binds a server to the provided address.
+  /// Binds a server to the provided address, resolving host names without
+  /// blocking.
   ///
   /// ```no_run
   /// use astra::Server;
   /// use std::net::SocketAddr;
   ///
-  /// let server = Server::bind("localhost:3000");
-  /// let server = Server::bind(SocketAddr::from(([127, 0, 0, 1], 3000)));
+  /// # async fn run() -> std::io::Result<()> {
+  /// let server = Server::bind("localhost:3000").await?;
+  /// let server = Server::bind(SocketAddr::from(([127, 0, 0, 1], 3000))).await?;
+  /// # Ok(())
+  /// # }
   /// ```
   ///
-  /// # Panics
+  /// # Errors
+  ///
+  /// Fails if the address can't be resolved. Errors binding the socket
+  /// itself are returned when the server starts serving.
+  pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Server> {
+    let addr = tokio::net::lookup_host(addr).await?.next().ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "the address did not resolve to anything",
+      )
+    })?;
+
+    Ok(Server::new(Bind::Tcp(addr)))
+  }
+
+  /// Binds a server to a Unix domain socket.
   ///
-  /// This method will panic if binding to the address fails.
-  pub async fn bind(addr: impl ToSocketAddrs) -> Server {
-    let addr = addr.to_socket_addrs().unwrap().next().unwrap();
+  /// A socket file already at `path` is replaced, and the file is removed
+  /// again once the server stops.
+  pub fn bind_unix(path: impl Into<PathBuf>) -> Server {
+    Server::new(Bind::Unix {
+      path: path.into(),
+      mode: None,
+    })
+  }
 
+  /// Serves a socket that is already listening, such as one inherited
+  /// through systemd socket activation. The server takes ownership of the
+  /// descriptor and closes it once it stops. Serving fails if `fd` isn't a
+  /// listening stream socket, or if another server already took it.
+  pub fn from_fd(fd: i32) -> Server {
+    Server::new(Bind::Fd(fd))
+  }
+
+  fn new(addr: Bind) -> Server {
     Server {
       addr,
       http1_only: None,
@@ -152,6 +358,21 @@ impl Server {
       http1_pipeline_flush: None,
       http1_title_case_headers: None,
       http1_preserve_header_case: None,
+      http1_header_read_timeout: None,
+      http1_keep_alive_timeout: None,
+      request_timeout: None,
+      idle_timeout: None,
+      max_connections: None,
+      saturation: Saturation::default(),
+      max_workers: None,
+      worker_keep_alive: None,
+      share_listener: false,
+      drain: Drain::new(),
+      metrics: None,
+      socket: SocketOptions::default(),
+      on_bind: None,
+      on_connection: None,
+      on_panic: None,
     }
   }
 
@@ -176,39 +397,114 @@ impl Server {
 
   /// Like [`Self::serve`] but does not wrap `service` in an `Arc` and expects it to
   /// implement `Clone` and `Sync` internally.
-  pub async fn serve_clone<S>(self, service: S) -> io::Result<()>
+  pub async fn serve_clone<S>(mut self, service: S) -> io::Result<()>
+  where
+    S: Service + Clone + Sync,
+  {
+    let addr = match self.addr {
+      Bind::Tcp(addr) if self.share_listener && addr.port() != 0 => addr,
+      _ => return self.accept(service).await,
+    };
+
+    let membership = Membership::join(addr, service, self.drain.clone());
+    if membership.is_owner() {
+      return self.accept(membership.service()).await;
+    }
+
+    // Another server accepts for this one.
+    if let Some(on_bind) = self.on_bind.take() {
+      on_bind(Some(addr));
+    }
+    membership.wait().await;
+    Ok(())
+  }
+
+  async fn accept<S>(mut self, service: S) -> io::Result<()>
   where
     S: Service + Clone,
   {
-    // let executor = executor::Executor::new(self.max_workers, self.worker_keep_alive);
-    let http = Arc::new(Http::new());
-    // self.configure(&mut http);
+    let executor = Executor::new(self.max_workers, self.worker_keep_alive)?;
+    let mut http = Http::new();
+    self.configure(&mut http);
+    let http = Arc::new(http);
 
     // let reactor = Reactor::new().expect("failed to create reactor");
 
-    let addr = self.addr;
-    let server = TcpListener::bind(addr).await?;
+    let server = Listener::bind(&self.addr, &self.socket).await?;
+    if let Some(on_bind) = self.on_bind.take() {
+      on_bind(server.local_addr());
+    }
+
+    let limit = self
+      .max_connections
+      .map(|max| Arc::new(Semaphore::new(max)));
 
     loop {
-      let (conn, _) = server.accept().await?;
+      // When pausing, a slot is waited for before accepting at all.
+      let mut permit = match (&limit, self.saturation) {
+        (Some(limit), Saturation::Pause) => tokio::select! {
+          permit = limit.clone().acquire_owned() => permit.ok(),
+          _ = self.drain.wait() => break,
+        },
+        _ => None,
+      };
 
-      let http = http.clone();
-      let service = service.clone();
-      let info = ConnectionInfo {
-        peer_addr: conn.peer_addr().ok(),
+      let (conn, peer_addr) = tokio::select! {
+        accepted = server.accept() => accepted?,
+        _ = self.drain.wait() => break,
       };
 
-      tokio::task::spawn(async move {
-        if let Err(err) = http
-          .serve_connection(conn, service::HyperService(service, info))
-          .await
-        {
+      if let (Some(limit), None) = (&limit, &permit) {
+        match limit.clone().try_acquire_owned() {
+          Ok(acquired) => permit = Some(acquired),
+          Err(_) => {
+            tokio::task::spawn(reject(conn));
+            continue;
+          }
+        }
+      }
+
+      let http = http.clone();
+      let service = service.clone();
+      let info = ConnectionInfo { peer_addr };
+      if let Some(on_connection) = &self.on_connection {
+        on_connection(&info);
+      }
+      let keep_alive_timeout = self.http1_keep_alive_timeout;
+      let idle_timeout = self.idle_timeout;
+      let request_timeout = self.request_timeout;
+      let metrics = self.metrics.clone();
+      let drain = self.drain.clone();
+      let on_panic = self.on_panic.clone();
+
+      executor.spawn(async move {
+        let activity = Activity::new();
+        let conn = http.serve_connection(
+          Tracked::new(conn, activity.clone()),
+          service::HyperService {
+            service,
+            info,
+            activity: activity.clone(),
+            drain: drain.clone(),
+            timeout: request_timeout,
+            on_panic,
+          },
+        );
+
+        let res = drive(conn, &activity, keep_alive_timeout, idle_timeout, &drain).await;
+        if let Err(err) = res {
           eprintln!("Error on connection: {err}");
         };
+        if let Some(metrics) = metrics {
+          metrics.record(activity.requests(), activity.age());
+          let (writes, flushes) = activity.io();
+          metrics.transfer().io(writes, flushes);
+        }
+        drop(permit);
       });
     }
 
-    #[allow(unreachable_code)]
+    executor.shutdown().await;
     Ok(())
   }
 
@@ -220,17 +516,216 @@ impl Server {
     self
   }
 
-  /// Set the maximum buffer size.
+  /// Sets whether HTTP/1 connections support half-closures: when the client
+  /// shuts down its write side, the server still finishes the response.
+  ///
+  /// Default is `false`.
+  pub fn http1_half_close(mut self, val: bool) -> Self {
+    self.http1_half_close = Some(val);
+    self
+  }
+
+  /// Set the maximum buffer size. Sizes below [`MIN_BUF_SIZE`] are raised
+  /// to it.
   ///
   /// Default is ~ 400kb.
   pub fn http1_max_buf_size(mut self, val: usize) -> Self {
-    self.http1_max_buf_size = Some(val);
+    self.http1_max_buf_size = Some(val.max(MIN_BUF_SIZE));
+    self
+  }
+
+  /// Sets whether HTTP/1 connections should try to use vectored writes, or
+  /// always flatten headers and body into a single buffer before writing.
+  ///
+  /// Flattening corks small responses into one syscall at the cost of a
+  /// copy; vectored writes avoid the copy. Default is to pick automatically
+  /// based on whether the transport supports vectored writes.
+  pub fn http1_writev(mut self, val: bool) -> Self {
+    self.http1_writev = Some(val);
+    self
+  }
+
+  /// Aggregates flushes to better support pipelined responses.
+  ///
+  /// Default is `false`.
+  pub fn http1_pipeline_flush(mut self, val: bool) -> Self {
+    self.http1_pipeline_flush = Some(val);
+    self
+  }
+
+  /// Writes response header names in title case (`Content-Length`) instead
+  /// of lowercase.
+  ///
+  /// Default is `false`.
+  pub fn http1_title_case_headers(mut self, val: bool) -> Self {
+    self.http1_title_case_headers = Some(val);
+    self
+  }
+
+  /// Keeps the case of header names as they were received or set, for
+  /// clients that depend on it.
+  ///
+  /// Default is `false`.
+  pub fn http1_preserve_header_case(mut self, val: bool) -> Self {
+    self.http1_preserve_header_case = Some(val);
+    self
+  }
+
+  /// Only accepts HTTP/1 connections.
+  ///
+  /// Default is `false`.
+  pub fn http1_only(mut self, val: bool) -> Self {
+    self.http1_only = Some(val);
     self
   }
 
-  /// Get the local address of the bound socket
-  pub fn local_addr(&self) -> SocketAddr {
-    self.addr
+  /// Closes connections that don't finish sending the headers of a request
+  /// within this time, counted from when the server starts waiting for the
+  /// request. This also limits how long a keep-alive connection may sit idle.
+  ///
+  /// Default is no timeout.
+  pub fn http1_header_read_timeout(mut self, val: Duration) -> Self {
+    self.http1_header_read_timeout = Some(val);
+    self
+  }
+
+  /// Closes keep-alive connections that have neither read nor written
+  /// anything for this long while no request is being handled.
+  ///
+  /// Default is no timeout.
+  pub fn http1_keep_alive_timeout(mut self, val: Duration) -> Self {
+    self.http1_keep_alive_timeout = Some(val);
+    self
+  }
+
+  /// Answers `408 Request Timeout` and closes the connection when the
+  /// service takes longer than this to produce the response, reading of the
+  /// request body included. Streaming the response body afterwards is not
+  /// limited. The service can move the [`Deadline`] of a request.
+  ///
+  /// Default is no timeout.
+  pub fn request_timeout(mut self, val: Duration) -> Self {
+    self.request_timeout = Some(val);
+    self
+  }
+
+  /// Drops connections that have neither read nor written anything for this
+  /// long, even with a request in flight.
+  ///
+  /// Default is no timeout.
+  pub fn idle_timeout(mut self, val: Duration) -> Self {
+    self.idle_timeout = Some(val);
+    self
+  }
+
+  /// Runs connections on a multi-threaded runtime of the server's own, with
+  /// this many worker threads, instead of the runtime the server runs on.
+  /// Once the server stops accepting, it waits for those connections to
+  /// close before stopping the runtime.
+  ///
+  /// Default is to use the surrounding runtime.
+  pub fn max_workers(mut self, val: usize) -> Self {
+    self.max_workers = Some(val);
+    self
+  }
+
+  /// How long idle blocking threads of the runtime started by
+  /// [`Self::max_workers`] are kept.
+  ///
+  /// Default is tokio's, 10 seconds.
+  pub fn worker_keep_alive(mut self, val: Duration) -> Self {
+    self.worker_keep_alive = Some(val);
+    self
+  }
+
+  /// Shares the listening socket with other servers of the process asking
+  /// for the same address, such as ones started by other Node worker
+  /// threads. The first one binds the socket and hands requests to all of
+  /// them in turn; it keeps accepting until it stops. Ignored for port `0`
+  /// and Unix sockets.
+  ///
+  /// Default is `false`.
+  pub fn share_listener(mut self, val: bool) -> Self {
+    self.share_listener = val;
+    self
+  }
+
+  /// Limits how many connections are open at once.
+  ///
+  /// Default is no limit.
+  pub fn max_connections(mut self, val: usize) -> Self {
+    self.max_connections = Some(val);
+    self
+  }
+
+  /// Sets what happens to new connections once the limit set with
+  /// [`Self::max_connections`] is reached.
+  ///
+  /// Default is [`Saturation::Pause`].
+  pub fn saturation(mut self, val: Saturation) -> Self {
+    self.saturation = val;
+    self
+  }
+
+  /// Sets the handle used to stop accepting connections.
+  ///
+  /// Once draining starts, the serve future resolves as soon as the open
+  /// connections have closed.
+  pub fn drain(mut self, drain: Drain) -> Self {
+    self.drain = drain;
+    self
+  }
+
+  /// Records how each connection was used in `metrics` once it closes.
+  pub fn metrics(mut self, metrics: Arc<ConnectionMetrics>) -> Self {
+    self.metrics = Some(metrics);
+    self
+  }
+
+  /// Sets low-level options of the TCP socket and of accepted connections.
+  /// Ignored for Unix sockets.
+  pub fn socket_options(mut self, val: SocketOptions) -> Self {
+    self.socket = val;
+    self
+  }
+
+  /// Sets the permissions of the socket file of a server bound with
+  /// [`Self::bind_unix`], such as `0o660`. Ignored for TCP.
+  pub fn unix_mode(mut self, val: u32) -> Self {
+    if let Bind::Unix { mode, .. } = &mut self.addr {
+      *mode = Some(val);
+    }
+    self
+  }
+
+  /// Calls `f` once the socket is bound, with its address unless it is a
+  /// Unix socket. Unlike [`Self::local_addr`], this is the actual port when
+  /// binding port `0`.
+  pub fn on_bind(mut self, f: impl FnOnce(Option<SocketAddr>) + Send + 'static) -> Self {
+    self.on_bind = Some(Box::new(f));
+    self
+  }
+
+  /// Calls `f` for every connection accepted, before it is served.
+  pub fn on_connection(mut self, f: impl Fn(&ConnectionInfo) + Send + Sync + 'static) -> Self {
+    self.on_connection = Some(Arc::new(f));
+    self
+  }
+
+  /// Calls `f` with the message of every panic while handling a request,
+  /// which is answered with a `500`.
+  pub fn on_panic(mut self, f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+    self.on_panic = Some(Arc::new(f));
+    self
+  }
+
+  /// Get the local address the server binds to, unless it is a Unix socket
+  /// or an inherited one.
+  pub fn local_addr(&self) -> Option<SocketAddr> {
+    match self.addr {
+      Bind::Tcp(addr) => Some(addr),
+      Bind::Unix { .. } | Bind::Fd(_) => None,
+    }
   }
 
   fn configure<T>(&self, http: &mut Http<T>) {
@@ -259,6 +754,7 @@ impl Server {
             http1_title_case_headers,
             http1_preserve_header_case,
             http1_only,
+            http1_header_read_timeout,
         ],
         [
             max_buf_size => http1_max_buf_size,
@@ -268,49 +764,241 @@ impl Server {
   }
 }
 
+/// Answers a connection the server has no room for.
+async fn reject(mut conn: Stream) {
+  const RESPONSE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+  if conn.write_all(RESPONSE).await.is_ok() {
+    let _ = conn.shutdown().await;
+  }
+}
+
+/// Drives a connection until it closes.
+///
+/// The connection is shut down gracefully, right away if idle and after its
+/// in-flight response otherwise, once it has been idle for
+/// `keep_alive_timeout` or once draining starts. It is dropped, mid-response
+/// or not, once it has been quiet for `idle_timeout` or when the drain is
+/// forced.
+async fn drive<S>(
+  conn: Connection<Tracked<Stream>, service::HyperService<S>>,
+  activity: &Activity,
+  keep_alive_timeout: Option<Duration>,
+  idle_timeout: Option<Duration>,
+  drain: &Drain,
+) -> hyper::Result<()>
+where
+  S: Service + Clone,
+{
+  tokio::pin!(conn);
+  let mut closing = false;
+  let deadline = |timeout: Option<Duration>| async move {
+    match timeout {
+      Some(timeout) => tokio::time::sleep_until(activity.deadline(timeout)).await,
+      None => std::future::pending().await,
+    }
+  };
+  let idle = |timeout: Option<Duration>| async move {
+    match timeout {
+      Some(timeout) => activity.idle(timeout).await,
+      None => std::future::pending().await,
+    }
+  };
+
+  loop {
+    tokio::select! {
+      res = conn.as_mut() => return res,
+      _ = drain.forced() => return Ok(()),
+      _ = deadline(idle_timeout) => {
+        if idle_timeout.is_some_and(|timeout| activity.is_quiet(timeout)) {
+          return Ok(());
+        }
+      }
+      _ = drain.wait(), if !closing => {
+        conn.as_mut().graceful_shutdown();
+        closing = true;
+      }
+      _ = idle(keep_alive_timeout), if !closing => {
+        conn.as_mut().graceful_shutdown();
+        closing = true;
+      }
+    }
+  }
+}
+
 mod service {
+  use std::any::Any;
+  use std::panic::AssertUnwindSafe;
   use std::task::Context;
 
+  use futures::FutureExt;
+
   use super::*;
 
   type HyperRequest = hyper::Request<hyper::Body>;
 
-  pub struct HyperService<S>(pub S, pub ConnectionInfo);
+  pub struct HyperService<S> {
+    pub service: S,
+    pub info: ConnectionInfo,
+    pub activity: Arc<Activity>,
+    pub drain: Drain,
+    /// How long `service` gets to respond, unless it moves the [`Deadline`].
+    pub timeout: Option<Duration>,
+    pub on_panic: Option<OnPanic>,
+  }
 
   impl<S> hyper::service::Service<HyperRequest> for HyperService<S>
   where
     S: Service + Clone,
-    // <S as Deref>::Target: Unpin,
   {
     type Response = Response;
     type Error = Infallible;
-    type Future = Lazy<S>;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
 
     fn poll_ready(&mut self, _: &mut Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
       std::task::Poll::Ready(Ok(()))
     }
 
     fn call(&mut self, req: HyperRequest) -> Self::Future {
-      Lazy(self.0.clone(), Some(req), self.1.clone())
+      let (parts, body) = req.into_parts();
+      let mut req = Request::from_parts(parts, Body(body));
+      let deadline = (self.timeout.is_some() || self.service.needs_deadline())
+        .then(|| Deadline::new(self.timeout));
+      if let Some(deadline) = &deadline {
+        req.extensions_mut().insert(deadline.clone());
+      }
+
+      // A panic while handling one request must not take the whole
+      // connection down with it.
+      let in_flight = self.activity.request();
+      let res = AssertUnwindSafe(self.service.call(req, self.info.clone())).catch_unwind();
+      let drain = self.drain.clone();
+      let on_panic = self.on_panic.clone();
+      Box::pin(async move {
+        let res = match &deadline {
+          Some(deadline) => deadline.enforce(res).await,
+          None => Some(res.await),
+        };
+        drop(in_flight);
+        let mut res = match res {
+          Some(res) => res.unwrap_or_else(|panic| internal_error(&*panic, on_panic.as_deref())),
+          None => timed_out(),
+        };
+        // Tells the client to move to another server rather than reuse a
+        // connection about to close; hyper closes it after this response.
+        if drain.is_draining() {
+          res.headers_mut().insert(
+            hyper::header::CONNECTION,
+            hyper::header::HeaderValue::from_static("close"),
+          );
+        }
+        Ok(res)
+      })
     }
   }
 
-  pub struct Lazy<S>(S, Option<HyperRequest>, ConnectionInfo);
+  /// The response when the service took too long; the connection may still
+  /// be receiving the body, so it is not reused.
+  fn timed_out() -> Response {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = hyper::StatusCode::REQUEST_TIMEOUT;
+    res.headers_mut().insert(
+      hyper::header::CONNECTION,
+      hyper::header::HeaderValue::from_static("close"),
+    );
+    res
+  }
 
-  impl<S> Unpin for Lazy<S> {}
+  fn internal_error(
+    panic: &(dyn Any + Send),
+    on_panic: Option<&(dyn Fn(&str) + Send + Sync)>,
+  ) -> Response {
+    let message = panic
+      .downcast_ref::<&str>()
+      .copied()
+      .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+      .unwrap_or("unknown panic");
+    match on_panic {
+      Some(on_panic) => on_panic(message),
+      None => eprintln!("Panic while handling request: {message}"),
+    }
 
-  impl<S> Future for Lazy<S>
-  where
-    S: Service,
-  {
-    type Output = Result<Response, Infallible>;
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+    res
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use std::net::SocketAddr;
+  use std::time::Duration;
+
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio::net::TcpStream;
+  use tokio::sync::oneshot;
+
+  use super::{Body, ConnectionInfo, Drain, Request, Response, ResponseFuture, Server, Service};
 
-    fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> std::task::Poll<Self::Output> {
-      let (parts, body) = self.1.take().unwrap().into_parts();
-      let req = Request::from_parts(parts, Body(body));
+  /// Answers after `delay`, without reading or writing in between.
+  struct Slow {
+    delay: Duration,
+  }
 
-      let res = self.0.call(req, self.2.clone());
-      std::task::Poll::Ready(Ok(res))
+  impl Service for Slow {
+    fn call(&self, _: Request, _: ConnectionInfo) -> ResponseFuture {
+      let delay = self.delay;
+      Box::pin(async move {
+        tokio::time::sleep(delay).await;
+        Response::new(Body::new("done"))
+      })
     }
   }
+
+  /// CPU time used by the calling thread.
+  fn thread_cpu_time() -> Duration {
+    // SAFETY: `clock_gettime` only writes to `time`.
+    let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+  }
+
+  #[tokio::test(flavor = "current_thread")]
+  async fn keep_alive_timer_waits_for_slow_handlers() {
+    let drain = Drain::new();
+    let (bound, addr) = oneshot::channel::<Option<SocketAddr>>();
+    let server = Server::bind("127.0.0.1:0")
+      .await
+      .unwrap()
+      .http1_keep_alive_timeout(Duration::from_millis(20))
+      .drain(drain.clone())
+      .on_bind(move |addr| {
+        let _ = bound.send(addr);
+      });
+    let serving = tokio::spawn(server.serve(Slow {
+      delay: Duration::from_millis(400),
+    }));
+    let addr = addr.await.unwrap().unwrap();
+
+    // The server runs on this thread, so a spinning connection shows up in
+    // its CPU time.
+    let started = thread_cpu_time();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+      .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+      .await
+      .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let used = thread_cpu_time() - started;
+
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("done"), "{response}");
+    assert!(used < Duration::from_millis(100), "used {used:?} of CPU");
+
+    drain.start();
+    serving.await.unwrap().unwrap();
+  }
 }
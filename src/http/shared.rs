@@ -0,0 +1,142 @@
+//! Listeners shared between the servers of several Node worker threads.
+//!
+//! Worker threads each load the addon's JS bindings, but share its native
+//! side. The first server listening on an address with
+//! [`Server::share_listener`](super::Server::share_listener) binds it and
+//! owns the group. Servers listening on the same address afterwards join the
+//! group instead of binding, and the owner hands requests to every member in
+//! turn, so handlers run on several event loops.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+use hyper::StatusCode;
+
+use super::{Body, ConnectionInfo, Drain, Request, Response, ResponseFuture, Service};
+
+/// Groups by the address their owner is bound to.
+static GROUPS: Mutex<Vec<(SocketAddr, Arc<Group>)>> = Mutex::new(Vec::new());
+
+struct Member {
+  service: Box<dyn Service + Sync>,
+  /// Stops the member's server; a draining member gets no more requests.
+  drain: Drain,
+}
+
+struct Group {
+  members: ArcSwap<Vec<Arc<Member>>>,
+  next: AtomicUsize,
+  /// Started once the owner stops accepting.
+  closed: Drain,
+}
+
+/// A server's place in the group of its address. Leaving the group happens
+/// on drop; when the owner leaves, the group closes.
+pub(crate) struct Membership {
+  addr: SocketAddr,
+  group: Arc<Group>,
+  member: Arc<Member>,
+  owner: bool,
+}
+
+impl Membership {
+  /// Joins the group listening on `addr`, creating it if there is none.
+  pub fn join(addr: SocketAddr, service: impl Service + Sync, drain: Drain) -> Membership {
+    let member = Arc::new(Member {
+      service: Box::new(service),
+      drain,
+    });
+
+    let mut groups = GROUPS.lock().unwrap();
+    if let Some((_, group)) = groups.iter().find(|(bound, _)| *bound == addr) {
+      group.members.rcu(|members| {
+        let mut members = Vec::clone(members);
+        members.push(member.clone());
+        members
+      });
+
+      return Membership {
+        addr,
+        group: group.clone(),
+        member,
+        owner: false,
+      };
+    }
+
+    let group = Arc::new(Group {
+      members: ArcSwap::from_pointee(vec![member.clone()]),
+      next: AtomicUsize::new(0),
+      closed: Drain::new(),
+    });
+    groups.push((addr, group.clone()));
+
+    Membership {
+      addr,
+      group,
+      member,
+      owner: true,
+    }
+  }
+
+  /// Whether this server binds the address and accepts its connections.
+  pub fn is_owner(&self) -> bool {
+    self.owner
+  }
+
+  /// The service the owner serves connections with.
+  pub fn service(&self) -> GroupService {
+    GroupService(self.group.clone())
+  }
+
+  /// Resolves once the member stops, or the owner does.
+  pub async fn wait(&self) {
+    tokio::select! {
+      _ = self.member.drain.wait() => {}
+      _ = self.group.closed.wait() => {}
+    }
+  }
+}
+
+impl Drop for Membership {
+  fn drop(&mut self) {
+    let member = &self.member;
+    self.group.members.rcu(|members| {
+      let mut members = Vec::clone(members);
+      members.retain(|other| !Arc::ptr_eq(other, member));
+      members
+    });
+
+    if self.owner {
+      self.group.closed.start();
+      let mut groups = GROUPS.lock().unwrap();
+      groups.retain(|(addr, group)| *addr != self.addr || !Arc::ptr_eq(group, &self.group));
+    }
+  }
+}
+
+/// Hands requests to the members of a group in turn, skipping the ones
+/// that are draining.
+#[derive(Clone)]
+pub(crate) struct GroupService(Arc<Group>);
+
+impl Service for GroupService {
+  fn call(&self, request: Request, info: ConnectionInfo) -> ResponseFuture {
+    let members = self.0.members.load();
+    let start = self.0.next.fetch_add(1, Ordering::Relaxed);
+
+    let member = (0..members.len())
+      .map(|offset| &members[(start + offset) % members.len()])
+      .find(|member| !member.drain.is_draining());
+
+    match member {
+      Some(member) => member.service.call(request, info),
+      None => {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        Box::pin(std::future::ready(res))
+      }
+    }
+  }
+}
@@ -0,0 +1,76 @@
+//! Pre-parsing of `application/json` bodies.
+//!
+//! When enabled through [`ActixApp::parse_json`](crate::ActixApp::parse_json),
+//! JSON bodies are parsed on the runtime threads and handed to the handler as
+//! `req.jsonBody`, skipping the `Buffer` → string → `JSON.parse` round trip on
+//! the JS thread. Building with the `simd-json` feature swaps serde_json for
+//! simd-json.
+
+#[cfg(not(feature = "simd-json"))]
+pub type Value = serde_json::Value;
+#[cfg(feature = "simd-json")]
+pub type Value = simd_json::OwnedValue;
+
+/// Whether JSON bodies are parsed ahead of the handler.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseJson {
+  #[default]
+  Off,
+  /// Malformed bodies reach the handler without `req.jsonBody`.
+  Lenient,
+  /// Malformed bodies are rejected with `400`.
+  Strict,
+}
+
+/// A request body parsed ahead of the JS handler, stored in the request
+/// extensions.
+#[derive(Clone, Debug)]
+pub struct JsonBody(pub Value);
+
+/// Returns `true` if the `Content-Type` is `application/json` or uses the
+/// `+json` structured syntax suffix.
+pub fn is_json(content_type: &str) -> bool {
+  let Some(mime) = content_type.split(';').next() else {
+    return false;
+  };
+  let mime = mime.trim().to_ascii_lowercase();
+
+  mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+/// Why a JSON body could not be parsed.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+  pub message: String,
+  /// 1-based position of the error, when the parser reports it.
+  pub line: Option<usize>,
+  pub column: Option<usize>,
+}
+
+/// Parses a JSON body.
+///
+/// Unless strict parsing is enabled, malformed bodies are left for the
+/// handler, whose `req.json()` rejects as usual.
+#[cfg(not(feature = "simd-json"))]
+pub fn parse(body: &[u8]) -> Result<Value, ParseError> {
+  serde_json::from_slice(body).map_err(|err| ParseError {
+    message: err.to_string(),
+    line: Some(err.line()),
+    column: Some(err.column()),
+  })
+}
+
+/// Parses a JSON body.
+///
+/// Unless strict parsing is enabled, malformed bodies are left for the
+/// handler, whose `req.json()` rejects as usual.
+#[cfg(feature = "simd-json")]
+pub fn parse(body: &[u8]) -> Result<Value, ParseError> {
+  // simd-json parses in place, so it needs its own mutable copy.
+  let mut body = body.to_vec();
+  simd_json::to_owned_value(&mut body).map_err(|err| ParseError {
+    message: err.to_string(),
+    line: None,
+    column: None,
+  })
+}
@@ -1,20 +1,115 @@
 #![deny(clippy::all)]
 
+pub mod abort;
+mod access_log;
+pub mod archive;
+pub mod background;
+mod base64;
+mod batch;
+pub mod buffer;
+pub mod client;
+pub mod client_hints;
+mod dispatch;
+pub mod dns;
+pub mod error;
+pub mod events;
+pub mod expect;
+pub mod express;
+pub mod fast_path;
+pub mod form;
+pub mod guard;
+pub mod headers;
+pub mod health;
+pub mod html;
 pub mod http;
+pub mod json;
+pub mod metrics;
+pub mod normalize;
+pub mod options;
+mod outgoing;
+pub mod patch;
+pub mod proxy;
+pub mod quota;
+pub mod rejection;
+pub mod reload;
+pub mod request;
+pub mod response;
+pub mod routes;
+pub mod shadow;
+pub mod shutdown;
+pub mod signature;
+pub mod signed_url;
+pub mod status;
+pub mod stream;
+pub mod structured;
+pub mod tenant;
+pub mod timing;
+pub mod trace;
+pub mod trust;
+pub mod tus;
+mod vhost;
+pub mod warmup;
+pub mod writer;
 
-use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 // use astra as http;
-use futures::Future;
-use http::{Body, ConnectionInfo, Request, ResponseBuilder, Server};
-use hyper::service::Service;
-use hyper::StatusCode;
-use matchit::{MatchError, Router};
+use abort::Signals;
+use access_log::AccessLog;
+use arc_swap::ArcSwap;
+use archive::{Archive, ArchiveOptions, ArchiveSnapshot};
+use background::{Background, Settled};
+use batch::Batcher;
+use client::{Client, ClientOptions, FetchOptions};
+use client_hints::{ClientHints, ClientHintsOptions, Hints};
+use dispatch::Dispatcher;
+use error::{ActixError, ErrorCode};
+use events::{Event, EventKind, Events};
+use expect::{ExpectContinue, ExpectContinueOptions};
+use fast_path::{FastPaths, FixedResponse, StaticResponse};
+use futures::future;
+use guard::Guard;
+use headers::{LazyHeaders, RequestHeaders};
+use health::{Health, HealthOptions};
+use http::{ConnectionInfo, ConnectionMetrics, Drain, Server};
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::{HeaderMap, Method, Version};
+use json::{JsonBody, ParseJson};
+use metrics::ConnectionMetricsSnapshot;
 use napi::{
   bindgen_prelude::*,
-  threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction},
+  threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+  },
   JsFunction, JsObject,
 };
+use normalize::{NormalizationSnapshot, NormalizeOptions, Normalizer};
+use options::{ListenAddress, ListenerOptions, ServerOptions, UnixListenOptions, WriteOptions};
+use patch::Patch;
+use proxy::{Proxy, ProxyOptions, UpstreamOptions, UpstreamSnapshot};
+use quota::{Quota, QuotaOptions};
+use rejection::Formatter;
+use reload::{Snapshot, WatchOptions};
+use request::{NativeRequests, NodeRequest, SharedObject};
+use routes::{MatchOptions, Pipeline, RegisteredRoute, RouteOptions, RouterOptions, Routes};
+use shadow::Reporter;
+use shutdown::{Shutdown, ShutdownOptions};
+use signature::{ResponseSigningOptions, SignatureOptions, Signer, Verifier};
+use signed_url::{SignedUrlOptions, SignedUrls};
+use tenant::{Tenant, TenantOptions};
+use timing::RouteTiming;
+use tokio::sync::oneshot;
+use trace::{TraceContext, Tracer, TracingOptions};
+use trust::{Origin, TrustProxy};
+use tus::{Tus, TusOptions};
+use vhost::{Hosts, Site};
+use warmup::{Warmup, WarmupRequest, Warmups};
 
 #[macro_use]
 extern crate napi_derive;
@@ -26,8 +121,9 @@ pub fn actix() -> ActixApp {
   }
 }
 
-type MyRequest = Request;
-type RouterNode = ThreadsafeFunction<MyRequest, ErrorStrategy::Fatal>;
+type MyRequest = hyper::Request<Bytes>;
+type RouterNode = ThreadsafeFunction<Arc<MyRequest>, ErrorStrategy::Fatal>;
+type BatchNode = ThreadsafeFunction<Vec<Arc<MyRequest>>, ErrorStrategy::Fatal>;
 
 #[derive(Clone, Default)]
 #[napi]
@@ -35,100 +131,1503 @@ pub struct ActixApp {
   pub hostname: Option<String>,
   pub port: Option<u16>,
 
-  router: Router<RouterNode>,
+  /// Shared with the running server, so routes can change after `listen()`.
+  routes: Arc<ArcSwap<Routes>>,
+  fast_paths: Arc<ArcSwap<FastPaths>>,
+  hosts: Arc<ArcSwap<Hosts>>,
+  parse_json: ParseJson,
+  warmups: Warmups,
+  server: ServerOptions,
+  client: Client,
+  /// Shared with every server started by `listen()`.
+  metrics: Arc<ConnectionMetrics>,
+  /// Bound address of each listener of the last `listen()`, once bound.
+  addresses: Arc<ArcSwap<Vec<Option<SocketAddr>>>>,
+  events: Arc<Events>,
+  /// Promises passed to `req.waitUntil()`, shared with every server.
+  background: Arc<Background>,
+  /// Milliseconds a stopping server waits for them.
+  background_timeout: Option<u32>,
+  /// Set by `app.handleSignals()`.
+  shutdown: Option<Arc<Shutdown>>,
 }
 
 #[napi]
 impl ActixApp {
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn get(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::GET, path, handler, callback, options)
+  }
+
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn post(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::POST, path, handler, callback, options)
+  }
+
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn put(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::PUT, path, handler, callback, options)
+  }
+
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn patch(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::PATCH, path, handler, callback, options)
+  }
+
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn delete(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::DELETE, path, handler, callback, options)
+  }
+
+  /// Overrides the automatic `HEAD` handling, which runs the `GET` handler.
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn head(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::HEAD, path, handler, callback, options)
+  }
+
+  /// Overrides the automatic `OPTIONS` answer listing the allowed methods.
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "path: string, handler: import('./types').Handler<Req> | Array<import('./types').Handler<Req>>, callback?: import('./types').Handler<Req> | RouteOptions | undefined | null, options?: RouteOptions | undefined | null"
+  )]
+  pub fn options(
+    &mut self,
+    env: Env,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    self.route(env, Method::OPTIONS, path, handler, callback, options)
+  }
+
+  /// Removes the handlers for `method`, or every handler of the route when
+  /// no method is given. With `when`, only the handlers registered with
+  /// these conditions are removed. Returns whether anything was removed.
+  #[napi]
+  pub fn remove(
+    &mut self,
+    env: Env,
+    path: String,
+    method: Option<String>,
+    when: Option<MatchOptions>,
+  ) -> Result<bool> {
+    let method = method
+      .map(|method| parse_method(&method))
+      .transpose()
+      .map_err(|err| err.into_js(&env))?;
+
+    self.update_routes(|routes| {
+      routes
+        .remove(method.clone(), &path, when.clone())
+        .map_err(|err| err.into_js(&env))
+    })
+  }
+
+  /// Builds the URL of a named route. Parameters missing from the route
+  /// template are appended as the query string.
+  #[napi]
+  pub fn url_for(
+    &self,
+    env: Env,
+    name: String,
+    params: Option<HashMap<String, Either<String, f64>>>,
+  ) -> Result<String> {
+    let params = params
+      .into_iter()
+      .flatten()
+      .map(|(name, value)| match value {
+        Either::A(value) => (name, value),
+        Either::B(value) => (name, value.to_string()),
+      })
+      .collect();
+
+    self
+      .routes
+      .load()
+      .url_for(&name, params)
+      .map_err(|err| err.into_js(&env))
+  }
+
+  /// Configures how request paths are matched against routes.
+  ///
+  /// Call this before registering routes: with `caseSensitive: false` route
+  /// templates are lowercased as they are inserted.
+  #[napi]
+  pub fn router_options(&mut self, options: RouterOptions) -> Result<()> {
+    self.update_routes(|routes| {
+      routes.set_options(options);
+      Ok(())
+    })
+  }
+
+  /// Limits how many requests each API key can make to the app's routes
+  /// and proxies.
+  /// Passing `null` removes the quota.
+  #[napi]
+  pub fn quota(&mut self, env: Env, options: Option<QuotaOptions>) -> Result<()> {
+    let quota = options
+      .map(Quota::new)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_quota(quota.clone());
+      Ok(())
+    })
+  }
+
+  /// Rejects requests without a valid HMAC signature, looking the secret
+  /// of each key up with `lookup`. Passing `null` turns verification off.
+  ///
+  /// Routes registered with `unsigned: true` are not checked.
+  #[napi(
+    ts_args_type = "lookup: import('./types').KeyLookup | null, options?: SignatureOptions | undefined | null"
+  )]
+  pub fn verify_signatures(
+    &mut self,
+    env: Env,
+    lookup: Option<JsFunction>,
+    options: Option<SignatureOptions>,
+  ) -> Result<()> {
+    let verifier = lookup
+      .map(|lookup| Verifier::new(&env, lookup, options.unwrap_or_default()))
+      .transpose()?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_verifier(verifier.clone());
+      Ok(())
+    })
+  }
+
+  /// Signs every response with a shared secret, adding the
+  /// `x-content-sha256` and `x-signature` headers. Streamed responses and
+  /// ones over 16 MiB can't be signed: each is logged, and sent unsigned
+  /// unless `rejectUnsigned` is set. Passing `null` turns signing off.
+  #[napi]
+  pub fn sign_responses(
+    &mut self,
+    env: Env,
+    options: Option<ResponseSigningOptions>,
+  ) -> Result<()> {
+    let signer = options
+      .map(Signer::new)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_signer(signer.clone());
+      Ok(())
+    })
+  }
+
+  /// Only serves paths under `options.prefixes` to URLs minted with
+  /// `app.signUrl()` that have not expired; others get `403` before any
+  /// fast path or handler runs. Passing `null` turns the check off.
+  #[napi]
+  pub fn signed_urls(&mut self, env: Env, options: Option<SignedUrlOptions>) -> Result<()> {
+    let signed_urls = options
+      .map(SignedUrls::new)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_signed_urls(signed_urls.clone());
+      Ok(())
+    })
+  }
+
+  /// Asks browsers for client hints with `Accept-CH` and `Critical-CH` on
+  /// routed responses, and hands the hints requests carry to handlers as
+  /// `req.clientHints`. Passing `null` turns both off.
+  #[napi]
+  pub fn client_hints(&mut self, env: Env, options: Option<ClientHintsOptions>) -> Result<()> {
+    let client_hints = options
+      .map(ClientHints::new)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_client_hints(client_hints.clone());
+      Ok(())
+    })
+  }
+
+  /// Decides whether clients sending `Expect: 100-continue` may upload
+  /// their body, before it is read: bodies over `options.maxLength` get
+  /// `413`, and `options.check` can refuse others. Passing `null` turns the
+  /// policy off, leaving the `100 Continue` to be sent when the body is read.
+  #[napi]
+  pub fn expect_continue(
+    &mut self,
+    env: Env,
+    options: Option<ExpectContinueOptions>,
+  ) -> Result<()> {
+    let expect_continue = options
+      .map(|options| ExpectContinue::new(&env, options))
+      .transpose()?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_expect_continue(expect_continue.clone());
+      Ok(())
+    })
+  }
+
+  /// Signs `url`, a path with an optional query string, so it is served for
+  /// the next `ttl` seconds under the prefixes of `app.signedUrls()`. The
+  /// URL must be percent-encoded the way clients will send it.
+  #[napi]
+  pub fn sign_url(&self, env: Env, url: String, ttl: u32) -> Result<String> {
+    let signed = match self.routes.load().signed_urls() {
+      Some(signed_urls) => signed_urls.sign(&url, ttl),
+      None => Err(ActixError::new(
+        ErrorCode::NotConfigured,
+        "call `app.signedUrls()` before signing URLs",
+      )),
+    };
+    signed.map_err(|err| err.into_js(&env))
+  }
+
+  /// Declares a tenant, recognized by a path prefix or a header value, with
+  /// its own quota and body size limit. Declaring a tenant again under the
+  /// same name replaces it.
+  #[napi]
+  pub fn tenant(&mut self, env: Env, options: TenantOptions) -> Result<()> {
+    let tenant = Arc::new(Tenant::new(options).map_err(|err| err.into_js(&env))?);
+
+    self.update_routes(|routes| {
+      routes.set_tenant(tenant.clone());
+      Ok(())
+    })
+  }
+
+  /// Serves requests for `host` from the routes of another app. The host may
+  /// start with `*.` to match every subdomain.
+  ///
+  /// Routes added to `app` later are served too. Its own virtual hosts and
+  /// settings such as `parseJson` are not used.
+  #[napi]
+  pub fn vhost(&mut self, env: Env, host: String, app: &ActixApp) -> Result<()> {
+    let host = vhost::normalize(&host).map_err(|err| err.into_js(&env))?;
+    let site = app.site();
+
+    let mut hosts = Hosts::clone(&self.hosts.load());
+    hosts.insert(host, site);
+    self.hosts.store(Arc::new(hosts));
+    Ok(())
+  }
+
+  /// Swaps in the routes, middleware, upload endpoints, proxies and static
+  /// responses of `app`, built while this one keeps serving, in one step.
+  /// Connections stay open and requests already routed finish on the old
+  /// routes. Settings of this app are kept.
+  #[napi]
+  pub fn reload(&self, app: &ActixApp) {
+    Snapshot::of(app).publish(&self.routes, &self.fast_paths);
+  }
+
+  /// Calls `rebuild` whenever a file under `paths` changes, and reloads the
+  /// app it returns, or its promise resolves to. Meant for development.
+  /// Returns a function that stops watching.
+  #[napi(
+    ts_args_type = "paths: Array<string>, rebuild: () => ActixApp | void | Promise<ActixApp | void>, options?: WatchOptions | undefined | null",
+    ts_return_type = "() => void"
+  )]
+  pub fn watch(
+    &self,
+    env: Env,
+    paths: Vec<String>,
+    rebuild: JsFunction,
+    options: Option<WatchOptions>,
+  ) -> Result<JsFunction> {
+    reload::watch(
+      &env,
+      paths,
+      rebuild,
+      options.unwrap_or_default(),
+      self.routes.clone(),
+      self.fast_paths.clone(),
+    )
+  }
+
+  /// Serves a constant response on `path`, for `GET` and `HEAD`, straight
+  /// from memory: the JS thread is never involved. Registering the same
+  /// path again replaces the response. Static paths are matched before the
+  /// router, so they shadow routes on the same path. A `guard` is checked
+  /// before the response is served.
+  #[napi]
+  pub fn get_static(&mut self, env: Env, path: String, response: StaticResponse) -> Result<()> {
+    if !path.starts_with('/') {
+      return Err(
+        ActixError::new(
+          ErrorCode::InvalidRoute,
+          format!("static path `{path}` must start with `/`"),
+        )
+        .into_js(&env),
+      );
+    }
+
+    self.set_fast_path(&path, FixedResponse::from_static(&env, response)?);
+    Ok(())
+  }
+
+  /// Serves `/robots.txt` from memory, without calling into JS.
+  #[napi]
+  pub fn robots_txt(&mut self, content: String) {
+    let content_type = HeaderValue::from_static("text/plain; charset=utf-8");
+    self.set_fast_path("/robots.txt", FixedResponse::asset(content_type, content));
+  }
+
+  /// Serves `/favicon.ico` from memory, without calling into JS.
+  #[napi]
+  pub fn favicon(&mut self, env: Env, icon: Buffer, content_type: Option<String>) -> Result<()> {
+    let content_type = match content_type {
+      Some(content_type) => HeaderValue::try_from(content_type)
+        .map_err(|err| ActixError::new(ErrorCode::InvalidHeader, err.to_string()).into_js(&env))?,
+      None => HeaderValue::from_static("image/x-icon"),
+    };
+
+    self.set_fast_path(
+      "/favicon.ico",
+      FixedResponse::asset(content_type, Vec::from(icon)),
+    );
+    Ok(())
+  }
+
+  /// Parses `application/json` bodies before calling the handler, exposing
+  /// the result as `req.jsonBody`. With `strict`, malformed bodies are
+  /// rejected with `400` instead of reaching the handler.
+  #[napi]
+  pub fn parse_json(&mut self, enabled: bool, strict: Option<bool>) {
+    self.parse_json = match (enabled, strict.unwrap_or(false)) {
+      (false, _) => ParseJson::Off,
+      (true, false) => ParseJson::Lenient,
+      (true, true) => ParseJson::Strict,
+    };
+  }
+
+  /// Makes `req.headers` a native object with `get()`, `has()` and
+  /// `entries()` that converts a header to a string only when it is read,
+  /// instead of a `Headers` filled with every header up front. Takes effect
+  /// immediately, even on a running server.
+  #[napi]
+  pub fn lazy_headers(&mut self, enabled: bool) -> Result<()> {
+    self.update_routes(|routes| {
+      routes.set_lazy_headers(enabled);
+      Ok(())
+    })
+  }
+
+  /// Hands requests to handlers as `NodeRequest`s, which read the method,
+  /// URL, headers and body from the native request when they are used and
+  /// add `params`, `path` and `query`, instead of building a WHATWG
+  /// `Request` for every request. Takes effect immediately, even on a
+  /// running server.
+  #[napi]
+  pub fn native_requests(&mut self, enabled: bool) -> Result<()> {
+    self.update_routes(|routes| {
+      routes.set_native_requests(enabled);
+      Ok(())
+    })
+  }
+
+  /// Gives requests a `signal` that is aborted when the client disconnects
+  /// before the response is ready, so long-running work can be cancelled.
+  /// Takes effect immediately, even on a running server.
+  #[napi]
+  pub fn abort_signals(&mut self, enabled: bool) -> Result<()> {
+    self.update_routes(|routes| {
+      routes.set_abort_signals(enabled);
+      Ok(())
+    })
+  }
+
+  /// Believes the `Forwarded` or `X-Forwarded-*` headers of peers within
+  /// `ranges`, CIDR blocks or addresses, when setting `req.ip`,
+  /// `req.protocol` and `req.hostname`. `loopback`, `linklocal` and
+  /// `uniquelocal` name the blocks of those names. Passing `null` trusts
+  /// no proxy.
+  #[napi]
+  pub fn trust_proxy(&mut self, env: Env, ranges: Option<Vec<String>>) -> Result<()> {
+    let trust_proxy = ranges
+      .map(TrustProxy::new)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_trust_proxy(trust_proxy.clone());
+      Ok(())
+    })
+  }
+
+  /// Fixes requests of legacy clients rather than rejecting them: fills in
+  /// a missing `Host`, reads `;` in queries as `&` and percent-encodes
+  /// characters queries may not hold as they are. Passing `null` stops.
+  #[napi]
+  pub fn normalize(&mut self, env: Env, options: Option<NormalizeOptions>) -> Result<()> {
+    let normalizer = options
+      .map(Normalizer::new)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_normalizer(normalizer.clone());
+      Ok(())
+    })
+  }
+
+  /// How many requests each fix of `app.normalize()` was applied to.
+  #[napi]
+  pub fn normalization_metrics(&self) -> NormalizationSnapshot {
+    self
+      .routes
+      .load()
+      .normalizer()
+      .map(Normalizer::snapshot)
+      .unwrap_or_default()
+  }
+
+  /// Answers Kubernetes-style probes before routing. The `liveness` path
+  /// is served without calling into JS, so it answers even while the event
+  /// loop is busy. The `readiness` path answers `503` while the server
+  /// drains, or when `check` returns `false`, throws or takes longer than
+  /// `timeout` milliseconds. Passing `null` removes both.
+  #[napi]
+  pub fn health(&mut self, env: Env, options: Option<HealthOptions>) -> Result<()> {
+    let health = options
+      .map(|options| Health::new(&env, options))
+      .transpose()?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_health(health.clone());
+      Ok(())
+    })
+  }
+
+  /// Serves resumable uploads with the tus protocol at `path`: `POST` to it
+  /// creates an upload, and `HEAD`, `PATCH` and `DELETE` on `path/<id>`
+  /// query, append to and cancel it. Bodies are written to
+  /// `options.directory` without going through JS. Passing `null` removes
+  /// the endpoint.
+  #[napi]
+  pub fn tus(&mut self, env: Env, path: String, options: Option<TusOptions>) -> Result<()> {
+    let tus = options
+      .map(|options| Tus::new(&env, &path, options))
+      .transpose()?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_upload(&path, tus.clone());
+      Ok(())
+    })
+  }
+
+  /// Forwards requests under `prefix` to `target`, an `http:`, `https:` or
+  /// `unix:` URL or a list of weighted upstreams, with the client configured
+  /// at the time. Bodies are streamed both ways without going through JS. Passing
+  /// `null` removes the proxy.
+  ///
+  /// A request under several prefixes goes to the longest one's proxy. The
+  /// app's and tenants' quotas apply to proxied requests, but body size
+  /// limits and signature checks don't.
+  #[napi]
+  pub fn proxy(
+    &mut self,
+    env: Env,
+    prefix: String,
+    target: Option<Either<String, Vec<UpstreamOptions>>>,
+    options: Option<ProxyOptions>,
+  ) -> Result<()> {
+    let proxy = target
+      .map(|target| {
+        let upstreams = match target {
+          Either::A(url) => vec![UpstreamOptions { url, weight: None }],
+          Either::B(upstreams) => upstreams,
+        };
+        Proxy::new(
+          &prefix,
+          upstreams,
+          options.unwrap_or_default(),
+          self.client.clone(),
+          self.events.clone(),
+        )
+      })
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_proxy(&prefix, proxy.clone());
+      Ok(())
+    })
+  }
+
+  /// Runs `requests` through the routes, middleware and handlers every time
+  /// the server starts, before it accepts connections, so the first clients
+  /// don't pay for cold code paths. Handlers see them with `req.warmup` set.
+  /// Takes effect on the next `listen()`.
   #[napi]
-  pub fn get(&mut self, path: String, callback: JsFunction) -> Result<()> {
-    // req_to_jsreq(ctx).map(|v| vec![v])
-    let callback = callback.create_threadsafe_function(0, |ctx| {
-    req_to_jsreq(ctx).map(|v| vec![v])
-      // let obj = ctx.env.create_object()?;
-      // obj.set_named_property("url", ctx.env.create_string("some url")?)?;
-      // Ok(vec![obj])
-    })?;
+  pub fn warmup(&mut self, env: Env, requests: Vec<WarmupRequest>) -> Result<()> {
+    self.warmups = Warmups::new(requests).map_err(|err| err.into_js(&env))?;
+    Ok(())
+  }
+
+  /// How long a stopping server waits for the promises passed to
+  /// `req.waitUntil()` before `listen()` resolves anyway, in milliseconds.
+  /// Defaults to 30 seconds. Takes effect on the next `listen()`.
+  #[napi]
+  pub fn background_timeout(&mut self, ms: u32) {
+    self.background_timeout = Some(ms);
+  }
+
+  /// Stops the server on `SIGINT` or `SIGTERM`, or the signals listed in
+  /// `options`: it stops accepting, emits `shutdown`, and gives open
+  /// connections `options.timeout` to finish before closing them, after
+  /// which `listen()` resolves. Takes effect on the next `listen()`. Once
+  /// the server stops, the signals end the process again, as they do by
+  /// default.
+  #[napi]
+  pub fn handle_signals(
+    &mut self,
+    env: Env,
+    enabled: bool,
+    options: Option<ShutdownOptions>,
+  ) -> Result<()> {
+    self.shutdown = enabled
+      .then(|| Shutdown::new(options.unwrap_or_default()))
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+    Ok(())
+  }
+
+  /// Chooses the body of the responses to requests rejected before reaching
+  /// a handler, such as malformed or oversized bodies. `format` receives the
+  /// error and returns a string, a `Buffer`, a value sent as JSON, or nothing
+  /// for an empty body. The status code is not up to the formatter.
+  ///
+  /// Passing `null` restores empty bodies.
+  #[napi(ts_args_type = "format: import('./types').ErrorFormatter | null")]
+  pub fn error_formatter(&mut self, format: Option<JsFunction>) -> Result<()> {
+    let formatter = format.map(Formatter::new).transpose()?.map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_formatter(formatter.clone());
+      Ok(())
+    })
+  }
+
+  /// Logs every request once its response is ready. `format` is
+  /// `"common"`, `"combined"`, `"dev"`, `"tiny"` or a template of tokens
+  /// such as `:method :url :status :response-time ms`. Lines go to `output`,
+  /// a function or a writable stream, or to stdout without one. Passing
+  /// `null` turns logging off.
+  #[napi(
+    ts_args_type = "format: 'common' | 'combined' | 'dev' | 'tiny' | (string & {}) | null, output?: import('./types').LogOutput | undefined | null"
+  )]
+  pub fn access_log(
+    &mut self,
+    env: Env,
+    format: Option<String>,
+    output: Option<Either<JsFunction, JsObject>>,
+  ) -> Result<()> {
+    let access_log = format
+      .map(|format| AccessLog::new(&env, &format, output))
+      .transpose()?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_access_log(access_log.clone());
+      Ok(())
+    })
+  }
+
+  /// Copies selected responses to `output` as they are sent, for auditing
+  /// or offline analysis: a file path, appended to, or a function called
+  /// with each record. Bodies are copied chunk by chunk as they stream out;
+  /// records are dropped, rather than held, when the output falls 1024
+  /// records behind. Passing `null` turns archiving off.
+  #[napi(
+    ts_args_type = "options: ArchiveOptions | null, output?: string | ((record: import('./types').ArchiveRecord) => void) | undefined | null"
+  )]
+  pub fn archive(
+    &mut self,
+    env: Env,
+    options: Option<ArchiveOptions>,
+    output: Option<Either<String, JsFunction>>,
+  ) -> Result<()> {
+    let archive = match (options, output) {
+      (Some(options), Some(output)) => Some(Arc::new(Archive::new(&env, options, output)?)),
+      (Some(_), None) => {
+        return Err(
+          ActixError::new(
+            ErrorCode::InvalidOption,
+            "`app.archive()` needs a file path or a function to write to",
+          )
+          .into_js(&env),
+        )
+      }
+      (None, _) => None,
+    };
+
+    self.update_routes(|routes| {
+      routes.set_archive(archive.clone());
+      Ok(())
+    })
+  }
 
+  /// How many records `app.archive()` dropped because its output fell
+  /// behind.
+  #[napi]
+  pub fn archive_metrics(&self) -> ArchiveSnapshot {
     self
-      .router
-      .insert(path, callback)
-      .map_err(|err| Error::from_reason(err.to_string()))?;
+      .routes
+      .load()
+      .archive()
+      .map(|archive| archive.snapshot())
+      .unwrap_or_default()
+  }
+
+  /// Records a span for every request and exports them to an OpenTelemetry
+  /// collector over OTLP/HTTP. Requests carrying a `traceparent` header join
+  /// its trace; handlers get their span as `req.traceparent`, to pass on to
+  /// the requests they make. Passing `null` turns tracing off.
+  #[napi]
+  pub fn tracing(&mut self, env: Env, options: Option<TracingOptions>) -> Result<()> {
+    let tracer = options
+      .map(|options| Tracer::new(options, self.client.clone()))
+      .transpose()
+      .map_err(|err| err.into_js(&env))?
+      .map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_tracer(tracer.clone());
+      Ok(())
+    })
+  }
+
+  /// Runs `handler` alongside the handler of an already registered route,
+  /// with the same request, once the primary handler has answered. Its
+  /// response is dropped; when it differs from the primary one, the
+  /// difference is reported to `app.onShadowMismatch()`. Passing `null`
+  /// removes the shadow handler.
+  ///
+  /// Of the handlers registered for `method` and `path` with `when`, the
+  /// shadowed one is the one with the same conditions; without `when`, the
+  /// one registered without any.
+  #[napi(
+    ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+    ts_args_type = "method: string, path: string, handler: import('./types').Handler<Req> | null, when?: MatchOptions | undefined | null"
+  )]
+  pub fn shadow(
+    &mut self,
+    env: Env,
+    method: String,
+    path: String,
+    handler: Option<JsFunction>,
+    when: Option<MatchOptions>,
+  ) -> Result<()> {
+    let method = parse_method(&method).map_err(|err| err.into_js(&env))?;
+    let shadow = handler
+      .map(|handler| {
+        // The primary pipeline is done with its request object by now.
+        handler.create_threadsafe_function(0, |ctx| new_jsreq(ctx).map(|v| vec![v]))
+      })
+      .transpose()?;
+
+    self.update_routes(|routes| {
+      routes
+        .set_shadow(&method, &path, when.clone(), shadow.clone())
+        .map_err(|err| err.into_js(&env))
+    })
+  }
+
+  /// Calls `callback` with every response of a shadow handler that differs
+  /// from the primary handler's in status, headers or body. Without a
+  /// callback, mismatches are logged.
+  #[napi(ts_args_type = "callback: ((mismatch: import('./types').ShadowMismatch) => void) | null")]
+  pub fn on_shadow_mismatch(&mut self, callback: Option<JsFunction>) -> Result<()> {
+    let reporter = callback.map(Reporter::new).transpose()?.map(Arc::new);
+
+    self.update_routes(|routes| {
+      routes.set_mismatches(reporter.clone());
+      Ok(())
+    })
+  }
+
+  /// Tunes how responses are written to the socket. Takes effect on the next
+  /// `listen()`.
+  #[napi]
+  pub fn write_strategy(&mut self, env: Env, options: WriteOptions) -> Result<()> {
+    let options = options.server_options().map_err(|err| err.into_js(&env))?;
+    self.configure(env, options)
+  }
+
+  /// Sets HTTP/1 options of the server, keeping the ones not given. Takes
+  /// effect on the next `listen()`.
+  #[napi]
+  pub fn configure(&mut self, env: Env, options: ServerOptions) -> Result<()> {
+    options.validate().map_err(|err| err.into_js(&env))?;
+    self.server.merge(options);
+    Ok(())
+  }
+
+  /// Calls `listener` on server events: `listening` once every listener is
+  /// bound, `connection` for each accepted connection, `error` when a
+  /// listener fails (with the error's `code`, such as `EADDRINUSE`) or a
+  /// request handler panics, and `close` once the server stopped.
+  #[napi(
+    ts_generic_types = "E extends keyof import('./types').AppEvents",
+    ts_args_type = "event: E, listener: import('./types').AppEvents[E]"
+  )]
+  pub fn on(&self, env: Env, event: String, listener: JsFunction) -> Result<()> {
+    let kind = EventKind::parse(&event).map_err(|err| err.into_js(&env))?;
+    self.events.on(&env, kind, listener)
+  }
+
+  /// Removes the listeners of `event`, or of every event.
+  #[napi(ts_args_type = "event?: keyof import('./types').AppEvents | undefined | null")]
+  pub fn remove_all_listeners(&self, env: Env, event: Option<String>) -> Result<()> {
+    let kind = event
+      .as_deref()
+      .map(EventKind::parse)
+      .transpose()
+      .map_err(|err| err.into_js(&env))?;
+    self.events.remove_all(kind);
+    Ok(())
+  }
+
+  /// The address the server is bound to, like Node's `server.address()`.
+  /// `null` until the server is bound, and for Unix sockets. With several
+  /// listeners, this is the first one's.
+  #[napi]
+  pub fn address(&self) -> Option<ListenAddress> {
+    let addresses = self.addresses.load();
+    addresses
+      .iter()
+      .flatten()
+      .next()
+      .copied()
+      .map(ListenAddress::from)
+  }
+
+  /// Time spent in the JS middleware and handlers of each route.
+  #[napi]
+  pub fn route_timings(&self) -> Vec<RouteTiming> {
+    self.routes.load().timings().snapshot()
+  }
+
+  /// Everything requests are answered by: upload endpoints, proxies,
+  /// static responses and routes with their conditions and names, in the
+  /// order requests are matched against them. Routes come in the order
+  /// their templates were first registered.
+  #[napi]
+  pub fn routes(&self) -> Vec<RegisteredRoute> {
+    self.routes.load().list(&self.fast_paths.load())
+  }
+
+  /// Routes whose requests take `thresholdMs` milliseconds or more on
+  /// average in JS, slowest first.
+  #[napi]
+  pub fn slow_routes(&self, threshold_ms: f64) -> Vec<RouteTiming> {
+    self.routes.load().timings().slow(threshold_ms)
+  }
 
+  /// Histograms of the requests served per connection and of how long
+  /// connections stayed open, over every connection closed so far.
+  #[napi]
+  pub fn connection_metrics(&self) -> ConnectionMetricsSnapshot {
+    self.metrics.as_ref().into()
+  }
+
+  /// Requests, failures, latency and ejections of every proxy upstream.
+  #[napi]
+  pub fn upstream_metrics(&self) -> Vec<UpstreamSnapshot> {
+    let routes = self.routes.load();
+    routes
+      .proxies()
+      .iter()
+      .flat_map(|proxy| proxy.snapshot())
+      .collect()
+  }
+
+  /// Replaces the client used by `app.fetch()` and by proxies set up
+  /// afterwards with one connecting and resolving host names as described by
+  /// `options`. Connections pooled by the previous client are not reused.
+  #[napi]
+  pub fn configure_client(&mut self, env: Env, options: ClientOptions) -> Result<()> {
+    self.client = Client::new(&options).map_err(|err| err.into_js(&env))?;
     Ok(())
   }
 
+  /// Forgets the host names cached by the client's DNS cache, if enabled.
   #[napi]
+  pub fn flush_dns_cache(&self) {
+    if let Some(cache) = self.client.dns_cache() {
+      cache.flush();
+    }
+  }
+
+  /// Sends an outbound request through the app's pooled client.
+  #[napi(ts_return_type = "Promise<Response>")]
+  pub fn fetch(&self, env: Env, url: String, options: Option<FetchOptions>) -> Result<JsObject> {
+    let client = self.client.clone();
+    let outgoing = client::build_request(&env, url, options)?;
+
+    env.execute_tokio_future(
+      async move { Ok(client.fetch(outgoing).await) },
+      |env, res| match res {
+        Ok(res) => res.into_js(env),
+        Err(err) => Err(err.into_js(env)),
+      },
+    )
+  }
+
+  /// Starts serving. `port` is either a port, or a list of listeners served
+  /// at once with the same routes, each with its own host and server
+  /// options. Port `0` picks a free port, available from `address()` once
+  /// the callback runs.
+  #[napi(
+    ts_args_type = "port: number | Array<ListenerOptions>, hostname?: string | import('./types').ListenCallback | undefined | null, callback?: import('./types').ListenCallback | undefined | null",
+    ts_return_type = "Promise<void>"
+  )]
   pub fn listen(
     &mut self,
     env: Env,
-    port: u16,
+    port: Either<u16, Vec<ListenerOptions>>,
     hostname: Option<Either<String, JsFunction>>,
     callback: Option<JsFunction>,
   ) -> Result<napi::JsObject> {
     let (hostname, callback) = match (hostname, callback) {
-      (None, None) => (String::from("127.0.0.1"), None),
-      (Some(Either::A(hostname)), None) => (hostname, None),
-      (Some(Either::B(callback)), None) => (String::from("127.0.0.1"), Some(callback)),
-      (Some(Either::A(hostname)), Some(callback)) => (hostname, Some(callback)),
+      (None, None) => (None, None),
+      (Some(Either::A(hostname)), None) => (Some(hostname), None),
+      (Some(Either::B(callback)), None) => (None, Some(callback)),
+      (Some(Either::A(hostname)), Some(callback)) => (Some(hostname), Some(callback)),
       _ => unreachable!(),
     };
 
-    self.hostname = Some(hostname.clone());
-    self.port = Some(port);
+    let listeners = match port {
+      Either::A(port) => vec![ListenerOptions {
+        port,
+        hostname,
+        options: None,
+      }],
+      Either::B(_) if hostname.is_some() => {
+        return Err(
+          ActixError::new(
+            ErrorCode::InvalidOption,
+            "pass the hostname of each listener in its options",
+          )
+          .into_js(&env),
+        )
+      }
+      Either::B(listeners) if listeners.is_empty() => {
+        return Err(
+          ActixError::new(ErrorCode::InvalidOption, "no listeners to listen on").into_js(&env),
+        )
+      }
+      Either::B(listeners) => listeners,
+    };
+
+    let servers = listeners
+      .into_iter()
+      .map(|listener| {
+        let mut options = self.server.clone();
+        if let Some(overrides) = listener.options {
+          overrides.validate().map_err(|err| err.into_js(&env))?;
+          options.merge(overrides);
+        }
+        let hostname = listener
+          .hostname
+          .unwrap_or_else(|| String::from("127.0.0.1"));
+
+        Ok((hostname, listener.port, options))
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    self.hostname = Some(servers[0].0.clone());
+    self.port = Some(servers[0].1);
+
+    let servers = servers
+      .into_iter()
+      .map(|(hostname, port, options)| async move {
+        let server = Server::bind((hostname.as_str(), port))
+          .await
+          .map_err(|err| {
+            io::Error::new(err.kind(), format!("cannot listen on `{hostname}`: {err}"))
+          })?;
+        Ok(options.apply(server))
+      })
+      .collect();
+
+    self.serve(env, servers, callback)
+  }
+
+  /// Starts serving on a Unix domain socket at `path`. The socket file is
+  /// replaced if it already exists and removed once the server stops.
+  #[napi(
+    ts_args_type = "path: string, options?: UnixListenOptions | undefined | null, callback?: import('./types').ListenCallback | undefined | null",
+    ts_return_type = "Promise<void>"
+  )]
+  pub fn listen_unix(
+    &mut self,
+    env: Env,
+    path: String,
+    options: Option<UnixListenOptions>,
+    callback: Option<JsFunction>,
+  ) -> Result<napi::JsObject> {
+    let UnixListenOptions { mode, options } = options.unwrap_or_default();
+
+    let mut server = self.server.clone();
+    if let Some(overrides) = options {
+      overrides.validate().map_err(|err| err.into_js(&env))?;
+      server.merge(overrides);
+    }
+
+    let unix = async move {
+      let unix = server.apply(Server::bind_unix(path));
+      Ok(match mode {
+        Some(mode) => unix.unix_mode(mode),
+        None => unix,
+      })
+    };
+
+    self.serve(env, vec![unix], callback)
+  }
 
-    let router = Arc::new(self.router.clone());
+  /// Starts serving on sockets that are already listening. `fd` defaults to
+  /// every socket passed by systemd socket activation (`LISTEN_FDS`), which
+  /// are only handed out to the first call. A descriptor is only served
+  /// once, and only if it is a listening stream socket.
+  #[napi(
+    ts_args_type = "fd?: number | undefined | null, options?: ServerOptions | undefined | null, callback?: import('./types').ListenCallback | undefined | null",
+    ts_return_type = "Promise<void>"
+  )]
+  pub fn listen_fd(
+    &mut self,
+    env: Env,
+    fd: Option<i32>,
+    options: Option<ServerOptions>,
+    callback: Option<JsFunction>,
+  ) -> Result<napi::JsObject> {
+    let fds = match fd {
+      Some(fd) if fd < 0 => {
+        return Err(
+          ActixError::new(
+            ErrorCode::InvalidOption,
+            format!("invalid file descriptor {fd}"),
+          )
+          .into_js(&env),
+        )
+      }
+      Some(fd) => vec![fd],
+      None => http::listen_fds(),
+    };
+    if fds.is_empty() {
+      return Err(
+        ActixError::new(
+          ErrorCode::InvalidOption,
+          "no file descriptor given and none passed through `LISTEN_FDS`",
+        )
+        .into_js(&env),
+      );
+    }
 
-    if let Some(callback) = callback {
-      callback.call1::<ActixApp, ()>(self.clone())?;
+    let mut server = self.server.clone();
+    if let Some(overrides) = options {
+      overrides.validate().map_err(|err| err.into_js(&env))?;
+      server.merge(overrides);
     }
 
+    let servers = fds
+      .into_iter()
+      .map(|fd| {
+        let server = server.clone();
+        async move { Ok(server.apply(Server::from_fd(fd))) }
+      })
+      .collect();
+
+    self.serve(env, servers, callback)
+  }
+}
+
+impl ActixApp {
+  /// Serves the app on every server at once, until one of them fails.
+  fn serve<F>(
+    &mut self,
+    env: Env,
+    servers: Vec<F>,
+    callback: Option<JsFunction>,
+  ) -> Result<napi::JsObject>
+  where
+    F: Future<Output = io::Result<Server>> + Send + 'static,
+  {
+    let drain = Drain::new();
+    let metrics = self.metrics.clone();
+    let dispatcher = Dispatcher::new(
+      self.site(),
+      self.hosts.clone(),
+      self.parse_json,
+      drain.clone(),
+      self.metrics.clone(),
+      self.background.clone(),
+    );
+
+    // The callback runs once every server is bound, so `address()` already
+    // holds the ports picked for port `0`.
+    let listening: Option<ThreadsafeFunction<ActixApp, ErrorStrategy::Fatal>> = callback
+      .map(|callback| {
+        callback.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<ActixApp>| {
+          Ok(vec![ctx.value])
+        })
+      })
+      .transpose()?;
+    let app = self.clone();
+
+    let addresses = self.addresses.clone();
+    addresses.store(Arc::new(vec![None; servers.len()]));
+    let events = self.events.clone();
+    let warmups = self.warmups.clone();
+    let background = self.background.clone();
+    let background_timeout = self
+      .background_timeout
+      .map_or(background::DEFAULT_TIMEOUT, |ms| {
+        Duration::from_millis(ms.into())
+      });
+    let shutdown = self.shutdown.clone();
+
     env.execute_tokio_future(
-      #[allow(unreachable_code)]
       async move {
-        let router = Arc::clone(&router);
-        // let tcp_listener = TcpListener::bind((hostname, port)).await?;
-
-        let handler = move |router: Arc<Router<RouterNode>>, req: MyRequest| {
-          let val = Arc::clone(&router);
-          let val = val.at(req.uri().path());
-
-          match val {
-            Ok(callback) => {
-              let callback = callback.value.clone();
-
-              tokio::spawn(async move {
-                let a = callback.call_async::<u16>(req).await.unwrap();
-                println!("Callback resuelto: {a}");
-              });
-
-              ResponseBuilder::new()
-                .status(StatusCode::FOUND)
-                .body(Body::empty())
-                .unwrap()
-            }
-            Err(MatchError::NotFound) => ResponseBuilder::new()
-              .status(StatusCode::NOT_FOUND)
-              .body(Body::empty())
-              .unwrap(),
+        let signals = shutdown.map(|shutdown| {
+          let drain = drain.clone();
+          let events = events.clone();
+          tokio::spawn(async move { shutdown.watch(drain, events).await })
+        });
+
+        if !warmups.is_empty() {
+          dispatcher.warm_up(&warmups).await;
+        }
+
+        let (servers, bound): (Vec<_>, Vec<_>) = servers
+          .into_iter()
+          .enumerate()
+          .map(|(index, server)| {
+            let drain = drain.clone();
+            let dispatcher = dispatcher.clone();
+            let metrics = metrics.clone();
+            let addresses = addresses.clone();
+            let events = events.clone();
+            let (tx, rx) = oneshot::channel();
+
+            let server = async move {
+              let server = match server.await {
+                Ok(server) => server,
+                Err(err) => {
+                  events.emit(Event::error(&err));
+                  return Err(err);
+                }
+              };
+
+              server
+                .drain(drain)
+                .metrics(metrics)
+                .on_bind(move |addr| {
+                  addresses.rcu(|addresses| {
+                    let mut addresses = Vec::clone(addresses);
+                    addresses[index] = addr;
+                    addresses
+                  });
+                  let _ = tx.send(());
+                })
+                .on_connection({
+                  let events = events.clone();
+                  move |info| {
+                    if events.has(EventKind::Connection) {
+                      events.emit(Event::Connection {
+                        remote: info.peer_addr(),
+                      });
+                    }
+                  }
+                })
+                .on_panic({
+                  let events = events.clone();
+                  move |message| {
+                    if events.has(EventKind::Error) {
+                      events.emit(Event::Error {
+                        message: format!("panic while handling request: {message}"),
+                        code: None,
+                      });
+                    } else {
+                      eprintln!("Panic while handling request: {message}");
+                    }
+                  }
+                })
+                .serve_clone(dispatcher)
+                .await
+                .inspect_err(|err| events.emit(Event::error(err)))
+            };
+            (server, rx)
+          })
+          .unzip();
+
+        let bound_events = events.clone();
+        tokio::spawn(async move {
+          // A server that failed to bind drops its sender, and the error
+          // is reported through the `error` event instead.
+          if future::try_join_all(bound).await.is_err() {
+            return;
           }
-        };
 
-        Server::bind((hostname, port))
-          .await
-          .serve(move |req: Request, _: ConnectionInfo| handler.clone()(router.clone(), req))
-          .await
-          .unwrap();
+          let addresses = app.addresses.load();
+          bound_events.emit(Event::Listening(addresses.iter().flatten().next().copied()));
+          if let Some(listening) = listening {
+            listening.call(app, ThreadsafeFunctionCallMode::NonBlocking);
+          }
+        });
 
-        Ok(())
+        let served = future::try_join_all(servers).await;
+        if let Some(signals) = signals {
+          signals.abort();
+        }
+        let abandoned = background.settle(background_timeout).await;
+        if abandoned > 0 {
+          eprintln!("Stopped with {abandoned} background promises still pending");
+        }
+        events.emit(Event::Close);
+
+        Ok(served.map(drop).map_err(|err| ActixError::listen(&err)))
+      },
+      |env, served| match served {
+        Ok(()) => env.get_undefined(),
+        Err(err) => Err(err.into_js(env)),
       },
-      |&mut env, _| env.get_undefined(),
     )
   }
+
+  /// Registers a route. `handler` is either the handler itself, or a list of
+  /// middleware run before the handler passed as `callback`. Route options
+  /// come last in both forms.
+  fn route(
+    &mut self,
+    env: Env,
+    method: Method,
+    path: String,
+    handler: Either<Vec<JsFunction>, JsFunction>,
+    callback: Option<Either<JsFunction, RouteOptions>>,
+    options: Option<RouteOptions>,
+  ) -> Result<()> {
+    let (functions, options) = match (handler, callback, options) {
+      (Either::A(mut middleware), Some(Either::A(callback)), options) => {
+        middleware.push(callback);
+        (middleware, options)
+      }
+      (Either::B(callback), None, None) => (vec![callback], None),
+      (Either::B(callback), Some(Either::B(options)), None) => (vec![callback], Some(options)),
+      (Either::A(_), _, _) => {
+        return Err(
+          ActixError::new(
+            ErrorCode::InvalidRoute,
+            format!("route `{method} {path}` has middleware but no handler"),
+          )
+          .into_js(&env),
+        )
+      }
+      (Either::B(_), _, _) => {
+        return Err(
+          ActixError::new(
+            ErrorCode::InvalidRoute,
+            format!("route `{method} {path}` takes middleware as an array"),
+          )
+          .into_js(&env),
+        )
+      }
+    };
+
+    let mut options = options.unwrap_or_default();
+    let guard = options
+      .guard
+      .take()
+      .map(|guard| Guard::new(&env, guard))
+      .transpose()?
+      .map(Arc::new);
+    if options.batch == Some(true) {
+      let [handler] = <[JsFunction; 1]>::try_from(functions).map_err(|_| {
+        ActixError::new(
+          ErrorCode::InvalidRoute,
+          format!("batched route `{method} {path}` takes no middleware"),
+        )
+        .into_js(&env)
+      })?;
+      let handler: BatchNode = handler.create_threadsafe_function(
+        0,
+        |ctx: ThreadSafeCallContext<Vec<Arc<MyRequest>>>| {
+          let mut reqs = ctx.env.create_array_with_length(ctx.value.len())?;
+          for (index, req) in ctx.value.into_iter().enumerate() {
+            let req = req_to_jsreq(ThreadSafeCallContext {
+              env: ctx.env,
+              value: req,
+            })?;
+            reqs.set_element(index as u32, req)?;
+          }
+          Ok(vec![reqs])
+        },
+      )?;
+      let max_batch = options
+        .max_batch
+        .map_or(batch::DEFAULT_MAX_BATCH, |max| max as usize);
+      let batcher = Arc::new(Batcher::new(handler, max_batch));
+
+      return self.update_routes(|routes| {
+        routes
+          .insert(
+            method.clone(),
+            &path,
+            Pipeline::from([]),
+            Some(batcher.clone()),
+            guard.clone(),
+            &options,
+          )
+          .map_err(|err| err.into_js(&env))
+      });
+    }
+
+    let pipeline = functions
+      .into_iter()
+      .map(|callback| {
+        // req_to_jsreq(ctx).map(|v| vec![v])
+        callback.create_threadsafe_function(0, |ctx| {
+          req_to_jsreq(ctx).map(|v| vec![v])
+          // let obj = ctx.env.create_object()?;
+          // obj.set_named_property("url", ctx.env.create_string("some url")?)?;
+          // Ok(vec![obj])
+        })
+      })
+      .collect::<Result<Pipeline>>()?;
+
+    self.update_routes(|routes| {
+      routes
+        .insert(
+          method.clone(),
+          &path,
+          pipeline.clone(),
+          None,
+          guard.clone(),
+          &options,
+        )
+        .map_err(|err| err.into_js(&env))
+    })
+  }
+
+  /// Applies a change to a copy of the routes and publishes it to the server.
+  ///
+  /// `app.watch()` publishes rebuilt routes from another thread, so the
+  /// change is applied again to whatever that stored in the meantime.
+  fn update_routes<T>(&self, mut update: impl FnMut(&mut Routes) -> Result<T>) -> Result<T> {
+    let mut result = None;
+    self.routes.rcu(|current| {
+      let mut routes = Routes::clone(current);
+      let updated = update(&mut routes);
+      let next = match updated {
+        Ok(_) => Arc::new(routes),
+        Err(_) => Arc::clone(current),
+      };
+      result = Some(updated);
+      next
+    });
+
+    // `rcu` calls its closure at least once.
+    result.unwrap()
+  }
+
+  fn site(&self) -> Site {
+    Site {
+      routes: self.routes.clone(),
+      fast_paths: self.fast_paths.clone(),
+    }
+  }
+
+  fn set_fast_path(&self, path: &str, response: FixedResponse) {
+    let mut fast_paths = FastPaths::clone(&self.fast_paths.load());
+    fast_paths.insert(path.to_owned(), response);
+    self.fast_paths.store(Arc::new(fast_paths));
+  }
+}
+
+fn parse_method(method: &str) -> std::result::Result<Method, ActixError> {
+  Method::from_bytes(method.to_ascii_uppercase().as_bytes()).map_err(|_| {
+    ActixError::new(
+      ErrorCode::InvalidValue,
+      format!("invalid method `{method}`"),
+    )
+  })
+}
+
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+  headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+}
+
+fn req_to_jsreq(ctx: ThreadSafeCallContext<Arc<MyRequest>>) -> Result<JsObject> {
+  if ctx.value.extensions().get::<SharedObject>().is_some() {
+    let req = ctx.value.clone();
+    return request::shared_object(ctx.env, &req, || new_jsreq(ctx));
+  }
+  new_jsreq(ctx)
 }
 
-fn req_to_jsreq(ctx: ThreadSafeCallContext<MyRequest>) -> Result<JsObject> {
+fn new_jsreq(ctx: ThreadSafeCallContext<Arc<MyRequest>>) -> Result<JsObject> {
   let req = ctx.value;
+  let mut jsreq = if req.extensions().get::<NativeRequests>().is_some() {
+    NodeRequest::create(ctx.env, req.clone())?
+  } else {
+    let mut jsreq = whatwg_request(&ctx.env, &req)?;
+    jsreq.set_named_property(
+      "getStructuredHeader",
+      request::structured_header_getter(ctx.env)?,
+    )?;
+    jsreq
+  };
+
+  if let Some(content_type) = content_type(req.headers()).filter(|value| form::is_form(value)) {
+    let form = form::parse(req.body(), form::charset(content_type));
+    jsreq.set_named_property("form", form.into_js(&ctx.env)?)?;
+  }
+
+  if let Some(JsonBody(value)) = req.extensions().get::<JsonBody>() {
+    jsreq.set_named_property("jsonBody", ctx.env.to_js_value(value)?)?;
+  }
+
+  if let Some(patch) = req.extensions().get::<Patch>() {
+    let (name, value) = patch.to_value();
+    jsreq.set_named_property(name, ctx.env.to_js_value(&value)?)?;
+  }
+
+  if let Some(tenant) = req.extensions().get::<Arc<Tenant>>() {
+    jsreq.set_named_property("tenant", tenant.to_js(&ctx.env)?)?;
+  }
+
+  if let Some(background) = req.extensions().get::<Arc<Background>>() {
+    let background = background.clone();
+    let wait_until = ctx
+      .env
+      .create_function_from_closure("waitUntil", move |ctx| {
+        background.track(ctx.get::<Promise<Settled>>(0)?);
+        ctx.env.get_undefined()
+      })?;
+    jsreq.set_named_property("waitUntil", wait_until)?;
+  }
+
+  if req.extensions().get::<Warmup>().is_some() {
+    jsreq.set_named_property("warmup", ctx.env.get_boolean(true)?)?;
+  }
+
+  if let Some(signals) = req.extensions().get::<Signals>() {
+    let signal = signals.signal(&ctx.env)?;
+    // `signal` is a getter of `Request.prototype`; an own property shadows
+    // it.
+    jsreq.define_properties(&[Property::new("signal")?.with_value(&signal)])?;
+  }
+
+  if let Some(hints) = req.extensions().get::<Hints>() {
+    jsreq.set_named_property("clientHints", hints.to_js(&ctx.env)?)?;
+  }
+
+  if let Some(origin) = req.extensions().get::<Origin>() {
+    if let Some(ip) = origin.ip() {
+      jsreq.set_named_property("ip", ctx.env.create_string(ip)?)?;
+    }
+    jsreq.set_named_property("protocol", ctx.env.create_string(origin.protocol())?)?;
+    if let Some(hostname) = origin.hostname() {
+      jsreq.set_named_property("hostname", ctx.env.create_string(hostname)?)?;
+    }
+  }
+
+  if let Some(trace) = req.extensions().get::<TraceContext>() {
+    jsreq.set_named_property("traceparent", ctx.env.create_string(&trace.traceparent())?)?;
+  }
+
+  if let Some(info) = req.extensions().get::<ConnectionInfo>() {
+    jsreq.set_named_property(
+      "connection",
+      connection_to_js(&ctx.env, info, req.version())?,
+    )?;
+  }
+
+  Ok(jsreq)
+}
+
+/// Builds a WHATWG `Request` through the global constructor.
+fn whatwg_request(env: &Env, req: &Arc<MyRequest>) -> Result<JsObject> {
   let href = String::from("http://localhost:3000/fake");
   // let href = {
   //   let href = req.connection_info().clone();
@@ -138,65 +1637,69 @@ fn req_to_jsreq(ctx: ThreadSafeCallContext<MyRequest>) -> Result<JsObject> {
   //
   //   format!("{scheme}://{host}{pathname}")
   // };
-  let method = req.method().as_str().to_owned();
-  let headers = req.headers().clone();
-
-  let jsreq = ctx
-    .env
+  let jsreq = env
     .get_global()?
     .get_named_property::<JsFunction>("Request")?;
 
-  let href = ctx.env.create_string(&href)?;
-  let mut options = ctx.env.create_object()?;
+  let href = env.create_string(&href)?;
+  let mut options = env.create_object()?;
 
-  let method = ctx.env.create_string(&method)?;
+  let method = env.create_string(req.method().as_str())?;
   options.set_named_property("method", method)?;
 
-  let mut js_headers = ctx.env.create_object()?;
+  let mut js_headers = env.create_object()?;
 
-  for (name, value) in headers {
-    assert!(name.is_some());
-    let name = name.unwrap();
+  // Lazy requests only hand over `Content-Type`, which `formData()` and
+  // `blob()` need to read the body.
+  let lazy = req.extensions().get::<LazyHeaders>().is_some();
+  for (name, value) in req.headers() {
+    if lazy && name != header::CONTENT_TYPE {
+      continue;
+    }
     let name = name.as_str();
     let value = value
       .to_str()
-      .map_err(|err| Error::from_reason(err.to_string()))?;
-    let value = ctx.env.create_string(value)?;
+      .map_err(|err| ActixError::new(ErrorCode::InvalidHeader, err.to_string()).into_js(env))?;
+    let value = env.create_string(value)?;
 
     js_headers.set_named_property(name, value)?;
   }
   options.set_named_property("headers", js_headers)?;
 
-  // if !body.into_data_stream().is_empty() {
-  //   let body = ctx.env.create_arraybuffer_with_data(body.to_vec())?;
-  //   options.set_named_property("body", body.into_unknown())?;
-  // }
-  //
+  // The WHATWG `Request` rejects bodies on GET and HEAD requests.
+  let body = req.body();
+  if !body.is_empty() && req.method() != Method::GET && req.method() != Method::HEAD {
+    options.set_named_property("body", buffer::to_js(env, body.clone())?)?;
+  }
 
-  jsreq.new_instance(&[href.into_unknown(), options.into_unknown()])
+  let mut jsreq = jsreq.new_instance(&[href.into_unknown(), options.into_unknown()])?;
+  if lazy {
+    RequestHeaders::attach(*env, &mut jsreq, req.clone())?;
+  }
+  Ok(jsreq)
 }
 
-#[derive(Clone)]
-struct ServiceFn<T>(Arc<Router<RouterNode>>, Arc<RwLock<T>>);
-
-impl<T, F, Request, R, E> Service<Request> for ServiceFn<T>
-where
-  T: FnMut(Arc<Router<RouterNode>>, Request) -> F,
-  F: Future<Output = std::result::Result<R, E>>,
-{
-  type Response = R;
-  type Error = E;
-  type Future = F;
+/// The `req.connection` object: who is on the other end and which protocol
+/// the request came over. Connections are never encrypted, since the server
+/// does not terminate TLS itself.
+fn connection_to_js(env: &Env, info: &ConnectionInfo, version: Version) -> Result<JsObject> {
+  let mut connection = env.create_object()?;
 
-  fn poll_ready(
-    &mut self,
-    _: &mut std::task::Context<'_>,
-  ) -> std::task::Poll<std::prelude::v1::Result<(), Self::Error>> {
-    std::task::Poll::Ready(Ok(()))
+  if let Some(addr) = info.peer_addr() {
+    connection.set_named_property("remoteAddress", env.create_string(&addr.ip().to_string())?)?;
+    connection.set_named_property("remotePort", env.create_uint32(addr.port().into())?)?;
   }
 
-  fn call(&mut self, req: Request) -> Self::Future {
-    let mut handler = self.1.write().unwrap();
-    handler(self.0.clone(), req)
-  }
+  let protocol = match version {
+    Version::HTTP_09 => "http/0.9",
+    Version::HTTP_10 => "http/1.0",
+    Version::HTTP_11 => "http/1.1",
+    Version::HTTP_2 => "h2",
+    Version::HTTP_3 => "h3",
+    _ => "unknown",
+  };
+  connection.set_named_property("protocol", env.create_string(protocol)?)?;
+  connection.set_named_property("encrypted", env.get_boolean(false)?)?;
+
+  Ok(connection)
 }
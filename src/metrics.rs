@@ -0,0 +1,85 @@
+//! Connection metrics handed to JS by `app.connectionMetrics()`.
+
+use crate::http::metrics::{ConnectionMetrics, Histogram, Transfer};
+
+/// A histogram as seen from JS.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct HistogramSnapshot {
+  /// Upper bound of each bucket, inclusive.
+  pub bounds: Vec<f64>,
+  /// How many values fell in each bucket. The last count, with no bound,
+  /// holds the values above every bound.
+  pub counts: Vec<f64>,
+  pub count: f64,
+  pub sum: f64,
+}
+
+impl From<&Histogram> for HistogramSnapshot {
+  fn from(histogram: &Histogram) -> Self {
+    let counts = histogram.counts();
+
+    HistogramSnapshot {
+      bounds: histogram.bounds().to_vec(),
+      count: counts.iter().sum::<u64>() as f64,
+      counts: counts.into_iter().map(|count| count as f64).collect(),
+      sum: histogram.sum(),
+    }
+  }
+}
+
+/// How connections closed so far were used, to tune keep-alive.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionMetricsSnapshot {
+  /// Requests served by each connection.
+  pub requests_per_connection: HistogramSnapshot,
+  /// How long each connection stayed open, in seconds.
+  pub connection_duration: HistogramSnapshot,
+  pub transfer: TransferSnapshot,
+}
+
+/// How request bodies arrived and how responses were written.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct TransferSnapshot {
+  /// Requests with a body sent with `Transfer-Encoding: chunked`.
+  pub chunked_requests: f64,
+  /// Requests with a body sent with a `Content-Length`.
+  pub content_length_requests: f64,
+  /// Pieces request bodies were received in, and their average size in
+  /// bytes.
+  pub chunks: f64,
+  pub average_chunk_size: f64,
+  /// Writes to and flushes of the sockets of closed connections.
+  pub writes: f64,
+  pub flushes: f64,
+}
+
+impl From<Transfer> for TransferSnapshot {
+  fn from(transfer: Transfer) -> Self {
+    let average_chunk_size = match transfer.chunks {
+      0 => 0.0,
+      chunks => transfer.chunk_bytes as f64 / chunks as f64,
+    };
+
+    TransferSnapshot {
+      chunked_requests: transfer.chunked_requests as f64,
+      content_length_requests: transfer.sized_requests as f64,
+      chunks: transfer.chunks as f64,
+      average_chunk_size,
+      writes: transfer.writes as f64,
+      flushes: transfer.flushes as f64,
+    }
+  }
+}
+
+impl From<&ConnectionMetrics> for ConnectionMetricsSnapshot {
+  fn from(metrics: &ConnectionMetrics) -> Self {
+    ConnectionMetricsSnapshot {
+      requests_per_connection: metrics.requests().into(),
+      connection_duration: metrics.duration().into(),
+      transfer: metrics.transfer().snapshot().into(),
+    }
+  }
+}
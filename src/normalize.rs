@@ -0,0 +1,259 @@
+//! Fixing requests of legacy clients rather than rejecting them.
+//!
+//! `app.normalize()` rewrites requests before anything else sees them, the
+//! virtual host lookup included:
+//!
+//! - a request without `Host` gets the authority of its absolute-form
+//!   target, or `defaultHost`,
+//! - `;` separating query parameters becomes `&`,
+//! - characters a query may not hold unencoded, such as `{`, `^` or `|`,
+//!   are percent-encoded.
+//!
+//! Raw spaces and `"`, `<` or `>` never get here: the HTTP parser rejects
+//! targets holding them with `400`.
+//!
+//! `app.normalizationMetrics()` counts the requests each fix was applied
+//! to.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use hyper::header::{self, HeaderValue};
+use hyper::http::uri::{PathAndQuery, Uri};
+use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::Request;
+
+/// Bytes percent-encoded in queries: those outside of RFC 3986's `query`,
+/// but `%`, taken to start an escape already.
+const QUERY: &AsciiSet = &CONTROLS
+  .add(b' ')
+  .add(b'"')
+  .add(b'#')
+  .add(b'<')
+  .add(b'>')
+  .add(b'[')
+  .add(b'\\')
+  .add(b']')
+  .add(b'^')
+  .add(b'`')
+  .add(b'{')
+  .add(b'|')
+  .add(b'}');
+
+/// Options of `app.normalize()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct NormalizeOptions {
+  /// `Host` given to requests without one, when their target doesn't name
+  /// the host either.
+  pub default_host: Option<String>,
+  /// Treats `;` in queries as `&`. Defaults to `true`.
+  pub semicolons: Option<bool>,
+  /// Percent-encodes characters queries may not hold as they are. Defaults
+  /// to `true`.
+  pub encode_query: Option<bool>,
+}
+
+/// How many requests each fix was applied to.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct NormalizationSnapshot {
+  pub missing_host: f64,
+  pub semicolon_queries: f64,
+  pub encoded_queries: f64,
+}
+
+pub struct Normalizer {
+  default_host: Option<HeaderValue>,
+  semicolons: bool,
+  encode_query: bool,
+  missing_host: AtomicU64,
+  semicolon_queries: AtomicU64,
+  encoded_queries: AtomicU64,
+}
+
+impl Normalizer {
+  pub fn new(options: NormalizeOptions) -> Result<Self, ActixError> {
+    let default_host = options
+      .default_host
+      .map(|host| {
+        HeaderValue::from_str(&host).map_err(|_| {
+          ActixError::new(
+            ErrorCode::InvalidOption,
+            format!("invalid default host `{host}`"),
+          )
+        })
+      })
+      .transpose()?;
+
+    Ok(Normalizer {
+      default_host,
+      semicolons: options.semicolons.unwrap_or(true),
+      encode_query: options.encode_query.unwrap_or(true),
+      missing_host: AtomicU64::new(0),
+      semicolon_queries: AtomicU64::new(0),
+      encoded_queries: AtomicU64::new(0),
+    })
+  }
+
+  pub fn apply(&self, req: &mut Request) {
+    if !req.headers().contains_key(header::HOST) {
+      let host = req
+        .uri()
+        .authority()
+        .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok())
+        .or_else(|| self.default_host.clone());
+      if let Some(host) = host {
+        req.headers_mut().insert(header::HOST, host);
+        self.missing_host.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+
+    let Some(query) = req.uri().query() else {
+      return;
+    };
+    let mut fixed = query.to_owned();
+    if self.semicolons && fixed.contains(';') {
+      fixed = fixed.replace(';', "&");
+      self.semicolon_queries.fetch_add(1, Ordering::Relaxed);
+    }
+    if self.encode_query {
+      let encoded = percent_encode(fixed.as_bytes(), QUERY).to_string();
+      if encoded != fixed {
+        fixed = encoded;
+        self.encoded_queries.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    if fixed == query {
+      return;
+    }
+
+    let path_and_query = format!("{}?{fixed}", req.uri().path());
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+      *req.uri_mut() = uri;
+    }
+  }
+
+  pub fn snapshot(&self) -> NormalizationSnapshot {
+    NormalizationSnapshot {
+      missing_host: self.missing_host.load(Ordering::Relaxed) as f64,
+      semicolon_queries: self.semicolon_queries.load(Ordering::Relaxed) as f64,
+      encoded_queries: self.encoded_queries.load(Ordering::Relaxed) as f64,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use hyper::header;
+
+  use super::{NormalizeOptions, Normalizer};
+  use crate::http::{Body, Request};
+
+  fn request(target: &str, host: Option<&str>) -> Request {
+    let mut builder = hyper::Request::builder().uri(target);
+    if let Some(host) = host {
+      builder = builder.header(header::HOST, host);
+    }
+    builder.body(Body::empty()).unwrap()
+  }
+
+  fn host(req: &Request) -> Option<&str> {
+    req
+      .headers()
+      .get(header::HOST)
+      .map(|host| host.to_str().unwrap())
+  }
+
+  #[test]
+  fn takes_missing_hosts_from_the_target_then_the_default() {
+    let normalizer = Normalizer::new(NormalizeOptions {
+      default_host: Some("fallback.test".into()),
+      ..Default::default()
+    })
+    .unwrap();
+
+    let mut absolute = request("http://example.com:8080/a", None);
+    normalizer.apply(&mut absolute);
+    assert_eq!(host(&absolute), Some("example.com:8080"));
+
+    let mut origin = request("/a", None);
+    normalizer.apply(&mut origin);
+    assert_eq!(host(&origin), Some("fallback.test"));
+
+    let mut given = request("http://example.com/a", Some("other.test"));
+    normalizer.apply(&mut given);
+    assert_eq!(host(&given), Some("other.test"));
+
+    assert_eq!(normalizer.snapshot().missing_host, 2.0);
+  }
+
+  #[test]
+  fn leaves_hosts_missing_without_a_default() {
+    let normalizer = Normalizer::new(NormalizeOptions::default()).unwrap();
+    let mut req = request("/a", None);
+    normalizer.apply(&mut req);
+    assert_eq!(host(&req), None);
+    assert_eq!(normalizer.snapshot().missing_host, 0.0);
+  }
+
+  #[test]
+  fn splits_queries_on_semicolons() {
+    let normalizer = Normalizer::new(NormalizeOptions::default()).unwrap();
+    let mut req = request("/search?a=1;b=2&c=3", Some("h"));
+    normalizer.apply(&mut req);
+    assert_eq!(req.uri(), "/search?a=1&b=2&c=3");
+    assert_eq!(normalizer.snapshot().semicolon_queries, 1.0);
+  }
+
+  #[test]
+  fn encodes_characters_queries_may_not_hold() {
+    let normalizer = Normalizer::new(NormalizeOptions::default()).unwrap();
+    let mut req = request("/a?q={x}|y^&p=%20", Some("h"));
+    normalizer.apply(&mut req);
+    assert_eq!(req.uri(), "/a?q=%7Bx%7D%7Cy%5E&p=%20");
+    assert_eq!(normalizer.snapshot().encoded_queries, 1.0);
+  }
+
+  #[test]
+  fn keeps_the_authority_of_absolute_targets() {
+    let normalizer = Normalizer::new(NormalizeOptions::default()).unwrap();
+    let mut req = request("http://example.com/a?x=1;y=2", None);
+    normalizer.apply(&mut req);
+    assert_eq!(req.uri(), "http://example.com/a?x=1&y=2");
+  }
+
+  #[test]
+  fn leaves_valid_queries_and_disabled_fixes_alone() {
+    let normalizer = Normalizer::new(NormalizeOptions {
+      semicolons: Some(false),
+      encode_query: Some(false),
+      ..Default::default()
+    })
+    .unwrap();
+    let mut req = request("/a?x=1;y={}", Some("h"));
+    normalizer.apply(&mut req);
+    assert_eq!(req.uri(), "/a?x=1;y={}");
+
+    let snapshot = normalizer.snapshot();
+    assert_eq!(snapshot.semicolon_queries, 0.0);
+    assert_eq!(snapshot.encoded_queries, 0.0);
+  }
+
+  #[test]
+  fn rejects_invalid_default_hosts() {
+    let error = Normalizer::new(NormalizeOptions {
+      default_host: Some("bad\nhost".into()),
+      ..Default::default()
+    })
+    .err()
+    .unwrap();
+    assert_eq!(
+      error.to_string(),
+      "INVALID_OPTION: invalid default host `bad\nhost`"
+    );
+  }
+}
@@ -0,0 +1,326 @@
+//! Server tuning options settable from JS.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Keepalive, Saturation, Server, SocketOptions};
+
+const MIN_BUF_SIZE: u32 = crate::http::MIN_BUF_SIZE as u32;
+
+/// How responses are written to the socket, set with `app.writeStrategy()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+  /// `"flatten"` copies headers and body into one buffer so small responses
+  /// go out in a single write, `"queue"` uses vectored writes instead of
+  /// copying, and `"auto"` (the default) picks based on the transport.
+  #[napi(ts_type = "'auto' | 'flatten' | 'queue'")]
+  pub strategy: Option<String>,
+  /// Aggregates flushes of pipelined responses.
+  pub pipeline_flush: Option<bool>,
+  /// Maximum size of the read and write buffers of a connection, in bytes.
+  /// At least 8192.
+  pub max_buf_size: Option<u32>,
+}
+
+impl WriteOptions {
+  fn writev(&self) -> Result<Option<bool>, ActixError> {
+    match self.strategy.as_deref() {
+      None | Some("auto") => Ok(None),
+      Some("flatten") => Ok(Some(false)),
+      Some("queue") => Ok(Some(true)),
+      Some(other) => Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        format!("unknown write strategy `{other}`, expected \"auto\", \"flatten\" or \"queue\""),
+      )),
+    }
+  }
+
+  /// The server options these write options stand for.
+  pub fn server_options(&self) -> Result<ServerOptions, ActixError> {
+    Ok(ServerOptions {
+      writev: self.writev()?,
+      pipeline_flush: self.pipeline_flush,
+      max_buf_size: self.max_buf_size,
+      ..Default::default()
+    })
+  }
+}
+
+/// Every HTTP/1 knob of the server, set with `app.configure()`.
+///
+/// Unset options keep hyper's defaults. Changes take effect on the next
+/// `listen()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ServerOptions {
+  /// Reuses connections for several requests. Defaults to `true`.
+  pub keep_alive: Option<bool>,
+  /// Finishes the response when the client half-closes its side of the
+  /// connection. Defaults to `false`.
+  pub half_close: Option<bool>,
+  /// Maximum size of the read and write buffers of a connection, in bytes.
+  /// At least 8192.
+  pub max_buf_size: Option<u32>,
+  /// Aggregates flushes of pipelined responses. Defaults to `false`.
+  pub pipeline_flush: Option<bool>,
+  /// Forces vectored writes on or off; unset picks based on the transport.
+  pub writev: Option<bool>,
+  /// Writes header names in title case. Defaults to `false`.
+  pub title_case_headers: Option<bool>,
+  /// Keeps the case of header names as received. Defaults to `false`.
+  pub preserve_header_case: Option<bool>,
+  /// Only accepts HTTP/1 connections. Defaults to `false`.
+  pub http1_only: Option<bool>,
+  /// Milliseconds a keep-alive connection may wait for its next request
+  /// before it is closed gracefully. The timer only runs between requests,
+  /// like Node's `server.keepAliveTimeout`. `0` (the default) disables the
+  /// timeout.
+  pub keep_alive_timeout: Option<u32>,
+  /// Milliseconds a client gets to send the headers of a request, counted
+  /// from when the server starts waiting for it. `0` (the default) disables
+  /// the timeout. Node calls it `server.headersTimeout`.
+  pub header_read_timeout: Option<u32>,
+  /// Milliseconds a request gets to be received and answered, up to the
+  /// response headers; late ones get a `408` and their connection is
+  /// closed. `0` (the default) disables the timeout.
+  pub request_timeout: Option<u32>,
+  /// Milliseconds a connection may go without reading or writing anything
+  /// before it is dropped, like Node's `server.timeout`. Unlike
+  /// `keepAliveTimeout`, the timer also runs while a request is in flight,
+  /// and the connection is dropped mid-response rather than closed
+  /// gracefully. `0` (the default) disables the timeout.
+  pub idle_timeout: Option<u32>,
+  /// Maximum number of connections open at once. Unlimited by default.
+  pub max_connections: Option<u32>,
+  /// What to do with new connections once `maxConnections` is reached:
+  /// `"pause"` (the default) stops accepting until one closes, `"reject"`
+  /// answers them with a `503`.
+  #[napi(ts_type = "'pause' | 'reject'")]
+  pub saturation: Option<String>,
+  /// Runs connections on a runtime of their own with this many worker
+  /// threads. Unset shares the addon's runtime.
+  pub max_workers: Option<u32>,
+  /// Milliseconds idle blocking threads of that runtime are kept. Defaults
+  /// to 10 seconds.
+  pub worker_keep_alive: Option<u32>,
+  /// Lets apps in other worker threads of the process listen on the same
+  /// address: the first one accepts connections and hands requests to each
+  /// of them in turn. Defaults to `false`.
+  pub share_listener: Option<bool>,
+  /// Sets `SO_REUSEPORT`, so several processes can listen on the same port
+  /// with the kernel spreading connections between them. Unix only.
+  /// Defaults to `true` in cluster workers.
+  pub reuse_port: Option<bool>,
+  /// Sets `SO_REUSEADDR`. Defaults to `true` on Unix.
+  pub reuse_address: Option<bool>,
+  /// Disables Nagle's algorithm on accepted connections.
+  pub no_delay: Option<bool>,
+  /// Maximum number of connections waiting to be accepted. Defaults to
+  /// 1024.
+  pub backlog: Option<u32>,
+  /// Milliseconds a connection stays idle before TCP keep-alive probes are
+  /// sent. Setting any `tcpKeepAlive*` option enables the probes.
+  pub tcp_keep_alive_idle: Option<u32>,
+  /// Milliseconds between TCP keep-alive probes.
+  pub tcp_keep_alive_interval: Option<u32>,
+  /// Unanswered TCP keep-alive probes before the connection is dropped.
+  pub tcp_keep_alive_retries: Option<u32>,
+}
+
+impl ServerOptions {
+  /// Checks the options, so mistakes are reported when they are set rather
+  /// than when the server starts.
+  pub fn validate(&self) -> std::result::Result<(), ActixError> {
+    let invalid = |message: String| Err(ActixError::new(ErrorCode::InvalidOption, message));
+    match self.max_buf_size {
+      Some(size) if size < MIN_BUF_SIZE => {
+        return invalid(format!(
+          "`maxBufSize` must be at least {MIN_BUF_SIZE}, got {size}"
+        ))
+      }
+      _ => {}
+    }
+    if self.max_connections == Some(0) {
+      return invalid(String::from("`maxConnections` must be at least 1"));
+    }
+    if self.max_workers == Some(0) {
+      return invalid(String::from("`maxWorkers` must be at least 1"));
+    }
+    self.saturation().map(|_| ())
+  }
+
+  fn saturation(&self) -> std::result::Result<Saturation, ActixError> {
+    match self.saturation.as_deref() {
+      None | Some("pause") => Ok(Saturation::Pause),
+      Some("reject") => Ok(Saturation::Reject),
+      Some(other) => Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        format!("unknown saturation `{other}`, expected \"pause\" or \"reject\""),
+      )),
+    }
+  }
+
+  /// Overrides these options with the ones set in `other`.
+  pub fn merge(&mut self, other: ServerOptions) {
+    macro_rules! merge {
+      ($($option:ident),* $(,)?) => {
+        $(
+          if other.$option.is_some() {
+            self.$option = other.$option;
+          }
+        )*
+      };
+    }
+
+    merge!(
+      keep_alive,
+      half_close,
+      max_buf_size,
+      pipeline_flush,
+      writev,
+      title_case_headers,
+      preserve_header_case,
+      http1_only,
+      keep_alive_timeout,
+      header_read_timeout,
+      request_timeout,
+      idle_timeout,
+      max_connections,
+      saturation,
+      max_workers,
+      worker_keep_alive,
+      share_listener,
+      reuse_port,
+      reuse_address,
+      no_delay,
+      backlog,
+      tcp_keep_alive_idle,
+      tcp_keep_alive_interval,
+      tcp_keep_alive_retries,
+    );
+  }
+
+  pub fn apply(&self, mut server: Server) -> Server {
+    if let Some(keep_alive) = self.keep_alive {
+      server = server.http1_keep_alive(keep_alive);
+    }
+    if let Some(half_close) = self.half_close {
+      server = server.http1_half_close(half_close);
+    }
+    if let Some(max_buf_size) = self.max_buf_size {
+      server = server.http1_max_buf_size(max_buf_size as usize);
+    }
+    if let Some(pipeline_flush) = self.pipeline_flush {
+      server = server.http1_pipeline_flush(pipeline_flush);
+    }
+    if let Some(writev) = self.writev {
+      server = server.http1_writev(writev);
+    }
+    if let Some(title_case_headers) = self.title_case_headers {
+      server = server.http1_title_case_headers(title_case_headers);
+    }
+    if let Some(preserve_header_case) = self.preserve_header_case {
+      server = server.http1_preserve_header_case(preserve_header_case);
+    }
+    if let Some(http1_only) = self.http1_only {
+      server = server.http1_only(http1_only);
+    }
+    if let Some(timeout) = self.keep_alive_timeout.filter(|ms| *ms > 0) {
+      server = server.http1_keep_alive_timeout(Duration::from_millis(timeout.into()));
+    }
+    if let Some(timeout) = self.header_read_timeout.filter(|ms| *ms > 0) {
+      server = server.http1_header_read_timeout(Duration::from_millis(timeout.into()));
+    }
+    if let Some(timeout) = self.request_timeout.filter(|ms| *ms > 0) {
+      server = server.request_timeout(Duration::from_millis(timeout.into()));
+    }
+    if let Some(timeout) = self.idle_timeout.filter(|ms| *ms > 0) {
+      server = server.idle_timeout(Duration::from_millis(timeout.into()));
+    }
+    if let Some(max_connections) = self.max_connections {
+      server = server.max_connections(max_connections as usize);
+    }
+    if let Ok(saturation) = self.saturation() {
+      server = server.saturation(saturation);
+    }
+    if let Some(max_workers) = self.max_workers {
+      server = server.max_workers(max_workers as usize);
+    }
+    if let Some(keep_alive) = self.worker_keep_alive {
+      server = server.worker_keep_alive(Duration::from_millis(keep_alive.into()));
+    }
+    if let Some(share_listener) = self.share_listener {
+      server = server.share_listener(share_listener);
+    }
+
+    server.socket_options(self.socket_options())
+  }
+
+  fn socket_options(&self) -> SocketOptions {
+    let millis = |ms: Option<u32>| ms.map(|ms| Duration::from_millis(ms.into()));
+
+    let keepalive = Keepalive {
+      idle: millis(self.tcp_keep_alive_idle),
+      interval: millis(self.tcp_keep_alive_interval),
+      retries: self.tcp_keep_alive_retries,
+    };
+    let keepalive_set =
+      keepalive.idle.is_some() || keepalive.interval.is_some() || keepalive.retries.is_some();
+
+    // Sockets bound natively bypass the cluster primary, so workers of a
+    // cluster can only listen on the same port with `SO_REUSEPORT`.
+    let cluster_worker = cfg!(unix) && std::env::var_os("NODE_UNIQUE_ID").is_some();
+
+    SocketOptions {
+      reuse_port: self.reuse_port.or(cluster_worker.then_some(true)),
+      reuse_address: self.reuse_address,
+      nodelay: self.no_delay,
+      backlog: self.backlog,
+      keepalive: keepalive_set.then_some(keepalive),
+    }
+  }
+}
+
+/// One socket served by `app.listen([...])`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ListenerOptions {
+  pub port: u16,
+  /// Defaults to `127.0.0.1`.
+  pub hostname: Option<String>,
+  /// Overrides the options set with `app.configure()` for this listener.
+  pub options: Option<ServerOptions>,
+}
+
+/// Options of `app.listenUnix()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct UnixListenOptions {
+  /// Permissions of the socket file, such as `0o660`.
+  pub mode: Option<u32>,
+  /// Overrides the options set with `app.configure()` for this listener.
+  pub options: Option<ServerOptions>,
+}
+
+/// Where the server is bound, as returned by `app.address()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ListenAddress {
+  pub address: String,
+  /// `"IPv4"` or `"IPv6"`.
+  #[napi(ts_type = "'IPv4' | 'IPv6'")]
+  pub family: String,
+  pub port: u16,
+}
+
+impl From<SocketAddr> for ListenAddress {
+  fn from(addr: SocketAddr) -> Self {
+    ListenAddress {
+      address: addr.ip().to_string(),
+      family: String::from(if addr.is_ipv4() { "IPv4" } else { "IPv6" }),
+      port: addr.port(),
+    }
+  }
+}
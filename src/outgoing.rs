@@ -0,0 +1,108 @@
+//! The status, headers and chunks Node-style `res` objects are given.
+//!
+//! `ExpressResponse`, `ResponseWriter` and `NodeResponse` take them the way
+//! Node's `ServerResponse` does, and check them the same way.
+
+use hyper::body::Bytes;
+use hyper::header::{AsHeaderName, HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use napi::bindgen_prelude::{Buffer, Either};
+use napi::{Env, JsObject, JsString, JsUnknown, Result};
+
+use crate::buffer;
+use crate::error::{ActixError, ErrorCode};
+
+/// A status code between 100 and 999.
+pub fn parse_status(env: &Env, code: u32) -> Result<StatusCode> {
+  u16::try_from(code)
+    .ok()
+    .and_then(|code| StatusCode::from_u16(code).ok())
+    .ok_or_else(|| {
+      ActixError::new(
+        ErrorCode::InvalidStatus,
+        format!("invalid status code {code}"),
+      )
+      .into_js(env)
+    })
+}
+
+pub fn parse_name(env: &Env, name: String) -> Result<HeaderName> {
+  HeaderName::try_from(name.as_str()).map_err(|_| {
+    ActixError::new(
+      ErrorCode::InvalidHeader,
+      format!("invalid header name `{name}`"),
+    )
+    .into_js(env)
+  })
+}
+
+/// The values of a header given as a string, or as an array of them.
+pub fn header_values(env: &Env, value: JsUnknown) -> Result<Vec<HeaderValue>> {
+  let values = if value.is_array()? {
+    let array = value.coerce_to_object()?;
+    (0..array.get_array_length()?)
+      .map(|idx| array.get_element::<JsUnknown>(idx)?.coerce_to_string())
+      .collect::<Result<Vec<_>>>()?
+  } else {
+    vec![value.coerce_to_string()?]
+  };
+
+  values
+    .into_iter()
+    .map(|value| {
+      let value = value.into_utf8()?;
+      HeaderValue::from_bytes(value.as_slice()).map_err(|err| {
+        ActixError::new(ErrorCode::InvalidHeader, "invalid header value")
+          .caused_by(err)
+          .into_js(env)
+      })
+    })
+    .collect()
+}
+
+/// The headers given to `writeHead()`: an object, or an array of names and
+/// values one after the other.
+pub fn head_headers(env: &Env, headers: JsObject) -> Result<Vec<(HeaderName, HeaderValue)>> {
+  let mut head = Vec::new();
+  if headers.is_array()? {
+    let len = headers.get_array_length()?;
+    for idx in (0..len.saturating_sub(1)).step_by(2) {
+      let name: JsUnknown = headers.get_element(idx)?;
+      let name = parse_name(env, name.coerce_to_string()?.into_utf8()?.into_owned()?)?;
+      for value in header_values(env, headers.get_element(idx + 1)?)? {
+        head.push((name.clone(), value));
+      }
+    }
+    return Ok(head);
+  }
+
+  let names = headers.get_property_names()?;
+  for idx in 0..names.get_array_length()? {
+    let name: JsString = names.get_element(idx)?;
+    let name = name.into_utf8()?.into_owned()?;
+    let values = header_values(env, headers.get_named_property(&name)?)?;
+    let name = parse_name(env, name)?;
+    for value in values {
+      head.push((name.clone(), value));
+    }
+  }
+  Ok(head)
+}
+
+/// The values of header `name` joined with `, `, as `getHeader()` gives
+/// them.
+pub fn joined(headers: &HeaderMap, name: impl AsHeaderName) -> Option<String> {
+  let mut values = headers.get_all(name).iter();
+  let first = String::from_utf8_lossy(values.next()?.as_bytes()).into_owned();
+  Some(values.fold(first, |joined, value| {
+    joined + ", " + &String::from_utf8_lossy(value.as_bytes())
+  }))
+}
+
+/// A chunk passed to `write()` or `end()`.
+pub fn chunk_bytes(chunk: Either<String, Buffer>) -> Bytes {
+  match chunk {
+    Either::A(text) => Bytes::from(text),
+    Either::B(bytes) => buffer::from_js(bytes),
+  }
+}
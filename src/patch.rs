@@ -0,0 +1,309 @@
+//! Bodies of `PATCH` requests: JSON Merge Patch (RFC 7386) and JSON Patch
+//! (RFC 6902).
+//!
+//! Both are parsed and validated before the handler runs. Merge patches are
+//! handed over as `req.mergePatch`, JSON Patch documents as `req.patch`, an
+//! array of operations whose required members are known to be present.
+//! Malformed documents are rejected with `400`.
+
+use serde_json::{Map, Value};
+
+use crate::json::ParseError;
+use crate::rejection::Rejection;
+
+const MERGE_PATCH: &str = "application/merge-patch+json";
+const JSON_PATCH: &str = "application/json-patch+json";
+
+/// A parsed patch document, stored in the request extensions.
+#[derive(Clone, Debug)]
+pub enum Patch {
+  Merge(Value),
+  Json(Vec<Operation>),
+}
+
+/// A JSON Patch operation.
+#[derive(Clone, Debug)]
+pub struct Operation {
+  pub op: Op,
+  pub path: String,
+  /// Source of `move` and `copy`.
+  pub from: Option<String>,
+  /// Value of `add`, `replace` and `test`.
+  pub value: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+  Add,
+  Remove,
+  Replace,
+  Move,
+  Copy,
+  Test,
+}
+
+impl Op {
+  fn parse(op: &str) -> Option<Self> {
+    match op {
+      "add" => Some(Op::Add),
+      "remove" => Some(Op::Remove),
+      "replace" => Some(Op::Replace),
+      "move" => Some(Op::Move),
+      "copy" => Some(Op::Copy),
+      "test" => Some(Op::Test),
+      _ => None,
+    }
+  }
+
+  fn as_str(self) -> &'static str {
+    match self {
+      Op::Add => "add",
+      Op::Remove => "remove",
+      Op::Replace => "replace",
+      Op::Move => "move",
+      Op::Copy => "copy",
+      Op::Test => "test",
+    }
+  }
+}
+
+/// Returns `true` if the `Content-Type` is one of the patch formats.
+pub fn is_patch(content_type: &str) -> bool {
+  matches!(mime(content_type).as_str(), MERGE_PATCH | JSON_PATCH)
+}
+
+fn mime(content_type: &str) -> String {
+  content_type
+    .split(';')
+    .next()
+    .unwrap_or_default()
+    .trim()
+    .to_ascii_lowercase()
+}
+
+/// Parses and validates a patch body of the given `Content-Type`.
+pub fn parse(content_type: &str, body: &[u8]) -> Result<Patch, Rejection> {
+  let document: Value = serde_json::from_slice(body).map_err(|err| {
+    Rejection::Json(ParseError {
+      message: err.to_string(),
+      line: Some(err.line()),
+      column: Some(err.column()),
+    })
+  })?;
+
+  if mime(content_type) == MERGE_PATCH {
+    return Ok(Patch::Merge(document));
+  }
+
+  let Value::Array(operations) = document else {
+    return Err(invalid("a JSON Patch document must be an array"));
+  };
+  operations
+    .into_iter()
+    .enumerate()
+    .map(|(index, operation)| {
+      operation_from(operation).map_err(|message| invalid(format!("operation {index}: {message}")))
+    })
+    .collect::<Result<_, _>>()
+    .map(Patch::Json)
+}
+
+fn invalid(message: impl Into<String>) -> Rejection {
+  Rejection::Patch {
+    message: message.into(),
+  }
+}
+
+fn operation_from(operation: Value) -> Result<Operation, String> {
+  let Value::Object(mut operation) = operation else {
+    return Err(String::from("must be an object"));
+  };
+
+  let op = match operation.remove("op") {
+    Some(Value::String(op)) => Op::parse(&op).ok_or_else(|| format!("unknown op `{op}`"))?,
+    _ => return Err(String::from("missing `op`")),
+  };
+  let path = pointer(&mut operation, "path")?.ok_or("missing `path`")?;
+
+  let from = match op {
+    Op::Move | Op::Copy => {
+      let from = pointer(&mut operation, "from")?.ok_or("missing `from`")?;
+      // A value can't be moved into one of its own children.
+      if op == Op::Move && path.starts_with(&format!("{from}/")) {
+        return Err(format!("cannot move `{from}` into its child `{path}`"));
+      }
+      Some(from)
+    }
+    _ => None,
+  };
+
+  let value = match op {
+    Op::Add | Op::Replace | Op::Test => Some(operation.remove("value").ok_or("missing `value`")?),
+    _ => None,
+  };
+
+  Ok(Operation {
+    op,
+    path,
+    from,
+    value,
+  })
+}
+
+/// Takes a JSON Pointer (RFC 6901) member out of an operation.
+fn pointer(operation: &mut Map<String, Value>, member: &str) -> Result<Option<String>, String> {
+  let pointer = match operation.remove(member) {
+    Some(Value::String(pointer)) => pointer,
+    Some(_) => return Err(format!("`{member}` must be a string")),
+    None => return Ok(None),
+  };
+
+  if !pointer.is_empty() && !pointer.starts_with('/') {
+    return Err(format!("`{member}` must be empty or start with `/`"));
+  }
+  let mut escapes = pointer.split('~').skip(1);
+  if escapes.any(|escaped| !escaped.starts_with(['0', '1'])) {
+    return Err(format!("`{member}` has an invalid `~` escape"));
+  }
+
+  Ok(Some(pointer))
+}
+
+impl Operation {
+  fn to_value(&self) -> Value {
+    let mut operation = Map::new();
+    operation.insert(String::from("op"), Value::from(self.op.as_str()));
+    operation.insert(String::from("path"), Value::from(self.path.as_str()));
+    if let Some(from) = &self.from {
+      operation.insert(String::from("from"), Value::from(from.as_str()));
+    }
+    if let Some(value) = &self.value {
+      operation.insert(String::from("value"), value.clone());
+    }
+    Value::Object(operation)
+  }
+}
+
+impl Patch {
+  /// The name of the request property the patch is handed over as, and its
+  /// value.
+  pub fn to_value(&self) -> (&'static str, Value) {
+    match self {
+      Patch::Merge(document) => ("mergePatch", document.clone()),
+      Patch::Json(operations) => (
+        "patch",
+        Value::Array(operations.iter().map(Operation::to_value).collect()),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::{is_patch, parse, Op, Patch};
+  use crate::rejection::Rejection;
+
+  const JSON_PATCH: &str = "application/json-patch+json";
+
+  /// Why a JSON Patch document is invalid.
+  fn invalid(body: &str) -> String {
+    match parse(JSON_PATCH, body.as_bytes()) {
+      Err(Rejection::Patch { message }) => message,
+      other => panic!("expected an invalid patch, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn recognizes_both_content_types() {
+    assert!(is_patch("application/merge-patch+json"));
+    assert!(is_patch("Application/JSON-Patch+JSON; charset=utf-8"));
+    assert!(!is_patch("application/json"));
+  }
+
+  #[test]
+  fn takes_any_json_as_a_merge_patch() {
+    let Ok(Patch::Merge(document)) = parse("application/merge-patch+json", b"{\"a\":null}") else {
+      panic!("not a merge patch");
+    };
+    assert_eq!(document, json!({ "a": null }));
+  }
+
+  #[test]
+  fn parses_operations() {
+    let body = br#"[
+      { "op": "add", "path": "/a/-", "value": 1 },
+      { "op": "remove", "path": "" },
+      { "op": "move", "from": "/a", "path": "/ab" },
+      { "op": "copy", "from": "/m~1n", "path": "/c~0d" },
+      { "op": "test", "path": "/x", "value": null }
+    ]"#;
+    let Ok(Patch::Json(operations)) = parse(JSON_PATCH, body) else {
+      panic!("not a JSON Patch");
+    };
+    let ops: Vec<_> = operations.iter().map(|operation| operation.op).collect();
+    assert_eq!(ops, [Op::Add, Op::Remove, Op::Move, Op::Copy, Op::Test]);
+    assert_eq!(operations[3].from.as_deref(), Some("/m~1n"));
+    assert_eq!(operations[4].value, Some(json!(null)));
+  }
+
+  #[test]
+  fn rejects_malformed_json_with_its_position() {
+    match parse(JSON_PATCH, b"[\n{]") {
+      Err(Rejection::Json(err)) => assert_eq!(err.line, Some(2)),
+      other => panic!("expected a JSON error, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_operations() {
+    assert_eq!(invalid("{}"), "a JSON Patch document must be an array");
+    assert_eq!(invalid("[1]"), "operation 0: must be an object");
+    assert_eq!(invalid(r#"[{"path":"/a"}]"#), "operation 0: missing `op`");
+    assert_eq!(
+      invalid(r#"[{"op":"remove","path":"/a"},{"op":"frob","path":"/a"}]"#),
+      "operation 1: unknown op `frob`"
+    );
+    assert_eq!(
+      invalid(r#"[{"op":"remove"}]"#),
+      "operation 0: missing `path`"
+    );
+    assert_eq!(
+      invalid(r#"[{"op":"remove","path":3}]"#),
+      "operation 0: `path` must be a string"
+    );
+    assert_eq!(
+      invalid(r#"[{"op":"add","path":"/a"}]"#),
+      "operation 0: missing `value`"
+    );
+    assert_eq!(
+      invalid(r#"[{"op":"copy","path":"/a"}]"#),
+      "operation 0: missing `from`"
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_pointers() {
+    assert_eq!(
+      invalid(r#"[{"op":"remove","path":"a"}]"#),
+      "operation 0: `path` must be empty or start with `/`"
+    );
+    assert_eq!(
+      invalid(r#"[{"op":"remove","path":"/a~2"}]"#),
+      "operation 0: `path` has an invalid `~` escape"
+    );
+    assert_eq!(
+      invalid(r#"[{"op":"remove","path":"/a~"}]"#),
+      "operation 0: `path` has an invalid `~` escape"
+    );
+  }
+
+  #[test]
+  fn rejects_moving_a_value_into_its_child() {
+    assert_eq!(
+      invalid(r#"[{"op":"move","from":"/a","path":"/a/b"}]"#),
+      "operation 0: cannot move `/a` into its child `/a/b`"
+    );
+  }
+}
@@ -0,0 +1,1000 @@
+//! Reverse proxying, without going through JS.
+//!
+//! `app.proxy("/api", "http://localhost:4000")` forwards every request
+//! under `/api` to the upstream with the app's pooled client, before
+//! routing; under several prefixes, the longest one's. Bodies are streamed
+//! both ways, never buffered:
+//!
+//! - the path is appended to the upstream URL's path, after `rewrite`
+//!   replaces the prefix if set,
+//! - hop-by-hop headers are dropped in both directions,
+//! - `Host` becomes the upstream's, `localhost` for a `unix:` socket,
+//!   unless `preserveHost` is set,
+//! - `X-Forwarded-For`, `X-Forwarded-Host` and `X-Forwarded-Proto` tell the
+//!   upstream about the client, unless `xForwarded` is `false`.
+//!
+//! An upstream that can't be reached gets `502`, and one slower than
+//! `timeout` to answer gets `504`.
+//!
+//! Proxied requests count against the app's quota and their tenant's, as
+//! routed ones do. The checks that need the body are left to the upstream:
+//! neither `maxBodySize` nor `app.verifySignatures()` applies to them.
+//!
+//! Given several upstreams, each request goes to one of them, in
+//! proportion to their weights:
+//!
+//! - `"round-robin"`, the default, takes them in turn,
+//! - `"random"` draws one at random,
+//! - `"hash"` hashes the path, a header or a cookie onto a ring of
+//!   upstreams, so requests with the same key reach the same upstream, and
+//!   adding or removing one only moves the keys of its neighbours.
+//!
+//! With `hedge`, a `GET` or `HEAD` still unanswered after the proxy's usual
+//! response time, a percentile of the last 256 that didn't fail, is sent to
+//! another upstream too, and whichever answers first is used. The other
+//! request is dropped, closing its connection. Nothing is hedged until 256
+//! responses have been seen, nor are requests with a body.
+//!
+//! With `affinity`, responses set a cookie naming the upstream that
+//! answered, by a hash of its URL, and requests carrying it go back to that
+//! upstream while it isn't ejected, whatever the balance.
+//!
+//! With `rewriteHtml`, HTML responses are rewritten as they stream through,
+//! as described in [`crate::html`].
+//!
+//! Requests, failures and latency are counted per upstream, for
+//! `app.upstreamMetrics()`. With `outlierDetection`, an upstream failing
+//! several requests in a row, by not answering, answering `5xx` or being
+//! slower than `maxLatency`, is left out for a cooldown, and an `ejection`
+//! event is emitted. The last upstream available is never ejected.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::Either;
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::http::request::Parts;
+use hyper::http::uri::{Authority, Scheme};
+use hyper::{Method, StatusCode, Uri};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::client::{self, Client, UnixSocket};
+use crate::error::{ActixError, ErrorCode};
+use crate::events::{Event, Events};
+use crate::html::{HtmlRewrite, HtmlRewriteOptions};
+use crate::http::{Body, ConnectionInfo, Request, Response, ResponseBuilder};
+
+const FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Headers that only concern one connection (RFC 9110, section 7.6.1).
+const HOP_BY_HOP: [&str; 9] = [
+  "connection",
+  "keep-alive",
+  "proxy-authenticate",
+  "proxy-authorization",
+  "proxy-connection",
+  "te",
+  "trailer",
+  "transfer-encoding",
+  "upgrade",
+];
+
+/// Points each unit of weight puts on the hash ring.
+const POINTS_PER_WEIGHT: u32 = 100;
+/// Highest weight of an upstream, which keeps the ring to 100,000 points
+/// per upstream.
+const MAX_WEIGHT: u32 = 1000;
+
+/// Responses the hedging delay is taken from.
+const HEDGE_SAMPLES: usize = 256;
+/// Responses between two updates of the hedging delay.
+const HEDGE_REFRESH: usize = 32;
+
+/// Failures in a row that eject an upstream by default.
+const CONSECUTIVE_FAILURES: u32 = 5;
+/// How long an upstream stays ejected by default.
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// An upstream of `app.proxy()`, with its share of the requests.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamOptions {
+  /// An absolute `http:` or `https:` URL, or `unix:/path/to.sock` with an
+  /// optional `:/base/path`.
+  pub url: String,
+  /// Relative share of the requests, from 1 to 1000. Defaults to 1.
+  pub weight: Option<u32>,
+}
+
+/// When upstreams are left out for failing.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct OutlierOptions {
+  /// Failed requests in a row that eject an upstream. Defaults to 5.
+  pub consecutive_failures: Option<u32>,
+  /// Milliseconds after which a response counts as a failure. Unlimited by
+  /// default.
+  pub max_latency: Option<u32>,
+  /// Milliseconds an ejected upstream is left out. Defaults to 30000.
+  pub cooldown: Option<u32>,
+}
+
+/// Keeps clients on the upstream that first answered them, with a cookie.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct AffinityOptions {
+  /// Name of the cookie. Defaults to `"upstream"`.
+  pub cookie: Option<String>,
+  /// Seconds the cookie lasts. By default it ends with the browser session.
+  pub max_age: Option<u32>,
+}
+
+/// When idempotent requests are sent a second time.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct HedgeOptions {
+  /// Percentile of the proxy's recent response times after which a `GET`
+  /// or `HEAD` without an answer is sent to another upstream as well, above
+  /// 0 and below 100. Defaults to 95.
+  pub percentile: Option<f64>,
+}
+
+/// How an upstream has fared, as returned by `app.upstreamMetrics()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamSnapshot {
+  /// The prefix of the proxy.
+  pub prefix: String,
+  pub url: String,
+  pub requests: f64,
+  pub failures: f64,
+  /// Average time to the response headers, in milliseconds.
+  pub average_latency: f64,
+  pub ejections: f64,
+  /// Requests sent to the upstream as hedges of slow ones.
+  pub hedges: f64,
+  /// Whether the upstream is currently left out.
+  pub ejected: bool,
+}
+
+/// What `balance: "hash"` hashes. The path, unless a header or a cookie is
+/// named.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct HashOnOptions {
+  pub header: Option<String>,
+  pub cookie: Option<String>,
+}
+
+/// Options for `app.proxy()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ProxyOptions {
+  /// Replaces the prefix in forwarded paths; `""` strips it. By default
+  /// paths are forwarded whole.
+  pub rewrite: Option<String>,
+  /// Forwards the client's `Host` header rather than the upstream's.
+  /// Defaults to `false`.
+  pub preserve_host: Option<bool>,
+  /// Adds the `X-Forwarded-*` headers. Defaults to `true`.
+  pub x_forwarded: Option<bool>,
+  /// Milliseconds to wait for the upstream's response headers. Unlimited
+  /// by default.
+  pub timeout: Option<u32>,
+  /// How requests are spread over several upstreams: `"round-robin"`,
+  /// `"random"` or `"hash"`. Defaults to `"round-robin"`.
+  #[napi(ts_type = "'round-robin' | 'random' | 'hash'")]
+  pub balance: Option<String>,
+  /// The key of `"hash"`.
+  pub hash_on: Option<HashOnOptions>,
+  /// Leaves out upstreams that keep failing. Off by default.
+  pub outlier_detection: Option<OutlierOptions>,
+  /// Sends slow idempotent requests to a second upstream. Off by default.
+  pub hedge: Option<HedgeOptions>,
+  /// Sends requests with the affinity cookie to the upstream it names, and
+  /// sets it on responses to those without it. Off by default.
+  pub affinity: Option<AffinityOptions>,
+  /// Rewrites HTML responses as they stream through.
+  pub rewrite_html: Option<HtmlRewriteOptions>,
+}
+
+/// Requests under a prefix, forwarded to upstreams.
+pub struct Proxy {
+  prefix: String,
+  upstreams: Vec<Upstream>,
+  balance: Balance,
+  rewrite: Option<String>,
+  preserve_host: bool,
+  x_forwarded: bool,
+  timeout: Option<Duration>,
+  outliers: Option<Outliers>,
+  hedge: Option<Hedge>,
+  affinity: Option<Affinity>,
+  html: Option<Arc<HtmlRewrite>>,
+  /// Held while deciding whether to eject an upstream and ejecting it, so
+  /// that two failing upstreams can't eject the last ones available.
+  ejecting: Mutex<()>,
+  /// What the ejection deadlines of the upstreams count from.
+  epoch: Instant,
+  client: Client,
+  events: Arc<Events>,
+}
+
+struct Upstream {
+  url: String,
+  scheme: Scheme,
+  authority: Authority,
+  /// The upstream URL's path, without its trailing `/`.
+  base: String,
+  /// The socket of a `unix:` upstream, whose authority is `localhost`.
+  socket: Option<PathBuf>,
+  weight: u32,
+  stats: Mutex<Stats>,
+  /// Milliseconds since the proxy's epoch until which the upstream is left
+  /// out, read on every request without locking `stats`.
+  ejected_until: AtomicU64,
+}
+
+#[derive(Default)]
+struct Stats {
+  requests: u64,
+  failures: u64,
+  /// Failures since the last success.
+  consecutive_failures: u32,
+  latency: Duration,
+  ejections: u64,
+  hedges: u64,
+}
+
+struct Outliers {
+  consecutive_failures: u32,
+  max_latency: Option<Duration>,
+  cooldown: Duration,
+}
+
+/// The affinity cookie, and what it's set to for each upstream.
+struct Affinity {
+  cookie: String,
+  /// The value naming each upstream, by index.
+  ids: Vec<String>,
+  /// The `Set-Cookie` header naming each upstream, by index.
+  set_cookies: Vec<HeaderValue>,
+}
+
+/// The response times hedging goes by.
+struct Hedge {
+  /// From 0 to 1.
+  quantile: f64,
+  /// The response times of the last `HEDGE_SAMPLES` requests that didn't
+  /// fail, as a ring, with the index of the oldest once full.
+  latencies: Mutex<(Vec<Duration>, usize)>,
+  /// Microseconds, 0 until there are `HEDGE_SAMPLES` response times.
+  delay: AtomicU64,
+}
+
+impl Hedge {
+  /// How long to wait before hedging a request, once it's known.
+  fn delay(&self) -> Option<Duration> {
+    match self.delay.load(Ordering::Relaxed) {
+      0 => None,
+      micros => Some(Duration::from_micros(micros)),
+    }
+  }
+
+  fn observe(&self, latency: Duration) {
+    let mut latencies = self.latencies.lock().unwrap();
+    let (samples, oldest) = &mut *latencies;
+    if samples.len() < HEDGE_SAMPLES {
+      samples.push(latency);
+      if samples.len() < HEDGE_SAMPLES {
+        return;
+      }
+    } else {
+      samples[*oldest] = latency;
+      *oldest = (*oldest + 1) % HEDGE_SAMPLES;
+      if *oldest % HEDGE_REFRESH != 0 {
+        return;
+      }
+    }
+
+    let mut sorted = samples.clone();
+    sorted.sort_unstable();
+    let idx = ((HEDGE_SAMPLES - 1) as f64 * self.quantile).round() as usize;
+    let micros = sorted[idx].as_micros().max(1) as u64;
+    self.delay.store(micros, Ordering::Relaxed);
+  }
+}
+
+/// The upstream a request went to, its response and how long it took.
+type Attempt<'u> = (
+  &'u Upstream,
+  std::result::Result<hyper::Response<hyper::Body>, ActixError>,
+  Duration,
+);
+
+/// Why a request counts as failed.
+#[derive(Clone, Copy, Debug)]
+enum Failure {
+  /// No response, or a `5xx` one.
+  Error,
+  /// A response slower than `maxLatency`.
+  Latency,
+}
+
+impl Failure {
+  fn reason(self) -> &'static str {
+    match self {
+      Failure::Error => "error",
+      Failure::Latency => "latency",
+    }
+  }
+}
+
+/// How an upstream is chosen for a request.
+enum Balance {
+  RoundRobin(AtomicUsize),
+  Random(SystemRandom),
+  Hash {
+    key: HashKey,
+    /// Points of the upstreams, by hash, each with the index of its
+    /// upstream.
+    ring: Vec<(u64, usize)>,
+  },
+}
+
+enum HashKey {
+  Path,
+  Header(HeaderName),
+  Cookie(String),
+}
+
+impl Proxy {
+  pub fn new(
+    prefix: &str,
+    upstreams: Vec<UpstreamOptions>,
+    options: ProxyOptions,
+    client: Client,
+    events: Arc<Events>,
+  ) -> std::result::Result<Self, ActixError> {
+    let invalid = |message: String| ActixError::new(ErrorCode::InvalidOption, message);
+    if upstreams.is_empty() {
+      return Err(invalid("a proxy needs at least one upstream".into()));
+    }
+    let upstreams = upstreams
+      .iter()
+      .map(Upstream::new)
+      .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let balance = match options.balance.as_deref() {
+      None | Some("round-robin") => Balance::RoundRobin(AtomicUsize::new(0)),
+      Some("random") => Balance::Random(SystemRandom::new()),
+      Some("hash") => {
+        let hash_on = options.hash_on.unwrap_or_default();
+        let key = match (hash_on.header, hash_on.cookie) {
+          (Some(_), Some(_)) => {
+            return Err(invalid(
+              "`hashOn` takes a header or a cookie, not both".into(),
+            ))
+          }
+          (Some(name), None) => HashKey::Header(
+            HeaderName::from_bytes(name.as_bytes())
+              .map_err(|_| invalid(format!("invalid header name `{name}`")))?,
+          ),
+          (None, Some(name)) => HashKey::Cookie(name),
+          (None, None) => HashKey::Path,
+        };
+        Balance::Hash {
+          key,
+          ring: ring(&upstreams),
+        }
+      }
+      Some(other) => {
+        return Err(invalid(format!(
+          "unknown balance `{other}`, expected `round-robin`, `random` or `hash`"
+        )))
+      }
+    };
+
+    let hedge = options
+      .hedge
+      .map(|hedge| {
+        let percentile = hedge.percentile.unwrap_or(95.0);
+        if !(percentile > 0.0 && percentile < 100.0) {
+          return Err(invalid(
+            "the hedging percentile must be above 0 and below 100".into(),
+          ));
+        }
+        Ok(Hedge {
+          quantile: percentile / 100.0,
+          latencies: Mutex::new((Vec::with_capacity(HEDGE_SAMPLES), 0)),
+          delay: AtomicU64::new(0),
+        })
+      })
+      .transpose()?;
+
+    let prefix = prefix.trim_end_matches('/');
+    let affinity = options
+      .affinity
+      .map(|affinity| {
+        let cookie = affinity.cookie.unwrap_or_else(|| "upstream".into());
+        if cookie.is_empty() || !cookie.bytes().all(is_tchar) {
+          return Err(invalid(format!("invalid cookie name `{cookie}`")));
+        }
+        let path = if prefix.is_empty() { "/" } else { prefix };
+        // Upstream URLs are kept from clients.
+        let ids: Vec<String> = upstreams
+          .iter()
+          .map(|upstream| format!("{:016x}", hash(&upstream.url)))
+          .collect();
+        let set_cookies = ids
+          .iter()
+          .map(|id| {
+            let mut value = format!("{cookie}={id}; Path={path}; HttpOnly; SameSite=Lax");
+            if let Some(max_age) = affinity.max_age {
+              value.push_str(&format!("; Max-Age={max_age}"));
+            }
+            HeaderValue::try_from(value)
+              .map_err(|_| invalid(format!("invalid cookie path `{path}`")))
+          })
+          .collect::<std::result::Result<_, _>>()?;
+        Ok(Affinity {
+          cookie,
+          ids,
+          set_cookies,
+        })
+      })
+      .transpose()?;
+
+    Ok(Proxy {
+      prefix: prefix.to_owned(),
+      upstreams,
+      balance,
+      rewrite: options
+        .rewrite
+        .map(|rewrite| rewrite.trim_end_matches('/').to_owned()),
+      preserve_host: options.preserve_host.unwrap_or(false),
+      x_forwarded: options.x_forwarded.unwrap_or(true),
+      timeout: options.timeout.map(|ms| Duration::from_millis(ms.into())),
+      outliers: options.outlier_detection.map(|outliers| Outliers {
+        consecutive_failures: outliers
+          .consecutive_failures
+          .unwrap_or(CONSECUTIVE_FAILURES)
+          .max(1),
+        max_latency: outliers
+          .max_latency
+          .map(|ms| Duration::from_millis(ms.into())),
+        cooldown: outliers
+          .cooldown
+          .map_or(COOLDOWN, |ms| Duration::from_millis(ms.into())),
+      }),
+      hedge,
+      affinity,
+      html: options
+        .rewrite_html
+        .map(|options| Arc::new(HtmlRewrite::new(options))),
+      ejecting: Mutex::new(()),
+      epoch: Instant::now(),
+      client,
+      events,
+    })
+  }
+
+  pub fn prefix(&self) -> &str {
+    &self.prefix
+  }
+
+  /// Whether a request path is under the prefix.
+  pub fn matches(&self, path: &str) -> bool {
+    path
+      .strip_prefix(self.prefix.as_str())
+      .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+  }
+
+  /// The upstream `parts` are sent to, past ejected ones.
+  fn select(&self, parts: &Parts) -> &Upstream {
+    if let [upstream] = self.upstreams.as_slice() {
+      return upstream;
+    }
+    let now = self.now();
+    if let Some(upstream) = self.affine(parts) {
+      if !upstream.ejected(now) {
+        return upstream;
+      }
+    }
+    // If every upstream is ejected, none is left out.
+    let skip_ejected = self.upstreams.iter().any(|upstream| !upstream.ejected(now));
+    let available = |upstream: &Upstream| !skip_ejected || !upstream.ejected(now);
+
+    let ticket = match &self.balance {
+      Balance::RoundRobin(next) => next.fetch_add(1, Ordering::Relaxed) as u64,
+      Balance::Random(random) => {
+        let mut bytes = [0; 8];
+        // The system's generator doesn't fail once it works at all.
+        let _ = random.fill(&mut bytes);
+        u64::from_ne_bytes(bytes)
+      }
+      Balance::Hash { key, ring } => {
+        let keyed = match key {
+          HashKey::Path => Some(hash(parts.uri.path())),
+          HashKey::Header(name) => parts.headers.get(name).map(|value| hash(value.as_bytes())),
+          HashKey::Cookie(name) => cookie(&parts.headers, name).map(hash),
+        };
+        if let Some(keyed) = keyed {
+          // The first point past the key, moving on from those of ejected
+          // upstreams.
+          let start = ring.partition_point(|(point, _)| *point < keyed);
+          let upstream = ring
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(ring.len())
+            .map(|(_, idx)| &self.upstreams[*idx])
+            .find(|upstream| available(upstream));
+          return upstream.unwrap_or(&self.upstreams[0]);
+        }
+        // Requests without the key are spread by their path.
+        hash(parts.uri.path())
+      }
+    };
+
+    let total: u64 = self
+      .upstreams
+      .iter()
+      .filter(|upstream| available(upstream))
+      .map(|upstream| upstream.weight as u64)
+      .sum();
+    let mut ticket = ticket % total;
+    for upstream in self.upstreams.iter().filter(|upstream| available(upstream)) {
+      match ticket.checked_sub(upstream.weight.into()) {
+        Some(rest) => ticket = rest,
+        None => return upstream,
+      }
+    }
+    &self.upstreams[0]
+  }
+
+  pub async fn forward(&self, req: Request) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let upstream = self.select(&parts);
+
+    let path = parts.uri.path();
+    let mut path = match &self.rewrite {
+      Some(rewrite) => format!("{rewrite}{}", &path[self.prefix.len()..]),
+      None => path.to_owned(),
+    };
+    if let Some(query) = parts.uri.query() {
+      path.push('?');
+      path.push_str(query);
+    }
+
+    let client_host = parts.headers.get(header::HOST).cloned();
+    let peer = parts
+      .extensions
+      .get::<ConnectionInfo>()
+      .and_then(ConnectionInfo::peer_addr);
+    let headers = &mut parts.headers;
+    strip_hop_by_hop(headers);
+    if self.x_forwarded {
+      if let Some(peer) = peer {
+        let forwarded = match headers.get(FORWARDED_FOR) {
+          Some(previous) => format!("{}, {}", previous.to_str().unwrap_or_default(), peer.ip()),
+          None => peer.ip().to_string(),
+        };
+        if let Ok(forwarded) = HeaderValue::try_from(forwarded) {
+          headers.insert(FORWARDED_FOR, forwarded);
+        }
+      }
+      if let Some(host) = client_host {
+        headers.insert(FORWARDED_HOST, host);
+      }
+      // The server does not terminate TLS.
+      headers.insert(FORWARDED_PROTO, HeaderValue::from_static("http"));
+    }
+
+    // Only requests without side effects or a body can be sent twice.
+    let hedge = self
+      .hedge
+      .as_ref()
+      .filter(|_| matches!(parts.method, Method::GET | Method::HEAD))
+      .filter(|_| body.0.is_end_stream())
+      .and_then(Hedge::delay);
+    let Some(outgoing) = self.outgoing(upstream, &parts, &path, body.0) else {
+      return status(StatusCode::BAD_REQUEST);
+    };
+
+    let started = Instant::now();
+    let sent = async {
+      match hedge {
+        Some(delay) => self.hedged(upstream, outgoing, &parts, &path, delay).await,
+        None => self.attempt(upstream, outgoing).await,
+      }
+    };
+    let (upstream, res, latency) = match self.timeout {
+      Some(timeout) => match tokio::time::timeout(timeout, sent).await {
+        Ok(sent) => sent,
+        Err(_) => {
+          self.record(upstream, Some(Failure::Error), started.elapsed());
+          return status(StatusCode::GATEWAY_TIMEOUT);
+        }
+      },
+      None => sent.await,
+    };
+    let res = match res {
+      Ok(res) => res,
+      Err(err) => {
+        eprintln!("Proxy to {} failed: {err}", upstream.url);
+        self.record(upstream, Some(Failure::Error), latency);
+        return status(StatusCode::BAD_GATEWAY);
+      }
+    };
+    let slow = self
+      .outliers
+      .as_ref()
+      .and_then(|outliers| outliers.max_latency)
+      .is_some_and(|max_latency| latency > max_latency);
+    let failure = match res.status() {
+      status if status.is_server_error() => Some(Failure::Error),
+      _ if slow => Some(Failure::Latency),
+      _ => None,
+    };
+    self.record(upstream, failure, latency);
+    if let (Some(hedge), None) = (&self.hedge, failure) {
+      hedge.observe(latency);
+    }
+
+    let affine = self.affine(&parts);
+    let (mut res_parts, mut body) = res.into_parts();
+    strip_hop_by_hop(&mut res_parts.headers);
+    if let Some(html) = &self.html {
+      // Ranges are of the upstream's bytes.
+      if res_parts.status != StatusCode::PARTIAL_CONTENT && HtmlRewrite::applies(&res_parts.headers)
+      {
+        res_parts.headers.remove(header::CONTENT_LENGTH);
+        body = html.stream(body);
+      }
+    }
+    if let Some(affinity) = &self.affinity {
+      if !affine.is_some_and(|affine| std::ptr::eq(affine, upstream)) {
+        let set_cookie = affinity.set_cookies[self.index(upstream)].clone();
+        res_parts.headers.append(header::SET_COOKIE, set_cookie);
+      }
+    }
+    Response::from_parts(res_parts, Body(body))
+  }
+
+  /// The upstream the request's affinity cookie names, if any.
+  fn affine(&self, parts: &Parts) -> Option<&Upstream> {
+    let affinity = self.affinity.as_ref()?;
+    let id = cookie(&parts.headers, &affinity.cookie)?;
+    let idx = affinity.ids.iter().position(|other| other == id)?;
+    Some(&self.upstreams[idx])
+  }
+
+  /// Where `upstream` is in the list of upstreams.
+  fn index(&self, upstream: &Upstream) -> usize {
+    self
+      .upstreams
+      .iter()
+      .position(|other| std::ptr::eq(other, upstream))
+      .unwrap_or(0)
+  }
+
+  /// The request sent to `upstream` for `path`, a path and query relative to
+  /// its base.
+  fn outgoing(
+    &self,
+    upstream: &Upstream,
+    parts: &Parts,
+    path: &str,
+    body: hyper::Body,
+  ) -> Option<hyper::Request<hyper::Body>> {
+    let mut target = format!("{}{path}", upstream.base);
+    if !target.starts_with('/') {
+      target.insert(0, '/');
+    }
+    // Requests over a socket are sent in origin form.
+    let uri = match upstream.socket {
+      Some(_) => Uri::builder().path_and_query(target).build(),
+      None => Uri::builder()
+        .scheme(upstream.scheme.clone())
+        .authority(upstream.authority.clone())
+        .path_and_query(target)
+        .build(),
+    };
+
+    let mut outgoing = hyper::Request::new(body);
+    *outgoing.method_mut() = parts.method.clone();
+    *outgoing.uri_mut() = uri.ok()?;
+    *outgoing.headers_mut() = parts.headers.clone();
+    if !self.preserve_host {
+      if let Ok(host) = HeaderValue::from_str(upstream.authority.as_str()) {
+        outgoing.headers_mut().insert(header::HOST, host);
+      }
+    }
+    if let Some(socket) = &upstream.socket {
+      outgoing.extensions_mut().insert(UnixSocket(socket.clone()));
+    }
+    Some(outgoing)
+  }
+
+  /// Sends a request to `upstream`, with how long its response took.
+  async fn attempt<'u>(
+    &self,
+    upstream: &'u Upstream,
+    outgoing: hyper::Request<hyper::Body>,
+  ) -> Attempt<'u> {
+    let started = Instant::now();
+    let res = self.client.send(outgoing).await;
+    (upstream, res, started.elapsed())
+  }
+
+  /// Sends `outgoing` to `upstream`, and the same request to another
+  /// upstream if no response came within `delay`. The first response wins
+  /// and the other request is dropped, closing its connection; a failed
+  /// attempt leaves the other one to answer.
+  async fn hedged<'u>(
+    &'u self,
+    upstream: &'u Upstream,
+    outgoing: hyper::Request<hyper::Body>,
+    parts: &Parts,
+    path: &str,
+    delay: Duration,
+  ) -> Attempt<'u> {
+    let first = self.attempt(upstream, outgoing);
+    tokio::pin!(first);
+    tokio::select! {
+      attempt = &mut first => return attempt,
+      _ = tokio::time::sleep(delay) => {}
+    }
+
+    let other = self.alternate(upstream);
+    let Some(outgoing) = self.outgoing(other, parts, path, hyper::Body::empty()) else {
+      return first.await;
+    };
+    other.stats.lock().unwrap().hedges += 1;
+    let second = self.attempt(other, outgoing);
+    tokio::pin!(second);
+
+    let (done, pending) = tokio::select! {
+      attempt = &mut first => (attempt, Either::Right(second)),
+      attempt = &mut second => (attempt, Either::Left(first)),
+    };
+    if let (upstream, Err(err), latency) = &done {
+      eprintln!("Proxy to {} failed: {err}", upstream.url);
+      self.record(upstream, Some(Failure::Error), *latency);
+      return pending.await;
+    }
+    done
+  }
+
+  /// Another upstream than `upstream` to hedge with, past ejected ones, or
+  /// `upstream` itself if it's the only one available.
+  fn alternate<'u>(&'u self, upstream: &'u Upstream) -> &'u Upstream {
+    let now = self.now();
+    let idx = self.index(upstream);
+    self
+      .upstreams
+      .iter()
+      .cycle()
+      .skip(idx + 1)
+      .take(self.upstreams.len() - 1)
+      .find(|other| !other.ejected(now))
+      .unwrap_or(upstream)
+  }
+}
+
+impl Proxy {
+  /// Counts a request to `upstream`, ejecting it if it keeps failing and
+  /// another upstream is available.
+  fn record(&self, upstream: &Upstream, failure: Option<Failure>, latency: Duration) {
+    let failures = {
+      let mut stats = upstream.stats.lock().unwrap();
+      stats.requests += 1;
+      stats.latency += latency;
+      if failure.is_none() {
+        stats.consecutive_failures = 0;
+        return;
+      }
+      stats.failures += 1;
+      stats.consecutive_failures += 1;
+      stats.consecutive_failures
+    };
+    let (Some(outliers), Some(failure)) = (&self.outliers, failure) else {
+      return;
+    };
+    if failures < outliers.consecutive_failures {
+      return;
+    }
+
+    {
+      let _ejecting = self.ejecting.lock().unwrap();
+      let now = self.now();
+      let others = self
+        .upstreams
+        .iter()
+        .any(|other| !std::ptr::eq(other, upstream) && !other.ejected(now));
+      if upstream.ejected(now) || !others {
+        return;
+      }
+      let cooldown = outliers.cooldown.as_millis() as u64;
+      upstream
+        .ejected_until
+        .store(now + cooldown, Ordering::Relaxed);
+
+      let mut stats = upstream.stats.lock().unwrap();
+      stats.ejections += 1;
+      stats.consecutive_failures = 0;
+    }
+    self.events.emit(Event::Ejection {
+      prefix: self.prefix.clone(),
+      upstream: upstream.url.clone(),
+      reason: failure.reason(),
+      cooldown: outliers.cooldown,
+    });
+  }
+
+  /// How each upstream has fared so far.
+  pub fn snapshot(&self) -> Vec<UpstreamSnapshot> {
+    let now = self.now();
+    self
+      .upstreams
+      .iter()
+      .map(|upstream| {
+        let stats = upstream.stats.lock().unwrap();
+        let average_latency = match stats.requests {
+          0 => 0.0,
+          requests => stats.latency.as_secs_f64() * 1000.0 / requests as f64,
+        };
+        UpstreamSnapshot {
+          prefix: self.prefix.clone(),
+          url: upstream.url.clone(),
+          requests: stats.requests as f64,
+          failures: stats.failures as f64,
+          average_latency,
+          ejections: stats.ejections as f64,
+          hedges: stats.hedges as f64,
+          ejected: upstream.ejected(now),
+        }
+      })
+      .collect()
+  }
+
+  /// Milliseconds since the epoch, as ejection deadlines are kept.
+  fn now(&self) -> u64 {
+    self.epoch.elapsed().as_millis() as u64
+  }
+}
+
+impl Upstream {
+  /// Whether the upstream is left out at `now`, as given by `Proxy::now()`.
+  fn ejected(&self, now: u64) -> bool {
+    now < self.ejected_until.load(Ordering::Relaxed)
+  }
+
+  fn new(options: &UpstreamOptions) -> std::result::Result<Self, ActixError> {
+    let invalid = |message: String| ActixError::new(ErrorCode::InvalidOption, message);
+    let weight = options.weight.unwrap_or(1);
+    if !(1..=MAX_WEIGHT).contains(&weight) {
+      return Err(invalid(format!(
+        "upstream weights must be between 1 and {MAX_WEIGHT}"
+      )));
+    }
+    let upstream = |scheme, authority, base: &str, socket| Upstream {
+      url: options.url.clone(),
+      scheme,
+      authority,
+      base: base.trim_end_matches('/').to_owned(),
+      socket,
+      weight,
+      stats: Mutex::default(),
+      ejected_until: AtomicU64::new(0),
+    };
+
+    if let Some((socket, base)) = client::unix_target(&options.url) {
+      if socket.as_os_str().is_empty() {
+        return Err(invalid("the upstream socket path is empty".into()));
+      }
+      if base.contains('?') {
+        return Err(invalid("the upstream URL can't have a query".into()));
+      }
+      let authority = Authority::from_static("localhost");
+      return Ok(upstream(Scheme::HTTP, authority, base, Some(socket)));
+    }
+
+    let target = &options.url;
+    let target: Uri = target
+      .parse()
+      .map_err(|err| invalid(format!("invalid upstream `{target}`: {err}")))?;
+    let (Some(scheme), Some(authority)) = (target.scheme(), target.authority()) else {
+      return Err(invalid(
+        "the upstream must be an absolute `http:` or `https:` URL, or a `unix:` socket".into(),
+      ));
+    };
+    if *scheme != Scheme::HTTP && *scheme != Scheme::HTTPS {
+      return Err(invalid(
+        "the upstream must be an absolute `http:` or `https:` URL, or a `unix:` socket".into(),
+      ));
+    }
+    if target.query().is_some() {
+      return Err(invalid("the upstream URL can't have a query".into()));
+    }
+
+    Ok(upstream(
+      scheme.clone(),
+      authority.clone(),
+      target.path(),
+      None,
+    ))
+  }
+}
+
+/// The hash ring of `upstreams`, with points in proportion to their weights.
+fn ring(upstreams: &[Upstream]) -> Vec<(u64, usize)> {
+  let mut ring = Vec::new();
+  for (idx, upstream) in upstreams.iter().enumerate() {
+    for point in 0..upstream.weight * POINTS_PER_WEIGHT {
+      let id = match &upstream.socket {
+        Some(socket) => format!("{}{}#{point}", socket.display(), upstream.base),
+        None => format!("{}{}#{point}", upstream.authority, upstream.base),
+      };
+      ring.push((hash(id.as_bytes()), idx));
+    }
+  }
+  ring.sort_unstable();
+  ring
+}
+
+fn hash(key: impl AsRef<[u8]>) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  key.as_ref().hash(&mut hasher);
+  hasher.finish()
+}
+
+/// The value of cookie `name`, if the request has it.
+fn cookie<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h str> {
+  headers
+    .get_all(header::COOKIE)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(';'))
+    .filter_map(|pair| pair.trim().split_once('='))
+    .find(|(cookie, _)| *cookie == name)
+    .map(|(_, value)| value)
+}
+
+/// Whether a byte may be part of a token, such as a cookie name (RFC 9110,
+/// section 5.6.2).
+fn is_tchar(byte: u8) -> bool {
+  byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Removes the hop-by-hop headers, and those `Connection` names.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+  let named: Vec<HeaderName> = headers
+    .get_all(header::CONNECTION)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+    .collect();
+  for name in named {
+    headers.remove(name);
+  }
+  for name in HOP_BY_HOP {
+    headers.remove(name);
+  }
+}
+
+fn status(status: StatusCode) -> Response {
+  ResponseBuilder::new()
+    .status(status)
+    .body(Body::empty())
+    .unwrap()
+}
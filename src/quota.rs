@@ -0,0 +1,281 @@
+//! Request quotas keyed by API key.
+//!
+//! A quota counts the requests made with each API key over fixed hourly and
+//! daily windows, aligned to UTC, and rejects requests once a window's limit
+//! is used up. Quotas can be set for a whole app with `app.quota()` and for a
+//! single route through its `quota` option; both apply when present.
+//!
+//! Only requests that pass the route's guard, signature verification and
+//! body checks are counted, so a rejected request can't use up the budget
+//! of the key it carries.
+//!
+//! Counters live in a [`QuotaStore`]. The default [`MemoryStore`] keeps them
+//! in the process; other stores can share counters between processes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::Request;
+use percent_encoding::percent_decode_str;
+
+use crate::error::{ActixError, ErrorCode};
+
+const LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const LEGACY_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const LEGACY_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const LEGACY_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Quota settings, accepted by `app.quota()` and the `quota` route option.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct QuotaOptions {
+  /// Header carrying the API key. Defaults to `x-api-key`.
+  pub header: Option<String>,
+  /// Query string parameter carrying the API key, checked when the header is
+  /// absent.
+  pub query: Option<String>,
+  /// Requests allowed per key and hour.
+  pub hourly: Option<u32>,
+  /// Requests allowed per key and day.
+  pub daily: Option<u32>,
+  /// Rejects requests without an API key with `401`. Otherwise they are not
+  /// counted. Defaults to `false`.
+  pub required: Option<bool>,
+  /// Sends the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+  /// headers of the IETF draft with every metered response. Defaults to
+  /// `true`.
+  pub headers: Option<bool>,
+  /// Also sends the `X-RateLimit-*` headers older clients look for, with the
+  /// reset as a Unix timestamp. Defaults to `true`.
+  pub legacy_headers: Option<bool>,
+}
+
+/// A fixed counting window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Window {
+  Hour,
+  Day,
+}
+
+impl Window {
+  pub fn secs(self) -> u64 {
+    match self {
+      Window::Hour => 60 * 60,
+      Window::Day => 24 * 60 * 60,
+    }
+  }
+}
+
+/// Storage for quota counters.
+pub trait QuotaStore: Send + Sync {
+  /// Counts a request made with `key` in the window starting at `start`
+  /// (seconds since the Unix epoch), returning the number of requests made
+  /// in that window so far, this one included.
+  fn incr(&self, key: &str, window: Window, start: u64) -> u64;
+}
+
+/// Keys the [`MemoryStore`] keeps counters for at most.
+const MAX_KEYS: usize = 100_000;
+
+/// Keeps counters in memory. Counters of windows that ended are dropped
+/// when the next window starts, so only keys used in the current windows
+/// take room. Past [`MAX_KEYS`], keys used only once are dropped to make
+/// room; if none are, new keys are counted without being kept.
+#[derive(Default)]
+pub struct MemoryStore {
+  counters: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+  counts: HashMap<(String, Window), (u64, u64)>,
+  /// Start of the latest window seen, by window.
+  current: HashMap<Window, u64>,
+}
+
+impl QuotaStore for MemoryStore {
+  fn incr(&self, key: &str, window: Window, start: u64) -> u64 {
+    let mut counters = self.counters.lock().unwrap();
+    let Counters { counts, current } = &mut *counters;
+
+    let latest = current.entry(window).or_insert(start);
+    if start > *latest {
+      *latest = start;
+      counts.retain(|(_, kept), counter| *kept != window || counter.0 >= start);
+    }
+
+    let entry = (key.to_owned(), window);
+    if !counts.contains_key(&entry) && counts.len() >= MAX_KEYS {
+      counts.retain(|_, counter| counter.1 > 1);
+      if counts.len() >= MAX_KEYS {
+        return 1;
+      }
+    }
+
+    let counter = counts.entry(entry).or_insert((start, 0));
+    if counter.0 != start {
+      *counter = (start, 0);
+    }
+    counter.1 += 1;
+    counter.1
+  }
+}
+
+/// Where a limit stands after counting a request.
+#[derive(Clone, Copy, Debug)]
+pub struct Usage {
+  pub limit: u64,
+  pub remaining: u64,
+  /// Seconds until the window resets.
+  pub reset: u64,
+  /// When the window resets, in seconds since the Unix epoch.
+  pub reset_at: u64,
+}
+
+/// The outcome of checking a request against a quota.
+#[derive(Debug)]
+pub enum Verdict {
+  /// The request has no API key and keys are optional.
+  Unmetered,
+  /// Holds the usage of the limit closest to running out.
+  Allowed(Usage),
+  /// Holds the usage of the limit that ran out.
+  Exceeded(Usage),
+  MissingKey,
+}
+
+/// A configured quota together with its counters.
+pub struct Quota {
+  header: HeaderName,
+  query: Option<String>,
+  limits: Vec<(Window, u64)>,
+  required: bool,
+  headers: bool,
+  legacy_headers: bool,
+  store: Arc<dyn QuotaStore>,
+}
+
+impl fmt::Debug for Quota {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Quota")
+      .field("header", &self.header)
+      .field("query", &self.query)
+      .field("limits", &self.limits)
+      .field("required", &self.required)
+      .field("headers", &self.headers)
+      .field("legacy_headers", &self.legacy_headers)
+      .finish_non_exhaustive()
+  }
+}
+
+impl Quota {
+  /// Creates a quota counting requests in memory.
+  pub fn new(options: QuotaOptions) -> Result<Self, ActixError> {
+    Self::with_store(options, Arc::new(MemoryStore::default()))
+  }
+
+  pub fn with_store(options: QuotaOptions, store: Arc<dyn QuotaStore>) -> Result<Self, ActixError> {
+    let header = match options.header {
+      Some(header) => HeaderName::try_from(header)
+        .map_err(|err| ActixError::new(ErrorCode::InvalidOption, err.to_string()))?,
+      None => HeaderName::from_static("x-api-key"),
+    };
+
+    let limits: Vec<_> = [(Window::Hour, options.hourly), (Window::Day, options.daily)]
+      .into_iter()
+      .filter_map(|(window, limit)| Some((window, u64::from(limit?))))
+      .collect();
+    if limits.is_empty() {
+      return Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        "a quota needs an `hourly` or `daily` limit",
+      ));
+    }
+
+    Ok(Quota {
+      header,
+      query: options.query,
+      limits,
+      required: options.required.unwrap_or(false),
+      headers: options.headers.unwrap_or(true),
+      legacy_headers: options.legacy_headers.unwrap_or(true),
+      store,
+    })
+  }
+
+  /// Counts a request against every limit of the quota.
+  pub fn check<B>(&self, req: &Request<B>) -> Verdict {
+    let Some(key) = self.key(req) else {
+      return if self.required {
+        Verdict::MissingKey
+      } else {
+        Verdict::Unmetered
+      };
+    };
+
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |now| now.as_secs());
+
+    let mut closest: Option<Usage> = None;
+    for &(window, limit) in &self.limits {
+      let start = now - now % window.secs();
+      let count = self.store.incr(&key, window, start);
+      let usage = Usage {
+        limit,
+        remaining: limit.saturating_sub(count),
+        reset: start + window.secs() - now,
+        reset_at: start + window.secs(),
+      };
+
+      if count > limit {
+        return Verdict::Exceeded(usage);
+      }
+      if closest.is_none_or(|closest| usage.remaining < closest.remaining) {
+        closest = Some(usage);
+      }
+    }
+
+    closest.map_or(Verdict::Unmetered, Verdict::Allowed)
+  }
+
+  /// Adds the rate limit headers describing `usage` to a response, as
+  /// configured.
+  pub fn set_headers(&self, headers: &mut HeaderMap, usage: Usage) {
+    if self.headers {
+      headers.insert(LIMIT, HeaderValue::from(usage.limit));
+      headers.insert(REMAINING, HeaderValue::from(usage.remaining));
+      headers.insert(RESET, HeaderValue::from(usage.reset));
+    }
+    if self.legacy_headers {
+      headers.insert(LEGACY_LIMIT, HeaderValue::from(usage.limit));
+      headers.insert(LEGACY_REMAINING, HeaderValue::from(usage.remaining));
+      headers.insert(LEGACY_RESET, HeaderValue::from(usage.reset_at));
+    }
+  }
+
+  fn key<B>(&self, req: &Request<B>) -> Option<String> {
+    if let Some(value) = req.headers().get(&self.header) {
+      return value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty())
+        .map(str::to_owned);
+    }
+
+    let name = self.query.as_deref()?;
+    req.uri().query()?.split('&').find_map(|pair| {
+      let (key, value) = pair.split_once('=')?;
+      (percent_decode_str(key).decode_utf8().ok()? == name)
+        .then(|| percent_decode_str(value).decode_utf8().ok())
+        .flatten()
+        .filter(|value| !value.is_empty())
+        .map(|value| value.into_owned())
+    })
+  }
+}
@@ -0,0 +1,183 @@
+//! Requests rejected before reaching a handler, and how they are rendered.
+//!
+//! Whether a request is rejected, and with which status, is always decided
+//! here. An error formatter set with `app.errorFormatter()` only chooses the
+//! body of the response, from a plain object describing the error.
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::StatusCode;
+use napi::bindgen_prelude::{Buffer, FromNapiValue};
+use napi::threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction};
+use napi::{sys, Env, JsFunction, JsObject, JsUnknown, Result, ValueType};
+
+use crate::buffer;
+use crate::dispatch::Returned;
+use crate::error::ErrorCode;
+use crate::http::{Body, Response, ResponseBuilder};
+use crate::json::ParseError;
+
+/// Why a request was rejected.
+#[derive(Clone, Debug)]
+pub enum Rejection {
+  /// The body could not be read from the connection.
+  BodyRead { message: String },
+  /// The body is larger than allowed.
+  BodyTooLarge { limit: u64 },
+  /// The body is not valid JSON and strict JSON parsing is enabled, or it
+  /// is a patch document.
+  Json(ParseError),
+  /// The body is valid JSON but not a valid patch document.
+  Patch { message: String },
+}
+
+impl Rejection {
+  pub fn status(&self) -> StatusCode {
+    match self {
+      Rejection::BodyRead { .. } | Rejection::Json(_) | Rejection::Patch { .. } => {
+        StatusCode::BAD_REQUEST
+      }
+      Rejection::BodyTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+    }
+  }
+
+  fn kind(&self) -> &'static str {
+    match self {
+      Rejection::BodyRead { .. } => "bodyRead",
+      Rejection::BodyTooLarge { .. } => "bodyTooLarge",
+      Rejection::Json(_) => "json",
+      Rejection::Patch { .. } => "patch",
+    }
+  }
+
+  fn code(&self) -> ErrorCode {
+    match self {
+      Rejection::BodyRead { .. } => ErrorCode::BodyReadFailed,
+      Rejection::BodyTooLarge { .. } => ErrorCode::BodyTooLarge,
+      Rejection::Json(_) => ErrorCode::InvalidJson,
+      Rejection::Patch { .. } => ErrorCode::InvalidPatch,
+    }
+  }
+
+  fn message(&self) -> String {
+    match self {
+      Rejection::BodyRead { message } | Rejection::Patch { message } => message.clone(),
+      Rejection::BodyTooLarge { limit } => format!("body is larger than {limit} bytes"),
+      Rejection::Json(err) => err.message.clone(),
+    }
+  }
+
+  /// The object handed to the error formatter.
+  fn to_js(&self, env: &Env) -> Result<JsObject> {
+    let mut error = env.create_object()?;
+    error.set_named_property("kind", env.create_string(self.kind())?)?;
+    error.set_named_property("code", env.create_string(self.code().as_str())?)?;
+    error.set_named_property("status", env.create_uint32(self.status().as_u16().into())?)?;
+    error.set_named_property("message", env.create_string(&self.message())?)?;
+
+    match self {
+      Rejection::BodyRead { .. } | Rejection::Patch { .. } => {}
+      Rejection::BodyTooLarge { limit } => {
+        error.set_named_property("limit", env.create_double(*limit as f64)?)?;
+      }
+      Rejection::Json(err) => {
+        if let (Some(line), Some(column)) = (err.line, err.column) {
+          error.set_named_property("line", env.create_uint32(line as u32)?)?;
+          error.set_named_property("column", env.create_uint32(column as u32)?)?;
+        }
+      }
+    }
+
+    Ok(error)
+  }
+}
+
+/// Renders rejections through a JS callback.
+pub struct Formatter(ThreadsafeFunction<Rejection, ErrorStrategy::Fatal>);
+
+impl Formatter {
+  pub fn new(callback: JsFunction) -> Result<Self> {
+    let callback = callback
+      .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Rejection>| {
+        ctx.value.to_js(&ctx.env).map(|error| vec![error])
+      })?;
+
+    Ok(Formatter(callback))
+  }
+
+  /// Builds the response for a rejection. If the formatter fails, the
+  /// status is sent without a body.
+  pub async fn render(&self, rejection: Rejection) -> Response {
+    let status = rejection.status();
+
+    let formatted = match self.0.call_async::<Returned<Formatted>>(rejection).await {
+      Ok(returned) => returned.settle().await,
+      Err(err) => Err(err),
+    };
+
+    match formatted {
+      Ok(formatted) => formatted.into_response(status),
+      Err(err) => {
+        eprintln!("Error formatting rejection: {err}");
+        bare(status)
+      }
+    }
+  }
+}
+
+/// Renders a rejection with the formatter, if there is one.
+pub async fn render(formatter: Option<&Formatter>, rejection: Rejection) -> Response {
+  match formatter {
+    Some(formatter) => formatter.render(rejection).await,
+    None => bare(rejection.status()),
+  }
+}
+
+fn bare(status: StatusCode) -> Response {
+  ResponseBuilder::new()
+    .status(status)
+    .body(Body::empty())
+    .unwrap()
+}
+
+/// What the formatter returned: text, bytes, a value sent as JSON, or
+/// nothing for an empty body.
+enum Formatted {
+  Empty,
+  Text(String),
+  Bytes(Bytes),
+  Json(serde_json::Value),
+}
+
+impl Formatted {
+  fn into_response(self, status: StatusCode) -> Response {
+    let (content_type, body) = match self {
+      Formatted::Empty => return bare(status),
+      Formatted::Text(text) => ("text/plain; charset=utf-8", Bytes::from(text)),
+      Formatted::Bytes(bytes) => ("application/octet-stream", bytes),
+      Formatted::Json(value) => ("application/json", Bytes::from(value.to_string())),
+    };
+
+    ResponseBuilder::new()
+      .status(status)
+      .header(header::CONTENT_TYPE, HeaderValue::from_static(content_type))
+      .body(Body::new(body))
+      .unwrap()
+  }
+}
+
+impl FromNapiValue for Formatted {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let unknown = JsUnknown::from_napi_value(env, value)?;
+    match unknown.get_type()? {
+      ValueType::Null | ValueType::Undefined => Ok(Formatted::Empty),
+      ValueType::String => String::from_napi_value(env, value).map(Formatted::Text),
+      _ if unknown.is_buffer()? => {
+        Buffer::from_napi_value(env, value).map(|bytes| Formatted::Bytes(buffer::from_js(bytes)))
+      }
+      _ => Env::from_raw(env)
+        .from_js_value(unknown)
+        .map(Formatted::Json),
+    }
+  }
+}
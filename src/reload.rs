@@ -0,0 +1,172 @@
+//! Swapping in new routes without a restart.
+//!
+//! `app.reload(next)` takes the routes, middleware, upload endpoints,
+//! proxies and static responses of `next`, an app built but never started,
+//! and publishes them to the running server in one atomic store. Connections stay open: requests
+//! already routed finish with the routes they started with, and the next
+//! ones see the new routes. Settings such as quotas or tracing are kept.
+//!
+//! `app.watch(paths, rebuild)` is the development mode on top: it polls
+//! the files under `paths` and, when one is added, removed or modified,
+//! calls `rebuild()` and reloads the app it returns, or its promise
+//! resolves to. Returning nothing leaves the routes as they are.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use napi::bindgen_prelude::{ClassInstance, FromNapiValue};
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
+use napi::{sys, Env, JsFunction, JsUnknown, Result, ValueType};
+
+use crate::dispatch::Returned;
+use crate::fast_path::FastPaths;
+use crate::routes::Routes;
+use crate::ActixApp;
+
+/// Milliseconds between two looks at the watched files.
+const INTERVAL: u32 = 500;
+
+/// Options of `app.watch()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct WatchOptions {
+  /// Milliseconds between two looks at the files. Defaults to 500.
+  pub interval: Option<u32>,
+}
+
+/// The routes of an app, taken on the JS thread to be swapped in later.
+pub(crate) struct Snapshot {
+  routes: Arc<Routes>,
+  fast_paths: Arc<FastPaths>,
+}
+
+impl Snapshot {
+  pub fn of(app: &ActixApp) -> Self {
+    Snapshot {
+      routes: app.routes.load_full(),
+      fast_paths: app.fast_paths.load_full(),
+    }
+  }
+
+  /// Publishes the routes to whatever serves `routes` and `fast_paths`.
+  pub(crate) fn publish(&self, routes: &ArcSwap<Routes>, fast_paths: &ArcSwap<FastPaths>) {
+    routes.rcu(|current| {
+      let mut next = Routes::clone(current);
+      next.replace_routes(&self.routes);
+      next
+    });
+    fast_paths.store(self.fast_paths.clone());
+  }
+}
+
+/// What `rebuild()` returned, once settled.
+struct Rebuilt(Option<Snapshot>);
+
+impl FromNapiValue for Rebuilt {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let unknown = JsUnknown::from_napi_value(env, value)?;
+    if matches!(unknown.get_type()?, ValueType::Undefined | ValueType::Null) {
+      return Ok(Rebuilt(None));
+    }
+    let app = ClassInstance::<ActixApp>::from_napi_value(env, value)?;
+    Ok(Rebuilt(Some(Snapshot::of(&app))))
+  }
+}
+
+/// Starts polling `paths`, returning the function that stops it.
+pub(crate) fn watch(
+  env: &Env,
+  paths: Vec<String>,
+  rebuild: JsFunction,
+  options: WatchOptions,
+  routes: Arc<ArcSwap<Routes>>,
+  fast_paths: Arc<ArcSwap<FastPaths>>,
+) -> Result<JsFunction> {
+  let mut rebuild = rebuild.create_threadsafe_function(0, |_| Ok(Vec::<JsUnknown>::new()))?;
+  // Watching alone shouldn't keep the process alive.
+  rebuild.unref(env)?;
+
+  let paths = paths.into_iter().map(PathBuf::from).collect();
+  let polling = napi::bindgen_prelude::spawn(poll(paths, options, rebuild, routes, fast_paths));
+  let polling = polling.abort_handle();
+  env.create_function_from_closure("close", move |ctx| {
+    polling.abort();
+    ctx.env.get_undefined()
+  })
+}
+
+/// Reloads the app serving `routes` and `fast_paths` whenever a file under
+/// `paths` changes, until aborted.
+async fn poll(
+  paths: Vec<PathBuf>,
+  options: WatchOptions,
+  rebuild: ThreadsafeFunction<(), ErrorStrategy::Fatal>,
+  routes: Arc<ArcSwap<Routes>>,
+  fast_paths: Arc<ArcSwap<FastPaths>>,
+) {
+  let interval = Duration::from_millis(options.interval.unwrap_or(INTERVAL).max(1).into());
+  let paths = Arc::new(paths);
+  let mut seen = scan(paths.clone()).await;
+
+  loop {
+    tokio::time::sleep(interval).await;
+    let current = scan(paths.clone()).await;
+    if current == seen {
+      continue;
+    }
+    seen = current;
+
+    let rebuilt = match rebuild.call_async::<Returned<Rebuilt>>(()).await {
+      Ok(returned) => returned.settle().await,
+      Err(err) => Err(err),
+    };
+    match rebuilt {
+      Ok(Rebuilt(Some(snapshot))) => snapshot.publish(&routes, &fast_paths),
+      Ok(Rebuilt(None)) => {}
+      Err(err) => eprintln!("Error rebuilding routes: {err}"),
+    }
+  }
+}
+
+/// The modification time of every file under `paths`.
+async fn scan(paths: Arc<Vec<PathBuf>>) -> HashMap<PathBuf, SystemTime> {
+  tokio::task::spawn_blocking(move || {
+    let mut files = HashMap::new();
+    for path in paths.iter() {
+      visit(path, &mut files);
+    }
+    files
+  })
+  .await
+  .unwrap_or_default()
+}
+
+/// Dependencies, hidden directories such as `.git` and symbolic links are
+/// skipped.
+fn visit(path: &Path, files: &mut HashMap<PathBuf, SystemTime>) {
+  let Ok(metadata) = std::fs::metadata(path) else {
+    return;
+  };
+  if metadata.is_file() {
+    if let Ok(modified) = metadata.modified() {
+      files.insert(path.to_owned(), modified);
+    }
+    return;
+  }
+
+  let Ok(entries) = std::fs::read_dir(path) else {
+    return;
+  };
+  for entry in entries.flatten() {
+    let name = entry.file_name();
+    let name = name.to_string_lossy();
+    let link = entry.file_type().is_ok_and(|kind| kind.is_symlink());
+    if link || name.starts_with('.') || name == "node_modules" {
+      continue;
+    }
+    visit(&entry.path(), files);
+  }
+}
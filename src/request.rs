@@ -0,0 +1,272 @@
+//! `NodeRequest`: requests handed to JS without building a WHATWG `Request`.
+//!
+//! Going through the global `Request` constructor copies the method, URL,
+//! every header and the body into JS on every request. A `NodeRequest`
+//! reads them from the Rust request when they are asked for, and also
+//! exposes what a `Request` has no room for, such as route parameters and
+//! the parsed query string. Enabled with `app.nativeRequests(true)`.
+
+use std::cell::RefCell;
+use std::mem;
+use std::sync::{Arc, Weak};
+
+use hyper::header;
+use napi::bindgen_prelude::FromNapiValue;
+use napi::{Env, JsBuffer, JsFunction, JsObject, JsUnknown, NapiRaw, Ref, Result, ValueType};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::form;
+use crate::headers::RequestHeaders;
+use crate::routes::Params;
+use crate::structured;
+use crate::MyRequest;
+
+/// Marks requests handed to JS as `NodeRequest`s.
+#[derive(Clone, Copy, Debug)]
+pub struct NativeRequests;
+
+/// Marks requests whose route runs middleware before its handler. They all
+/// get the same JS object, so what one sets on `req`, such as `req.user`,
+/// is seen by the ones after it.
+#[derive(Clone, Copy, Debug)]
+pub struct SharedObject;
+
+/// JS values kept for the env of this thread.
+#[derive(Default)]
+struct Objects {
+  /// Objects of the [`SharedObject`] requests, per request.
+  shared: Vec<(Weak<MyRequest>, Ref<()>)>,
+  structured_header: Option<Ref<()>>,
+}
+
+impl Drop for Objects {
+  /// Only runs once the thread, and so its env, is gone; the env released
+  /// the references along with it.
+  fn drop(&mut self) {
+    for (_, object) in self.shared.drain(..) {
+      mem::forget(object);
+    }
+    if let Some(getter) = self.structured_header.take() {
+      mem::forget(getter);
+    }
+  }
+}
+
+thread_local! {
+  static OBJECTS: RefCell<Objects> = RefCell::default();
+}
+
+/// The JS object of `req`, built with `build` the first time it's asked for.
+pub fn shared_object(
+  env: Env,
+  req: &Arc<MyRequest>,
+  build: impl FnOnce() -> Result<JsObject>,
+) -> Result<JsObject> {
+  let found = OBJECTS.with(|objects| {
+    let shared = &mut objects.borrow_mut().shared;
+    // Nothing runs JS for a request once it's dropped.
+    let mut unref = Ok(());
+    shared.retain_mut(|(weak, object)| {
+      if weak.strong_count() > 0 {
+        return true;
+      }
+      if let Err(err) = object.unref(env) {
+        unref = Err(err);
+      }
+      false
+    });
+    unref?;
+    shared
+      .iter()
+      .find(|(weak, _)| weak.as_ptr() == Arc::as_ptr(req))
+      .map(|(_, object)| env.get_reference_value::<JsObject>(object))
+      .transpose()
+  })?;
+  if let Some(object) = found {
+    return Ok(object);
+  }
+
+  let object = build()?;
+  let reference = env.create_reference(&object)?;
+  OBJECTS.with(|objects| {
+    let shared = &mut objects.borrow_mut().shared;
+    shared.push((Arc::downgrade(req), reference));
+  });
+  Ok(object)
+}
+
+/// `req.getStructuredHeader()` of WHATWG requests. Created once and shared
+/// by every request, it reads the header from `this`.
+pub fn structured_header_getter(env: Env) -> Result<JsFunction> {
+  let cached = OBJECTS.with(|objects| {
+    let objects = objects.borrow();
+    objects
+      .structured_header
+      .as_ref()
+      .map(|getter| env.get_reference_value::<JsFunction>(getter))
+      .transpose()
+  })?;
+  if let Some(getter) = cached {
+    return Ok(getter);
+  }
+
+  let getter = env.create_function_from_closure("getStructuredHeader", |ctx| {
+    let name = ctx.get::<String>(0)?;
+    let kind = match ctx.length {
+      1 => None,
+      _ => ctx.get::<Option<String>>(1)?,
+    };
+    let value = header_value(ctx.env, &ctx.this()?, &name)?;
+    structured::value_to_js(ctx.env, &name, value.as_deref(), kind.as_deref())
+  })?;
+  let reference = env.create_reference(&getter)?;
+  OBJECTS.with(|objects| objects.borrow_mut().structured_header = Some(reference));
+  Ok(getter)
+}
+
+/// Header `name` of a request object: from its `Headers` or
+/// `RequestHeaders`, or from the plain object Express middleware gives it.
+fn header_value(env: &Env, req: &JsObject, name: &str) -> Result<Option<String>> {
+  let headers: JsObject = req.get_named_property("headers")?;
+  let get = headers.get_named_property::<JsUnknown>("get")?;
+  let value = if get.get_type()? == ValueType::Function {
+    let get: JsFunction = get.try_into()?;
+    get.call(Some(&headers), &[env.create_string(name)?])?
+  } else {
+    headers.get_named_property::<JsUnknown>(&name.to_ascii_lowercase())?
+  };
+
+  match value.get_type()? {
+    ValueType::String => Ok(Some(value.coerce_to_string()?.into_utf8()?.into_owned()?)),
+    ValueType::Object if value.is_array()? => {
+      let values = value.coerce_to_object()?;
+      let mut joined = Vec::new();
+      for idx in 0..values.get_array_length()? {
+        let value: JsUnknown = values.get_element(idx)?;
+        joined.push(value.coerce_to_string()?.into_utf8()?.into_owned()?);
+      }
+      Ok(Some(joined.join(", ")))
+    }
+    _ => Ok(None),
+  }
+}
+
+/// A request as seen by handlers once `app.nativeRequests(true)` is set.
+/// Mirrors the parts of `Request` handlers use, reading the Rust request
+/// lazily.
+#[napi]
+pub struct NodeRequest {
+  req: Arc<MyRequest>,
+}
+
+#[napi]
+impl NodeRequest {
+  #[napi(getter)]
+  pub fn method(&self) -> &str {
+    self.req.method().as_str()
+  }
+
+  /// The absolute URL, built from the `Host` header. The server does not
+  /// terminate TLS, so the scheme is always `http`.
+  #[napi(getter)]
+  pub fn url(&self) -> String {
+    let host = self
+      .req
+      .headers()
+      .get(header::HOST)
+      .and_then(|host| host.to_str().ok())
+      .unwrap_or("localhost");
+    let path = self
+      .req
+      .uri()
+      .path_and_query()
+      .map_or("/", |path| path.as_str());
+
+    format!("http://{host}{path}")
+  }
+
+  #[napi(getter)]
+  pub fn path(&self) -> &str {
+    self.req.uri().path()
+  }
+
+  /// Parameters captured by the route template, such as `id` in
+  /// `/users/:id`.
+  #[napi(getter, ts_return_type = "Record<string, string>")]
+  pub fn params(&self, env: Env) -> Result<JsObject> {
+    let mut params = env.create_object()?;
+    if let Some(Params(captured)) = self.req.extensions().get::<Params>() {
+      for (name, value) in captured {
+        params.set_named_property(name, env.create_string(value)?)?;
+      }
+    }
+    Ok(params)
+  }
+
+  /// The query string, decoded like an urlencoded form.
+  #[napi(
+    getter,
+    ts_return_type = "{ [key: string]: import('./types').FormValue }"
+  )]
+  pub fn query(&self, env: Env) -> Result<JsUnknown> {
+    let query = self.req.uri().query().unwrap_or_default();
+    form::parse(query.as_bytes(), None).into_js(&env)
+  }
+
+  /// A copy of the body, or `null` when there is none. The bytes read from
+  /// the connection are shared with the server, and JS may write to the
+  /// `Buffer` it gets.
+  #[napi(getter)]
+  pub fn body(&self, env: Env) -> Result<Option<JsBuffer>> {
+    let body = self.req.body();
+    if body.is_empty() {
+      return Ok(None);
+    }
+    env
+      .create_buffer_with_data(body.to_vec())
+      .map(|buffer| Some(buffer.into_raw()))
+  }
+
+  /// The body decoded as UTF-8.
+  #[napi]
+  pub fn text(&self) -> String {
+    String::from_utf8_lossy(self.req.body()).into_owned()
+  }
+
+  /// The body parsed as JSON. Throws if it is not valid JSON.
+  #[napi]
+  pub fn json(&self, env: Env) -> Result<JsUnknown> {
+    let value: serde_json::Value = serde_json::from_slice(self.req.body()).map_err(|err| {
+      ActixError::new(ErrorCode::InvalidJson, "invalid JSON body")
+        .caused_by(err)
+        .into_js(&env)
+    })?;
+    env.to_js_value(&value)
+  }
+
+  /// Header `name` parsed as a structured field (RFC 8941), or `null` if it
+  /// is missing or malformed. `kind` can be left out for registered fields.
+  #[napi(
+    ts_args_type = "name: string, kind?: import('./types').StructuredFieldType | undefined | null",
+    ts_return_type = "import('./types').StructuredField | null"
+  )]
+  pub fn get_structured_header(
+    &self,
+    env: Env,
+    name: String,
+    kind: Option<String>,
+  ) -> Result<JsUnknown> {
+    structured::header_to_js(&env, self.req.headers(), &name, kind.as_deref())
+  }
+}
+
+impl NodeRequest {
+  /// Builds the object handed to JS, with lazy `headers`.
+  pub fn create(env: Env, req: Arc<MyRequest>) -> Result<JsObject> {
+    let instance = NodeRequest { req: req.clone() }.into_instance(env)?;
+    // SAFETY: the instance was just created in this scope.
+    let mut jsreq = unsafe { JsObject::from_napi_value(env.raw(), instance.raw())? };
+    RequestHeaders::attach(env, &mut jsreq, req)?;
+    Ok(jsreq)
+  }
+}
@@ -0,0 +1,274 @@
+//! `NodeResponse`: responses built in JS and turned straight into native
+//! responses.
+//!
+//! Handlers can answer with more than a status by returning a
+//! `NodeResponse`, built with `NodeResponse.json()`, `.text()` or
+//! `.stream()` and adjusted with the chainable `.status()` and `.header()`.
+//! Its status, headers and body are kept as they will be sent, so returning
+//! it involves no parsing on the way out.
+//!
+//! Handlers written for fetch-based frameworks can return a WHATWG
+//! `Response` instead. Its status, status text and headers are read when
+//! it is returned, and its body is streamed like `NodeResponse.stream()`.
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use napi::bindgen_prelude::{Buffer, ClassInstance, Either, FromNapiValue, This};
+use napi::{
+  sys, Env, JsBoolean, JsFunction, JsNumber, JsObject, JsString, JsUnknown, Result, ValueType,
+};
+
+use crate::buffer;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Response, ResponseBuilder};
+use crate::outgoing;
+use crate::status;
+use crate::stream::{self, Streamed};
+
+/// What a `NodeResponse` sends.
+enum Payload {
+  Bytes {
+    content_type: &'static str,
+    bytes: Bytes,
+  },
+  Stream(Streamed),
+  /// A body fed from the Rust side, such as a `ResponseWriter`'s.
+  Body(Body),
+}
+
+/// A response for a handler to return.
+#[napi]
+pub struct NodeResponse {
+  status: StatusCode,
+  headers: HeaderMap,
+  /// Taken once the response is sent.
+  payload: Option<Payload>,
+}
+
+#[napi]
+impl NodeResponse {
+  /// A `200` response with a text or binary body, or none.
+  #[napi(constructor)]
+  pub fn new(body: Option<Either<String, Buffer>>) -> Self {
+    let (content_type, bytes) = match body {
+      Some(Either::A(text)) => ("text/plain; charset=utf-8", Bytes::from(text)),
+      Some(Either::B(bytes)) => ("application/octet-stream", buffer::from_js(bytes)),
+      None => ("", Bytes::new()),
+    };
+    NodeResponse::with(Payload::Bytes {
+      content_type,
+      bytes,
+    })
+  }
+
+  /// A `200` response with `value` serialized as JSON.
+  #[napi(factory)]
+  pub fn json(env: Env, value: JsUnknown) -> Result<Self> {
+    let value: serde_json::Value = env.from_js_value(value)?;
+    let bytes = serde_json::to_vec(&value).map_err(|err| {
+      ActixError::new(ErrorCode::InvalidValue, "the value can't be sent as JSON")
+        .caused_by(err)
+        .into_js(&env)
+    })?;
+
+    Ok(NodeResponse::with(Payload::Bytes {
+      content_type: "application/json",
+      bytes: Bytes::from(bytes),
+    }))
+  }
+
+  /// A `200` response with a UTF-8 text body.
+  #[napi(factory)]
+  pub fn text(text: String) -> Self {
+    NodeResponse::with(Payload::Bytes {
+      content_type: "text/plain; charset=utf-8",
+      bytes: Bytes::from(text),
+    })
+  }
+
+  /// A `200` response streaming the strings and buffers of a sync or async
+  /// iterable, such as a Node.js `Readable`, as the client reads them.
+  #[napi(
+    factory,
+    ts_args_type = "iterable: Iterable<string | Buffer> | AsyncIterable<string | Buffer>"
+  )]
+  pub fn stream(env: Env, iterable: JsObject) -> Result<Self> {
+    Streamed::raw(&env, iterable).map(|streamed| NodeResponse::with(Payload::Stream(streamed)))
+  }
+
+  /// Sets the status code, between 100 and 999.
+  #[napi]
+  pub fn status(&mut self, env: Env, this: This, code: u32) -> Result<This> {
+    self.status = outgoing::parse_status(&env, code)?;
+    Ok(this)
+  }
+
+  /// Adds a header. Setting `Content-Type` replaces the one implied by the
+  /// body; other headers can be repeated.
+  #[napi]
+  pub fn header(&mut self, env: Env, this: This, name: String, value: String) -> Result<This> {
+    let name = outgoing::parse_name(&env, name)?;
+    let value = HeaderValue::try_from(value).map_err(|err| {
+      ActixError::new(ErrorCode::InvalidHeader, "invalid header value")
+        .caused_by(err)
+        .into_js(&env)
+    })?;
+    self.headers.append(name, value);
+    Ok(this)
+  }
+}
+
+impl NodeResponse {
+  fn with(payload: Payload) -> Self {
+    NodeResponse {
+      status: StatusCode::OK,
+      headers: HeaderMap::new(),
+      payload: Some(payload),
+    }
+  }
+
+  /// A response sent exactly as given, without a default `Content-Type`.
+  pub fn from_parts(status: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+    NodeResponse {
+      status,
+      headers,
+      payload: Some(Payload::Bytes {
+        content_type: "",
+        bytes: body,
+      }),
+    }
+  }
+
+  /// A response whose body is already being produced.
+  pub fn with_body(status: StatusCode, headers: HeaderMap, body: Body) -> Self {
+    NodeResponse {
+      status,
+      headers,
+      payload: Some(Payload::Body(body)),
+    }
+  }
+
+  /// Takes the response out of an object returned by a handler, if it is a
+  /// `NodeResponse`.
+  ///
+  /// # Safety
+  ///
+  /// `env` and `value` must be valid, on the JS thread.
+  pub unsafe fn take(env: sys::napi_env, value: sys::napi_value) -> Result<Option<Response>> {
+    let object = JsObject::from_napi_value(env, value)?;
+    if !NodeResponse::instance_of(Env::from_raw(env), object)? {
+      return Ok(None);
+    }
+
+    let mut instance = ClassInstance::<NodeResponse>::from_napi_value(env, value)?;
+    let payload = instance.payload.take().ok_or_else(|| {
+      ActixError::new(
+        ErrorCode::AlreadyUsed,
+        "a response can only be returned once",
+      )
+      .into_js(&Env::from_raw(env))
+    })?;
+    let headers = std::mem::take(&mut instance.headers);
+
+    let mut res = match payload {
+      Payload::Stream(streamed) => streamed.into_response(),
+      Payload::Body(body) => ResponseBuilder::new().body(body).unwrap(),
+      Payload::Bytes {
+        content_type,
+        bytes,
+      } => {
+        let mut res = ResponseBuilder::new().body(Body::new(bytes)).unwrap();
+        if !content_type.is_empty() {
+          res
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        res
+      }
+    };
+    *res.status_mut() = instance.status;
+
+    if headers.contains_key(header::CONTENT_TYPE) {
+      res.headers_mut().remove(header::CONTENT_TYPE);
+    }
+    for (name, value) in headers.iter() {
+      res.headers_mut().append(name, value.clone());
+    }
+    Ok(Some(res))
+  }
+}
+
+/// Translates a WHATWG `Response` returned by a handler, if it is one.
+///
+/// # Safety
+///
+/// `env` must be valid, on the JS thread.
+pub unsafe fn from_fetch(env: sys::napi_env, object: &JsObject) -> Result<Option<Response>> {
+  let env = Env::from_raw(env);
+  let constructor: JsUnknown = env.get_global()?.get_named_property("Response")?;
+  if constructor.get_type()? != ValueType::Function || !object.instanceof(constructor)? {
+    return Ok(None);
+  }
+
+  let body_used: JsBoolean = object.get_named_property("bodyUsed")?;
+  if body_used.get_value()? {
+    return Err(
+      ActixError::new(ErrorCode::AlreadyUsed, "the response body was already read").into_js(&env),
+    );
+  }
+
+  let code: JsNumber = object.get_named_property("status")?;
+  let code = u16::try_from(code.get_uint32()?).unwrap_or(0);
+  let text: JsString = object.get_named_property("statusText")?;
+  let text = text.into_utf8()?.into_owned()?;
+  // Only a phrase other than the standard one needs to be sent as is.
+  let reason = StatusCode::from_u16(code)
+    .ok()
+    .and_then(|code| code.canonical_reason())
+    .is_none_or(|canonical| canonical != text)
+    .then_some(text)
+    .filter(|text| !text.is_empty());
+  let (code, reason) = status::parse(code, reason).map_err(|err| err.into_js(&env))?;
+
+  let headers = header_map(&env, object.get_named_property("headers")?)?;
+
+  let body: JsUnknown = object.get_named_property("body")?;
+  let mut res = match body.get_type()? {
+    ValueType::Object => Streamed::raw(&env, body.coerce_to_object()?)?.into_response(),
+    _ => ResponseBuilder::new().body(Body::empty()).unwrap(),
+  };
+  *res.status_mut() = code;
+  *res.headers_mut() = headers;
+  if let Some(reason) = reason {
+    res.extensions_mut().insert(reason);
+  }
+  Ok(Some(res))
+}
+
+/// Copies the `[name, value]` pairs of an iterable, such as a WHATWG
+/// `Headers`, which keeps each `Set-Cookie` apart.
+pub(crate) fn header_map(env: &Env, pairs: JsObject) -> Result<HeaderMap> {
+  let invalid = |err: &dyn std::fmt::Display| {
+    ActixError::new(ErrorCode::InvalidHeader, err.to_string()).into_js(env)
+  };
+
+  let iterator = stream::iterator(env, pairs)?;
+  let next: JsFunction = iterator.get_named_property("next")?;
+  let mut map = HeaderMap::new();
+  loop {
+    let result: JsObject = next.call_without_args(Some(&iterator))?.try_into()?;
+    let done: JsUnknown = result.get_named_property("done")?;
+    if done.coerce_to_bool()?.get_value()? {
+      return Ok(map);
+    }
+
+    let entry: JsObject = result.get_named_property("value")?;
+    let name = entry.get_element::<JsString>(0)?.into_utf8()?;
+    let value = entry.get_element::<JsString>(1)?.into_utf8()?;
+    map.append(
+      HeaderName::from_bytes(name.as_slice()).map_err(|err| invalid(&err))?,
+      HeaderValue::from_bytes(value.as_slice()).map_err(|err| invalid(&err))?,
+    );
+  }
+}
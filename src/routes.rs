@@ -0,0 +1,1090 @@
+//! The route table and how request paths are matched against it.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::{HeaderMap, HeaderName};
+use hyper::Method;
+use matchit::{InsertError, Router};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::access_log::AccessLog;
+use crate::archive::Archive;
+use crate::batch::Batcher;
+use crate::client_hints::ClientHints;
+use crate::error::{ActixError, ErrorCode};
+use crate::expect::ExpectContinue;
+use crate::fast_path::FastPaths;
+use crate::guard::{Guard, GuardOptions};
+use crate::health::Health;
+use crate::normalize::Normalizer;
+use crate::proxy::Proxy;
+use crate::quota::{Quota, QuotaOptions};
+use crate::rejection::Formatter;
+use crate::shadow::Reporter;
+use crate::signature::{Signer, Verifier};
+use crate::signed_url::SignedUrls;
+use crate::tenant::{Tenant, Tenants};
+use crate::timing::{Timing, Timings};
+use crate::trace::Tracer;
+use crate::trust::TrustProxy;
+use crate::tus::Tus;
+use crate::RouterNode;
+
+/// Options controlling how request paths are matched, set with
+/// `app.routerOptions()`.
+#[napi(object)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouterOptions {
+  /// When `false`, `/users/` also matches `/users` and vice versa.
+  /// Defaults to `true`.
+  pub strict_slash: Option<bool>,
+  /// When `false`, static route segments are matched case-insensitively.
+  /// Parameter values are kept as they were sent. Defaults to `true`.
+  pub case_sensitive: Option<bool>,
+  /// Collapses repeated slashes (`//a///b` → `/a/b`). Defaults to `false`.
+  pub merge_slashes: Option<bool>,
+  /// Answers non-canonical paths with a `301` to the canonical form instead
+  /// of serving them directly. Defaults to `false`.
+  pub redirect: Option<bool>,
+}
+
+impl RouterOptions {
+  fn strict_slash(&self) -> bool {
+    self.strict_slash.unwrap_or(true)
+  }
+
+  fn case_sensitive(&self) -> bool {
+    self.case_sensitive.unwrap_or(true)
+  }
+
+  fn merge_slashes(&self) -> bool {
+    self.merge_slashes.unwrap_or(false)
+  }
+
+  fn redirect(&self) -> bool {
+    self.redirect.unwrap_or(false)
+  }
+
+  /// Normalizes a route template, leaving `{param}` names untouched.
+  fn template(&self, template: &str) -> String {
+    if self.case_sensitive() {
+      return template.to_owned();
+    }
+
+    let mut depth = 0;
+    template
+      .chars()
+      .map(|c| {
+        match c {
+          '{' => depth += 1,
+          '}' => depth -= 1,
+          _ => {}
+        }
+        if depth == 0 {
+          c.to_ascii_lowercase()
+        } else {
+          c
+        }
+      })
+      .collect()
+  }
+
+  /// Normalizes a request path before matching. Case is folded apart, by
+  /// [`Self::fold`], so that parameter values keep theirs.
+  fn path<'p>(&self, path: &'p str) -> Cow<'p, str> {
+    if self.merge_slashes() && path.contains("//") {
+      let mut merged = String::with_capacity(path.len());
+      for c in path.chars() {
+        if !(c == '/' && merged.ends_with('/')) {
+          merged.push(c);
+        }
+      }
+      return Cow::Owned(merged);
+    }
+    Cow::Borrowed(path)
+  }
+
+  /// The path as matched against templates normalized by
+  /// [`Self::template`]. Folding ASCII case keeps every byte in place, so
+  /// parameters matched in the folded path can be read back from `path`.
+  fn fold<'p>(&self, path: &'p str) -> Cow<'p, str> {
+    if !self.case_sensitive() && path.chars().any(|c| c.is_ascii_uppercase()) {
+      Cow::Owned(path.to_ascii_lowercase())
+    } else {
+      Cow::Borrowed(path)
+    }
+  }
+}
+
+/// The middleware of a route followed by its handler, called in order.
+pub(crate) type Pipeline = Arc<[RouterNode]>;
+
+/// A registered handler together with its route-level settings.
+#[derive(Clone)]
+pub(crate) struct Route {
+  pub pipeline: Pipeline,
+  pub quota: Option<Arc<Quota>>,
+  pub guard: Option<Arc<Guard>>,
+  /// Skips the app's signature verification.
+  pub unsigned: bool,
+  /// Handler compared against the primary one, whose response is dropped.
+  pub shadow: Option<Arc<RouterNode>>,
+  pub timing: Arc<Timing>,
+  /// Set for routes whose handler takes requests in batches, in place of
+  /// the pipeline.
+  pub batch: Option<Arc<Batcher>>,
+  /// What requests must carry besides the path, set with `when`.
+  pub conditions: Option<Arc<Conditions>>,
+  /// Largest request body accepted, in place of the tenant's.
+  pub max_body_size: Option<u64>,
+  /// Time requests get to be answered, in place of the server's
+  /// `requestTimeout`; `Some(None)` lifts it.
+  pub timeout: Option<Option<Duration>>,
+  /// The template, conditions and name the route was registered with, as
+  /// `app.routes()` lists them.
+  path: String,
+  when: Option<MatchOptions>,
+  name: Option<String>,
+}
+
+/// The header values and query parameters a route requires, compiled from
+/// its `when` option.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Conditions {
+  headers: Vec<(HeaderName, String)>,
+  query: Vec<(String, String)>,
+}
+
+impl Conditions {
+  fn new(options: MatchOptions) -> Result<Self, ActixError> {
+    let mut headers = options
+      .headers
+      .into_iter()
+      .flatten()
+      .map(|(name, value)| {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+          ActixError::new(
+            ErrorCode::InvalidOption,
+            format!("invalid header name `{name}`"),
+          )
+        })?;
+        Ok((name, value))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut query: Vec<_> = options.query.into_iter().flatten().collect();
+    // Sorted, so that the same conditions compare equal however given.
+    headers.sort_by(|a, b| (a.0.as_str(), &a.1).cmp(&(b.0.as_str(), &b.1)));
+    query.sort();
+    Ok(Conditions { headers, query })
+  }
+
+  fn matches(&self, headers: &HeaderMap, query: Option<&str>) -> bool {
+    let headers_match = self.headers.iter().all(|(name, expected)| {
+      headers.get_all(name).iter().any(|value| {
+        let Ok(value) = value.to_str() else {
+          return false;
+        };
+        let media_type = value.split(';').next().unwrap_or_default().trim();
+        expected == "*"
+          || value.trim().eq_ignore_ascii_case(expected)
+          || media_type.eq_ignore_ascii_case(expected)
+      })
+    });
+    if !headers_match || self.query.is_empty() {
+      return headers_match;
+    }
+
+    let params: Vec<(Cow<'_, str>, Cow<'_, str>)> = query
+      .unwrap_or_default()
+      .split('&')
+      .filter(|pair| !pair.is_empty())
+      .map(|pair| {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (decode_component(name), decode_component(value))
+      })
+      .collect();
+    self.query.iter().all(|(name, expected)| {
+      params
+        .iter()
+        .any(|(param, value)| param == name && (expected == "*" || value == expected))
+    })
+  }
+}
+
+/// A query string component, with `+` standing for a space.
+fn decode_component(component: &str) -> Cow<'_, str> {
+  if component.contains('+') {
+    let spaced = component.replace('+', " ");
+    Cow::Owned(percent_decode_str(&spaced).decode_utf8_lossy().into_owned())
+  } else {
+    percent_decode_str(component).decode_utf8_lossy()
+  }
+}
+
+/// The handlers registered for a single route template, per method.
+#[derive(Clone)]
+pub(crate) struct Endpoint {
+  /// The template as the router matches it.
+  template: String,
+  handlers: Vec<(Method, Route)>,
+}
+
+impl Endpoint {
+  fn find(&self, method: &Method) -> Option<&Route> {
+    self
+      .handlers
+      .iter()
+      .find(|(registered, _)| registered == method)
+      .map(|(_, node)| node)
+  }
+
+  /// Finds the handler for a method whose conditions the request meets,
+  /// trying those with conditions first, in the order they were added.
+  fn find_matching(
+    &self,
+    method: &Method,
+    headers: &HeaderMap,
+    query: Option<&str>,
+  ) -> Option<&Route> {
+    let mut fallback = None;
+    for (registered, route) in &self.handlers {
+      if registered != method {
+        continue;
+      }
+      match &route.conditions {
+        Some(conditions) if conditions.matches(headers, query) => return Some(route),
+        Some(_) => {}
+        None => fallback = fallback.or(Some(route)),
+      }
+    }
+    fallback
+  }
+
+  /// Finds the handler for a method. `HEAD` falls back to the `GET` handler;
+  /// hyper leaves the body out of responses to `HEAD` requests.
+  fn get(&self, method: &Method, headers: &HeaderMap, query: Option<&str>) -> Option<&Route> {
+    self
+      .find_matching(method, headers, query)
+      .or_else(|| match *method {
+        Method::HEAD => self.find_matching(&Method::GET, headers, query),
+        _ => None,
+      })
+  }
+
+  /// Whether a handler is registered for a method, met conditions or not.
+  fn handles(&self, method: &Method) -> bool {
+    self.find(method).is_some() || (*method == Method::HEAD && self.find(&Method::GET).is_some())
+  }
+
+  /// The value of the `Allow` header for this route, including the methods
+  /// answered automatically.
+  fn allow(&self) -> String {
+    let mut methods: Vec<&str> = Vec::new();
+    // Handlers with conditions can share a method.
+    for (method, _) in &self.handlers {
+      if !methods.contains(&method.as_str()) {
+        methods.push(method.as_str());
+      }
+    }
+
+    if self.find(&Method::GET).is_some() && self.find(&Method::HEAD).is_none() {
+      methods.push(Method::HEAD.as_str());
+    }
+    if self.find(&Method::OPTIONS).is_none() {
+      methods.push(Method::OPTIONS.as_str());
+    }
+
+    methods.join(", ")
+  }
+}
+
+/// Per-route options, passed as the last argument when registering a route.
+#[napi(object)]
+#[derive(Default)]
+pub struct RouteOptions {
+  /// Name used to build URLs for the route with `app.urlFor()`.
+  pub name: Option<String>,
+  /// Quota applied to this route, on top of the app's.
+  pub quota: Option<QuotaOptions>,
+  /// Accepts requests without a signature even when the app verifies them.
+  pub unsigned: Option<bool>,
+  /// Calls the handler with an array of requests, to be answered with an
+  /// array of statuses in the same order. Takes no middleware.
+  pub batch: Option<bool>,
+  /// Most requests handed to a batched handler at once. Defaults to 64.
+  pub max_batch: Option<u32>,
+  /// Header values and query parameters requests must also have. Several
+  /// handlers can share a method and path as long as their conditions
+  /// differ.
+  pub when: Option<MatchOptions>,
+  /// Largest request body accepted, in bytes. A tenant's lower limit
+  /// still applies.
+  pub max_body_size: Option<u32>,
+  /// Milliseconds requests get to be received and answered, in place of the
+  /// server's `requestTimeout`. `0` lifts the timeout.
+  pub timeout: Option<u32>,
+  /// Credentials requests must carry, checked before the body is read and
+  /// the handler called.
+  pub guard: Option<GuardOptions>,
+}
+
+/// A route as listed by `app.routes()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct RegisteredRoute {
+  /// What answers the route: a handler, a response set with
+  /// `app.getStatic()` or `app.favicon()`, an upload endpoint or a proxy.
+  #[napi(ts_type = "'route' | 'static' | 'upload' | 'proxy'")]
+  pub kind: String,
+  /// `*` for proxies, which forward every method.
+  pub method: String,
+  /// The template, as it was registered; the prefix of a proxy.
+  pub path: String,
+  /// The conditions of the route, if it was registered with some.
+  pub when: Option<MatchOptions>,
+  pub name: Option<String>,
+}
+
+impl RegisteredRoute {
+  pub fn new(kind: &str, method: &str, path: impl Into<String>) -> Self {
+    RegisteredRoute {
+      kind: kind.to_owned(),
+      method: method.to_owned(),
+      path: path.into(),
+      ..RegisteredRoute::default()
+    }
+  }
+}
+
+/// Conditions of a route besides its path.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct MatchOptions {
+  /// Values headers must have. A value matches the whole header or its
+  /// media type, before any `;` parameter, ignoring case. `*` only requires
+  /// the header.
+  pub headers: Option<HashMap<String, String>>,
+  /// Values query parameters must have. `*` only requires the parameter.
+  pub query: Option<HashMap<String, String>>,
+}
+
+/// Parameters captured from the path of a request, percent-decoded, in
+/// the order they appear in the route template.
+#[derive(Clone, Debug, Default)]
+pub struct Params(pub Vec<(String, String)>);
+
+/// The outcome of looking up a request.
+pub(crate) enum Lookup<'r> {
+  Found(&'r Route, Params),
+  /// An `OPTIONS` request without an explicit handler; holds the `Allow`
+  /// header value.
+  Options(String),
+  /// The path matched but not the method; holds the `Allow` header value.
+  MethodNotAllowed(String),
+  /// The path only matched after normalization and redirects are enabled;
+  /// holds the canonical path.
+  Redirect(String),
+  NotFound,
+}
+
+/// Registered routes together with the options used to match them.
+#[derive(Clone, Default)]
+pub(crate) struct Routes {
+  /// Indexes into `endpoints`.
+  router: Router<usize>,
+  /// Endpoints in the order their templates were first registered.
+  endpoints: Vec<Endpoint>,
+  options: RouterOptions,
+  /// Route templates by name, as they were registered.
+  names: HashMap<String, String>,
+  /// Quota applied to every route.
+  quota: Option<Arc<Quota>>,
+  tenants: Tenants,
+  verifier: Option<Arc<Verifier>>,
+  signer: Option<Arc<Signer>>,
+  formatter: Option<Arc<Formatter>>,
+  mismatches: Option<Arc<Reporter>>,
+  access_log: Option<Arc<AccessLog>>,
+  archive: Option<Arc<Archive>>,
+  tracer: Option<Arc<Tracer>>,
+  health: Option<Arc<Health>>,
+  signed_urls: Option<Arc<SignedUrls>>,
+  client_hints: Option<Arc<ClientHints>>,
+  expect_continue: Option<Arc<ExpectContinue>>,
+  /// Upload endpoints, answered before routing.
+  uploads: Vec<Arc<Tus>>,
+  /// Prefixes forwarded to upstreams, before routing.
+  proxies: Vec<Arc<Proxy>>,
+  /// Hands headers to JS through `RequestHeaders` rather than copying them.
+  lazy_headers: bool,
+  /// Hands requests to JS as `NodeRequest`s rather than WHATWG `Request`s.
+  native_requests: bool,
+  /// Gives requests a `signal` aborted when the client disconnects.
+  abort_signals: bool,
+  /// Proxies whose forwarding headers tell where requests came from.
+  trust_proxy: Option<Arc<TrustProxy>>,
+  /// Fixes applied to requests of legacy clients, before anything else.
+  normalizer: Option<Arc<Normalizer>>,
+  timings: Timings,
+  /// Whether a route was given a timeout, which requests need a deadline
+  /// for.
+  timeouts: bool,
+}
+
+impl Routes {
+  pub fn set_options(&mut self, options: RouterOptions) {
+    self.options = options;
+  }
+
+  /// Takes the routes of `other`, its upload endpoints and proxies, and
+  /// the options they were added with, keeping every other setting.
+  pub fn replace_routes(&mut self, other: &Routes) {
+    self.router = other.router.clone();
+    self.endpoints = other.endpoints.clone();
+    self.options = other.options;
+    self.names = other.names.clone();
+    self.uploads = other.uploads.clone();
+    self.proxies = other.proxies.clone();
+    self.timings = other.timings.clone();
+    self.timeouts = other.timeouts;
+  }
+
+  pub fn quota(&self) -> Option<&Arc<Quota>> {
+    self.quota.as_ref()
+  }
+
+  pub fn set_quota(&mut self, quota: Option<Arc<Quota>>) {
+    self.quota = quota;
+  }
+
+  pub fn verifier(&self) -> Option<&Arc<Verifier>> {
+    self.verifier.as_ref()
+  }
+
+  pub fn set_verifier(&mut self, verifier: Option<Arc<Verifier>>) {
+    self.verifier = verifier;
+  }
+
+  pub fn signer(&self) -> Option<&Arc<Signer>> {
+    self.signer.as_ref()
+  }
+
+  pub fn set_signer(&mut self, signer: Option<Arc<Signer>>) {
+    self.signer = signer;
+  }
+
+  pub fn formatter(&self) -> Option<&Formatter> {
+    self.formatter.as_deref()
+  }
+
+  pub fn set_formatter(&mut self, formatter: Option<Arc<Formatter>>) {
+    self.formatter = formatter;
+  }
+
+  pub fn mismatches(&self) -> Option<&Arc<Reporter>> {
+    self.mismatches.as_ref()
+  }
+
+  pub fn set_mismatches(&mut self, reporter: Option<Arc<Reporter>>) {
+    self.mismatches = reporter;
+  }
+
+  pub fn access_log(&self) -> Option<&Arc<AccessLog>> {
+    self.access_log.as_ref()
+  }
+
+  pub fn set_access_log(&mut self, access_log: Option<Arc<AccessLog>>) {
+    self.access_log = access_log;
+  }
+
+  pub fn archive(&self) -> Option<&Arc<Archive>> {
+    self.archive.as_ref()
+  }
+
+  pub fn set_archive(&mut self, archive: Option<Arc<Archive>>) {
+    self.archive = archive;
+  }
+
+  pub fn tracer(&self) -> Option<&Arc<Tracer>> {
+    self.tracer.as_ref()
+  }
+
+  pub fn set_tracer(&mut self, tracer: Option<Arc<Tracer>>) {
+    self.tracer = tracer;
+  }
+
+  pub fn health(&self) -> Option<&Health> {
+    self.health.as_deref()
+  }
+
+  pub fn set_health(&mut self, health: Option<Arc<Health>>) {
+    self.health = health;
+  }
+
+  pub fn signed_urls(&self) -> Option<&SignedUrls> {
+    self.signed_urls.as_deref()
+  }
+
+  pub fn set_signed_urls(&mut self, signed_urls: Option<Arc<SignedUrls>>) {
+    self.signed_urls = signed_urls;
+  }
+
+  pub fn client_hints(&self) -> Option<&ClientHints> {
+    self.client_hints.as_deref()
+  }
+
+  pub fn set_client_hints(&mut self, client_hints: Option<Arc<ClientHints>>) {
+    self.client_hints = client_hints;
+  }
+
+  pub fn expect_continue(&self) -> Option<&ExpectContinue> {
+    self.expect_continue.as_deref()
+  }
+
+  pub fn set_expect_continue(&mut self, expect_continue: Option<Arc<ExpectContinue>>) {
+    self.expect_continue = expect_continue;
+  }
+
+  pub fn uploads(&self) -> &[Arc<Tus>] {
+    &self.uploads
+  }
+
+  /// Replaces the upload endpoint at `path`, or removes it.
+  pub fn set_upload(&mut self, path: &str, tus: Option<Arc<Tus>>) {
+    let path = path.trim_end_matches('/');
+    self.uploads.retain(|tus| tus.path() != path);
+    self.uploads.extend(tus);
+  }
+
+  pub fn proxies(&self) -> &[Arc<Proxy>] {
+    &self.proxies
+  }
+
+  /// Replaces the proxy at `prefix`, or removes it.
+  pub fn set_proxy(&mut self, prefix: &str, proxy: Option<Arc<Proxy>>) {
+    let prefix = prefix.trim_end_matches('/');
+    self.proxies.retain(|proxy| proxy.prefix() != prefix);
+    self.proxies.extend(proxy);
+  }
+
+  pub fn lazy_headers(&self) -> bool {
+    self.lazy_headers
+  }
+
+  pub fn set_lazy_headers(&mut self, lazy: bool) {
+    self.lazy_headers = lazy;
+  }
+
+  pub fn native_requests(&self) -> bool {
+    self.native_requests
+  }
+
+  pub fn set_native_requests(&mut self, native: bool) {
+    self.native_requests = native;
+  }
+
+  pub fn abort_signals(&self) -> bool {
+    self.abort_signals
+  }
+
+  pub fn set_abort_signals(&mut self, enabled: bool) {
+    self.abort_signals = enabled;
+  }
+
+  pub fn trust_proxy(&self) -> Option<&TrustProxy> {
+    self.trust_proxy.as_deref()
+  }
+
+  pub fn set_trust_proxy(&mut self, trust_proxy: Option<Arc<TrustProxy>>) {
+    self.trust_proxy = trust_proxy;
+  }
+
+  pub fn normalizer(&self) -> Option<&Normalizer> {
+    self.normalizer.as_deref()
+  }
+
+  pub fn set_normalizer(&mut self, normalizer: Option<Arc<Normalizer>>) {
+    self.normalizer = normalizer;
+  }
+
+  pub fn timeouts(&self) -> bool {
+    self.timeouts
+  }
+
+  pub fn timings(&self) -> &Timings {
+    &self.timings
+  }
+
+  pub fn tenants(&self) -> &Tenants {
+    &self.tenants
+  }
+
+  /// Adds a tenant, replacing the one with the same name, if any, in place.
+  pub fn set_tenant(&mut self, tenant: Arc<Tenant>) {
+    match self
+      .tenants
+      .iter_mut()
+      .find(|declared| declared.name() == tenant.name())
+    {
+      Some(declared) => *declared = tenant,
+      None => self.tenants.push(tenant),
+    }
+  }
+
+  pub fn insert(
+    &mut self,
+    method: Method,
+    template: &str,
+    pipeline: Pipeline,
+    batch: Option<Arc<Batcher>>,
+    guard: Option<Arc<Guard>>,
+    options: &RouteOptions,
+  ) -> Result<(), ActixError> {
+    if let Some(name) = &options.name {
+      match self.names.get(name) {
+        Some(named) if named != template => {
+          return Err(ActixError::new(
+            ErrorCode::RouteConflict,
+            format!("route name `{name}` is already used by `{named}`"),
+          ))
+        }
+        _ => {}
+      }
+    }
+
+    let when = options.when.clone();
+    let conditions = when.clone().map(Conditions::new).transpose()?;
+    let quota = options.quota.clone().map(Quota::new).transpose()?;
+    let route = Route {
+      pipeline,
+      quota: quota.map(Arc::new),
+      guard,
+      unsigned: options.unsigned.unwrap_or(false),
+      shadow: None,
+      timing: Arc::default(),
+      batch,
+      conditions: conditions.map(Arc::new),
+      max_body_size: options.max_body_size.map(u64::from),
+      timeout: options
+        .timeout
+        .map(|ms| (ms > 0).then(|| Duration::from_millis(ms.into()))),
+      path: template.to_owned(),
+      when: when.clone(),
+      name: options.name.clone(),
+    };
+    let timing = route.timing.clone();
+
+    let original = template;
+    let template = self.options.template(template);
+
+    match self.position(&template) {
+      Some(idx) => {
+        let existing = self.endpoints[idx]
+          .handlers
+          .iter()
+          .find(|(registered, other)| {
+            *registered == method && other.conditions == route.conditions
+          });
+        if let Some((_, existing)) = existing {
+          let existing = existing.path.as_str();
+          return Err(
+            ActixError::new(
+              ErrorCode::RouteConflict,
+              format!(
+                "route `{method} {original}` is already registered as `{existing}`; remove it \
+             first with `app.remove()`, or tell the handlers apart with `when`"
+              ),
+            )
+            .with_detail("method", method.as_str())
+            .with_detail("path", original)
+            .with_detail("existing", existing),
+          );
+        }
+        self.endpoints[idx].handlers.push((method.clone(), route));
+      }
+      None => {
+        let idx = self.endpoints.len();
+        if let Err(err) = self.router.insert(template.as_str(), idx) {
+          return Err(self.insert_error(&method, original, err));
+        }
+        self.endpoints.push(Endpoint {
+          template,
+          handlers: vec![(method.clone(), route)],
+        });
+      }
+    }
+
+    self.timings.insert(method.as_str(), original, when, timing);
+    self.timeouts |= options.timeout.is_some();
+
+    if let Some(name) = &options.name {
+      self.names.insert(name.clone(), original.to_owned());
+    }
+    Ok(())
+  }
+
+  /// Where the endpoint of a template, as the router matches it, is.
+  fn position(&self, template: &str) -> Option<usize> {
+    self
+      .endpoints
+      .iter()
+      .position(|endpoint| endpoint.template == template)
+  }
+
+  /// Drops the endpoint at `idx`, pointing the router at the endpoints
+  /// moved down in its place.
+  fn remove_endpoint(&mut self, idx: usize) {
+    let removed = self.endpoints.remove(idx);
+    self.router.remove(removed.template.as_str());
+    for (idx, endpoint) in self.endpoints.iter().enumerate().skip(idx) {
+      // Putting back a template that was just removed cannot conflict.
+      self.router.remove(endpoint.template.as_str());
+      self.router.insert(endpoint.template.as_str(), idx).unwrap();
+    }
+  }
+
+  /// The methods registered for a template, and the template as it was
+  /// first registered.
+  fn registered(&self, template: &str) -> (Vec<&str>, Option<&str>) {
+    let Some(idx) = self.position(template) else {
+      return (Vec::new(), None);
+    };
+    let handlers = &self.endpoints[idx].handlers;
+    let mut methods = Vec::new();
+    for (method, _) in handlers {
+      if !methods.contains(&method.as_str()) {
+        methods.push(method.as_str());
+      }
+    }
+    (
+      methods,
+      handlers.first().map(|(_, route)| route.path.as_str()),
+    )
+  }
+
+  /// Explains why matchit refused `template`, and what to do about it.
+  fn insert_error(&self, method: &Method, template: &str, err: InsertError) -> ActixError {
+    let InsertError::Conflict { with } = &err else {
+      return ActixError::new(
+        ErrorCode::InvalidRoute,
+        format!("route `{method} {template}` is malformed: {err}"),
+      )
+      .with_detail("method", method.as_str())
+      .with_detail("path", template)
+      .caused_by(err);
+    };
+
+    let (methods, original) = self.registered(with);
+    let existing = original.unwrap_or(with);
+    let registered_for = match methods.is_empty() {
+      true => String::new(),
+      false => format!(" (registered for {})", methods.join(", ")),
+    };
+    let suggestion = if template.contains("{*") || existing.contains("{*") {
+      "a catch-all parameter can't share its position with a parameter of another name; \
+       register one route and branch in its handler"
+    } else {
+      "parameters in the same position must have the same name; rename them to match, or \
+       register one route and branch in its handler"
+    };
+
+    ActixError::new(
+      ErrorCode::RouteConflict,
+      format!(
+        "route `{method} {template}` conflicts with `{existing}`{registered_for}: {suggestion}"
+      ),
+    )
+    .with_detail("method", method.as_str())
+    .with_detail("path", template)
+    .with_detail("existing", existing)
+    .caused_by(err)
+  }
+
+  /// Everything requests are answered by, in the order they are tried:
+  /// upload endpoints, proxies, `fast_paths` by path, then routes, whose
+  /// templates come in the order they were first registered.
+  pub fn list(&self, fast_paths: &FastPaths) -> Vec<RegisteredRoute> {
+    let mut list = Vec::new();
+    for tus in &self.uploads {
+      let path = tus.path();
+      list.push(RegisteredRoute::new("upload", "POST", path));
+      let upload = format!("{}/{{id}}", path.trim_end_matches('/'));
+      for method in ["HEAD", "PATCH", "DELETE"] {
+        list.push(RegisteredRoute::new("upload", method, upload.as_str()));
+      }
+    }
+    for proxy in &self.proxies {
+      list.push(RegisteredRoute::new("proxy", "*", proxy.prefix()));
+    }
+
+    let mut paths = fast_paths.keys().collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+      list.push(RegisteredRoute::new("static", "GET", path.as_str()));
+    }
+
+    for endpoint in &self.endpoints {
+      for (method, route) in &endpoint.handlers {
+        list.push(RegisteredRoute {
+          when: route.when.clone(),
+          name: route.name.clone(),
+          ..RegisteredRoute::new("route", method.as_str(), route.path.as_str())
+        });
+      }
+    }
+    list
+  }
+
+  /// Sets or clears the shadow handler of an already registered route: the
+  /// one registered with the conditions `when`, or without any.
+  pub fn set_shadow(
+    &mut self,
+    method: &Method,
+    template: &str,
+    when: Option<MatchOptions>,
+    shadow: Option<RouterNode>,
+  ) -> Result<(), ActixError> {
+    let conditions = when.map(Conditions::new).transpose()?;
+    let template = self.options.template(template);
+    let Some(idx) = self.position(&template) else {
+      return Err(ActixError::new(
+        ErrorCode::RouteNotFound,
+        format!("route `{method} {template}` is not registered"),
+      ));
+    };
+
+    let route = self.endpoints[idx]
+      .handlers
+      .iter_mut()
+      .find(|(registered, route)| {
+        registered == method && route.conditions.as_deref() == conditions.as_ref()
+      });
+    let Some((_, route)) = route else {
+      let with = match conditions {
+        Some(_) => " with these conditions",
+        None => " without conditions",
+      };
+      return Err(ActixError::new(
+        ErrorCode::RouteNotFound,
+        format!("route `{method} {template}` is not registered{with}"),
+      ));
+    };
+    route.shadow = shadow.map(Arc::new);
+    Ok(())
+  }
+
+  /// Removes the handlers for `method`, or every handler of the route if
+  /// no method is given; with `when`, only those registered with these
+  /// conditions. Returns whether anything was removed.
+  pub fn remove(
+    &mut self,
+    method: Option<Method>,
+    template: &str,
+    when: Option<MatchOptions>,
+  ) -> Result<bool, ActixError> {
+    let conditions = when.map(Conditions::new).transpose()?;
+    let template = self.options.template(template);
+    let Some(idx) = self.position(&template) else {
+      return Ok(false);
+    };
+
+    let mut removed = Vec::new();
+    self.endpoints[idx].handlers.retain(|(registered, route)| {
+      let matches = method.as_ref().is_none_or(|method| method == registered)
+        && conditions
+          .as_ref()
+          .is_none_or(|conditions| route.conditions.as_deref() == Some(conditions));
+      if matches {
+        removed.push((route.timing.clone(), route.name.clone()));
+      }
+      !matches
+    });
+    if self.endpoints[idx].handlers.is_empty() {
+      self.remove_endpoint(idx);
+    }
+
+    for (timing, name) in &removed {
+      self.timings.remove(timing);
+      // A name shared by several methods stays while one of them is left.
+      let Some(name) = name else {
+        continue;
+      };
+      let named = self
+        .endpoints
+        .iter()
+        .flat_map(|endpoint| &endpoint.handlers)
+        .any(|(_, route)| route.name.as_ref() == Some(name));
+      if !named {
+        self.names.remove(name);
+      }
+    }
+
+    Ok(!removed.is_empty())
+  }
+
+  /// Builds the URL of a named route, filling in its parameters.
+  ///
+  /// Parameters that don't appear in the template are appended as the query
+  /// string.
+  pub fn url_for(
+    &self,
+    name: &str,
+    mut params: BTreeMap<String, String>,
+  ) -> Result<String, ActixError> {
+    let Some(template) = self.names.get(name) else {
+      return Err(ActixError::new(
+        ErrorCode::RouteNotFound,
+        format!("no route is named `{name}`"),
+      ));
+    };
+
+    let mut url = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(idx) = rest.find(['{', '}']) {
+      url.push_str(&rest[..idx]);
+
+      // `{{` and `}}` are escaped braces.
+      if let Some(escaped) = rest[idx..]
+        .strip_prefix("{{")
+        .or_else(|| rest[idx..].strip_prefix("}}"))
+      {
+        url.push_str(&rest[idx..idx + 1]);
+        rest = escaped;
+        continue;
+      }
+
+      let Some(end) = rest[idx..].find('}') else {
+        break;
+      };
+      let param = &rest[idx + 1..idx + end];
+      let (param, set) = match param.strip_prefix('*') {
+        Some(param) => (param, CATCH_ALL),
+        None => (param, SEGMENT),
+      };
+
+      let Some(value) = params.remove(param) else {
+        return Err(ActixError::new(
+          ErrorCode::InvalidValue,
+          format!("missing parameter `{param}` for route `{name}`"),
+        ));
+      };
+      url.extend(utf8_percent_encode(&value, set));
+      rest = &rest[idx + end + 1..];
+    }
+    url.push_str(rest);
+
+    let query = params
+      .iter()
+      .map(|(name, value)| {
+        format!(
+          "{}={}",
+          utf8_percent_encode(name, SEGMENT),
+          utf8_percent_encode(value, SEGMENT)
+        )
+      })
+      .collect::<Vec<_>>();
+    if !query.is_empty() {
+      url.push('?');
+      url.push_str(&query.join("&"));
+    }
+
+    Ok(url)
+  }
+
+  /// `path` as the router matches it, with slashes merged and case folded
+  /// as the options say.
+  pub fn matched_path<'p>(&self, path: &'p str) -> Cow<'p, str> {
+    match self.options.path(path) {
+      Cow::Borrowed(path) => self.options.fold(path),
+      Cow::Owned(path) => Cow::Owned(self.options.fold(&path).into_owned()),
+    }
+  }
+
+  pub fn lookup(
+    &self,
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    query: Option<&str>,
+  ) -> Lookup<'_> {
+    let normalized = self.options.path(path);
+
+    let mut candidates = vec![normalized.clone()];
+    if !self.options.strict_slash() {
+      if let Some(toggled) = toggle_slash(&normalized) {
+        candidates.push(Cow::Owned(toggled));
+      }
+    }
+
+    for candidate in candidates {
+      let folded = self.options.fold(&candidate);
+      let Ok(matched) = self.router.at(&folded) else {
+        continue;
+      };
+      // Where each parameter sits in `folded`, and so in `candidate`.
+      let ranges = matched
+        .params
+        .iter()
+        .map(|(name, value)| {
+          let start = value.as_ptr() as usize - folded.as_ptr() as usize;
+          (name, start..start + value.len())
+        })
+        .collect::<Vec<_>>();
+
+      if self.options.redirect() {
+        // Literal segments as registered, parameters as sent.
+        let mut canonical = folded.clone().into_owned();
+        for (_, range) in &ranges {
+          canonical.replace_range(range.clone(), &candidate[range.clone()]);
+        }
+        if canonical != path {
+          return Lookup::Redirect(canonical);
+        }
+      }
+
+      let endpoint = &self.endpoints[*matched.value];
+      return match endpoint.get(method, headers, query) {
+        Some(node) => {
+          let params = ranges
+            .into_iter()
+            .map(|(name, range)| {
+              let value = percent_decode_str(&candidate[range]).decode_utf8_lossy();
+              (name.to_owned(), value.into_owned())
+            })
+            .collect();
+          Lookup::Found(node, Params(params))
+        }
+        // Handlers exist, but the request doesn't meet their conditions.
+        None if endpoint.handles(method) => Lookup::NotFound,
+        None if *method == Method::OPTIONS => Lookup::Options(endpoint.allow()),
+        None => Lookup::MethodNotAllowed(endpoint.allow()),
+      };
+    }
+
+    Lookup::NotFound
+  }
+}
+
+/// Everything but unreserved characters is escaped in path segments.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+  .remove(b'-')
+  .remove(b'_')
+  .remove(b'.')
+  .remove(b'~');
+
+/// Catch-all parameters may span several segments.
+const CATCH_ALL: &AsciiSet = &SEGMENT.remove(b'/');
+
+/// Adds or strips a trailing slash; the root path has no alternative.
+fn toggle_slash(path: &str) -> Option<String> {
+  match path {
+    "/" | "" => None,
+    _ => match path.strip_suffix('/') {
+      Some(stripped) => Some(stripped.to_owned()),
+      None => Some(format!("{path}/")),
+    },
+  }
+}
@@ -0,0 +1,288 @@
+//! Shadow handlers: a second implementation of a route run alongside the
+//! primary one, to check that both answer the same way.
+//!
+//! The shadow runs once the primary handler has answered, with the same
+//! request, and its response is never sent. Responses are compared by
+//! status, headers (but hop-by-hop ones and `date`) and the SHA-256 of
+//! their bodies. The primary body is hashed as it is sent, so the
+//! comparison waits for it to end; bodies over [`MAX_HASHED`] are not
+//! compared. When the two responses differ, the mismatch is handed to the
+//! callback set with `app.onShadowMismatch()`.
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderMap};
+use hyper::{Method, StatusCode};
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, JsFunction, JsObject, Result};
+use ring::digest;
+use tokio::sync::oneshot;
+
+use crate::http::{Body, Response};
+
+/// Most body bytes hashed for a comparison.
+const MAX_HASHED: u64 = 16 * 1024 * 1024;
+
+/// Headers that describe the connection or the moment rather than the
+/// response, left out of comparisons.
+const IGNORED: [header::HeaderName; 9] = [
+  header::CONNECTION,
+  header::DATE,
+  header::PROXY_AUTHENTICATE,
+  header::PROXY_AUTHORIZATION,
+  header::TE,
+  header::TRAILER,
+  header::TRANSFER_ENCODING,
+  header::UPGRADE,
+  header::HeaderName::from_static("keep-alive"),
+];
+
+/// What is compared of a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Answer {
+  pub status: StatusCode,
+  /// Values joined with `, `, by name.
+  pub headers: BTreeMap<String, String>,
+  /// Hex SHA-256 of the body; `None` when it was too large, or not read to
+  /// its end.
+  pub body: Option<String>,
+}
+
+impl Answer {
+  fn new(status: StatusCode, headers: &HeaderMap) -> Self {
+    let mut compared = BTreeMap::<String, String>::new();
+    for (name, value) in headers {
+      if IGNORED.contains(name) {
+        continue;
+      }
+      let value = String::from_utf8_lossy(value.as_bytes());
+      compared
+        .entry(name.as_str().to_owned())
+        .and_modify(|joined| {
+          joined.push_str(", ");
+          joined.push_str(&value);
+        })
+        .or_insert_with(|| value.into_owned());
+    }
+
+    Answer {
+      status,
+      headers: compared,
+      body: None,
+    }
+  }
+
+  /// Reads the whole response, hashing its body.
+  pub async fn read(res: Response) -> Self {
+    let mut answer = Answer::new(res.status(), res.headers());
+    let mut body = res.into_body();
+    let mut hash = Hash::new();
+    while let Some(chunk) = body.data().await {
+      match chunk {
+        Ok(chunk) if hash.update(&chunk) => {}
+        _ => return answer,
+      }
+    }
+    answer.body = Some(hash.finish());
+    answer
+  }
+
+  /// Records the status and headers of the primary response. Its body is
+  /// hashed once the response is final, by [`hash_primary`]; the answer
+  /// resolves when that body ends, and not at all if it is dropped before.
+  pub fn observe(res: &mut Response) -> oneshot::Receiver<Answer> {
+    let (tx, rx) = oneshot::channel();
+    let answer = Answer::new(res.status(), res.headers());
+    res.extensions_mut().insert(Observed(answer, tx));
+    rx
+  }
+}
+
+/// The answer of a primary handler waiting for its body to be hashed.
+struct Observed(Answer, oneshot::Sender<Answer>);
+
+/// Hashes the body of a primary response observed with
+/// [`Answer::observe`] as it is sent.
+pub fn hash_primary(mut res: Response) -> Response {
+  let Some(Observed(answer, tx)) = res.extensions_mut().remove::<Observed>() else {
+    return res;
+  };
+
+  // The wrapped body no longer knows its length, so keep it in the headers
+  // rather than switching to chunked encoding.
+  if let Some(length) = HttpBody::size_hint(res.body()).exact() {
+    res
+      .headers_mut()
+      .entry(header::CONTENT_LENGTH)
+      .or_insert_with(|| header::HeaderValue::from(length));
+  }
+
+  res.map(|body| {
+    Body(hyper::Body::wrap_stream(Hashed {
+      body,
+      hash: Hash::new(),
+      answer: Some((answer, tx)),
+    }))
+  })
+}
+
+/// A running SHA-256, given up past [`MAX_HASHED`] bytes.
+struct Hash {
+  context: Option<digest::Context>,
+  read: u64,
+}
+
+impl Hash {
+  fn new() -> Self {
+    Hash {
+      context: Some(digest::Context::new(&digest::SHA256)),
+      read: 0,
+    }
+  }
+
+  /// Whether the body is still being hashed.
+  fn update(&mut self, data: &[u8]) -> bool {
+    self.read += data.len() as u64;
+    if self.read > MAX_HASHED {
+      self.context = None;
+    }
+    match &mut self.context {
+      Some(context) => {
+        context.update(data);
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn finish(&mut self) -> String {
+    match self.context.take() {
+      Some(context) => context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect(),
+      None => String::new(),
+    }
+  }
+}
+
+/// A response body hashed as it is sent.
+struct Hashed {
+  body: Body,
+  hash: Hash,
+  answer: Option<(Answer, oneshot::Sender<Answer>)>,
+}
+
+impl Stream for Hashed {
+  type Item = std::result::Result<Bytes, hyper::Error>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let polled = Pin::new(&mut self.body).poll_data(cx);
+    match &polled {
+      Poll::Ready(Some(Ok(data))) => {
+        self.hash.update(data);
+      }
+      Poll::Ready(None) => {
+        if let Some((mut answer, tx)) = self.answer.take() {
+          if self.hash.context.is_some() {
+            answer.body = Some(self.hash.finish());
+          }
+          let _ = tx.send(answer);
+        }
+      }
+      // A failed body isn't compared.
+      Poll::Ready(Some(Err(_))) => self.answer = None,
+      Poll::Pending => {}
+    }
+    polled
+  }
+}
+
+/// A primary and a shadow handler that answered the same request
+/// differently.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+  pub method: Method,
+  pub path: String,
+  pub primary: Answer,
+  pub shadow: Answer,
+}
+
+impl Mismatch {
+  /// Compares the two responses; `None` when they agree. Bodies are only
+  /// compared when both were hashed.
+  pub fn compare(method: &Method, path: &str, primary: Answer, shadow: Answer) -> Option<Self> {
+    let bodies_differ = matches!((&primary.body, &shadow.body), (Some(a), Some(b)) if a != b);
+    let differ =
+      primary.status != shadow.status || primary.headers != shadow.headers || bodies_differ;
+    differ.then(|| Mismatch {
+      method: method.clone(),
+      path: path.to_owned(),
+      primary,
+      shadow,
+    })
+  }
+
+  fn to_js(&self, env: &Env) -> Result<JsObject> {
+    let mut mismatch = env.create_object()?;
+    mismatch.set_named_property("method", env.create_string(self.method.as_str())?)?;
+    mismatch.set_named_property("path", env.create_string(&self.path)?)?;
+    mismatch.set_named_property("primary", answer_to_js(env, &self.primary)?)?;
+    mismatch.set_named_property("shadow", answer_to_js(env, &self.shadow)?)?;
+    Ok(mismatch)
+  }
+}
+
+fn answer_to_js(env: &Env, answer: &Answer) -> Result<JsObject> {
+  let mut object = env.create_object()?;
+  object.set_named_property("status", env.create_uint32(answer.status.as_u16().into())?)?;
+  let mut headers = env.create_object()?;
+  for (name, value) in &answer.headers {
+    headers.set_named_property(name, env.create_string(value)?)?;
+  }
+  object.set_named_property("headers", headers)?;
+  match &answer.body {
+    Some(hash) => object.set_named_property("bodySha256", env.create_string(hash)?)?,
+    None => object.set_named_property("bodySha256", env.get_null()?)?,
+  }
+  Ok(object)
+}
+
+/// Hands mismatches to a JS callback, without waiting for it.
+pub struct Reporter(ThreadsafeFunction<Mismatch, ErrorStrategy::Fatal>);
+
+impl Reporter {
+  pub fn new(callback: JsFunction) -> Result<Self> {
+    let callback =
+      callback.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Mismatch>| {
+        ctx.value.to_js(&ctx.env).map(|mismatch| vec![mismatch])
+      })?;
+
+    Ok(Reporter(callback))
+  }
+
+  pub fn report(&self, mismatch: Mismatch) {
+    self
+      .0
+      .call(mismatch, ThreadsafeFunctionCallMode::NonBlocking);
+  }
+}
+
+/// Reports a mismatch through `reporter`, or logs it if there is none.
+pub fn report(reporter: Option<&Reporter>, mismatch: Mismatch) {
+  match reporter {
+    Some(reporter) => reporter.report(mismatch),
+    None => eprintln!(
+      "Shadow handler mismatch on {} {}: primary answered {}, shadow answered {}",
+      mismatch.method, mismatch.path, mismatch.primary.status, mismatch.shadow.status
+    ),
+  }
+}
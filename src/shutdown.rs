@@ -0,0 +1,251 @@
+//! Stopping on `SIGINT` and `SIGTERM`.
+//!
+//! With `app.handleSignals(true)`, the first signal makes a running server
+//! stop accepting connections and emit `shutdown`. Open connections get
+//! `timeout` milliseconds to finish; the ones still open then, or when a
+//! second signal arrives, are closed. `listen()` resolves as it does for
+//! any stop, after the `req.waitUntil()` work.
+//!
+//! Signals are only listened to while a server runs. On Unix, once no
+//! server listens to a signal anymore, it gets its default action back, and
+//! ends the process again.
+
+use std::io;
+use std::sync::Arc;
+#[cfg(unix)]
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::events::{Event, Events};
+use crate::http::Drain;
+
+/// Milliseconds open connections get to finish by default.
+const TIMEOUT: u32 = 30_000;
+
+/// Options of `app.handleSignals()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownOptions {
+  /// Signals that stop the server, among `SIGINT`, `SIGTERM`, `SIGHUP` and
+  /// `SIGQUIT`. Defaults to `SIGINT` and `SIGTERM`.
+  #[napi(ts_type = "Array<import('./types').Signal>")]
+  pub signals: Option<Vec<String>>,
+  /// Milliseconds open connections get to finish before being closed.
+  /// Defaults to 30 seconds.
+  pub timeout: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Signal {
+  Interrupt,
+  Terminate,
+  Hangup,
+  Quit,
+}
+
+impl Signal {
+  fn parse(name: &str) -> Option<Self> {
+    match name {
+      "SIGINT" => Some(Signal::Interrupt),
+      "SIGTERM" => Some(Signal::Terminate),
+      "SIGHUP" => Some(Signal::Hangup),
+      "SIGQUIT" => Some(Signal::Quit),
+      _ => None,
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Signal::Interrupt => "SIGINT",
+      Signal::Terminate => "SIGTERM",
+      Signal::Hangup => "SIGHUP",
+      Signal::Quit => "SIGQUIT",
+    }
+  }
+}
+
+pub struct Shutdown {
+  signals: Vec<Signal>,
+  timeout: Duration,
+}
+
+impl Shutdown {
+  pub fn new(options: ShutdownOptions) -> Result<Self, ActixError> {
+    let signals = match options.signals {
+      Some(names) => names
+        .iter()
+        .map(|name| {
+          Signal::parse(name).ok_or_else(|| {
+            ActixError::new(
+              ErrorCode::InvalidOption,
+              format!(
+              "unknown signal `{name}`, expected \"SIGINT\", \"SIGTERM\", \"SIGHUP\" or \"SIGQUIT\""
+            ),
+            )
+          })
+        })
+        .collect::<Result<Vec<_>, _>>()?,
+      None => vec![Signal::Interrupt, Signal::Terminate],
+    };
+    if signals.is_empty() {
+      return Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        "no signal to handle",
+      ));
+    }
+
+    Ok(Shutdown {
+      signals,
+      timeout: Duration::from_millis(options.timeout.unwrap_or(TIMEOUT).into()),
+    })
+  }
+
+  /// Drains `drain` on the first signal, and forces it after the timeout or
+  /// on a second signal.
+  pub async fn watch(&self, drain: Drain, events: Arc<Events>) {
+    let mut receiver = match Receiver::new(&self.signals) {
+      Ok(receiver) => receiver,
+      Err(err) => {
+        eprintln!("Error listening to signals: {err}");
+        return;
+      }
+    };
+
+    let signal = receiver.recv().await;
+    events.emit(Event::Shutdown {
+      signal: signal.name(),
+      timeout: self.timeout,
+    });
+    drain.start();
+
+    tokio::select! {
+      _ = tokio::time::sleep(self.timeout) => {}
+      _ = receiver.recv() => {}
+    }
+    drain.force();
+  }
+}
+
+/// The handlers of the signals listened to so far. Tokio installs its
+/// handler for a signal once for the life of the process; while no receiver
+/// listens to the signal, the default action takes its place.
+#[cfg(unix)]
+static HANDLERS: Mutex<Vec<Handler>> = Mutex::new(Vec::new());
+
+#[cfg(unix)]
+struct Handler {
+  number: libc::c_int,
+  receivers: usize,
+  /// Tokio's action, while the default one is installed.
+  saved: Option<libc::sigaction>,
+}
+
+#[cfg(unix)]
+impl Signal {
+  fn number(self) -> libc::c_int {
+    match self {
+      Signal::Interrupt => libc::SIGINT,
+      Signal::Terminate => libc::SIGTERM,
+      Signal::Hangup => libc::SIGHUP,
+      Signal::Quit => libc::SIGQUIT,
+    }
+  }
+}
+
+/// The signals being listened to.
+#[cfg(unix)]
+struct Receiver(Vec<(Signal, tokio::signal::unix::Signal)>);
+
+#[cfg(unix)]
+impl Receiver {
+  fn new(signals: &[Signal]) -> io::Result<Self> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let streams = signals
+      .iter()
+      .map(|&kind| Ok((kind, signal(SignalKind::from_raw(kind.number()))?)))
+      .collect::<io::Result<_>>()?;
+
+    let mut handlers = HANDLERS.lock().unwrap();
+    for &kind in signals {
+      let number = kind.number();
+      let handler = match handlers
+        .iter_mut()
+        .position(|handler| handler.number == number)
+      {
+        Some(idx) => &mut handlers[idx],
+        None => {
+          handlers.push(Handler {
+            number,
+            receivers: 0,
+            saved: None,
+          });
+          handlers.last_mut().unwrap()
+        }
+      };
+      handler.receivers += 1;
+      if let Some(saved) = handler.saved.take() {
+        // SAFETY: `saved` is the action tokio installed, taken back.
+        unsafe { libc::sigaction(number, &saved, std::ptr::null_mut()) };
+      }
+    }
+    Ok(Receiver(streams))
+  }
+
+  async fn recv(&mut self) -> Signal {
+    let pending = self.0.iter_mut().map(|(kind, stream)| {
+      Box::pin(async move {
+        // The stream only ends with the runtime.
+        stream.recv().await;
+        *kind
+      })
+    });
+    futures::future::select_all(pending).await.0
+  }
+}
+
+#[cfg(unix)]
+impl Drop for Receiver {
+  fn drop(&mut self) {
+    let mut handlers = HANDLERS.lock().unwrap();
+    for (kind, _) in &self.0 {
+      let number = kind.number();
+      let Some(handler) = handlers.iter_mut().find(|handler| handler.number == number) else {
+        continue;
+      };
+      handler.receivers -= 1;
+      if handler.receivers > 0 {
+        continue;
+      }
+      // SAFETY: both actions are plain data, and `SIG_DFL` needs no
+      // handler function.
+      unsafe {
+        let mut default: libc::sigaction = std::mem::zeroed();
+        default.sa_sigaction = libc::SIG_DFL;
+        let mut saved: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(number, &default, &mut saved) == 0 {
+          handler.saved = Some(saved);
+        }
+      }
+    }
+  }
+}
+
+/// Other platforms only have `Ctrl+C`, delivered as `SIGINT`.
+#[cfg(not(unix))]
+struct Receiver(bool);
+
+#[cfg(not(unix))]
+impl Receiver {
+  fn new(signals: &[Signal]) -> io::Result<Self> {
+    Ok(Receiver(signals.contains(&Signal::Interrupt)))
+  }
+
+  async fn recv(&mut self) -> Signal {
+    if !self.0 || tokio::signal::ctrl_c().await.is_err() {
+      std::future::pending::<()>().await;
+    }
+    Signal::Interrupt
+  }
+}
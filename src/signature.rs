@@ -0,0 +1,524 @@
+//! Verification of HMAC request signatures, in the style of AWS SigV4.
+//!
+//! Clients sign requests with a shared secret and send
+//!
+//! ```text
+//! Authorization: HMAC-SHA256 Credential=<key id>, SignedHeaders=host;x-date, Signature=<hex>
+//! X-Date: 20240101T120000Z
+//! ```
+//!
+//! The signature is the hex encoded HMAC-SHA256 of
+//!
+//! ```text
+//! HMAC-SHA256\n<x-date>\n<hex sha256 of the canonical request>
+//! ```
+//!
+//! where the canonical request is made of the method, the path, the query
+//! string with its `&`-separated pairs sorted, one `name:value` line per
+//! signed header, the signed header list and the hex SHA-256 of the body,
+//! each followed by a newline except the last.
+//!
+//! Responses can be signed the same way with a [`Signer`], so clients can
+//! check their integrity. HTTP/1 has no way to send a header after the body,
+//! so the body is hashed as it is read and then sent in one piece. Only
+//! bodies whose length is known up front, and at most [`MAX_SIGNED_BODY`],
+//! are signed this way: streamed responses are not held back whole. Each
+//! response that can't be signed is logged, and either sent unsigned or,
+//! with `rejectUnsigned`, replaced with a `500`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderName, HeaderValue};
+use napi::bindgen_prelude::{Buffer, FromNapiValue};
+use napi::threadsafe_function::{ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction};
+use napi::{sys, Env, JsFunction, JsUnknown, Result, ValueType};
+use ring::{digest, hmac};
+
+use crate::dispatch::Returned;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Response};
+use crate::MyRequest;
+
+const ALGORITHM: &str = "HMAC-SHA256";
+const DATE: HeaderName = HeaderName::from_static("x-date");
+const CONTENT_SHA256: HeaderName = HeaderName::from_static("x-content-sha256");
+const SIGNATURE: HeaderName = HeaderName::from_static("x-signature");
+
+/// Largest response body held back to be signed, in bytes.
+const MAX_SIGNED_BODY: u64 = 16 * 1024 * 1024;
+
+/// Options for `app.verifySignatures()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct SignatureOptions {
+  /// Headers every signature must cover. Defaults to `["host", "x-date"]`;
+  /// `x-date` is always required.
+  pub headers: Option<Vec<String>>,
+  /// Seconds the `x-date` of a request may differ from the server clock.
+  /// Defaults to 300.
+  pub max_skew: Option<u32>,
+}
+
+/// Checks request signatures, looking secrets up through a JS callback.
+pub struct Verifier {
+  lookup: ThreadsafeFunction<String, ErrorStrategy::Fatal>,
+  headers: Vec<HeaderName>,
+  max_skew: u64,
+}
+
+impl Verifier {
+  pub fn new(env: &Env, lookup: JsFunction, options: SignatureOptions) -> Result<Self> {
+    let mut headers = match options.headers {
+      Some(headers) => header_names(headers).map_err(|err| err.into_js(env))?,
+      None => vec![header::HOST],
+    };
+    if !headers.contains(&DATE) {
+      headers.push(DATE);
+    }
+
+    let lookup = lookup.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<String>| {
+      ctx.env.create_string(&ctx.value).map(|key| vec![key])
+    })?;
+
+    Ok(Verifier {
+      lookup,
+      headers,
+      max_skew: options.max_skew.unwrap_or(300).into(),
+    })
+  }
+
+  /// Returns `true` if the request carries a valid, fresh signature.
+  pub async fn verify(&self, req: &MyRequest) -> bool {
+    let Some(auth) = req
+      .headers()
+      .get(header::AUTHORIZATION)
+      .and_then(|value| value.to_str().ok())
+      .and_then(Authorization::parse)
+    else {
+      return false;
+    };
+
+    let signed: Vec<_> = auth.signed_headers.split(';').collect();
+    if !self
+      .headers
+      .iter()
+      .all(|required| signed.contains(&required.as_str()))
+    {
+      return false;
+    }
+
+    let Some(date) = req
+      .headers()
+      .get(DATE)
+      .and_then(|value| value.to_str().ok())
+    else {
+      return false;
+    };
+    let Some(timestamp) = parse_date(date) else {
+      return false;
+    };
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |now| now.as_secs());
+    if now.abs_diff(timestamp) > self.max_skew {
+      return false;
+    }
+
+    let Some(canonical) = canonical_request(req, &signed, auth.signed_headers) else {
+      return false;
+    };
+    let Some(signature) = decode_hex(auth.signature) else {
+      return false;
+    };
+
+    let Some(secret) = self.secret(auth.credential).await else {
+      return false;
+    };
+    let string_to_sign = format!(
+      "{ALGORITHM}\n{date}\n{}",
+      hex(digest::digest(&digest::SHA256, canonical.as_bytes()).as_ref())
+    );
+    let key = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+
+    hmac::verify(&key, string_to_sign.as_bytes(), &signature).is_ok()
+  }
+
+  async fn secret(&self, key_id: &str) -> Option<Vec<u8>> {
+    let returned = self
+      .lookup
+      .call_async::<Returned<Secret>>(key_id.to_owned())
+      .await;
+
+    match returned {
+      Ok(returned) => match returned.settle().await {
+        Ok(Secret(secret)) => secret,
+        Err(err) => {
+          eprintln!("Signing key lookup rejected: {err}");
+          None
+        }
+      },
+      Err(err) => {
+        eprintln!("Error looking up signing key: {err}");
+        None
+      }
+    }
+  }
+}
+
+/// Options for `app.signResponses()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct ResponseSigningOptions {
+  /// Sent as the `Credential` so clients know which secret to check with.
+  pub key_id: String,
+  pub secret: String,
+  /// Response headers covered by the signature, besides
+  /// `x-content-sha256`. Defaults to `["content-type"]`.
+  pub headers: Option<Vec<String>>,
+  /// Answers `500` in place of responses that can't be signed, rather than
+  /// sending them unsigned. Defaults to `false`.
+  pub reject_unsigned: Option<bool>,
+}
+
+/// Signs responses with a shared secret.
+///
+/// Adds `x-content-sha256` with the hex SHA-256 of the body, and
+/// `x-signature` in the same format as the `Authorization` header of signed
+/// requests. The signed string is
+///
+/// ```text
+/// HMAC-SHA256\n<status>\n<name:value lines>\n<signed headers>\n<body hash>
+/// ```
+///
+/// where headers missing from the response are signed with an empty value.
+/// Streamed responses, and ones over [`MAX_SIGNED_BODY`], can't be signed.
+pub struct Signer {
+  key_id: String,
+  key: hmac::Key,
+  headers: Vec<HeaderName>,
+  reject_unsigned: bool,
+}
+
+impl Signer {
+  pub fn new(options: ResponseSigningOptions) -> std::result::Result<Self, ActixError> {
+    let mut headers = match options.headers {
+      Some(headers) => header_names(headers)?,
+      None => vec![header::CONTENT_TYPE],
+    };
+    headers.retain(|name| *name != CONTENT_SHA256);
+    headers.push(CONTENT_SHA256);
+
+    HeaderValue::try_from(&options.key_id).map_err(|_| {
+      ActixError::new(
+        ErrorCode::InvalidOption,
+        format!("invalid key id `{}`", options.key_id),
+      )
+    })?;
+
+    Ok(Signer {
+      key_id: options.key_id,
+      key: hmac::Key::new(hmac::HMAC_SHA256, options.secret.as_bytes()),
+      headers,
+      reject_unsigned: options.reject_unsigned.unwrap_or(false),
+    })
+  }
+
+  pub async fn sign(&self, res: Response) -> Response {
+    let unsignable = match HttpBody::size_hint(res.body()).exact() {
+      None => Some("its body is streamed"),
+      Some(len) if len > MAX_SIGNED_BODY => Some("its body is over 16 MiB"),
+      Some(_) => None,
+    };
+    if let Some(reason) = unsignable {
+      if self.reject_unsigned {
+        eprintln!(
+          "Response with status {} rejected, {reason} and can't be signed",
+          res.status()
+        );
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        return res;
+      }
+      eprintln!(
+        "Response with status {} sent unsigned: {reason}",
+        res.status()
+      );
+      return res;
+    }
+    let (mut parts, mut body) = res.into_parts();
+
+    let mut hash = digest::Context::new(&digest::SHA256);
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+      match chunk {
+        Ok(chunk) => {
+          hash.update(&chunk);
+          buf.extend_from_slice(&chunk);
+        }
+        Err(err) => {
+          eprintln!("Error reading response body: {err}");
+          let mut res = Response::new(Body::empty());
+          *res.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+          return res;
+        }
+      }
+    }
+
+    let body_hash = hex(hash.finish().as_ref());
+    parts
+      .headers
+      .insert(CONTENT_SHA256, HeaderValue::try_from(&body_hash).unwrap());
+
+    let signed_headers = self
+      .headers
+      .iter()
+      .map(HeaderName::as_str)
+      .collect::<Vec<_>>()
+      .join(";");
+    let mut string_to_sign = format!("{ALGORITHM}\n{}\n", parts.status.as_u16());
+    for name in &self.headers {
+      let value = parts
+        .headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()))
+        .unwrap_or_default();
+      string_to_sign.push_str(&format!("{name}:{}\n", value.trim()));
+    }
+    string_to_sign.push_str(&format!("{signed_headers}\n{body_hash}"));
+
+    let signature = hex(hmac::sign(&self.key, string_to_sign.as_bytes()).as_ref());
+    let value = format!(
+      "{ALGORITHM} Credential={}, SignedHeaders={signed_headers}, Signature={signature}",
+      self.key_id
+    );
+    // The key id was checked when the signer was created, and the rest is
+    // made of header names and hex.
+    parts
+      .headers
+      .insert(SIGNATURE, HeaderValue::try_from(value).unwrap());
+
+    Response::from_parts(parts, Body::new(Bytes::from(buf)))
+  }
+}
+
+struct Authorization<'a> {
+  credential: &'a str,
+  signed_headers: &'a str,
+  signature: &'a str,
+}
+
+impl<'a> Authorization<'a> {
+  fn parse(value: &'a str) -> Option<Self> {
+    let params = value.strip_prefix(ALGORITHM)?.strip_prefix(' ')?;
+
+    let (mut credential, mut signed_headers, mut signature) = (None, None, None);
+    for param in params.split(',') {
+      match param.trim().split_once('=')? {
+        ("Credential", value) => credential = Some(value),
+        ("SignedHeaders", value) => signed_headers = Some(value),
+        ("Signature", value) => signature = Some(value),
+        _ => {}
+      }
+    }
+
+    Some(Authorization {
+      credential: credential?,
+      signed_headers: signed_headers?,
+      signature: signature?,
+    })
+  }
+}
+
+/// The headers to sign, as given in the options.
+fn header_names(names: Vec<String>) -> std::result::Result<Vec<HeaderName>, ActixError> {
+  names
+    .into_iter()
+    .map(|name| {
+      HeaderName::try_from(name.as_str()).map_err(|_| {
+        ActixError::new(
+          ErrorCode::InvalidHeader,
+          format!("invalid header name `{name}`"),
+        )
+      })
+    })
+    .collect()
+}
+
+fn canonical_request(req: &MyRequest, signed: &[&str], signed_headers: &str) -> Option<String> {
+  let mut query: Vec<_> = req
+    .uri()
+    .query()
+    .unwrap_or_default()
+    .split('&')
+    .filter(|pair| !pair.is_empty())
+    .collect();
+  query.sort_unstable();
+
+  let mut canonical = format!(
+    "{}\n{}\n{}\n",
+    req.method(),
+    req.uri().path(),
+    query.join("&")
+  );
+  for name in signed {
+    let mut values = req.headers().get_all(*name).iter().peekable();
+    values.peek()?;
+
+    let values = values
+      .map(|value| value.to_str().map(str::trim))
+      .collect::<std::result::Result<Vec<_>, _>>()
+      .ok()?;
+    canonical.push_str(&format!("{name}:{}\n", values.join(",")));
+  }
+  canonical.push_str(&format!(
+    "\n{signed_headers}\n{}",
+    hex(digest::digest(&digest::SHA256, req.body()).as_ref())
+  ));
+
+  Some(canonical)
+}
+
+/// A secret returned by the lookup callback: a string, a `Buffer`, or
+/// nothing for unknown keys.
+struct Secret(Option<Vec<u8>>);
+
+impl FromNapiValue for Secret {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let unknown = JsUnknown::from_napi_value(env, value)?;
+    match unknown.get_type()? {
+      ValueType::Null | ValueType::Undefined => Ok(Secret(None)),
+      ValueType::String => String::from_napi_value(env, value).map(|s| Secret(Some(s.into()))),
+      _ => Buffer::from_napi_value(env, value).map(|buffer| Secret(Some(buffer.into()))),
+    }
+  }
+}
+
+/// Parses an ISO 8601 basic timestamp (`20240101T120000Z`) into seconds
+/// since the Unix epoch.
+fn parse_date(date: &str) -> Option<u64> {
+  let digits = |range: std::ops::Range<usize>| -> Option<i64> {
+    let part = date.get(range)?;
+    part
+      .bytes()
+      .all(|b| b.is_ascii_digit())
+      .then(|| part.parse().ok())
+      .flatten()
+  };
+  let bytes = date.as_bytes();
+  if bytes.len() != 16 || bytes[8] != b'T' || bytes[15] != b'Z' {
+    return None;
+  }
+
+  let (year, month, day) = (digits(0..4)?, digits(4..6)?, digits(6..8)?);
+  let (hour, minute, second) = (digits(9..11)?, digits(11..13)?, digits(13..15)?);
+  if !(1..=12).contains(&month)
+    || !(1..=31).contains(&day)
+    || hour > 23
+    || minute > 59
+    || second > 59
+  {
+    return None;
+  }
+
+  // Days since the epoch of a proleptic Gregorian date.
+  let y = if month <= 2 { year - 1 } else { year };
+  let era = y.div_euclid(400);
+  let yoe = y - era * 400;
+  let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  let days = era * 146097 + doe - 719468;
+
+  u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decodes hex, failing on odd lengths and invalid digits.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+  // `from_str_radix` would also take a sign.
+  if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+    return None;
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_hex, header_names, hex, parse_date, Authorization};
+
+  #[test]
+  fn round_trips_hex() {
+    assert_eq!(hex(&[0x00, 0xab, 0xff]), "00abff");
+    assert_eq!(decode_hex("00abFF"), Some(vec![0x00, 0xab, 0xff]));
+    assert_eq!(decode_hex(""), Some(vec![]));
+  }
+
+  #[test]
+  fn rejects_malformed_hex() {
+    assert_eq!(decode_hex("abc"), None);
+    assert_eq!(decode_hex("+f"), None);
+    assert_eq!(decode_hex("-1"), None);
+    assert_eq!(decode_hex("0g"), None);
+    assert_eq!(decode_hex("éé"), None);
+  }
+
+  #[test]
+  fn parses_basic_timestamps() {
+    assert_eq!(parse_date("19700101T000000Z"), Some(0));
+    assert_eq!(parse_date("20240101T120000Z"), Some(1_704_110_400));
+    assert_eq!(parse_date("20240229T235959Z"), Some(1_709_251_199));
+  }
+
+  #[test]
+  fn rejects_malformed_timestamps() {
+    assert_eq!(parse_date("2024-01-01T12:00:00Z"), None);
+    assert_eq!(parse_date("20240101T120000"), None);
+    assert_eq!(parse_date("20241301T120000Z"), None);
+    assert_eq!(parse_date("20240100T120000Z"), None);
+    assert_eq!(parse_date("20240101T240000Z"), None);
+    assert_eq!(parse_date("20240101T120060Z"), None);
+    assert_eq!(parse_date("2024+101T120000Z"), None);
+    assert_eq!(parse_date("19690101T000000Z"), None);
+  }
+
+  #[test]
+  fn parses_authorization_parameters() {
+    let auth = Authorization::parse(
+      "HMAC-SHA256 Credential=key, SignedHeaders=host;x-date, Signature=abcd, Extra=1",
+    )
+    .unwrap();
+    assert_eq!(auth.credential, "key");
+    assert_eq!(auth.signed_headers, "host;x-date");
+    assert_eq!(auth.signature, "abcd");
+  }
+
+  #[test]
+  fn rejects_incomplete_authorization() {
+    assert!(Authorization::parse("HMAC-SHA256 Credential=key, SignedHeaders=host").is_none());
+    assert!(Authorization::parse("HMAC-SHA256 Credential=key, Signature").is_none());
+    assert!(
+      Authorization::parse("HMAC-SHA1 Credential=key, SignedHeaders=host, Signature=ab").is_none()
+    );
+    assert!(
+      Authorization::parse("HMAC-SHA256Credential=key, SignedHeaders=host, Signature=ab").is_none()
+    );
+  }
+
+  #[test]
+  fn validates_header_names() {
+    let names = header_names(vec!["Host".into(), "x-date".into()]).unwrap();
+    assert_eq!(names, ["host", "x-date"]);
+    assert_eq!(
+      header_names(vec!["bad header".into()])
+        .unwrap_err()
+        .to_string(),
+      "INVALID_HEADER: invalid header name `bad header`"
+    );
+  }
+}
@@ -0,0 +1,145 @@
+//! Time-limited signed URLs, for downloads that shouldn't need a session.
+//!
+//! `app.signUrl()` appends an expiry and an HMAC-SHA256 signature to a path:
+//!
+//! ```text
+//! /files/report.pdf?expires=1704110400&signature=<hex>
+//! ```
+//!
+//! The signature covers everything before `&signature=`, so neither the
+//! path, the query nor the expiry can be changed. Requests under a protected
+//! prefix are checked before routing, ahead of fast paths and handlers, so
+//! nothing is read from disk for a URL that was not handed out. A path is
+//! protected if it falls under a prefix either as sent or as the router
+//! matches it, so `//files/x` or, with `caseSensitive: false`, `/FILES/x`
+//! can't reach a protected route unsigned.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::StatusCode;
+use ring::hmac;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Request, Response, ResponseBuilder};
+use crate::routes::Routes;
+use crate::signature::{decode_hex, hex};
+
+const EXPIRES: &str = "expires";
+const SIGNATURE: &str = "&signature=";
+
+/// Configures signed URLs with `app.signedUrls()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct SignedUrlOptions {
+  /// Key of the HMAC; anyone holding it can mint URLs.
+  pub secret: String,
+  /// Paths under these prefixes are only served with a valid signature.
+  pub prefixes: Vec<String>,
+}
+
+pub struct SignedUrls {
+  key: hmac::Key,
+  prefixes: Vec<String>,
+}
+
+impl SignedUrls {
+  pub fn new(options: SignedUrlOptions) -> Result<Self, ActixError> {
+    if options.secret.is_empty() {
+      return Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        "signed URLs need a non-empty `secret`",
+      ));
+    }
+    if let Some(prefix) = options
+      .prefixes
+      .iter()
+      .find(|prefix| !prefix.starts_with('/'))
+    {
+      return Err(ActixError::new(
+        ErrorCode::InvalidOption,
+        format!("signed URL prefix `{prefix}` must start with `/`"),
+      ));
+    }
+
+    Ok(SignedUrls {
+      key: hmac::Key::new(hmac::HMAC_SHA256, options.secret.as_bytes()),
+      prefixes: options.prefixes,
+    })
+  }
+
+  /// Signs `url`, a path with an optional query, for `ttl` seconds.
+  pub fn sign(&self, url: &str, ttl: u32) -> Result<String, ActixError> {
+    if !url.starts_with('/') {
+      return Err(ActixError::new(
+        ErrorCode::InvalidValue,
+        format!("cannot sign `{url}`: only paths starting with `/` can be signed"),
+      ));
+    }
+
+    let expires = now() + u64::from(ttl);
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let unsigned = format!("{url}{separator}{EXPIRES}={expires}");
+    let signature = hex(hmac::sign(&self.key, unsigned.as_bytes()).as_ref());
+
+    Ok(format!("{unsigned}{SIGNATURE}{signature}"))
+  }
+
+  /// Whether the request needs a signature.
+  fn protects(&self, req: &Request, routes: &Routes) -> bool {
+    let path = req.uri().path();
+    let matched = routes.matched_path(path);
+    self.prefixes.iter().any(|prefix| {
+      let prefix = prefix.trim_end_matches('/');
+      is_under(path, prefix) || is_under(&matched, &routes.matched_path(prefix))
+    })
+  }
+
+  /// Rejects a protected request with `403` unless its URL carries a valid
+  /// signature that has not expired.
+  pub(crate) fn check(&self, req: &Request, routes: &Routes) -> Option<Response> {
+    if !self.protects(req, routes) || self.is_valid(req) {
+      return None;
+    }
+
+    Some(
+      ResponseBuilder::new()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::empty())
+        .unwrap(),
+    )
+  }
+
+  fn is_valid(&self, req: &Request) -> bool {
+    let Some(url) = req.uri().path_and_query() else {
+      return false;
+    };
+    let Some((unsigned, signature)) = url.as_str().rsplit_once(SIGNATURE) else {
+      return false;
+    };
+    let Some(signature) = decode_hex(signature) else {
+      return false;
+    };
+    if hmac::verify(&self.key, unsigned.as_bytes(), &signature).is_err() {
+      return false;
+    }
+
+    // The expiry is the last parameter before the signature.
+    let expires = unsigned
+      .rsplit_once(['?', '&'])
+      .and_then(|(_, param)| param.strip_prefix(EXPIRES)?.strip_prefix('='))
+      .and_then(|expires| expires.parse::<u64>().ok());
+    expires.is_some_and(|expires| now() <= expires)
+  }
+}
+
+fn is_under(path: &str, prefix: &str) -> bool {
+  path
+    .strip_prefix(prefix)
+    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |elapsed| elapsed.as_secs())
+}
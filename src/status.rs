@@ -0,0 +1,65 @@
+//! Status codes with custom reason phrases.
+//!
+//! Handlers answer with a bare status code, or with `{ status, reason }` to
+//! replace the reason phrase of the status line, which is useful for codes
+//! hyper has no phrase for. Reason phrases only exist in HTTP/1; they are
+//! left out of HTTP/2 responses.
+
+use hyper::ext::ReasonPhrase;
+use hyper::StatusCode;
+use napi::{Env, Result};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Response, ResponseBuilder};
+
+/// A status code with an optional reason phrase, as returned by `status()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+  pub status: u16,
+  pub reason: Option<String>,
+}
+
+/// Builds a `{ status, reason }` object for a handler to return, checking
+/// that the code is between 100 and 999 and that the reason is a valid
+/// reason phrase. Without a reason, the standard phrase of the code is used.
+#[napi(js_name = "status")]
+pub fn create_status(env: Env, code: u32, reason: Option<String>) -> Result<Status> {
+  let code = u16::try_from(code).unwrap_or(0);
+  let (status, reason) = parse(code, reason).map_err(|err| err.into_js(&env))?;
+
+  Ok(Status {
+    status: status.as_u16(),
+    reason: reason.map(|reason| String::from_utf8_lossy(reason.as_bytes()).into_owned()),
+  })
+}
+
+/// Validates a status code and its reason phrase.
+pub fn parse(
+  code: u16,
+  reason: Option<String>,
+) -> std::result::Result<(StatusCode, Option<ReasonPhrase>), ActixError> {
+  let invalid = |message: String| ActixError::new(ErrorCode::InvalidStatus, message);
+
+  let status =
+    StatusCode::from_u16(code).map_err(|_| invalid(format!("invalid status code {code}")))?;
+  let reason = reason
+    .map(|reason| {
+      ReasonPhrase::try_from(reason).map_err(|err| invalid(format!("invalid reason phrase: {err}")))
+    })
+    .transpose()?;
+
+  Ok((status, reason))
+}
+
+/// An empty response with the given status line.
+pub fn respond(status: StatusCode, reason: Option<ReasonPhrase>) -> Response {
+  let mut res = ResponseBuilder::new()
+    .status(status)
+    .body(Body::empty())
+    .unwrap();
+  if let Some(reason) = reason {
+    res.extensions_mut().insert(reason);
+  }
+  res
+}
@@ -0,0 +1,474 @@
+//! Streamed response bodies, serialized on the Rust side.
+//!
+//! `ndjson(iterable)` and `csv(columns, iterable)` wrap a sync or async
+//! iterable for a handler to return; `NodeResponse.stream()` takes one
+//! yielding strings and buffers, such as a Node.js `Readable`. Once the
+//! response starts, items are pulled from the iterator one at a time,
+//! encoded off the JS thread and written out in batches. Pulling waits
+//! whenever the client reads slower than the items come, so the iterator
+//! never runs far ahead of the socket.
+//!
+//! With `gzip` set, batches are compressed before they are written, each one
+//! flushed so the client can decode it as soon as it arrives.
+
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::StatusCode;
+use napi::bindgen_prelude::{Buffer, External, FromNapiValue, Uint8Array};
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{
+  sys, Env, Error, JsFunction, JsObject, JsUnknown, NapiRaw, NapiValue, Result, ValueType,
+};
+use serde_json::Value;
+
+use crate::buffer;
+use crate::dispatch::Returned;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Response, ResponseBuilder};
+
+/// Property of the object returned by `ndjson()` or `csv()` holding the
+/// stream.
+const STREAM_KEY: &str = "__actixStream";
+
+/// Bytes buffered before they are written out.
+const FLUSH_SIZE: usize = 16 * 1024;
+/// Longest time encoded items wait to be written out.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
+type Call = ThreadsafeFunction<(), ErrorStrategy::Fatal>;
+
+/// How items are written to the body.
+enum Format {
+  Ndjson,
+  /// RFC 4180 records, after a header record naming the columns.
+  Csv {
+    columns: Vec<String>,
+  },
+  /// Strings and buffers, written as they are.
+  Raw,
+}
+
+impl Format {
+  fn content_type(&self) -> &'static str {
+    match self {
+      Format::Ndjson => "application/x-ndjson",
+      Format::Csv { .. } => "text/csv; charset=utf-8",
+      Format::Raw => "application/octet-stream",
+    }
+  }
+
+  /// Writes what comes before the first item.
+  fn start(&self, buf: &mut Vec<u8>) {
+    match self {
+      Format::Ndjson | Format::Raw => {}
+      Format::Csv { columns } => csv_record(
+        columns.iter().map(|column| Cow::Borrowed(column.as_str())),
+        buf,
+      ),
+    }
+  }
+
+  fn encode(&self, item: &Value, buf: &mut Vec<u8>) -> Result<()> {
+    match self {
+      Format::Ndjson => {
+        serde_json::to_writer(&mut *buf, item)
+          .map_err(|err| Error::from_reason(err.to_string()))?;
+        buf.push(b'\n');
+      }
+      // Arrays are fields in column order, objects are looked up by column
+      // name.
+      Format::Csv { columns } => match item {
+        Value::Array(fields) => csv_record(
+          (0..columns.len()).map(|idx| csv_field(fields.get(idx))),
+          buf,
+        ),
+        Value::Object(fields) => csv_record(
+          columns.iter().map(|column| csv_field(fields.get(column))),
+          buf,
+        ),
+        _ => return Err(Error::from_reason("CSV rows must be arrays or objects")),
+      },
+      Format::Raw => match item {
+        Value::String(text) => buf.extend_from_slice(text.as_bytes()),
+        _ => return Err(Error::from_reason("streams take strings and buffers")),
+      },
+    }
+    Ok(())
+  }
+
+  /// Writes an item that was a `Buffer`.
+  fn write(&self, bytes: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    match self {
+      Format::Raw => {
+        buf.extend_from_slice(bytes);
+        Ok(())
+      }
+      Format::Ndjson | Format::Csv { .. } => {
+        Err(Error::from_reason("expected JSON values, got a buffer"))
+      }
+    }
+  }
+}
+
+/// The text of a CSV field. Missing and `null` values are empty, nested
+/// arrays and objects are written as JSON.
+fn csv_field(value: Option<&Value>) -> Cow<'_, str> {
+  match value {
+    None | Some(Value::Null) => Cow::Borrowed(""),
+    Some(Value::String(text)) => Cow::Borrowed(text),
+    Some(Value::Bool(flag)) => Cow::Borrowed(if *flag { "true" } else { "false" }),
+    Some(value) => Cow::Owned(value.to_string()),
+  }
+}
+
+/// Writes a record, quoting fields that contain a delimiter, a quote or a
+/// line break, and doubling the quotes inside them.
+fn csv_record<'f>(fields: impl Iterator<Item = Cow<'f, str>>, buf: &mut Vec<u8>) {
+  for (idx, field) in fields.enumerate() {
+    if idx > 0 {
+      buf.push(b',');
+    }
+    if field.contains([',', '"', '\r', '\n']) {
+      buf.push(b'"');
+      buf.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+      buf.push(b'"');
+    } else {
+      buf.extend_from_slice(field.as_bytes());
+    }
+  }
+  buf.extend_from_slice(b"\r\n");
+}
+
+/// Compresses batches as they are written, when gzip is on.
+struct Encoder(Option<GzEncoder<Vec<u8>>>);
+
+impl Encoder {
+  fn new(gzip: bool) -> Self {
+    Encoder(gzip.then(|| GzEncoder::new(Vec::new(), Compression::default())))
+  }
+
+  /// The bytes to send for a batch, which is emptied. Compressed batches
+  /// are flushed so they can be decoded on their own.
+  fn chunk(&mut self, batch: &mut Vec<u8>) -> Result<Bytes> {
+    match &mut self.0 {
+      None => Ok(Bytes::from(std::mem::replace(
+        batch,
+        Vec::with_capacity(FLUSH_SIZE),
+      ))),
+      Some(gzip) => {
+        gzip.write_all(batch).and_then(|_| gzip.flush())?;
+        batch.clear();
+        Ok(Bytes::from(std::mem::take(gzip.get_mut())))
+      }
+    }
+  }
+
+  /// The last bytes to send, with the gzip trailer.
+  fn finish(self, batch: Vec<u8>) -> Result<Bytes> {
+    match self.0 {
+      None => Ok(Bytes::from(batch)),
+      Some(mut gzip) => {
+        gzip.write_all(&batch)?;
+        Ok(Bytes::from(gzip.finish()?))
+      }
+    }
+  }
+}
+
+/// An iterator to stream, as returned by a handler.
+pub struct Streamed {
+  format: Format,
+  gzip: bool,
+  next: Call,
+  /// The iterator's `return()`, called if the client goes away first.
+  finish: Option<Call>,
+}
+
+impl Streamed {
+  /// Wraps `iterable` in the object handed back to JS.
+  fn new(env: &Env, iterable: JsObject, format: Format, gzip: bool) -> Result<Self> {
+    let iterator = iterator(env, iterable)?;
+    let next = bound(env, &iterator, "next")?.ok_or_else(|| {
+      ActixError::new(
+        ErrorCode::InvalidValue,
+        "the iterator has no `next()` method",
+      )
+      .into_js(env)
+    })?;
+    let finish = bound(env, &iterator, "return")?;
+
+    Ok(Streamed {
+      format,
+      gzip,
+      next,
+      finish,
+    })
+  }
+
+  /// Streams the strings and buffers of `iterable` as they are, such as
+  /// the chunks of a Node.js `Readable`.
+  pub fn raw(env: &Env, iterable: JsObject) -> Result<Self> {
+    Streamed::new(env, iterable, Format::Raw, false)
+  }
+
+  fn wrap(env: &Env, iterable: JsObject, format: Format, gzip: bool) -> Result<JsObject> {
+    let streamed = Streamed::new(env, iterable, format, gzip)?;
+    let mut wrapper = env.create_object()?;
+    wrapper.set_named_property(STREAM_KEY, External::new(Some(streamed)))?;
+    Ok(wrapper)
+  }
+
+  /// Takes the stream out of an object returned by a handler, if it is one.
+  ///
+  /// # Safety
+  ///
+  /// `env` and `object` must be valid, on the JS thread.
+  pub unsafe fn take(env: sys::napi_env, object: &JsObject) -> Result<Option<Streamed>> {
+    if !object.has_named_property(STREAM_KEY)? {
+      return Ok(None);
+    }
+
+    let external: JsUnknown = object.get_named_property(STREAM_KEY)?;
+    let streamed = External::<Option<Streamed>>::from_napi_value(env, external.raw())?
+      .take()
+      .ok_or_else(|| {
+        ActixError::new(ErrorCode::AlreadyUsed, "a stream can only be returned once")
+          .into_js(&Env::from_raw(env))
+      })?;
+    Ok(Some(streamed))
+  }
+
+  /// Starts the response; items are pulled as the body is sent.
+  pub fn into_response(self) -> Response {
+    let (sender, body) = hyper::Body::channel();
+    let content_type = HeaderValue::from_static(self.format.content_type());
+    let gzip = self.gzip;
+    tokio::spawn(self.pump(sender));
+
+    let mut res = ResponseBuilder::new()
+      .status(StatusCode::OK)
+      .body(Body::empty())
+      .unwrap();
+    res.headers_mut().insert(header::CONTENT_TYPE, content_type);
+    if gzip {
+      res
+        .headers_mut()
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    *res.body_mut() = Body(body);
+    res
+  }
+
+  /// Pulls the items into `sender` until the iterator is done.
+  ///
+  /// Items are batched up to [`FLUSH_SIZE`], but never held longer than
+  /// [`FLUSH_INTERVAL`], even while the iterator is slow to produce the
+  /// next one.
+  pub(crate) async fn pump(self, mut sender: hyper::body::Sender) {
+    let mut buf = Vec::with_capacity(FLUSH_SIZE);
+    let mut encoder = Encoder::new(self.gzip);
+    let mut flushed = Instant::now();
+    self.format.start(&mut buf);
+
+    loop {
+      let next = async {
+        match self.next.call_async::<Returned<Item>>(()).await {
+          Ok(returned) => returned.settle().await,
+          Err(err) => Err(err),
+        }
+      };
+      tokio::pin!(next);
+
+      let item = loop {
+        let due = tokio::time::Instant::from_std(flushed + FLUSH_INTERVAL);
+        tokio::select! {
+          item = &mut next => break item,
+          _ = tokio::time::sleep_until(due), if !buf.is_empty() => {
+            if let Err(stopped) = self.flush(&mut encoder, &mut buf, &mut sender).await {
+              return stop(sender, stopped);
+            }
+            flushed = Instant::now();
+          }
+        }
+      };
+
+      let encoded = match item {
+        Ok(Item::Done) => break,
+        Ok(Item::Value(value)) => self.format.encode(&value, &mut buf),
+        Ok(Item::Bytes(bytes)) => self.format.write(&bytes, &mut buf),
+        Err(err) => Err(err),
+      };
+      if let Err(err) = encoded {
+        return fail(sender, err);
+      }
+
+      if buf.len() >= FLUSH_SIZE || flushed.elapsed() >= FLUSH_INTERVAL {
+        if let Err(stopped) = self.flush(&mut encoder, &mut buf, &mut sender).await {
+          return stop(sender, stopped);
+        }
+        flushed = Instant::now();
+      }
+    }
+
+    match encoder.finish(buf) {
+      Ok(chunk) if chunk.is_empty() => {}
+      Ok(chunk) => {
+        let _ = sender.send_data(chunk).await;
+      }
+      Err(err) => fail(sender, err),
+    }
+  }
+
+  /// Sends the batched items. Fails with `None` when the client has gone
+  /// away, after telling the iterator.
+  async fn flush(
+    &self,
+    encoder: &mut Encoder,
+    buf: &mut Vec<u8>,
+    sender: &mut hyper::body::Sender,
+  ) -> std::result::Result<(), Option<Error>> {
+    let chunk = encoder.chunk(buf).map_err(Some)?;
+    // Waits for the client to read; fails once it has gone away.
+    if sender.send_data(chunk).await.is_err() {
+      if let Some(finish) = &self.finish {
+        finish.call((), ThreadsafeFunctionCallMode::NonBlocking);
+      }
+      return Err(None);
+    }
+    Ok(())
+  }
+}
+
+/// Ends a body after a failed flush.
+fn stop(sender: hyper::body::Sender, stopped: Option<Error>) {
+  if let Some(err) = stopped {
+    fail(sender, err);
+  }
+}
+
+/// Ends a body that could not be completed. The status is already sent;
+/// cutting the body short is the only way left to tell the client.
+fn fail(sender: hyper::body::Sender, err: Error) {
+  eprintln!("Error streaming response: {err}");
+  sender.abort();
+}
+
+/// Gets the iterator of a sync or async iterable, or the object itself if
+/// it already is an iterator.
+pub(crate) fn iterator(env: &Env, iterable: JsObject) -> Result<JsObject> {
+  let symbol: JsObject = env.get_global()?.get_named_property("Symbol")?;
+
+  for name in ["asyncIterator", "iterator"] {
+    let key: JsUnknown = symbol.get_named_property(name)?;
+    let method: JsUnknown = iterable.get_property(key)?;
+    if method.get_type()? == ValueType::Function {
+      let method: JsFunction = method.try_into()?;
+      return method.call_without_args(Some(&iterable))?.try_into();
+    }
+  }
+
+  Ok(iterable)
+}
+
+/// A method of `object` bound to it, callable from any thread.
+pub(crate) fn bound(env: &Env, object: &JsObject, name: &str) -> Result<Option<Call>> {
+  let method: JsUnknown = object.get_named_property(name)?;
+  if method.get_type()? != ValueType::Function {
+    return Ok(None);
+  }
+
+  let method = method.coerce_to_object()?;
+  let bind: JsFunction = method.get_named_property("bind")?;
+  // SAFETY: a second handle to the same value, in the same scope.
+  let this = unsafe { JsUnknown::from_raw_unchecked(env.raw(), object.raw()) };
+  let bound: JsFunction = bind.call(Some(&method), &[this])?.try_into()?;
+
+  bound
+    .create_threadsafe_function(
+      0,
+      |_: ThreadSafeCallContext<()>| Ok(Vec::<JsUnknown>::new()),
+    )
+    .map(Some)
+}
+
+/// What the iterator's `next()` returned, once settled.
+enum Item {
+  Value(Value),
+  Bytes(Bytes),
+  Done,
+}
+
+impl FromNapiValue for Item {
+  unsafe fn from_napi_value(env: sys::napi_env, value: sys::napi_value) -> Result<Self> {
+    let result = JsObject::from_napi_value(env, value)?;
+    let done: JsUnknown = result.get_named_property("done")?;
+    if done.coerce_to_bool()?.get_value()? {
+      return Ok(Item::Done);
+    }
+
+    let value: JsUnknown = result.get_named_property("value")?;
+    if value.is_buffer()? {
+      return Buffer::from_napi_value(env, value.raw())
+        .map(|bytes| Item::Bytes(buffer::from_js(bytes)));
+    }
+    if value.is_typedarray()? {
+      // Such as the chunks of a WHATWG `ReadableStream`.
+      return Uint8Array::from_napi_value(env, value.raw())
+        .map(|bytes| Item::Bytes(Bytes::copy_from_slice(&bytes)));
+    }
+    Env::from_raw(env).from_js_value(value).map(Item::Value)
+  }
+}
+
+/// Streams the items of `iterable` as newline-delimited JSON, for a handler
+/// to return. Items are pulled as the client reads.
+#[napi(
+  js_name = "ndjson",
+  ts_args_type = "iterable: AsyncIterable<unknown> | Iterable<unknown> | AsyncIterator<unknown> | Iterator<unknown>",
+  ts_return_type = "import('./types').StreamedBody"
+)]
+pub fn create_ndjson(env: Env, iterable: JsObject) -> Result<JsObject> {
+  Streamed::wrap(&env, iterable, Format::Ndjson, false)
+}
+
+/// Options of `csv()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct CsvOptions {
+  /// Compresses the body with gzip. Defaults to `false`; check the
+  /// request's `Accept-Encoding` first.
+  pub gzip: Option<bool>,
+}
+
+/// Streams the items of `iterable` as CSV, for a handler to return. Each
+/// item is an array of fields in column order, or an object whose fields
+/// are looked up by column name.
+#[napi(
+  js_name = "csv",
+  ts_args_type = "columns: Array<string>, iterable: AsyncIterable<Array<unknown> | Record<string, unknown>> | Iterable<Array<unknown> | Record<string, unknown>>, options?: CsvOptions | undefined | null",
+  ts_return_type = "import('./types').StreamedBody"
+)]
+pub fn create_csv(
+  env: Env,
+  columns: Vec<String>,
+  iterable: JsObject,
+  options: Option<CsvOptions>,
+) -> Result<JsObject> {
+  if columns.is_empty() {
+    return Err(
+      ActixError::new(ErrorCode::InvalidOption, "`columns` must not be empty").into_js(&env),
+    );
+  }
+
+  let gzip = options.and_then(|options| options.gzip).unwrap_or(false);
+  Streamed::wrap(&env, iterable, Format::Csv { columns }, gzip)
+}
@@ -0,0 +1,928 @@
+//! Structured field values for HTTP (RFC 8941).
+//!
+//! Client hints, `Priority`, `Cache-Status`, the HTTP message signature
+//! headers and the newer rate limit headers are all written in the same
+//! structured syntax. Parsing it here spares handlers from splitting them
+//! by hand: `req.getStructuredHeader(name)` returns the parsed value, and
+//! `serializeStructuredHeader()` writes one back.
+//!
+//! A field is an item, a list or a dictionary, and which one it is can't
+//! be told from its text. Registered fields are looked up by name; others
+//! need their type spelled out. In JS, members are `{ value, params }`
+//! objects, whose `value` is an array for inner lists. Integers and
+//! decimals are numbers, byte sequences are `Buffer`s and tokens are
+//! `{ token }` objects, to keep them apart from strings.
+
+use std::fmt::Write;
+
+use hyper::header::HeaderMap;
+use napi::{Env, JsBuffer, JsObject, JsString, JsUnknown, Result, ValueType};
+
+use crate::base64::{self, Alphabet};
+use crate::error::{ActixError, ErrorCode};
+
+/// The largest integer a field can hold.
+const MAX_INTEGER: i64 = 999_999_999_999_999;
+
+fn invalid(message: impl Into<String>) -> ActixError {
+  ActixError::new(ErrorCode::InvalidValue, message)
+}
+
+/// The three shapes of a structured field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+  Item,
+  List,
+  Dictionary,
+}
+
+impl Kind {
+  fn from_name(kind: &str) -> std::result::Result<Self, ActixError> {
+    match kind {
+      "item" => Ok(Kind::Item),
+      "list" => Ok(Kind::List),
+      "dictionary" => Ok(Kind::Dictionary),
+      _ => Err(invalid(format!(
+        "unknown structured field type `{kind}`, expected `item`, `list` or `dictionary`"
+      ))),
+    }
+  }
+
+  /// The type of a registered structured header.
+  fn of_header(name: &str) -> Option<Self> {
+    match name {
+      "accept-ch"
+      | "cache-status"
+      | "critical-ch"
+      | "proxy-status"
+      | "ratelimit"
+      | "ratelimit-policy"
+      | "sec-ch-ua"
+      | "sec-ch-ua-full-version-list" => Some(Kind::List),
+      "cdn-cache-control"
+      | "content-digest"
+      | "priority"
+      | "repr-digest"
+      | "signature"
+      | "signature-input"
+      | "want-content-digest"
+      | "want-repr-digest" => Some(Kind::Dictionary),
+      "cross-origin-embedder-policy" | "cross-origin-opener-policy" => Some(Kind::Item),
+      _ if name.starts_with("sec-ch-") => Some(Kind::Item),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BareItem {
+  Integer(i64),
+  Decimal(f64),
+  String(String),
+  Token(String),
+  ByteSequence(Vec<u8>),
+  Boolean(bool),
+}
+
+pub type Parameters = Vec<(String, BareItem)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+  pub bare: BareItem,
+  pub params: Parameters,
+}
+
+/// A member of a list or a value of a dictionary.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Member {
+  Item(Item),
+  InnerList(Vec<Item>, Parameters),
+}
+
+pub type List = Vec<Member>;
+
+/// Keys in the order they first appeared.
+pub type Dictionary = Vec<(String, Member)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+  Item(Item),
+  List(List),
+  Dictionary(Dictionary),
+}
+
+impl BareItem {
+  /// Strings and tokens, which many fields use interchangeably.
+  pub fn into_text(self) -> Option<String> {
+    match self {
+      BareItem::String(text) | BareItem::Token(text) => Some(text),
+      _ => None,
+    }
+  }
+
+  pub fn number(&self) -> Option<f64> {
+    match self {
+      BareItem::Integer(number) => Some(*number as f64),
+      BareItem::Decimal(number) => Some(*number),
+      _ => None,
+    }
+  }
+
+  pub fn flag(&self) -> Option<bool> {
+    match self {
+      BareItem::Boolean(flag) => Some(*flag),
+      _ => None,
+    }
+  }
+}
+
+/// Parses a field of the given type, returning `None` if it is malformed.
+/// Repeated header lines must be joined with `, ` beforehand.
+pub fn parse(input: &[u8], kind: Kind) -> Option<Field> {
+  let mut parser = Parser { input, pos: 0 };
+  parser.skip_sp();
+  let field = match kind {
+    Kind::Item => Field::Item(parser.item()?),
+    Kind::List => Field::List(parser.list()?),
+    Kind::Dictionary => Field::Dictionary(parser.dictionary()?),
+  };
+  parser.skip_sp();
+  parser.at_end().then_some(field)
+}
+
+pub fn parse_item(input: &[u8]) -> Option<Item> {
+  match parse(input, Kind::Item)? {
+    Field::Item(item) => Some(item),
+    _ => None,
+  }
+}
+
+pub fn parse_list(input: &[u8]) -> Option<List> {
+  match parse(input, Kind::List)? {
+    Field::List(list) => Some(list),
+    _ => None,
+  }
+}
+
+struct Parser<'a> {
+  input: &'a [u8],
+  pos: usize,
+}
+
+impl Parser<'_> {
+  fn list(&mut self) -> Option<List> {
+    let mut members = Vec::new();
+    while !self.at_end() {
+      members.push(self.member()?);
+      if !self.separator()? {
+        break;
+      }
+    }
+    Some(members)
+  }
+
+  fn dictionary(&mut self) -> Option<Dictionary> {
+    let mut dictionary: Dictionary = Vec::new();
+    while !self.at_end() {
+      let key = self.key()?;
+      let member = if self.peek() == Some(b'=') {
+        self.pos += 1;
+        self.member()?
+      } else {
+        Member::Item(Item {
+          bare: BareItem::Boolean(true),
+          params: self.params()?,
+        })
+      };
+      // A repeated key keeps its place but takes the last value.
+      match dictionary.iter_mut().find(|(existing, _)| *existing == key) {
+        Some((_, existing)) => *existing = member,
+        None => dictionary.push((key, member)),
+      }
+      if !self.separator()? {
+        break;
+      }
+    }
+    Some(dictionary)
+  }
+
+  /// Consumes the `,` between members. `Some(false)` means the input ended
+  /// instead; a trailing comma is malformed.
+  fn separator(&mut self) -> Option<bool> {
+    self.skip_ows();
+    if self.at_end() {
+      return Some(false);
+    }
+    if self.next()? != b',' {
+      return None;
+    }
+    self.skip_ows();
+    (!self.at_end()).then_some(true)
+  }
+
+  fn member(&mut self) -> Option<Member> {
+    if self.peek()? != b'(' {
+      return self.item().map(Member::Item);
+    }
+
+    self.pos += 1;
+    let mut items = Vec::new();
+    loop {
+      self.skip_sp();
+      if self.peek()? == b')' {
+        self.pos += 1;
+        return Some(Member::InnerList(items, self.params()?));
+      }
+      items.push(self.item()?);
+      if !matches!(self.peek()?, b' ' | b')') {
+        return None;
+      }
+    }
+  }
+
+  fn item(&mut self) -> Option<Item> {
+    let bare = self.bare_item()?;
+    let params = self.params()?;
+    Some(Item { bare, params })
+  }
+
+  fn params(&mut self) -> Option<Parameters> {
+    let mut params: Parameters = Vec::new();
+    while self.peek() == Some(b';') {
+      self.pos += 1;
+      self.skip_sp();
+      let key = self.key()?;
+      let value = if self.peek() == Some(b'=') {
+        self.pos += 1;
+        self.bare_item()?
+      } else {
+        BareItem::Boolean(true)
+      };
+      match params.iter_mut().find(|(existing, _)| *existing == key) {
+        Some((_, existing)) => *existing = value,
+        None => params.push((key, value)),
+      }
+    }
+    Some(params)
+  }
+
+  fn key(&mut self) -> Option<String> {
+    let start = self.pos;
+    match self.peek()? {
+      b'a'..=b'z' | b'*' => self.pos += 1,
+      _ => return None,
+    }
+    while let Some(b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'.' | b'*') = self.peek() {
+      self.pos += 1;
+    }
+    Some(self.text(start))
+  }
+
+  fn bare_item(&mut self) -> Option<BareItem> {
+    match self.peek()? {
+      b'"' => self.string().map(BareItem::String),
+      b':' => self.byte_sequence().map(BareItem::ByteSequence),
+      b'?' => {
+        self.pos += 1;
+        match self.next()? {
+          b'0' => Some(BareItem::Boolean(false)),
+          b'1' => Some(BareItem::Boolean(true)),
+          _ => None,
+        }
+      }
+      b'-' | b'0'..=b'9' => self.number(),
+      byte if byte.is_ascii_alphabetic() || byte == b'*' => Some(BareItem::Token(self.token())),
+      _ => None,
+    }
+  }
+
+  fn number(&mut self) -> Option<BareItem> {
+    let start = self.pos;
+    if self.peek() == Some(b'-') {
+      self.pos += 1;
+    }
+    let digits = self.pos;
+    if !self.peek()?.is_ascii_digit() {
+      return None;
+    }
+    let mut dot = None;
+    while let Some(byte) = self.peek() {
+      match byte {
+        b'0'..=b'9' => {}
+        b'.' if dot.is_none() => {
+          if self.pos - digits > 12 {
+            return None;
+          }
+          dot = Some(self.pos);
+        }
+        _ => break,
+      }
+      self.pos += 1;
+      let limit = if dot.is_some() { 16 } else { 15 };
+      if self.pos - digits > limit {
+        return None;
+      }
+    }
+
+    let text = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+    match dot {
+      None => text.parse().ok().map(BareItem::Integer),
+      Some(dot) => {
+        let fraction = self.pos - dot - 1;
+        if fraction == 0 || fraction > 3 {
+          return None;
+        }
+        text.parse().ok().map(BareItem::Decimal)
+      }
+    }
+  }
+
+  fn string(&mut self) -> Option<String> {
+    self.pos += 1;
+    let mut text = String::new();
+    loop {
+      match self.next()? {
+        b'\\' => match self.next()? {
+          escaped @ (b'"' | b'\\') => text.push(char::from(escaped)),
+          _ => return None,
+        },
+        b'"' => return Some(text),
+        byte @ 0x20..=0x7e => text.push(char::from(byte)),
+        _ => return None,
+      }
+    }
+  }
+
+  fn token(&mut self) -> String {
+    let start = self.pos;
+    while self.peek().is_some_and(is_tchar) {
+      self.pos += 1;
+    }
+    self.text(start)
+  }
+
+  fn byte_sequence(&mut self) -> Option<Vec<u8>> {
+    self.pos += 1;
+    let start = self.pos;
+    while self.peek()? != b':' {
+      self.pos += 1;
+    }
+    let encoded = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+    self.pos += 1;
+    base64::decode(encoded, Alphabet::Standard)
+  }
+
+  fn text(&self, start: usize) -> String {
+    String::from_utf8_lossy(&self.input[start..self.pos]).into_owned()
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.input.get(self.pos).copied()
+  }
+
+  fn next(&mut self) -> Option<u8> {
+    let byte = self.peek()?;
+    self.pos += 1;
+    Some(byte)
+  }
+
+  fn skip_sp(&mut self) {
+    while self.peek() == Some(b' ') {
+      self.pos += 1;
+    }
+  }
+
+  fn skip_ows(&mut self) {
+    while let Some(b' ' | b'\t') = self.peek() {
+      self.pos += 1;
+    }
+  }
+
+  fn at_end(&self) -> bool {
+    self.pos >= self.input.len()
+  }
+}
+
+/// Token characters, plus the `:` and `/` tokens may hold after the first.
+fn is_tchar(byte: u8) -> bool {
+  byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&byte)
+}
+
+fn is_key(key: &str) -> bool {
+  let mut bytes = key.bytes();
+  matches!(bytes.next(), Some(b'a'..=b'z' | b'*'))
+    && bytes.all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'.' | b'*'))
+}
+
+/// Writes a field in its canonical form.
+pub fn serialize(field: &Field) -> std::result::Result<String, ActixError> {
+  let mut out = String::new();
+  match field {
+    Field::Item(item) => write_item(&mut out, item)?,
+    Field::List(list) => {
+      for (idx, member) in list.iter().enumerate() {
+        if idx > 0 {
+          out.push_str(", ");
+        }
+        write_member(&mut out, member)?;
+      }
+    }
+    Field::Dictionary(dictionary) => {
+      for (idx, (key, member)) in dictionary.iter().enumerate() {
+        if idx > 0 {
+          out.push_str(", ");
+        }
+        write_key(&mut out, key)?;
+        match member {
+          Member::Item(Item {
+            bare: BareItem::Boolean(true),
+            params,
+          }) => write_params(&mut out, params)?,
+          member => {
+            out.push('=');
+            write_member(&mut out, member)?;
+          }
+        }
+      }
+    }
+  }
+  Ok(out)
+}
+
+fn write_member(out: &mut String, member: &Member) -> std::result::Result<(), ActixError> {
+  match member {
+    Member::Item(item) => write_item(out, item),
+    Member::InnerList(items, params) => {
+      out.push('(');
+      for (idx, item) in items.iter().enumerate() {
+        if idx > 0 {
+          out.push(' ');
+        }
+        write_item(out, item)?;
+      }
+      out.push(')');
+      write_params(out, params)
+    }
+  }
+}
+
+fn write_item(out: &mut String, item: &Item) -> std::result::Result<(), ActixError> {
+  write_bare_item(out, &item.bare)?;
+  write_params(out, &item.params)
+}
+
+fn write_params(out: &mut String, params: &Parameters) -> std::result::Result<(), ActixError> {
+  for (key, value) in params {
+    out.push(';');
+    write_key(out, key)?;
+    if *value != BareItem::Boolean(true) {
+      out.push('=');
+      write_bare_item(out, value)?;
+    }
+  }
+  Ok(())
+}
+
+fn write_key(out: &mut String, key: &str) -> std::result::Result<(), ActixError> {
+  if !is_key(key) {
+    return Err(invalid(format!("invalid structured field key `{key}`")));
+  }
+  out.push_str(key);
+  Ok(())
+}
+
+fn write_bare_item(out: &mut String, bare: &BareItem) -> std::result::Result<(), ActixError> {
+  match bare {
+    BareItem::Integer(number) => {
+      if number.abs() > MAX_INTEGER {
+        return Err(invalid(format!(
+          "{number} is out of range for a structured field integer"
+        )));
+      }
+      write!(out, "{number}").unwrap();
+    }
+    BareItem::Decimal(number) => {
+      // Rounded to three fractional digits, ties to even.
+      let rounded = (number * 1000.0).round_ties_even() / 1000.0;
+      if !rounded.is_finite() || rounded.abs() >= 1e12 {
+        return Err(invalid(format!(
+          "{number} is out of range for a structured field decimal"
+        )));
+      }
+      let text = format!("{rounded:.3}");
+      let text = text.trim_end_matches('0');
+      out.push_str(text);
+      if text.ends_with('.') {
+        out.push('0');
+      }
+    }
+    BareItem::String(text) => {
+      out.push('"');
+      for ch in text.chars() {
+        match ch {
+          '"' | '\\' => {
+            out.push('\\');
+            out.push(ch);
+          }
+          ' '..='~' => out.push(ch),
+          _ => {
+            return Err(invalid(
+              "structured field strings can only hold printable ASCII",
+            ))
+          }
+        }
+      }
+      out.push('"');
+    }
+    BareItem::Token(token) => {
+      let valid = token
+        .bytes()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == b'*')
+        && token.bytes().all(is_tchar);
+      if !valid {
+        return Err(invalid(format!("invalid token `{token}`")));
+      }
+      out.push_str(token);
+    }
+    BareItem::ByteSequence(bytes) => {
+      out.push(':');
+      base64::encode(out, bytes);
+      out.push(':');
+    }
+    BareItem::Boolean(flag) => out.push_str(if *flag { "?1" } else { "?0" }),
+  }
+  Ok(())
+}
+
+/// Parses header `name` of `headers` for `req.getStructuredHeader()`.
+/// Missing and malformed headers give `null`.
+pub fn header_to_js(
+  env: &Env,
+  headers: &HeaderMap,
+  name: &str,
+  kind: Option<&str>,
+) -> Result<JsUnknown> {
+  let mut joined = Vec::new();
+  for (idx, value) in headers
+    .get_all(name.to_ascii_lowercase())
+    .iter()
+    .enumerate()
+  {
+    if idx > 0 {
+      joined.extend_from_slice(b", ");
+    }
+    joined.extend_from_slice(value.as_bytes());
+  }
+  value_to_js(env, name, Some(joined), kind)
+}
+
+/// Parses `value`, the value of header `name` with repeated ones joined by
+/// `, `, like [`header_to_js`]. Missing and empty values give `null`.
+pub fn value_to_js(
+  env: &Env,
+  name: &str,
+  value: Option<impl AsRef<[u8]>>,
+  kind: Option<&str>,
+) -> Result<JsUnknown> {
+  let name = name.to_ascii_lowercase();
+  let kind = match kind {
+    Some(kind) => Kind::from_name(kind).map_err(|err| err.into_js(env))?,
+    None => Kind::of_header(&name).ok_or_else(|| {
+      invalid(format!(
+        "`{name}` is not a known structured header, pass its type"
+      ))
+      .into_js(env)
+    })?,
+  };
+
+  let value = value.filter(|value| !value.as_ref().is_empty());
+  match value.and_then(|value| parse(value.as_ref(), kind)) {
+    Some(field) => field_to_js(env, &field),
+    None => env.get_null().map(|null| null.into_unknown()),
+  }
+}
+
+fn field_to_js(env: &Env, field: &Field) -> Result<JsUnknown> {
+  match field {
+    Field::Item(item) => item_to_js(env, item).map(JsObject::into_unknown),
+    Field::List(list) => {
+      let mut array = env.create_array_with_length(list.len())?;
+      for (idx, member) in list.iter().enumerate() {
+        array.set_element(idx as u32, member_to_js(env, member)?)?;
+      }
+      Ok(array.into_unknown())
+    }
+    Field::Dictionary(dictionary) => {
+      let mut object = env.create_object()?;
+      for (key, member) in dictionary {
+        object.set_named_property(key, member_to_js(env, member)?)?;
+      }
+      Ok(object.into_unknown())
+    }
+  }
+}
+
+fn member_to_js(env: &Env, member: &Member) -> Result<JsObject> {
+  match member {
+    Member::Item(item) => item_to_js(env, item),
+    Member::InnerList(items, params) => {
+      let mut array = env.create_array_with_length(items.len())?;
+      for (idx, item) in items.iter().enumerate() {
+        array.set_element(idx as u32, item_to_js(env, item)?)?;
+      }
+      with_params(env, array.into_unknown(), params)
+    }
+  }
+}
+
+fn item_to_js(env: &Env, item: &Item) -> Result<JsObject> {
+  with_params(env, bare_item_to_js(env, &item.bare)?, &item.params)
+}
+
+fn with_params(env: &Env, value: JsUnknown, params: &Parameters) -> Result<JsObject> {
+  let mut object = env.create_object()?;
+  object.set_named_property("value", value)?;
+  let mut js_params = env.create_object()?;
+  for (key, value) in params {
+    js_params.set_named_property(key, bare_item_to_js(env, value)?)?;
+  }
+  object.set_named_property("params", js_params)?;
+  Ok(object)
+}
+
+fn bare_item_to_js(env: &Env, bare: &BareItem) -> Result<JsUnknown> {
+  Ok(match bare {
+    BareItem::Integer(number) => env.create_int64(*number)?.into_unknown(),
+    BareItem::Decimal(number) => env.create_double(*number)?.into_unknown(),
+    BareItem::String(text) => env.create_string(text)?.into_unknown(),
+    BareItem::Token(token) => {
+      let mut object = env.create_object()?;
+      object.set_named_property("token", env.create_string(token)?)?;
+      object.into_unknown()
+    }
+    BareItem::ByteSequence(bytes) => env
+      .create_buffer_with_data(bytes.clone())?
+      .into_raw()
+      .into_unknown(),
+    BareItem::Boolean(flag) => env.get_boolean(*flag)?.into_unknown(),
+  })
+}
+
+/// Writes a structured field from its JS form, as returned by
+/// `req.getStructuredHeader()`. Items may also be given as bare values,
+/// without `params`.
+#[napi(
+  js_name = "serializeStructuredHeader",
+  ts_args_type = "value: import('./types').StructuredBareItem | import('./types').StructuredField | Array<import('./types').StructuredBareItem | import('./types').StructuredMember> | Record<string, import('./types').StructuredBareItem | import('./types').StructuredMember>, kind: import('./types').StructuredFieldType"
+)]
+pub fn serialize_structured_header(env: Env, value: JsUnknown, kind: String) -> Result<String> {
+  let field = match Kind::from_name(&kind).map_err(|err| err.into_js(&env))? {
+    Kind::Item => Field::Item(item_from_js(&env, value)?),
+    Kind::List => {
+      let array: JsObject = value.try_into()?;
+      let list = (0..array.get_array_length()?)
+        .map(|idx| member_from_js(&env, array.get_element(idx)?))
+        .collect::<Result<_>>()?;
+      Field::List(list)
+    }
+    Kind::Dictionary => {
+      let object: JsObject = value.try_into()?;
+      let keys = object.get_property_names()?;
+      let dictionary = (0..keys.get_array_length()?)
+        .map(|idx| {
+          let key = keys
+            .get_element::<JsString>(idx)?
+            .into_utf8()?
+            .into_owned()?;
+          let member = member_from_js(&env, object.get_named_property(&key)?)?;
+          Ok((key, member))
+        })
+        .collect::<Result<_>>()?;
+      Field::Dictionary(dictionary)
+    }
+  };
+  serialize(&field).map_err(|err| err.into_js(&env))
+}
+
+/// `{ value, params }` objects, or bare values without parameters.
+fn split_params(env: &Env, value: JsUnknown) -> Result<(JsUnknown, Parameters)> {
+  if value.get_type()? != ValueType::Object || value.is_buffer()? {
+    return Ok((value, Vec::new()));
+  }
+  let object: JsObject = value.try_into()?;
+  if !object.has_named_property("value")? {
+    return Ok((object.into_unknown(), Vec::new()));
+  }
+
+  let params = match object.get_named_property::<JsUnknown>("params")? {
+    params if params.get_type()? == ValueType::Object => {
+      let params: JsObject = params.try_into()?;
+      let keys = params.get_property_names()?;
+      (0..keys.get_array_length()?)
+        .map(|idx| {
+          let key = keys
+            .get_element::<JsString>(idx)?
+            .into_utf8()?
+            .into_owned()?;
+          let value = bare_item_from_js(env, params.get_named_property(&key)?)?;
+          Ok((key, value))
+        })
+        .collect::<Result<_>>()?
+    }
+    _ => Vec::new(),
+  };
+  Ok((object.get_named_property("value")?, params))
+}
+
+fn member_from_js(env: &Env, value: JsUnknown) -> Result<Member> {
+  let (value, params) = split_params(env, value)?;
+  if !value.is_array()? {
+    let bare = bare_item_from_js(env, value)?;
+    return Ok(Member::Item(Item { bare, params }));
+  }
+
+  let array: JsObject = value.try_into()?;
+  let items = (0..array.get_array_length()?)
+    .map(|idx| item_from_js(env, array.get_element(idx)?))
+    .collect::<Result<_>>()?;
+  Ok(Member::InnerList(items, params))
+}
+
+fn item_from_js(env: &Env, value: JsUnknown) -> Result<Item> {
+  let (value, params) = split_params(env, value)?;
+  let bare = bare_item_from_js(env, value)?;
+  Ok(Item { bare, params })
+}
+
+fn bare_item_from_js(env: &Env, value: JsUnknown) -> Result<BareItem> {
+  let unexpected = || invalid("expected a string, number, boolean, Buffer or token").into_js(env);
+
+  match value.get_type()? {
+    ValueType::Number => {
+      let number = value.coerce_to_number()?.get_double()?;
+      if number.fract() == 0.0 && number.abs() <= MAX_INTEGER as f64 {
+        Ok(BareItem::Integer(number as i64))
+      } else {
+        Ok(BareItem::Decimal(number))
+      }
+    }
+    ValueType::String => {
+      let text = value.coerce_to_string()?.into_utf8()?.into_owned()?;
+      Ok(BareItem::String(text))
+    }
+    ValueType::Boolean => Ok(BareItem::Boolean(value.coerce_to_bool()?.get_value()?)),
+    ValueType::Object if value.is_buffer()? => {
+      let buffer: JsBuffer = value.try_into()?;
+      Ok(BareItem::ByteSequence(buffer.into_value()?.to_vec()))
+    }
+    ValueType::Object => {
+      let object: JsObject = value.try_into()?;
+      let token: JsString = object
+        .get_named_property::<JsUnknown>("token")?
+        .try_into()
+        .map_err(|_| unexpected())?;
+      Ok(BareItem::Token(token.into_utf8()?.into_owned()?))
+    }
+    _ => Err(unexpected()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse, parse_item, parse_list, serialize, BareItem, Field, Item, Kind, Member};
+
+  fn item(bare: BareItem) -> Item {
+    Item {
+      bare,
+      params: Vec::new(),
+    }
+  }
+
+  fn round_trip(input: &str, kind: Kind) -> String {
+    serialize(&parse(input.as_bytes(), kind).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn parses_bare_items() {
+    assert_eq!(parse_item(b"42"), Some(item(BareItem::Integer(42))));
+    assert_eq!(parse_item(b"-1.5"), Some(item(BareItem::Decimal(-1.5))));
+    assert_eq!(
+      parse_item(br#""a \"b\" \\""#),
+      Some(item(BareItem::String(r#"a "b" \"#.into())))
+    );
+    assert_eq!(
+      parse_item(b"text/html"),
+      Some(item(BareItem::Token("text/html".into())))
+    );
+    assert_eq!(
+      parse_item(b":aGk=:"),
+      Some(item(BareItem::ByteSequence(b"hi".to_vec())))
+    );
+    assert_eq!(parse_item(b"?0"), Some(item(BareItem::Boolean(false))));
+  }
+
+  #[test]
+  fn parses_parameters_keeping_the_last_repeated_one() {
+    let parsed = parse_item(b"abc;a=1;b;a=2").unwrap();
+    assert_eq!(
+      parsed.params,
+      vec![
+        ("a".into(), BareItem::Integer(2)),
+        ("b".into(), BareItem::Boolean(true)),
+      ]
+    );
+  }
+
+  #[test]
+  fn parses_lists_with_inner_lists() {
+    let list = parse_list(b"(\"foo\" bar);lvl=5, baz").unwrap();
+    assert_eq!(
+      list,
+      vec![
+        Member::InnerList(
+          vec![
+            item(BareItem::String("foo".into())),
+            item(BareItem::Token("bar".into())),
+          ],
+          vec![("lvl".into(), BareItem::Integer(5))],
+        ),
+        Member::Item(item(BareItem::Token("baz".into()))),
+      ]
+    );
+    assert_eq!(parse_list(b""), Some(Vec::new()));
+  }
+
+  #[test]
+  fn parses_dictionaries_in_first_appearance_order() {
+    let Some(Field::Dictionary(dictionary)) = parse(b"u=2, i, u=5;x", Kind::Dictionary) else {
+      panic!("not a dictionary");
+    };
+    assert_eq!(
+      dictionary,
+      vec![
+        (
+          "u".into(),
+          Member::Item(Item {
+            bare: BareItem::Integer(5),
+            params: vec![("x".into(), BareItem::Boolean(true))],
+          })
+        ),
+        ("i".into(), Member::Item(item(BareItem::Boolean(true)))),
+      ]
+    );
+  }
+
+  #[test]
+  fn rejects_malformed_fields() {
+    assert_eq!(parse_item(b""), None);
+    assert_eq!(parse_item(b"1234567890123456"), None);
+    assert_eq!(parse_item(b"1.2345"), None);
+    assert_eq!(parse_item(b"1234567890123.5"), None);
+    assert_eq!(parse_item(b"1."), None);
+    assert_eq!(parse_item(br#""\n""#), None);
+    assert_eq!(parse_item(b"\"open"), None);
+    assert_eq!(parse_item(b":aGk="), None);
+    assert_eq!(parse_item(b"?2"), None);
+    assert_eq!(parse_item(b"a;B=1"), None);
+    assert_eq!(parse_item(b"a b"), None);
+    assert_eq!(parse_list(b"a,"), None);
+    assert_eq!(parse_list(b"(a,b)"), None);
+    assert_eq!(parse(b"A=1", Kind::Dictionary), None);
+  }
+
+  #[test]
+  fn serializes_in_canonical_form() {
+    assert_eq!(round_trip("  a;x=?1, (b  c) ", Kind::List), "a;x, (b c)");
+    assert_eq!(round_trip("a=?1, b=?0;p", Kind::Dictionary), "a, b=?0;p");
+    assert_eq!(round_trip("1.500", Kind::Item), "1.5");
+    assert_eq!(round_trip("2.0", Kind::Item), "2.0");
+  }
+
+  #[test]
+  fn rounds_decimals_to_three_digits_ties_to_even() {
+    let decimal = |number| serialize(&Field::Item(item(BareItem::Decimal(number)))).unwrap();
+    assert_eq!(decimal(0.0025), "0.002");
+    assert_eq!(decimal(1.23456), "1.235");
+  }
+
+  #[test]
+  fn refuses_to_serialize_invalid_values() {
+    let fails = |bare| {
+      let err = serialize(&Field::Item(item(bare))).unwrap_err();
+      assert!(err.to_string().starts_with("INVALID_VALUE: "), "{err}");
+    };
+    fails(BareItem::Integer(1_000_000_000_000_000));
+    fails(BareItem::Decimal(1e12));
+    fails(BareItem::Decimal(f64::NAN));
+    fails(BareItem::String("café".into()));
+    fails(BareItem::Token("1a".into()));
+    fails(BareItem::Token(String::new()));
+
+    let bad_key = Field::Dictionary(vec![(
+      "Key".into(),
+      Member::Item(item(BareItem::Integer(1))),
+    )]);
+    assert!(serialize(&bad_key).is_err());
+  }
+}
@@ -0,0 +1,139 @@
+//! Tenants: groups of requests with their own limits.
+//!
+//! A tenant is recognized by a path prefix or by the value of a header,
+//! before the request is routed. Its quota counts requests separately from
+//! every other tenant, and its body size limit replaces the app's.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::header::{HeaderName, HeaderValue};
+use napi::{Env, JsObject, Result};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::Request;
+use crate::quota::{Quota, QuotaOptions};
+
+/// Declares a tenant with `app.tenant()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct TenantOptions {
+  pub name: String,
+  /// Matches requests whose path is this prefix or continues it with `/`.
+  pub prefix: Option<String>,
+  /// Matches requests carrying this header with `value`.
+  pub header: Option<String>,
+  pub value: Option<String>,
+  /// Quota counted for this tenant only.
+  pub quota: Option<QuotaOptions>,
+  /// Largest request body accepted, in bytes.
+  pub max_body_size: Option<u32>,
+  /// Labels describing the tenant's requests, available as
+  /// `req.tenant.labels`.
+  pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+  Prefix(String),
+  Header(HeaderName, HeaderValue),
+}
+
+/// A declared tenant. Requests belonging to it carry it in their extensions.
+#[derive(Debug)]
+pub struct Tenant {
+  name: String,
+  matcher: Matcher,
+  quota: Option<Quota>,
+  max_body_size: Option<u64>,
+  labels: Vec<(String, String)>,
+}
+
+impl Tenant {
+  pub fn new(options: TenantOptions) -> std::result::Result<Self, ActixError> {
+    let invalid = |message: String| ActixError::new(ErrorCode::InvalidOption, message);
+    let TenantOptions {
+      name,
+      prefix,
+      header,
+      value,
+      quota,
+      max_body_size,
+      labels,
+    } = options;
+
+    let matcher = match (prefix, header, value) {
+      (Some(prefix), None, None) if prefix.starts_with('/') => {
+        Matcher::Prefix(prefix.trim_end_matches('/').to_owned())
+      }
+      (None, Some(header), Some(value)) => Matcher::Header(
+        HeaderName::try_from(header).map_err(|err| invalid(err.to_string()))?,
+        HeaderValue::try_from(value).map_err(|err| invalid(err.to_string()))?,
+      ),
+      _ => {
+        return Err(invalid(format!(
+          "tenant `{name}` needs either a `prefix` starting with `/`, or a `header` and its `value`"
+        )))
+      }
+    };
+
+    let mut labels: Vec<_> = labels.into_iter().flatten().collect();
+    labels.sort();
+
+    Ok(Tenant {
+      name,
+      matcher,
+      quota: quota.map(Quota::new).transpose()?,
+      max_body_size: max_body_size.map(u64::from),
+      labels,
+    })
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn quota(&self) -> Option<&Quota> {
+    self.quota.as_ref()
+  }
+
+  pub fn max_body_size(&self) -> Option<u64> {
+    self.max_body_size
+  }
+
+  pub fn labels(&self) -> &[(String, String)] {
+    &self.labels
+  }
+
+  fn matches(&self, req: &Request) -> bool {
+    match &self.matcher {
+      Matcher::Prefix(prefix) => req
+        .uri()
+        .path()
+        .strip_prefix(prefix.as_str())
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+      Matcher::Header(name, value) => req.headers().get(name) == Some(value),
+    }
+  }
+
+  /// The `req.tenant` object handed to JS.
+  pub fn to_js(&self, env: &Env) -> Result<JsObject> {
+    let mut tenant = env.create_object()?;
+    tenant.set_named_property("name", env.create_string(&self.name)?)?;
+
+    let mut labels = env.create_object()?;
+    for (name, value) in &self.labels {
+      labels.set_named_property(name, env.create_string(value)?)?;
+    }
+    tenant.set_named_property("labels", labels)?;
+
+    Ok(tenant)
+  }
+}
+
+/// Tenants in declaration order; the first one matching a request wins.
+pub type Tenants = Vec<Arc<Tenant>>;
+
+pub fn find<'t>(tenants: &'t Tenants, req: &Request) -> Option<&'t Arc<Tenant>> {
+  tenants.iter().find(|tenant| tenant.matches(req))
+}
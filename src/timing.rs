@@ -0,0 +1,131 @@
+//! Time spent in the JS middleware and handlers of each route, to find the
+//! routes that keep the event loop busy.
+//!
+//! Two durations are recorded per request. The wall time runs from the
+//! first middleware call until the handler's promise settles. The
+//! synchronous time only covers the calls themselves, until each function
+//! returns, including the wait for the JS thread; it is an upper bound of
+//! how long the route blocked the event loop. CPU time is not measured:
+//! the JS thread is shared by every route, so its CPU clock can't be
+//! attributed to one of them.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::routes::MatchOptions;
+
+/// Timings of one route, updated concurrently.
+#[derive(Debug, Default)]
+pub struct Timing {
+  calls: AtomicU64,
+  wall_ns: AtomicU64,
+  max_wall_ns: AtomicU64,
+  sync_ns: AtomicU64,
+}
+
+impl Timing {
+  pub fn record(&self, wall: Duration, sync: Duration) {
+    let wall = wall.as_nanos() as u64;
+    self.calls.fetch_add(1, Ordering::Relaxed);
+    self.wall_ns.fetch_add(wall, Ordering::Relaxed);
+    self.max_wall_ns.fetch_max(wall, Ordering::Relaxed);
+    self
+      .sync_ns
+      .fetch_add(sync.as_nanos() as u64, Ordering::Relaxed);
+  }
+
+  fn snapshot(&self, method: &str, path: &str, when: Option<&MatchOptions>) -> RouteTiming {
+    let ms = |ns: u64| ns as f64 / 1e6;
+    let calls = self.calls.load(Ordering::Relaxed);
+    let average = |total: &AtomicU64| match calls {
+      0 => 0.0,
+      calls => ms(total.load(Ordering::Relaxed)) / calls as f64,
+    };
+
+    RouteTiming {
+      method: method.to_owned(),
+      path: path.to_owned(),
+      when: when.cloned(),
+      calls: calls as f64,
+      average_ms: average(&self.wall_ns),
+      max_ms: ms(self.max_wall_ns.load(Ordering::Relaxed)),
+      average_sync_ms: average(&self.sync_ns),
+    }
+  }
+}
+
+/// The timings of a route, as returned by `app.routeTimings()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct RouteTiming {
+  pub method: String,
+  pub path: String,
+  /// The conditions of the route, if it was registered with some.
+  pub when: Option<MatchOptions>,
+  pub calls: f64,
+  /// Average wall time per request, in milliseconds.
+  pub average_ms: f64,
+  pub max_ms: f64,
+  /// Average time spent in synchronous calls per request, in milliseconds.
+  pub average_sync_ms: f64,
+}
+
+/// A route as timed: its method, template and conditions.
+#[derive(Clone, Debug)]
+struct Entry {
+  method: String,
+  path: String,
+  when: Option<MatchOptions>,
+  timing: Arc<Timing>,
+}
+
+/// The timings of every route, in registration order.
+#[derive(Clone, Debug, Default)]
+pub struct Timings(Vec<Entry>);
+
+impl Timings {
+  pub fn insert(
+    &mut self,
+    method: &str,
+    path: &str,
+    when: Option<MatchOptions>,
+    timing: Arc<Timing>,
+  ) {
+    self.0.push(Entry {
+      method: method.to_owned(),
+      path: path.to_owned(),
+      when,
+      timing,
+    });
+  }
+
+  /// Forgets the route timed by `timing`.
+  pub fn remove(&mut self, timing: &Arc<Timing>) {
+    self.0.retain(|entry| !Arc::ptr_eq(&entry.timing, timing));
+  }
+
+  pub fn snapshot(&self) -> Vec<RouteTiming> {
+    self
+      .0
+      .iter()
+      .map(|entry| {
+        entry
+          .timing
+          .snapshot(&entry.method, &entry.path, entry.when.as_ref())
+      })
+      .collect()
+  }
+
+  /// Routes whose average wall time is at least `threshold_ms`, slowest
+  /// first.
+  pub fn slow(&self, threshold_ms: f64) -> Vec<RouteTiming> {
+    let mut slow: Vec<_> = self
+      .snapshot()
+      .into_iter()
+      .filter(|timing| timing.calls > 0.0 && timing.average_ms >= threshold_ms)
+      .collect();
+    slow.sort_by(|a, b| b.average_ms.total_cmp(&a.average_ms));
+    slow
+  }
+}
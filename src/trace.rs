@@ -0,0 +1,308 @@
+//! Distributed tracing, configured with `app.tracing()`.
+//!
+//! Each request gets a server span. Its W3C trace context is taken from the
+//! incoming `traceparent` header, or started anew, and handed to JS as
+//! `req.traceparent` so spans created in Node join the same trace. Finished
+//! spans are batched and exported to an OpenTelemetry collector over
+//! OTLP/HTTP with JSON encoding, through the app's HTTP client.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::client::Client;
+use crate::error::{ActixError, ErrorCode};
+use crate::http::Request;
+use crate::signature::{decode_hex, hex};
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+/// Spans waiting to be exported; more are dropped.
+const QUEUE_SIZE: usize = 4096;
+
+/// Configures tracing with `app.tracing()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct TracingOptions {
+  /// URL of the collector's OTLP/HTTP endpoint, such as
+  /// `http://localhost:4318`. `/v1/traces` is appended unless the URL
+  /// already ends with it.
+  pub endpoint: String,
+  pub service_name: String,
+  /// Share of new traces that are recorded, from 0 to 1. Requests joining a
+  /// trace follow its sampling decision. Defaults to 1.
+  pub sample_ratio: Option<f64>,
+  /// Largest number of spans sent at once. Defaults to 512.
+  pub batch_size: Option<u32>,
+  /// Milliseconds between exports. Defaults to 5000.
+  pub export_interval: Option<u32>,
+}
+
+/// The W3C trace context of a request's server span, stored in the request
+/// extensions.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceContext {
+  trace_id: [u8; 16],
+  span_id: [u8; 8],
+  parent_id: Option<[u8; 8]>,
+  sampled: bool,
+}
+
+impl TraceContext {
+  /// The `traceparent` value identifying this span.
+  pub fn traceparent(&self) -> String {
+    format!(
+      "00-{}-{}-{:02x}",
+      hex(&self.trace_id),
+      hex(&self.span_id),
+      u8::from(self.sampled)
+    )
+  }
+}
+
+/// Parses a `traceparent` header into its trace id, parent span id and
+/// sampled flag.
+fn parse_traceparent(value: &HeaderValue) -> Option<([u8; 16], [u8; 8], bool)> {
+  let mut parts = value.to_str().ok()?.trim().split('-');
+  let version = parts.next()?;
+  let (trace_id, parent_id, flags) = (parts.next()?, parts.next()?, parts.next()?);
+  // Version 00 has exactly four fields; later versions may add more.
+  if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+    return None;
+  }
+  if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+    return None;
+  }
+
+  let trace_id: [u8; 16] = decode_hex(trace_id)?.try_into().ok()?;
+  let parent_id: [u8; 8] = decode_hex(parent_id)?.try_into().ok()?;
+  let flags = decode_hex(flags)?[0];
+  // All-zero ids are invalid.
+  if trace_id == [0; 16] || parent_id == [0; 8] {
+    return None;
+  }
+
+  Some((trace_id, parent_id, flags & 1 == 1))
+}
+
+/// A span being recorded.
+pub struct ActiveSpan {
+  context: TraceContext,
+  method: Method,
+  path: String,
+  start: SystemTime,
+}
+
+/// A finished span, waiting to be exported.
+struct FinishedSpan {
+  context: TraceContext,
+  method: Method,
+  path: String,
+  status: StatusCode,
+  start: SystemTime,
+  end: SystemTime,
+}
+
+/// Starts request spans and exports them in the background.
+pub struct Tracer {
+  spans: mpsc::Sender<FinishedSpan>,
+  sample_ratio: f64,
+  random: SystemRandom,
+}
+
+impl Tracer {
+  pub fn new(options: TracingOptions, client: Client) -> Result<Self, ActixError> {
+    let invalid = |message: String| ActixError::new(ErrorCode::InvalidOption, message);
+    let sample_ratio = options.sample_ratio.unwrap_or(1.0);
+    if !(0.0..=1.0).contains(&sample_ratio) {
+      return Err(invalid(format!(
+        "`sampleRatio` must be between 0 and 1, got {sample_ratio}"
+      )));
+    }
+
+    let mut endpoint = options.endpoint.trim_end_matches('/').to_owned();
+    if !endpoint.ends_with("/v1/traces") {
+      endpoint.push_str("/v1/traces");
+    }
+    let endpoint = hyper::Uri::try_from(endpoint).map_err(|err| invalid(err.to_string()))?;
+
+    let exporter = Exporter {
+      endpoint,
+      service_name: options.service_name,
+      client,
+      batch_size: options.batch_size.unwrap_or(512).max(1) as usize,
+      interval: Duration::from_millis(options.export_interval.unwrap_or(5000).into()),
+    };
+    let (spans, queue) = mpsc::channel(QUEUE_SIZE);
+    napi::bindgen_prelude::spawn(exporter.run(queue));
+
+    Ok(Tracer {
+      spans,
+      sample_ratio,
+      random: SystemRandom::new(),
+    })
+  }
+
+  /// Starts the server span of a request, joining the trace of its
+  /// `traceparent` header if it has a valid one.
+  pub fn start(&self, req: &mut Request) -> ActiveSpan {
+    let mut span_id = [0; 8];
+    let _ = self.random.fill(&mut span_id);
+
+    let context = match req.headers().get(TRACEPARENT).and_then(parse_traceparent) {
+      Some((trace_id, parent_id, sampled)) => TraceContext {
+        trace_id,
+        span_id,
+        parent_id: Some(parent_id),
+        sampled,
+      },
+      None => {
+        let mut trace_id = [0; 16];
+        let _ = self.random.fill(&mut trace_id);
+        // The low bits of a random trace id are uniformly distributed, so
+        // they double as the sampling coin toss.
+        let toss = u64::from_be_bytes(trace_id[8..].try_into().unwrap()) as f64 / u64::MAX as f64;
+
+        TraceContext {
+          trace_id,
+          span_id,
+          parent_id: None,
+          sampled: toss < self.sample_ratio,
+        }
+      }
+    };
+    req.extensions_mut().insert(context);
+
+    ActiveSpan {
+      context,
+      method: req.method().clone(),
+      path: req.uri().path().to_owned(),
+      start: SystemTime::now(),
+    }
+  }
+
+  /// Ends a span once its response is ready, and queues it for export if
+  /// it is sampled.
+  pub fn finish(&self, span: ActiveSpan, status: StatusCode) {
+    if !span.context.sampled {
+      return;
+    }
+
+    let finished = FinishedSpan {
+      context: span.context,
+      method: span.method,
+      path: span.path,
+      status,
+      start: span.start,
+      end: SystemTime::now(),
+    };
+    // When the collector can't keep up, spans are dropped rather than
+    // slowing down requests.
+    let _ = self.spans.try_send(finished);
+  }
+}
+
+struct Exporter {
+  endpoint: hyper::Uri,
+  service_name: String,
+  client: Client,
+  batch_size: usize,
+  interval: Duration,
+}
+
+impl Exporter {
+  /// Exports batches until the tracer is dropped.
+  async fn run(self, mut queue: mpsc::Receiver<FinishedSpan>) {
+    let mut batch = Vec::with_capacity(self.batch_size);
+    let mut interval = tokio::time::interval(self.interval);
+
+    loop {
+      tokio::select! {
+        span = queue.recv() => match span {
+          Some(span) => {
+            batch.push(span);
+            if batch.len() < self.batch_size {
+              continue;
+            }
+          }
+          None => {
+            self.export(&mut batch).await;
+            return;
+          }
+        },
+        _ = interval.tick() => {}
+      }
+      self.export(&mut batch).await;
+    }
+  }
+
+  async fn export(&self, batch: &mut Vec<FinishedSpan>) {
+    if batch.is_empty() {
+      return;
+    }
+    let spans: Vec<_> = batch.drain(..).map(|span| span.to_otlp()).collect();
+
+    let body = json!({
+      "resourceSpans": [{
+        "resource": {
+          "attributes": [attribute("service.name", json!({ "stringValue": self.service_name }))],
+        },
+        "scopeSpans": [{
+          "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+          "spans": spans,
+        }],
+      }],
+    });
+
+    let request = hyper::Request::post(self.endpoint.clone())
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(hyper::Body::from(body.to_string()))
+      .unwrap();
+
+    match self.client.send(request).await {
+      Ok(res) if res.status().is_success() => {}
+      Ok(res) => eprintln!("Error exporting spans: collector answered {}", res.status()),
+      Err(err) => eprintln!("Error exporting spans: {err}"),
+    }
+  }
+}
+
+impl FinishedSpan {
+  fn to_otlp(&self) -> Value {
+    let nanos = |time: SystemTime| {
+      time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos())
+        .to_string()
+    };
+
+    let mut span = json!({
+      "traceId": hex(&self.context.trace_id),
+      "spanId": hex(&self.context.span_id),
+      "name": self.method.as_str(),
+      // SPAN_KIND_SERVER
+      "kind": 2,
+      "startTimeUnixNano": nanos(self.start),
+      "endTimeUnixNano": nanos(self.end),
+      "attributes": [
+        attribute("http.request.method", json!({ "stringValue": self.method.as_str() })),
+        attribute("url.path", json!({ "stringValue": self.path })),
+        attribute("http.response.status_code", json!({ "intValue": self.status.as_u16() })),
+      ],
+      // Server spans are only errors for 5xx responses; STATUS_CODE_ERROR.
+      "status": if self.status.is_server_error() { json!({ "code": 2 }) } else { json!({}) },
+    });
+    if let Some(parent_id) = self.context.parent_id {
+      span["parentSpanId"] = Value::from(hex(&parent_id));
+    }
+    span
+  }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+  json!({ "key": key, "value": value })
+}
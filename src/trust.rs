@@ -0,0 +1,467 @@
+//! Where a request came from, behind trusted reverse proxies.
+//!
+//! `req.ip`, `req.protocol` and `req.hostname` describe the connection by
+//! default: the peer's address, `http` and the `Host` header. With
+//! `app.trustProxy(ranges)`, a peer within the ranges is a proxy whose
+//! `Forwarded` header (RFC 7239) or, without one, `X-Forwarded-For`,
+//! `X-Forwarded-Proto` and `X-Forwarded-Host` headers are believed.
+//!
+//! Hops are walked from the nearest one, past every trusted proxy: the
+//! client is the first address outside the ranges. With `Forwarded`, the
+//! protocol and host are those the client's element records; with
+//! `X-Forwarded-*`, the first value of each header is used.
+//!
+//! Ranges are CIDR blocks or single addresses, or `loopback`, `linklocal`
+//! and `uniquelocal` for the blocks of those names.
+
+use std::net::IpAddr;
+
+use hyper::header::{self, HeaderMap, HeaderName};
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::ConnectionInfo;
+
+const FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+const LOOPBACK: [&str; 2] = ["127.0.0.0/8", "::1/128"];
+const LINK_LOCAL: [&str; 2] = ["169.254.0.0/16", "fe80::/10"];
+const UNIQUE_LOCAL: [&str; 4] = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7"];
+
+/// An address block.
+#[derive(Clone, Copy, Debug)]
+struct Cidr {
+  network: IpAddr,
+  prefix: u32,
+}
+
+impl Cidr {
+  fn parse(range: &str) -> Option<Self> {
+    let (network, prefix) = match range.split_once('/') {
+      Some((network, prefix)) => (network, Some(prefix.parse::<u32>().ok()?)),
+      None => (range, None),
+    };
+    let network = network.parse::<IpAddr>().ok()?;
+    let (_, width) = bits(network);
+    let prefix = prefix.unwrap_or(width);
+    if prefix > width {
+      return None;
+    }
+    // Addresses are compared canonically, so a block of IPv4-mapped
+    // addresses is the IPv4 block it maps.
+    match network.to_canonical() {
+      IpAddr::V4(mapped) if network.is_ipv6() && prefix >= 96 => Some(Cidr {
+        network: mapped.into(),
+        prefix: prefix - 96,
+      }),
+      _ => Some(Cidr { network, prefix }),
+    }
+  }
+
+  fn contains(&self, ip: IpAddr) -> bool {
+    let (network, width) = bits(self.network);
+    let (ip, ip_width) = bits(ip.to_canonical());
+    if width != ip_width {
+      return false;
+    }
+    let shift = width - self.prefix;
+    shift == 128 || network >> shift == ip >> shift
+  }
+}
+
+/// The address as a number, and its width in bits.
+fn bits(ip: IpAddr) -> (u128, u32) {
+  match ip {
+    IpAddr::V4(ip) => (u32::from(ip).into(), 32),
+    IpAddr::V6(ip) => (ip.into(), 128),
+  }
+}
+
+/// The proxies whose forwarding headers are believed.
+pub struct TrustProxy {
+  ranges: Vec<Cidr>,
+}
+
+impl TrustProxy {
+  pub fn new(ranges: Vec<String>) -> Result<Self, ActixError> {
+    let mut parsed = Vec::new();
+    for range in &ranges {
+      let named: &[&str] = match range.as_str() {
+        "loopback" => &LOOPBACK,
+        "linklocal" => &LINK_LOCAL,
+        "uniquelocal" => &UNIQUE_LOCAL,
+        range => {
+          let cidr = Cidr::parse(range).ok_or_else(|| {
+            ActixError::new(
+              ErrorCode::InvalidOption,
+              format!("invalid address range `{range}`"),
+            )
+          })?;
+          parsed.push(cidr);
+          continue;
+        }
+      };
+      parsed.extend(named.iter().filter_map(|range| Cidr::parse(range)));
+    }
+    Ok(TrustProxy { ranges: parsed })
+  }
+
+  fn trusts(&self, ip: IpAddr) -> bool {
+    self.ranges.iter().any(|range| range.contains(ip))
+  }
+}
+
+/// A proxy hop, as recorded by the proxy that received it.
+#[derive(Clone, Debug, Default)]
+struct Hop {
+  /// Who connected to the proxy: an address, possibly with a port, or an
+  /// obfuscated identifier such as `unknown`.
+  node: Option<String>,
+  proto: Option<String>,
+  host: Option<String>,
+}
+
+impl Hop {
+  fn ip(&self) -> Option<IpAddr> {
+    let node = self.node.as_deref()?;
+    if let Some(bracketed) = node.strip_prefix('[') {
+      return bracketed.split_once(']')?.0.parse().ok();
+    }
+    match node.split_once(':') {
+      // An IPv4 address with a port; bare IPv6 addresses have more colons.
+      Some((ip, port)) if !port.contains(':') => ip.parse().ok(),
+      _ => node.parse().ok(),
+    }
+  }
+}
+
+/// Where a request came from, kept in its extensions.
+#[derive(Clone, Debug)]
+pub struct Origin {
+  ip: Option<String>,
+  protocol: String,
+  hostname: Option<String>,
+}
+
+impl Origin {
+  pub fn of<B>(req: &hyper::Request<B>, trust: Option<&TrustProxy>) -> Origin {
+    let headers = req.headers();
+    let peer = req
+      .extensions()
+      .get::<ConnectionInfo>()
+      .and_then(ConnectionInfo::peer_addr)
+      .map(|addr| addr.ip().to_canonical());
+    let mut origin = Origin {
+      ip: peer.map(|ip| ip.to_string()),
+      protocol: String::from("http"),
+      hostname: headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(hostname),
+    };
+
+    let (Some(trust), Some(peer)) = (trust, peer) else {
+      return origin;
+    };
+    if !trust.trusts(peer) {
+      return origin;
+    }
+
+    let hops = if headers.contains_key(header::FORWARDED) {
+      forwarded(headers)
+    } else {
+      x_forwarded(headers)
+    };
+    let mut client = None;
+    for hop in hops.iter().rev() {
+      client = Some(hop);
+      if !hop.ip().is_some_and(|ip| trust.trusts(ip)) {
+        break;
+      }
+    }
+
+    if let Some(hop) = client {
+      if let Some(node) = &hop.node {
+        origin.ip = Some(hop.ip().map_or_else(|| node.clone(), |ip| ip.to_string()));
+      }
+      if let Some(proto) = &hop.proto {
+        origin.protocol = proto.to_ascii_lowercase();
+      }
+      if let Some(host) = &hop.host {
+        origin.hostname = Some(hostname(host));
+      }
+    }
+    origin
+  }
+
+  /// The client's address, unset for Unix domain sockets.
+  pub fn ip(&self) -> Option<&str> {
+    self.ip.as_deref()
+  }
+
+  pub fn protocol(&self) -> &str {
+    &self.protocol
+  }
+
+  /// The host the client asked for, without its port.
+  pub fn hostname(&self) -> Option<&str> {
+    self.hostname.as_deref()
+  }
+}
+
+/// The elements of the `Forwarded` headers, farthest first.
+fn forwarded(headers: &HeaderMap) -> Vec<Hop> {
+  let mut hops = Vec::new();
+  for value in headers.get_all(header::FORWARDED) {
+    let Ok(value) = value.to_str() else {
+      continue;
+    };
+    for element in split_unquoted(value, ',') {
+      let mut hop = Hop::default();
+      for pair in split_unquoted(element, ';') {
+        let Some((name, value)) = pair.split_once('=') else {
+          continue;
+        };
+        let value = unquote(value.trim());
+        match name.trim().to_ascii_lowercase().as_str() {
+          "for" => hop.node = Some(value),
+          "proto" => hop.proto = Some(value),
+          "host" => hop.host = Some(value),
+          _ => {}
+        }
+      }
+      hops.push(hop);
+    }
+  }
+  hops
+}
+
+/// The addresses of `X-Forwarded-For`, farthest first, each with the first
+/// `X-Forwarded-Proto` and `X-Forwarded-Host`.
+fn x_forwarded(headers: &HeaderMap) -> Vec<Hop> {
+  let first = |name: &HeaderName| {
+    let value = headers.get(name)?.to_str().ok()?;
+    let first = value.split(',').next()?.trim();
+    (!first.is_empty()).then(|| first.to_owned())
+  };
+  let proto = first(&FORWARDED_PROTO);
+  let host = first(&FORWARDED_HOST);
+
+  headers
+    .get_all(FORWARDED_FOR)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .map(str::trim)
+    .filter(|node| !node.is_empty())
+    .map(|node| Hop {
+      node: Some(node.to_owned()),
+      proto: proto.clone(),
+      host: host.clone(),
+    })
+    .collect()
+}
+
+/// Splits on `delimiter` outside of quoted strings.
+fn split_unquoted(value: &str, delimiter: char) -> impl Iterator<Item = &str> {
+  let mut parts = Vec::new();
+  let (mut start, mut quoted, mut escaped) = (0, false, false);
+  for (idx, ch) in value.char_indices() {
+    match ch {
+      _ if escaped => escaped = false,
+      '\\' if quoted => escaped = true,
+      '"' => quoted = !quoted,
+      ch if ch == delimiter && !quoted => {
+        parts.push(&value[start..idx]);
+        start = idx + 1;
+      }
+      _ => {}
+    }
+  }
+  parts.push(&value[start..]);
+  parts
+    .into_iter()
+    .map(str::trim)
+    .filter(|part| !part.is_empty())
+}
+
+/// A token, or the content of a quoted string.
+fn unquote(value: &str) -> String {
+  let Some(quoted) = value
+    .strip_prefix('"')
+    .and_then(|value| value.strip_suffix('"'))
+  else {
+    return value.to_owned();
+  };
+  let mut unquoted = String::with_capacity(quoted.len());
+  let mut chars = quoted.chars();
+  while let Some(ch) = chars.next() {
+    match ch {
+      '\\' => unquoted.extend(chars.next()),
+      ch => unquoted.push(ch),
+    }
+  }
+  unquoted
+}
+
+/// A `Host` value without its port. IPv6 literals keep their brackets.
+fn hostname(host: &str) -> String {
+  let host = host.trim();
+  let hostname = match host.strip_prefix('[') {
+    Some(bracketed) => bracketed
+      .split_once(']')
+      .map_or(host, |(ip, _)| &host[..ip.len() + 2]),
+    None => host.split(':').next().unwrap_or(host),
+  };
+  hostname.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::IpAddr;
+
+  use hyper::header::{HeaderMap, HeaderValue};
+
+  use super::{forwarded, hostname, unquote, x_forwarded, Cidr, Hop, TrustProxy};
+
+  fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+  }
+
+  fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+      headers.append(*name, HeaderValue::from_static(value));
+    }
+    headers
+  }
+
+  fn node(hop: &Hop) -> Option<&str> {
+    hop.node.as_deref()
+  }
+
+  #[test]
+  fn parses_blocks_and_single_addresses() {
+    let block = Cidr::parse("10.0.0.0/8").unwrap();
+    assert!(block.contains(ip("10.255.0.1")));
+    assert!(!block.contains(ip("11.0.0.1")));
+
+    let single = Cidr::parse("2001:db8::1").unwrap();
+    assert!(single.contains(ip("2001:db8::1")));
+    assert!(!single.contains(ip("2001:db8::2")));
+  }
+
+  #[test]
+  fn prefix_zero_covers_its_whole_family() {
+    let v4 = Cidr::parse("0.0.0.0/0").unwrap();
+    assert!(v4.contains(ip("203.0.113.9")));
+    assert!(!v4.contains(ip("2001:db8::1")));
+
+    let v6 = Cidr::parse("::/0").unwrap();
+    assert!(v6.contains(ip("2001:db8::1")));
+    assert!(!v6.contains(ip("203.0.113.9")));
+  }
+
+  #[test]
+  fn compares_ipv4_mapped_addresses_as_ipv4() {
+    let block = Cidr::parse("10.0.0.0/8").unwrap();
+    assert!(block.contains(ip("::ffff:10.1.2.3")));
+
+    let mapped = Cidr::parse("::ffff:10.0.0.0/104").unwrap();
+    assert!(mapped.contains(ip("10.1.2.3")));
+    assert!(mapped.contains(ip("::ffff:10.1.2.3")));
+    assert!(!mapped.contains(ip("11.1.2.3")));
+  }
+
+  #[test]
+  fn rejects_invalid_ranges() {
+    assert!(Cidr::parse("10.0.0.0/33").is_none());
+    assert!(Cidr::parse("::/129").is_none());
+    assert!(Cidr::parse("10.0.0.0/-1").is_none());
+    assert!(Cidr::parse("10.0.0/8").is_none());
+    assert!(TrustProxy::new(vec!["loopback".into(), "nearby".into()]).is_err());
+  }
+
+  #[test]
+  fn expands_named_ranges() {
+    let trust = TrustProxy::new(vec!["loopback".into(), "uniquelocal".into()]).unwrap();
+    assert!(trust.trusts(ip("127.0.0.1")));
+    assert!(trust.trusts(ip("::1")));
+    assert!(trust.trusts(ip("172.31.255.255")));
+    assert!(trust.trusts(ip("fd00::1")));
+    assert!(!trust.trusts(ip("172.32.0.1")));
+    assert!(!trust.trusts(ip("169.254.1.1")));
+  }
+
+  #[test]
+  fn reads_forwarded_elements_farthest_first() {
+    let hops = forwarded(&headers(&[
+      (
+        "forwarded",
+        r#"for="[2001:db8::1]:4711";proto=HTTPS, for=192.0.2.60"#,
+      ),
+      ("forwarded", "For=198.51.100.17:80;Host=example.com"),
+    ]));
+    let nodes: Vec<_> = hops.iter().map(node).collect();
+    assert_eq!(
+      nodes,
+      [
+        Some("[2001:db8::1]:4711"),
+        Some("192.0.2.60"),
+        Some("198.51.100.17:80")
+      ]
+    );
+    assert_eq!(hops[0].ip(), Some(ip("2001:db8::1")));
+    assert_eq!(hops[0].proto.as_deref(), Some("HTTPS"));
+    assert_eq!(hops[2].ip(), Some(ip("198.51.100.17")));
+    assert_eq!(hops[2].host.as_deref(), Some("example.com"));
+  }
+
+  #[test]
+  fn keeps_delimiters_inside_quoted_forwarded_values() {
+    let hops = forwarded(&headers(&[(
+      "forwarded",
+      r#"for="_a,b;c";host="x\"y", for=unknown"#,
+    )]));
+    assert_eq!(hops.len(), 2);
+    assert_eq!(node(&hops[0]), Some("_a,b;c"));
+    assert_eq!(hops[0].host.as_deref(), Some("x\"y"));
+    assert_eq!(hops[0].ip(), None);
+    assert_eq!(node(&hops[1]), Some("unknown"));
+  }
+
+  #[test]
+  fn reads_x_forwarded_headers() {
+    let hops = x_forwarded(&headers(&[
+      ("x-forwarded-for", "203.0.113.1, , 10.0.0.1"),
+      ("x-forwarded-for", "10.0.0.2"),
+      ("x-forwarded-proto", "https, http"),
+      ("x-forwarded-host", "example.com"),
+    ]));
+    let nodes: Vec<_> = hops.iter().map(node).collect();
+    assert_eq!(
+      nodes,
+      [Some("203.0.113.1"), Some("10.0.0.1"), Some("10.0.0.2")]
+    );
+    assert!(hops.iter().all(|hop| hop.proto.as_deref() == Some("https")));
+    assert!(hops
+      .iter()
+      .all(|hop| hop.host.as_deref() == Some("example.com")));
+  }
+
+  #[test]
+  fn unquotes_strings_and_leaves_tokens() {
+    assert_eq!(unquote(r#""a\"b\\c""#), r#"a"b\c"#);
+    assert_eq!(unquote("token"), "token");
+    assert_eq!(unquote("\"open"), "\"open");
+  }
+
+  #[test]
+  fn strips_ports_from_hosts() {
+    assert_eq!(hostname("Example.COM:8080"), "example.com");
+    assert_eq!(hostname("[::1]:3000"), "[::1]");
+    assert_eq!(hostname("[::1]"), "[::1]");
+    assert_eq!(hostname(" localhost "), "localhost");
+  }
+}
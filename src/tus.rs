@@ -0,0 +1,640 @@
+//! Resumable uploads with the tus protocol (<https://tus.io>), set up with
+//! `app.tus()`.
+//!
+//! Implements the core protocol with the `creation`, `expiration` and
+//! `termination` extensions. Uploads never go through JS: `PATCH` bodies
+//! are streamed into a [`Storage`] chunk by chunk, and JS is only told once
+//! an upload is complete. An upload is locked while a `PATCH` appends to
+//! it, so a client retrying before its previous request has died is
+//! answered with `423 Locked` rather than writing at the same offset twice.
+//!
+//! Offsets are kept in memory, so uploads don't survive a restart. Requests
+//! to the endpoint are answered before routing, without quotas or tenants.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future::BoxFuture;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode};
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, Error, JsFunction, JsObject, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::base64::{self, Alphabet};
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Request, Response, ResponseBuilder};
+use crate::signature::hex;
+
+const VERSION: &str = "1.0.0";
+const EXTENSIONS: &str = "creation,expiration,termination";
+/// The only content type `PATCH` requests may have.
+const OFFSET_STREAM: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+/// Sets up an upload endpoint with `app.tus()`.
+#[napi(object)]
+pub struct TusOptions {
+  /// Directory uploads are written to, one file per upload named after its
+  /// id. Created if missing.
+  pub directory: String,
+  /// Largest upload accepted, in bytes. Unlimited by default.
+  pub max_size: Option<f64>,
+  /// Milliseconds an upload is kept after it was last written to. Expired
+  /// unfinished uploads are deleted; finished ones are only forgotten, their
+  /// files left to the app. Unset keeps uploads until the server stops.
+  pub expiration: Option<u32>,
+  /// Called with `{ id, length, metadata, path }` once an upload is
+  /// complete.
+  #[napi(ts_type = "(upload: import('./types').CompletedUpload) => void")]
+  pub on_complete: Option<JsFunction>,
+}
+
+/// Where upload bodies are kept.
+pub trait Storage: Send + Sync {
+  /// Prepares an empty upload.
+  fn create<'s>(&'s self, id: &'s str) -> BoxFuture<'s, io::Result<()>>;
+  /// Opens an upload to append to it.
+  fn append<'s>(&'s self, id: &'s str) -> BoxFuture<'s, io::Result<Writer>>;
+  fn remove<'s>(&'s self, id: &'s str) -> BoxFuture<'s, io::Result<()>>;
+  /// Where the app can read a completed upload from.
+  fn location(&self, id: &str) -> Option<String>;
+}
+
+pub type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Uploads stored as files of a directory.
+pub struct Directory {
+  root: PathBuf,
+}
+
+impl Directory {
+  pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+    let root = root.into();
+    std::fs::create_dir_all(&root)?;
+    Ok(Directory { root })
+  }
+}
+
+impl Storage for Directory {
+  fn create<'s>(&'s self, id: &'s str) -> BoxFuture<'s, io::Result<()>> {
+    Box::pin(async move { tokio::fs::File::create(self.root.join(id)).await.map(drop) })
+  }
+
+  fn append<'s>(&'s self, id: &'s str) -> BoxFuture<'s, io::Result<Writer>> {
+    Box::pin(async move {
+      let file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(self.root.join(id))
+        .await?;
+      Ok(Box::new(file) as Writer)
+    })
+  }
+
+  fn remove<'s>(&'s self, id: &'s str) -> BoxFuture<'s, io::Result<()>> {
+    Box::pin(async move {
+      match tokio::fs::remove_file(self.root.join(id)).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+      }
+    })
+  }
+
+  fn location(&self, id: &str) -> Option<String> {
+    Some(self.root.join(id).to_string_lossy().into_owned())
+  }
+}
+
+/// An upload in progress, or finished but not yet expired.
+struct Upload {
+  length: u64,
+  /// `Upload-Metadata` as sent on creation.
+  metadata: Option<HeaderValue>,
+  offset: AtomicU64,
+  /// When the upload was created or last written to.
+  touched: Mutex<Instant>,
+  /// Held while a `PATCH` appends to the upload.
+  writing: tokio::sync::Mutex<()>,
+}
+
+impl Upload {
+  fn offset(&self) -> u64 {
+    self.offset.load(Ordering::Acquire)
+  }
+
+  fn is_complete(&self) -> bool {
+    self.offset() == self.length
+  }
+
+  fn touch(&self) {
+    *self.touched.lock().unwrap() = Instant::now();
+  }
+
+  fn idle(&self) -> Duration {
+    self.touched.lock().unwrap().elapsed()
+  }
+}
+
+/// A completed upload, as handed to `onComplete`.
+struct Completed {
+  id: String,
+  length: u64,
+  metadata: Vec<(String, String)>,
+  location: Option<String>,
+}
+
+impl Completed {
+  fn to_js(&self, env: &Env) -> Result<JsObject> {
+    let mut upload = env.create_object()?;
+    upload.set_named_property("id", env.create_string(&self.id)?)?;
+    upload.set_named_property("length", env.create_double(self.length as f64)?)?;
+
+    let mut metadata = env.create_object()?;
+    for (key, value) in &self.metadata {
+      metadata.set_named_property(key, env.create_string(value)?)?;
+    }
+    upload.set_named_property("metadata", metadata)?;
+
+    if let Some(location) = &self.location {
+      upload.set_named_property("path", env.create_string(location)?)?;
+    }
+    Ok(upload)
+  }
+}
+
+/// An upload endpoint.
+pub struct Tus {
+  path: String,
+  storage: Arc<dyn Storage>,
+  max_size: Option<u64>,
+  expiration: Option<Duration>,
+  on_complete: Option<ThreadsafeFunction<Completed, ErrorStrategy::Fatal>>,
+  uploads: Mutex<HashMap<String, Arc<Upload>>>,
+  random: SystemRandom,
+}
+
+impl Tus {
+  /// An endpoint storing uploads in `options.directory`.
+  pub fn new(env: &Env, path: &str, options: TusOptions) -> Result<Self> {
+    let storage = Directory::new(&options.directory).map_err(|err| {
+      ActixError::new(
+        ErrorCode::InvalidOption,
+        format!("cannot use `{}` for uploads: {err}", options.directory),
+      )
+      .caused_by_io(&err)
+      .into_js(env)
+    })?;
+    Tus::with_storage(env, path, Arc::new(storage), options)
+  }
+
+  /// An endpoint storing uploads in `storage`, ignoring `options.directory`.
+  pub fn with_storage(
+    env: &Env,
+    path: &str,
+    storage: Arc<dyn Storage>,
+    options: TusOptions,
+  ) -> Result<Self> {
+    let path = path.trim_end_matches('/');
+    if !path.starts_with('/') {
+      return Err(
+        ActixError::new(
+          ErrorCode::InvalidOption,
+          format!("upload path `{path}` must start with `/` and not be the root"),
+        )
+        .into_js(env),
+      );
+    }
+
+    let on_complete = options
+      .on_complete
+      .map(|callback| {
+        let mut callback =
+          callback.create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Completed>| {
+            ctx.value.to_js(&ctx.env).map(|upload| vec![upload])
+          })?;
+        // Uploads alone shouldn't keep the process alive.
+        callback.unref(env)?;
+        Ok::<_, Error>(callback)
+      })
+      .transpose()?;
+
+    Ok(Tus {
+      path: path.to_owned(),
+      storage,
+      max_size: options.max_size.map(|size| size as u64),
+      expiration: options
+        .expiration
+        .map(|ms| Duration::from_millis(ms.into())),
+      on_complete,
+      uploads: Mutex::default(),
+      random: SystemRandom::new(),
+    })
+  }
+
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+
+  /// Whether a request path belongs to this endpoint, and which upload it
+  /// points to if it isn't the endpoint itself.
+  pub fn matches<'p>(&self, path: &'p str) -> Option<Option<&'p str>> {
+    let rest = path.strip_prefix(self.path.as_str())?;
+    if rest.is_empty() || rest == "/" {
+      return Some(None);
+    }
+
+    let id = rest.strip_prefix('/')?;
+    (!id.contains('/')).then_some(Some(id))
+  }
+
+  pub async fn respond(&self, req: Request, id: Option<String>) -> Response {
+    if req.method() == Method::OPTIONS {
+      return self.discover();
+    }
+
+    let resumable = req.headers().get(TUS_RESUMABLE);
+    if resumable.is_none_or(|version| version != VERSION) {
+      return reply(StatusCode::PRECONDITION_FAILED)
+        .header(TUS_VERSION, VERSION)
+        .body(Body::empty())
+        .unwrap();
+    }
+
+    let res = match (req.method().clone(), id) {
+      (Method::POST, None) => self.create(&req).await,
+      (Method::HEAD, Some(id)) => self.head(&id),
+      (Method::PATCH, Some(id)) => self.patch(req, &id).await,
+      (Method::DELETE, Some(id)) => self.terminate(&id).await,
+      (_, None) => not_allowed("OPTIONS, POST"),
+      (_, Some(_)) => not_allowed("OPTIONS, HEAD, PATCH, DELETE"),
+    };
+    res.body(Body::empty()).unwrap()
+  }
+
+  /// Answers `OPTIONS` with what the server supports.
+  fn discover(&self) -> Response {
+    let mut res = reply(StatusCode::NO_CONTENT)
+      .header(TUS_VERSION, VERSION)
+      .header(TUS_EXTENSION, EXTENSIONS);
+    if let Some(max_size) = self.max_size {
+      res = res.header(TUS_MAX_SIZE, max_size);
+    }
+    res.body(Body::empty()).unwrap()
+  }
+
+  async fn create(&self, req: &Request) -> ResponseBuilder {
+    let Some(length) = header_u64(req, &UPLOAD_LENGTH) else {
+      return reply(StatusCode::BAD_REQUEST);
+    };
+    if self.max_size.is_some_and(|max_size| length > max_size) {
+      return reply(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let metadata = req.headers().get(UPLOAD_METADATA).cloned();
+    if metadata
+      .as_ref()
+      .is_some_and(|value| parse_metadata(value).is_none())
+    {
+      return reply(StatusCode::BAD_REQUEST);
+    }
+
+    self.sweep();
+
+    let mut id = [0u8; 16];
+    if self.random.fill(&mut id).is_err() {
+      eprintln!("Error creating upload: no randomness for its id");
+      return reply(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let id = hex(&id);
+    if let Err(err) = self.storage.create(&id).await {
+      eprintln!("Error creating upload: {err}");
+      return reply(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let upload = Arc::new(Upload {
+      length,
+      metadata,
+      offset: AtomicU64::new(0),
+      touched: Mutex::new(Instant::now()),
+      writing: tokio::sync::Mutex::new(()),
+    });
+    self
+      .uploads
+      .lock()
+      .unwrap()
+      .insert(id.clone(), upload.clone());
+
+    if upload.is_complete() {
+      self.complete(&id, &upload);
+    }
+
+    let res = reply(StatusCode::CREATED).header(header::LOCATION, format!("{}/{id}", self.path));
+    self.expires(res, &upload)
+  }
+
+  fn head(&self, id: &str) -> ResponseBuilder {
+    let Some(upload) = self.upload(id) else {
+      return reply(StatusCode::NOT_FOUND);
+    };
+
+    let mut res = reply(StatusCode::OK)
+      .header(UPLOAD_OFFSET, upload.offset())
+      .header(UPLOAD_LENGTH, upload.length)
+      .header(header::CACHE_CONTROL, "no-store");
+    if let Some(metadata) = &upload.metadata {
+      res = res.header(UPLOAD_METADATA, metadata);
+    }
+    self.expires(res, &upload)
+  }
+
+  async fn patch(&self, req: Request, id: &str) -> ResponseBuilder {
+    let content_type = req.headers().get(header::CONTENT_TYPE);
+    if content_type.is_none_or(|value| value != OFFSET_STREAM) {
+      return reply(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let Some(upload) = self.upload(id) else {
+      return reply(StatusCode::NOT_FOUND);
+    };
+    let Some(offset) = header_u64(&req, &UPLOAD_OFFSET) else {
+      return reply(StatusCode::BAD_REQUEST);
+    };
+
+    let Ok(_writing) = upload.writing.try_lock() else {
+      return reply(StatusCode::LOCKED);
+    };
+    if offset != upload.offset() {
+      return reply(StatusCode::CONFLICT);
+    }
+
+    let mut writer = match self.storage.append(id).await {
+      Ok(writer) => writer,
+      Err(err) => {
+        eprintln!("Error opening upload: {err}");
+        return reply(StatusCode::INTERNAL_SERVER_ERROR);
+      }
+    };
+    let appended = append(&upload, &mut writer, req.into_body()).await;
+    // Whatever was appended must be written out before another `PATCH` can
+    // append after it.
+    let flushed = writer.shutdown().await;
+    upload.touch();
+
+    let status = match (appended, flushed) {
+      (Ok(()), Ok(())) => StatusCode::NO_CONTENT,
+      (Err(status), _) => status,
+      (_, Err(err)) => {
+        eprintln!("Error writing upload: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+      }
+    };
+    // A retried `PATCH` with nothing left to append must not complete the
+    // upload again.
+    if offset < upload.length && upload.is_complete() {
+      self.complete(id, &upload);
+    }
+
+    let res = reply(status).header(UPLOAD_OFFSET, upload.offset());
+    self.expires(res, &upload)
+  }
+
+  async fn terminate(&self, id: &str) -> ResponseBuilder {
+    let Some(upload) = self.upload(id) else {
+      return reply(StatusCode::NOT_FOUND);
+    };
+    let Ok(_writing) = upload.writing.try_lock() else {
+      return reply(StatusCode::LOCKED);
+    };
+
+    self.uploads.lock().unwrap().remove(id);
+    if let Err(err) = self.storage.remove(id).await {
+      eprintln!("Error removing upload: {err}");
+    }
+    reply(StatusCode::NO_CONTENT)
+  }
+
+  /// Looks up an upload, dropping it if it has expired.
+  fn upload(&self, id: &str) -> Option<Arc<Upload>> {
+    let mut uploads = self.uploads.lock().unwrap();
+    let upload = uploads.get(id)?.clone();
+    if !self.is_expired(&upload) {
+      return Some(upload);
+    }
+
+    uploads.remove(id);
+    drop(uploads);
+    self.discard(id.to_owned(), &upload);
+    None
+  }
+
+  fn is_expired(&self, upload: &Upload) -> bool {
+    self
+      .expiration
+      .is_some_and(|expiration| upload.idle() > expiration)
+  }
+
+  /// Drops every expired upload.
+  fn sweep(&self) {
+    if self.expiration.is_none() {
+      return;
+    }
+
+    let mut expired = Vec::new();
+    self.uploads.lock().unwrap().retain(|id, upload| {
+      let keep = !self.is_expired(upload);
+      if !keep {
+        expired.push((id.clone(), upload.clone()));
+      }
+      keep
+    });
+    for (id, upload) in expired {
+      self.discard(id, &upload);
+    }
+  }
+
+  /// Deletes what was stored of an expired upload, unless it was completed
+  /// and so handed to the app.
+  fn discard(&self, id: String, upload: &Upload) {
+    if upload.is_complete() {
+      return;
+    }
+
+    let storage = self.storage.clone();
+    tokio::spawn(async move {
+      if let Err(err) = storage.remove(&id).await {
+        eprintln!("Error removing expired upload {id}: {err}");
+      }
+    });
+  }
+
+  fn complete(&self, id: &str, upload: &Upload) {
+    let Some(on_complete) = &self.on_complete else {
+      return;
+    };
+
+    let metadata = upload
+      .metadata
+      .as_ref()
+      .and_then(parse_metadata)
+      .unwrap_or_default();
+    let completed = Completed {
+      id: id.to_owned(),
+      length: upload.length,
+      metadata,
+      location: self.storage.location(id),
+    };
+    on_complete.call(completed, ThreadsafeFunctionCallMode::NonBlocking);
+  }
+
+  /// Adds `Upload-Expires` for unfinished uploads, when they expire.
+  fn expires(&self, res: ResponseBuilder, upload: &Upload) -> ResponseBuilder {
+    match self.expiration {
+      Some(expiration) if !upload.is_complete() => {
+        let left = expiration.saturating_sub(upload.idle());
+        res.header(
+          UPLOAD_EXPIRES,
+          httpdate::fmt_http_date(SystemTime::now() + left),
+        )
+      }
+      _ => res,
+    }
+  }
+}
+
+/// Streams a `PATCH` body into an upload, moving its offset after each chunk
+/// so an interrupted request can be resumed from what was written.
+async fn append(
+  upload: &Upload,
+  writer: &mut Writer,
+  mut body: Body,
+) -> std::result::Result<(), StatusCode> {
+  while let Some(chunk) = body.data().await {
+    let chunk: Bytes = chunk.map_err(|err| {
+      eprintln!("Error reading upload: {err}");
+      StatusCode::BAD_REQUEST
+    })?;
+
+    let offset = upload.offset() + chunk.len() as u64;
+    if offset > upload.length {
+      return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    writer.write_all(&chunk).await.map_err(|err| {
+      eprintln!("Error writing upload: {err}");
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    upload.offset.store(offset, Ordering::Release);
+  }
+  Ok(())
+}
+
+fn reply(status: StatusCode) -> ResponseBuilder {
+  ResponseBuilder::new()
+    .status(status)
+    .header(TUS_RESUMABLE, VERSION)
+}
+
+fn not_allowed(allow: &'static str) -> ResponseBuilder {
+  reply(StatusCode::METHOD_NOT_ALLOWED).header(header::ALLOW, allow)
+}
+
+fn header_u64(req: &Request, name: &HeaderName) -> Option<u64> {
+  req.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Decodes `Upload-Metadata`: comma-separated unique keys, each followed by
+/// its base64-encoded value unless it has none.
+fn parse_metadata(value: &HeaderValue) -> Option<Vec<(String, String)>> {
+  let value = value.to_str().ok()?;
+  value
+    .split(',')
+    .map(str::trim)
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| {
+      let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+      let decoded = base64::decode(encoded.trim(), Alphabet::Any)?;
+      Some((
+        key.to_owned(),
+        String::from_utf8_lossy(&decoded).into_owned(),
+      ))
+    })
+    .try_fold(Vec::new(), |mut pairs: Vec<(String, String)>, pair| {
+      let pair = pair?;
+      // Keys must be unique.
+      if pairs.iter().any(|(key, _)| *key == pair.0) {
+        return None;
+      }
+      pairs.push(pair);
+      Some(pairs)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use hyper::header::HeaderValue;
+
+  use super::parse_metadata;
+
+  fn parse(value: &'static str) -> Option<Vec<(String, String)>> {
+    parse_metadata(&HeaderValue::from_static(value))
+  }
+
+  fn pair(key: &str, value: &str) -> (String, String) {
+    (key.to_owned(), value.to_owned())
+  }
+
+  #[test]
+  fn decodes_base64_values() {
+    assert_eq!(
+      parse("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential"),
+      Some(vec![
+        pair("filename", "world_domination_plan.pdf"),
+        pair("is_confidential", ""),
+      ])
+    );
+  }
+
+  #[test]
+  fn tolerates_spacing_missing_padding_and_url_safe_values() {
+    assert_eq!(
+      parse(" a YQ , b Pz8_ ,"),
+      Some(vec![pair("a", "a"), pair("b", "???")])
+    );
+  }
+
+  #[test]
+  fn accepts_empty_metadata() {
+    assert_eq!(parse(""), Some(vec![]));
+  }
+
+  #[test]
+  fn rejects_malformed_values() {
+    assert_eq!(parse("name not*base64"), None);
+    assert_eq!(parse("name YQ YQ"), None);
+    assert_eq!(parse("ok YQ,name @@"), None);
+  }
+
+  #[test]
+  fn rejects_duplicate_keys() {
+    assert_eq!(parse("name YQ,name Yg"), None);
+  }
+
+  #[test]
+  fn replaces_invalid_utf8() {
+    assert_eq!(parse("name /w"), Some(vec![pair("name", "\u{fffd}")]));
+  }
+}
@@ -0,0 +1,83 @@
+//! Host-based routing.
+//!
+//! Sub-applications registered with `app.vhost()` answer requests whose
+//! `Host` header names them; everything else falls through to the app's own
+//! routes.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use hyper::header;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::fast_path::FastPaths;
+use crate::http::Request;
+use crate::routes::Routes;
+
+/// The routes of one application, shared with the app so that routes added
+/// to it later are served too.
+#[derive(Clone, Default)]
+pub(crate) struct Site {
+  pub routes: Arc<ArcSwap<Routes>>,
+  pub fast_paths: Arc<ArcSwap<FastPaths>>,
+}
+
+/// Sites keyed by host name, lowercased and without a port.
+///
+/// A key of the form `*.example.com` matches any subdomain of
+/// `example.com`, but not `example.com` itself.
+pub(crate) type Hosts = HashMap<String, Site>;
+
+/// Validates and normalizes a host name passed to `app.vhost()`.
+pub(crate) fn normalize(host: &str) -> Result<String, ActixError> {
+  let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+  let name = host.strip_prefix("*.").unwrap_or(&host);
+
+  if name.is_empty() || name.contains(['*', ':', '/']) {
+    return Err(ActixError::new(
+      ErrorCode::InvalidOption,
+      format!("invalid virtual host `{host}`"),
+    ));
+  }
+  Ok(host)
+}
+
+/// Finds the site for a request, trying an exact match on its host before
+/// the wildcards of each parent domain.
+pub(crate) fn find<'h>(hosts: &'h Hosts, req: &Request) -> Option<&'h Site> {
+  if hosts.is_empty() {
+    return None;
+  }
+
+  let host = host(req)?;
+  if let Some(site) = hosts.get(&host) {
+    return Some(site);
+  }
+
+  let mut parent = host.as_str();
+  while let Some((_, rest)) = parent.split_once('.') {
+    if let Some(site) = hosts.get(&format!("*.{rest}")) {
+      return Some(site);
+    }
+    parent = rest;
+  }
+  None
+}
+
+/// The host a request was sent to, from the `Host` header or, for requests
+/// in absolute form, the URI.
+fn host(req: &Request) -> Option<String> {
+  let authority = match req.headers().get(header::HOST) {
+    Some(value) => value.to_str().ok()?,
+    None => req.uri().host()?,
+  };
+
+  // Leave IPv6 literals alone; they are never virtual hosts anyway.
+  let host = match authority.rsplit_once(':') {
+    Some((host, port)) if !host.contains(':') && port.bytes().all(|b| b.is_ascii_digit()) => host,
+    _ => authority,
+  };
+
+  Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
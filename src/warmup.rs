@@ -0,0 +1,105 @@
+//! Requests run through the pipeline before a server starts accepting
+//! connections, set with `app.warmup()`.
+//!
+//! They go through routing, middleware and handlers like real requests, so
+//! V8 has compiled the handlers and every lazily built structure exists by
+//! the time the first client connects. Handlers can tell them apart with
+//! `req.warmup`.
+
+use std::collections::HashMap;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::{Body, Request};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Method, Uri};
+
+/// A request synthesized by `app.warmup()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct WarmupRequest {
+  /// Defaults to `GET`.
+  pub method: Option<String>,
+  /// Path and query, such as `/users?page=1`.
+  pub path: String,
+  pub headers: Option<HashMap<String, String>>,
+  pub body: Option<String>,
+}
+
+/// Marks warmup requests in their extensions.
+#[derive(Clone, Copy, Debug)]
+pub struct Warmup;
+
+/// A checked [`WarmupRequest`].
+#[derive(Clone, Debug)]
+struct Prepared {
+  method: Method,
+  uri: Uri,
+  headers: Vec<(HeaderName, HeaderValue)>,
+  body: String,
+}
+
+/// Warmup requests, checked when they are set.
+#[derive(Clone, Debug, Default)]
+pub struct Warmups(Vec<Prepared>);
+
+impl Warmups {
+  pub fn new(requests: Vec<WarmupRequest>) -> Result<Self, ActixError> {
+    let invalid = |request: &WarmupRequest, err: &dyn std::fmt::Display| {
+      ActixError::new(
+        ErrorCode::InvalidOption,
+        format!("invalid warmup request `{}`: {err}", request.path),
+      )
+    };
+
+    requests
+      .into_iter()
+      .map(|request| {
+        let method = match &request.method {
+          Some(method) => Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|err| invalid(&request, &err))?,
+          None => Method::GET,
+        };
+        if !request.path.starts_with('/') {
+          return Err(invalid(&request, &"the path must start with `/`"));
+        }
+        let uri = Uri::try_from(request.path.as_str()).map_err(|err| invalid(&request, &err))?;
+
+        let headers = request
+          .headers
+          .iter()
+          .flatten()
+          .map(|(name, value)| {
+            Ok((
+              HeaderName::try_from(name.as_str()).map_err(|err| invalid(&request, &err))?,
+              HeaderValue::try_from(value.as_str()).map_err(|err| invalid(&request, &err))?,
+            ))
+          })
+          .collect::<Result<_, ActixError>>()?;
+
+        Ok(Prepared {
+          method,
+          uri,
+          headers,
+          body: request.body.unwrap_or_default(),
+        })
+      })
+      .collect::<Result<_, _>>()
+      .map(Warmups)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// Builds the requests, ready to be dispatched.
+  pub fn requests(&self) -> impl Iterator<Item = Request> + '_ {
+    self.0.iter().map(|prepared| {
+      let mut req = Request::new(Body::new(prepared.body.clone()));
+      *req.method_mut() = prepared.method.clone();
+      *req.uri_mut() = prepared.uri.clone();
+      req.headers_mut().extend(prepared.headers.iter().cloned());
+      req.extensions_mut().insert(Warmup);
+      req
+    })
+  }
+}
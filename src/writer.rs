@@ -0,0 +1,721 @@
+//! Node-style `(req, res)` handlers that write their response as they go.
+//!
+//! `writer(handler)` wraps a handler written for `http.createServer()`:
+//! `res` is a [`ResponseWriter`] with the `ServerResponse` methods such
+//! servers use, `writeHead()`, `setHeader()`, `write()` and `end()`. The
+//! status and headers are sent at the first `writeHead()`, `flushHeaders()`
+//! or `write()`; from then on every chunk goes out as it is written,
+//! through a channel feeding the response body. A response ended before
+//! that is sent in one piece, with a `Content-Length`.
+//!
+//! As Node's, `write()` returns `false` once the chunks the server hasn't
+//! taken yet reach `highWaterMark` bytes, 16 KiB by default, and `res`
+//! emits `drain` when it has taken them all. The server only takes chunks
+//! as the client reads them, so producers waiting for `drain`, such as
+//! `readable.pipe(res)`, go no faster than the client, however slow it is.
+//! Chunks written past the mark are still queued, as in Node; once the
+//! client has gone away they are dropped, and `write()` returns `false`.
+//!
+//! Event streams (`Content-Type: text/event-stream`) get a comment line
+//! whenever nothing was written for `heartbeat` milliseconds, 15 seconds
+//! by default, so proxies keep them open and a client that went away is
+//! noticed when the write fails. `res.closed` resolves once the response
+//! is over, sent in full or given up on, which is when subscriptions made
+//! for the client should be dropped.
+//!
+//! A handler that throws, or whose promise rejects, fails the request with
+//! `500` if nothing was sent yet, and cuts the body short otherwise. So
+//! does one whose `res` is collected before `end()`.
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap};
+use hyper::StatusCode;
+use napi::bindgen_prelude::{Buffer, Either, FromNapiValue, This};
+use napi::threadsafe_function::{
+  ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{
+  sys, Env, Error, JsBoolean, JsDeferred, JsFunction, JsObject, JsUnknown, NapiRaw, Property,
+  Result, ValueType,
+};
+use tokio::sync::mpsc;
+
+use crate::error::{ActixError, ErrorCode};
+use crate::http::Body;
+use crate::outgoing::{self, chunk_bytes, head_headers, header_values, parse_name, parse_status};
+use crate::response::NodeResponse;
+
+/// Milliseconds between heartbeats of idle event streams.
+const HEARTBEAT: u32 = 15_000;
+
+/// Bytes queued before `write()` returns `false`, as in Node.
+const HIGH_WATER_MARK: u32 = 16 * 1024;
+
+/// Property of `res` holding its event listeners.
+const LISTENERS_KEY: &str = "__actixListeners";
+
+type Settle = JsDeferred<NodeResponse, Box<dyn FnOnce(Env) -> Result<NodeResponse>>>;
+
+/// Resolves `res.closed`.
+type Closed = JsDeferred<(), fn(Env) -> Result<()>>;
+
+type Chunks = mpsc::UnboundedSender<io::Result<Bytes>>;
+
+/// Emits `drain` on `res`.
+type Drain = ThreadsafeFunction<(), ErrorStrategy::Fatal>;
+
+/// Where a response stands.
+enum State {
+  /// Nothing sent yet; the handler's promise is pending.
+  Pending(Settle, Closed),
+  /// Status and headers sent; chunks go to the body.
+  Streaming(Chunks, Arc<Backlog>),
+  Ended,
+}
+
+/// The bytes written but not taken by the server yet.
+struct Backlog {
+  high_water_mark: usize,
+  bytes: AtomicUsize,
+  /// Set once `write()` returned `false`, until `drain` is emitted.
+  needs_drain: AtomicBool,
+}
+
+impl Backlog {
+  /// Counts a chunk about to be queued. Returns `false` if the queue is
+  /// full with it.
+  fn add(&self, len: usize) -> bool {
+    let buffered = self.bytes.fetch_add(len, Ordering::SeqCst) + len;
+    if buffered < self.high_water_mark {
+      return true;
+    }
+    // Set before the chunk is queued, so that taking it finds the flag.
+    self.needs_drain.store(true, Ordering::SeqCst);
+    false
+  }
+
+  /// Counts a chunk the server took. Returns `true` if `drain` is due.
+  fn take(&self, len: usize) -> bool {
+    self.bytes.fetch_sub(len, Ordering::SeqCst) == len
+      && self.needs_drain.swap(false, Ordering::SeqCst)
+  }
+}
+
+/// The state of a response, shared by `res` and the handler's promise.
+#[derive(Clone)]
+struct Sink(Rc<RefCell<Inner>>);
+
+struct Inner(State);
+
+impl Sink {
+  /// Fails the response: with `err` if nothing was sent yet, by cutting
+  /// the body short otherwise.
+  fn fail(&self, err: Error) {
+    match std::mem::replace(&mut self.0.borrow_mut().0, State::Ended) {
+      State::Pending(settle, closed) => {
+        settle.reject(err);
+        closed.resolve(|_| Ok(()));
+      }
+      State::Streaming(chunks, _) => {
+        eprintln!("Response writer failed: {err}");
+        let _ = chunks.send(Err(io::Error::other(err.reason)));
+      }
+      State::Ended => {}
+    }
+  }
+}
+
+impl Drop for Inner {
+  /// Fails responses whose `res` was collected before `end()`, rather than
+  /// leaving them hanging.
+  fn drop(&mut self) {
+    let err = "the handler did not end the response";
+    match std::mem::replace(&mut self.0, State::Ended) {
+      State::Pending(settle, closed) => {
+        settle.reject(Error::from_reason(err));
+        closed.resolve(|_| Ok(()));
+      }
+      State::Streaming(chunks, _) => {
+        let _ = chunks.send(Err(io::Error::other(err)));
+      }
+      State::Ended => {}
+    }
+  }
+}
+
+/// Options of `writer()`.
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct WriterOptions {
+  /// Milliseconds of silence after which an event stream gets a comment
+  /// line. Defaults to 15000; `0` turns heartbeats off.
+  pub heartbeat: Option<u32>,
+  /// Bytes queued before `write()` returns `false`. Defaults to 16384.
+  pub high_water_mark: Option<u32>,
+}
+
+/// Wraps a Node-style `(req, res)` handler into a handler.
+#[napi(
+  js_name = "writer",
+  ts_generic_types = "Req extends import('./types').AnyActixRequest = import('./types').ActixRequest",
+  ts_args_type = "handler: (req: Req, res: ResponseWriter) => void | Promise<void>, options?: WriterOptions | undefined | null",
+  ts_return_type = "import('./types').Handler<Req>"
+)]
+pub fn create_writer(
+  env: Env,
+  handler: JsFunction,
+  options: Option<WriterOptions>,
+) -> Result<JsFunction> {
+  let run = env.create_function_from_closure("writer", |ctx| {
+    let handler: JsFunction = ctx.get(0)?;
+    let heartbeat: u32 = ctx.get(1)?;
+    let high_water_mark: u32 = ctx.get(2)?;
+    let req: JsObject = ctx.get(3)?;
+    let heartbeat = (heartbeat > 0).then(|| Duration::from_millis(heartbeat.into()));
+    run(ctx.env, handler, heartbeat, high_water_mark as usize, req)
+  })?;
+
+  // The handler is `run` with the wrapped one and the options as its
+  // first arguments.
+  let options = options.unwrap_or_default();
+  let heartbeat = options.heartbeat.unwrap_or(HEARTBEAT);
+  let high_water_mark = options.high_water_mark.unwrap_or(HIGH_WATER_MARK);
+  let run = run.coerce_to_object()?;
+  let bind: JsFunction = run.get_named_property("bind")?;
+  let args = [
+    env.get_null()?.into_unknown(),
+    handler.into_unknown(),
+    env.create_uint32(heartbeat)?.into_unknown(),
+    env.create_uint32(high_water_mark)?.into_unknown(),
+  ];
+  bind.call(Some(&run), &args)?.try_into()
+}
+
+fn run(
+  env: &Env,
+  handler: JsFunction,
+  heartbeat: Option<Duration>,
+  high_water_mark: usize,
+  req: JsObject,
+) -> Result<JsObject> {
+  let (settle, promise) = env.create_deferred()?;
+  let (closed, closed_promise) = env.create_deferred()?;
+  let sink = Sink(Rc::new(RefCell::new(Inner(State::Pending(settle, closed)))));
+
+  let res = ResponseWriter {
+    status: Cell::new(StatusCode::OK),
+    headers: RefCell::default(),
+    heartbeat,
+    high_water_mark,
+    sink: sink.clone(),
+  }
+  .into_instance(*env)?;
+  // SAFETY: the instance was just created in this scope.
+  let mut res = unsafe { JsObject::from_napi_value(env.raw(), res.raw())? };
+  res.set_named_property("closed", closed_promise)?;
+
+  let returned = match handler.call(None, &[req.into_unknown(), res.into_unknown()]) {
+    Ok(returned) => returned,
+    Err(err) => {
+      sink.fail(err);
+      return Ok(promise);
+    }
+  };
+
+  // An async handler reports its errors through the promise it returns.
+  if returned.is_promise()? {
+    let returned = returned.coerce_to_object()?;
+    let then: JsFunction = returned.get_named_property("then")?;
+    let rejected = env.create_function_from_closure("rejected", move |ctx| {
+      let reason = ctx.get::<JsUnknown>(0)?.coerce_to_string()?;
+      sink.fail(Error::from_reason(reason.into_utf8()?.into_owned()?));
+      ctx.env.get_undefined()
+    })?;
+    let args = [env.get_undefined()?.into_unknown(), rejected.into_unknown()];
+    then.call(Some(&returned), &args)?;
+  }
+  Ok(promise)
+}
+
+/// The `res` of a `writer()` handler, mirroring Node's `ServerResponse`.
+#[napi]
+// Methods only take `&self`, and never hold a borrow while JS runs: the
+// listeners `emit()` calls may write to `res`.
+pub struct ResponseWriter {
+  status: Cell<StatusCode>,
+  headers: RefCell<HeaderMap>,
+  /// Set when the response may become an event stream.
+  heartbeat: Option<Duration>,
+  high_water_mark: usize,
+  sink: Sink,
+}
+
+#[napi]
+impl ResponseWriter {
+  #[napi(getter, js_name = "statusCode")]
+  pub fn status_code(&self) -> u16 {
+    self.status.get().as_u16()
+  }
+
+  #[napi(setter, js_name = "statusCode")]
+  pub fn set_status_code(&self, env: Env, code: u32) -> Result<()> {
+    self.status.set(parse_status(&env, code)?);
+    Ok(())
+  }
+
+  #[napi(getter)]
+  pub fn headers_sent(&self) -> bool {
+    !matches!(self.sink.0.borrow().0, State::Pending(..))
+  }
+
+  #[napi(getter)]
+  pub fn writable_ended(&self) -> bool {
+    matches!(self.sink.0.borrow().0, State::Ended)
+  }
+
+  /// Bytes written that the server hasn't taken yet.
+  #[napi(getter)]
+  pub fn writable_length(&self) -> u32 {
+    match &self.sink.0.borrow().0 {
+      State::Streaming(_, backlog) => backlog.bytes.load(Ordering::SeqCst) as u32,
+      _ => 0,
+    }
+  }
+
+  #[napi(getter)]
+  pub fn writable_high_water_mark(&self) -> u32 {
+    self.high_water_mark as u32
+  }
+
+  /// Whether `write()` returned `false` and `drain` wasn't emitted yet.
+  #[napi(getter)]
+  pub fn writable_need_drain(&self) -> bool {
+    match &self.sink.0.borrow().0 {
+      State::Streaming(_, backlog) => backlog.needs_drain.load(Ordering::SeqCst),
+      _ => false,
+    }
+  }
+
+  /// Replaces a header. Arrays set one header per item.
+  #[napi(ts_args_type = "name: string, value: string | number | Array<string>")]
+  pub fn set_header(&self, env: Env, this: This, name: String, value: JsUnknown) -> Result<This> {
+    self.check_pending(&env)?;
+    let name = parse_name(&env, name)?;
+    let values = header_values(&env, value)?;
+    let mut headers = self.headers.borrow_mut();
+    headers.remove(&name);
+    for value in values {
+      headers.append(name.clone(), value);
+    }
+    Ok(this)
+  }
+
+  /// Adds a header, keeping the values already set.
+  #[napi(ts_args_type = "name: string, value: string | number | Array<string>")]
+  pub fn append_header(
+    &self,
+    env: Env,
+    this: This,
+    name: String,
+    value: JsUnknown,
+  ) -> Result<This> {
+    self.check_pending(&env)?;
+    let name = parse_name(&env, name)?;
+    let values = header_values(&env, value)?;
+    let mut headers = self.headers.borrow_mut();
+    for value in values {
+      headers.append(name.clone(), value);
+    }
+    Ok(this)
+  }
+
+  /// The values of a header joined with `, `.
+  #[napi]
+  pub fn get_header(&self, name: String) -> Option<String> {
+    outgoing::joined(&self.headers.borrow(), name.as_str())
+  }
+
+  #[napi]
+  pub fn has_header(&self, name: String) -> bool {
+    self.headers.borrow().contains_key(name.as_str())
+  }
+
+  #[napi]
+  pub fn remove_header(&self, env: Env, name: String) -> Result<()> {
+    self.check_pending(&env)?;
+    self.headers.borrow_mut().remove(name.as_str());
+    Ok(())
+  }
+
+  /// Sends the status and headers, adding `headers` to those already set.
+  /// `headers` is an object, or an array of names and values one after the
+  /// other; they replace the headers of the same name. A status message
+  /// may come before the headers, as in Node, but the standard reason
+  /// phrase is always sent.
+  #[napi(
+    ts_args_type = "code: number, messageOrHeaders?: string | import('./types').OutgoingHeaders | undefined | null, headers?: import('./types').OutgoingHeaders | undefined | null"
+  )]
+  pub fn write_head(
+    &self,
+    env: Env,
+    this: This,
+    code: u32,
+    message_or_headers: Option<Either<String, JsObject>>,
+    headers: Option<JsObject>,
+  ) -> Result<This> {
+    self.check_pending(&env)?;
+    let status = parse_status(&env, code)?;
+    let headers = match message_or_headers {
+      Some(Either::B(headers)) => Some(headers),
+      Some(Either::A(_)) | None => headers,
+    };
+    if let Some(headers) = headers {
+      let head = head_headers(&env, headers)?;
+      let mut headers = self.headers.borrow_mut();
+      for (name, _) in &head {
+        headers.remove(name);
+      }
+      for (name, value) in head {
+        headers.append(name, value);
+      }
+    }
+    self.status.set(status);
+    self.start(&env, &this)?;
+    Ok(this)
+  }
+
+  /// Sends the status and headers set so far.
+  #[napi]
+  pub fn flush_headers(&self, env: Env, this: This) -> Result<()> {
+    if matches!(self.sink.0.borrow().0, State::Pending(..)) {
+      self.start(&env, &this)?;
+    }
+    Ok(())
+  }
+
+  /// Sends a chunk of the body, and the status and headers first if they
+  /// weren't yet. Returns `false` once the queue is full, or the client
+  /// went away; wait for `drain` before writing more.
+  #[napi]
+  pub fn write(&self, env: Env, this: This, chunk: Either<String, Buffer>) -> Result<bool> {
+    self.flush_headers(env, this)?;
+    match &self.sink.0.borrow().0 {
+      State::Streaming(chunks, backlog) => {
+        let chunk = chunk_bytes(chunk);
+        let queued = backlog.add(chunk.len());
+        // The client may be gone; like Node, writes are then dropped.
+        Ok(chunks.send(Ok(chunk)).is_ok() && queued)
+      }
+      _ => Err(ActixError::new(ErrorCode::WriteAfterEnd, "write after end").into_js(&env)),
+    }
+  }
+
+  /// Ends the response, with a last chunk of the body.
+  #[napi]
+  pub fn end(&self, this: This, chunk: Option<Either<String, Buffer>>) -> Result<This> {
+    let state = std::mem::replace(&mut self.sink.0.borrow_mut().0, State::Ended);
+    let body = chunk.map(chunk_bytes).unwrap_or_default();
+    match state {
+      State::Pending(settle, closed) => {
+        let headers = self.headers.take();
+        let response = NodeResponse::from_parts(self.status.get(), headers, body);
+        settle.resolve(Box::new(move |_| Ok(response)));
+        closed.resolve(|_| Ok(()));
+      }
+      State::Streaming(chunks, backlog) => {
+        if !body.is_empty() {
+          backlog.add(body.len());
+          let _ = chunks.send(Ok(body));
+        }
+      }
+      State::Ended => {}
+    }
+    Ok(this)
+  }
+
+  /// Listens for `event`. The writer itself emits `drain`; `res.closed`
+  /// tells when the response is over.
+  #[napi(ts_args_type = "event: string, listener: (...args: any[]) => void")]
+  pub fn on(&self, env: Env, mut this: This, event: String, listener: JsFunction) -> Result<This> {
+    add_listener(&env, &mut this, &event, listener, false)?;
+    Ok(this)
+  }
+
+  /// Listens for the next `event` only.
+  #[napi(ts_args_type = "event: string, listener: (...args: any[]) => void")]
+  pub fn once(
+    &self,
+    env: Env,
+    mut this: This,
+    event: String,
+    listener: JsFunction,
+  ) -> Result<This> {
+    add_listener(&env, &mut this, &event, listener, true)?;
+    Ok(this)
+  }
+
+  /// Removes the last `listener` added for `event`.
+  #[napi(ts_args_type = "event: string, listener: (...args: any[]) => void")]
+  pub fn remove_listener(
+    &self,
+    env: Env,
+    mut this: This,
+    event: String,
+    listener: JsFunction,
+  ) -> Result<This> {
+    let Some(listeners) = listeners(&this, &event)? else {
+      return Ok(this);
+    };
+    let mut kept = listeners;
+    if let Some(idx) = kept
+      .iter()
+      .rposition(|(added, _)| env.strict_equals(added, &listener).unwrap_or(false))
+    {
+      kept.remove(idx);
+      set_listeners(&env, &mut this, &event, kept)?;
+    }
+    Ok(this)
+  }
+
+  /// The same as `removeListener()`.
+  #[napi(ts_args_type = "event: string, listener: (...args: any[]) => void")]
+  pub fn off(&self, env: Env, this: This, event: String, listener: JsFunction) -> Result<This> {
+    self.remove_listener(env, this, event, listener)
+  }
+
+  /// Calls the listeners of `event` with `arg`. Returns `false` if there
+  /// were none.
+  #[napi(ts_args_type = "event: string, arg?: unknown")]
+  pub fn emit(
+    &self,
+    env: Env,
+    mut this: This,
+    event: String,
+    arg: Option<JsUnknown>,
+  ) -> Result<bool> {
+    let Some(called) = listeners(&this, &event)? else {
+      return Ok(false);
+    };
+    // `once()` listeners are removed before any is called, as in Node.
+    if called.iter().any(|(_, once)| *once) {
+      let mut kept = listeners(&this, &event)?.unwrap_or_default();
+      kept.retain(|(_, once)| !once);
+      set_listeners(&env, &mut this, &event, kept)?;
+    }
+    let args = arg.into_iter().collect::<Vec<_>>();
+    for (listener, _) in &called {
+      listener.call(Some(&this), &args)?;
+    }
+    Ok(!called.is_empty())
+  }
+}
+
+impl ResponseWriter {
+  fn check_pending(&self, env: &Env) -> Result<()> {
+    match self.sink.0.borrow().0 {
+      State::Pending(..) => Ok(()),
+      _ => Err(
+        ActixError::new(
+          ErrorCode::HeadersSent,
+          "cannot change headers after they are sent",
+        )
+        .into_js(env),
+      ),
+    }
+  }
+
+  /// Answers the request with a body fed by later writes.
+  fn start(&self, env: &Env, this: &JsObject) -> Result<()> {
+    if !matches!(self.sink.0.borrow().0, State::Pending(..)) {
+      return Ok(());
+    }
+    // `res` isn't kept alive for `drain`, so that a response collected
+    // before `end()` is still cut short.
+    let res = WeakObject::new(env, this)?;
+    let emit_drain = env.create_function_from_closure("drain", move |ctx| {
+      if let Some(res) = res.get()? {
+        let emit: JsFunction = res.get_named_property("emit")?;
+        emit.call(Some(&res), &[ctx.env.create_string("drain")?])?;
+      }
+      ctx.env.get_undefined()
+    })?;
+    let drain: Drain =
+      emit_drain.create_threadsafe_function(0, |_: ThreadSafeCallContext<()>| {
+        Ok(Vec::<JsUnknown>::new())
+      })?;
+
+    let (chunks, receiver) = mpsc::unbounded_channel();
+    let backlog = Arc::new(Backlog {
+      high_water_mark: self.high_water_mark,
+      bytes: AtomicUsize::new(0),
+      needs_drain: AtomicBool::new(false),
+    });
+    let state = State::Streaming(chunks, backlog.clone());
+    let State::Pending(settle, closed) = std::mem::replace(&mut self.sink.0.borrow_mut().0, state)
+    else {
+      return Ok(());
+    };
+
+    let event_stream = self
+      .headers
+      .borrow()
+      .get(header::CONTENT_TYPE)
+      .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+    let heartbeat = self.heartbeat.filter(|_| event_stream);
+    let feed = Feed {
+      receiver,
+      backlog,
+      drain,
+      closed: Some(closed),
+    };
+    let stream = futures::stream::unfold(feed, move |mut feed| async move {
+      let next = match heartbeat {
+        Some(every) => match tokio::time::timeout(every, feed.receiver.recv()).await {
+          Ok(next) => next,
+          // A comment line, which `EventSource` ignores.
+          Err(_) => return Some((Ok(Bytes::from_static(b":\n\n")), feed)),
+        },
+        None => feed.receiver.recv().await,
+      };
+      let chunk = next?;
+      let len = chunk.as_ref().map_or(0, Bytes::len);
+      if feed.backlog.take(len) {
+        feed.drain.call((), ThreadsafeFunctionCallMode::NonBlocking);
+      }
+      Some((chunk, feed))
+    });
+    let body = Body(hyper::Body::wrap_stream(stream));
+    let headers = self.headers.take();
+    let response = NodeResponse::with_body(self.status.get(), headers, body);
+    settle.resolve(Box::new(move |_| Ok(response)));
+    Ok(())
+  }
+}
+
+/// The source of a streamed body. Dropped once the body is sent in full or
+/// the server gives up on it, which resolves `res.closed`.
+struct Feed {
+  receiver: mpsc::UnboundedReceiver<io::Result<Bytes>>,
+  backlog: Arc<Backlog>,
+  drain: Drain,
+  closed: Option<Closed>,
+}
+
+impl Drop for Feed {
+  fn drop(&mut self) {
+    if let Some(closed) = self.closed.take() {
+      closed.resolve(|_| Ok(()));
+    }
+  }
+}
+
+/// A reference to a JS object that doesn't keep it from being collected.
+/// Only used and dropped on the JS thread.
+struct WeakObject {
+  env: sys::napi_env,
+  reference: sys::napi_ref,
+}
+
+impl WeakObject {
+  fn new(env: &Env, object: &JsObject) -> Result<Self> {
+    let mut reference = ptr::null_mut();
+    // SAFETY: `object` is a live value of `env`.
+    let status = unsafe { sys::napi_create_reference(env.raw(), object.raw(), 0, &mut reference) };
+    if status != sys::Status::napi_ok {
+      return Err(Error::from_reason("failed to reference the response"));
+    }
+    Ok(WeakObject {
+      env: env.raw(),
+      reference,
+    })
+  }
+
+  /// The object, unless it was collected.
+  fn get(&self) -> Result<Option<JsObject>> {
+    let mut value = ptr::null_mut();
+    // SAFETY: the reference is alive until `self` is dropped.
+    let status = unsafe { sys::napi_get_reference_value(self.env, self.reference, &mut value) };
+    if status != sys::Status::napi_ok || value.is_null() {
+      return Ok(None);
+    }
+    // SAFETY: `value` is the object referenced.
+    unsafe { JsObject::from_napi_value(self.env, value) }.map(Some)
+  }
+}
+
+impl Drop for WeakObject {
+  fn drop(&mut self) {
+    // SAFETY: dropped on the JS thread, along with the function holding it.
+    unsafe { sys::napi_delete_reference(self.env, self.reference) };
+  }
+}
+
+/// The listeners of `event` on `res`, and whether each is a `once()` one.
+fn listeners(res: &JsObject, event: &str) -> Result<Option<Vec<(JsFunction, bool)>>> {
+  let all = res.get_named_property::<JsUnknown>(LISTENERS_KEY)?;
+  if all.get_type()? != ValueType::Object {
+    return Ok(None);
+  }
+  let added = all
+    .coerce_to_object()?
+    .get_named_property::<JsUnknown>(event)?;
+  if added.get_type()? != ValueType::Object {
+    return Ok(None);
+  }
+  let added = added.coerce_to_object()?;
+  let mut listeners = Vec::new();
+  for idx in 0..added.get_array_length()? {
+    let entry: JsObject = added.get_element(idx)?;
+    let once: JsBoolean = entry.get_element(1)?;
+    listeners.push((entry.get_element(0)?, once.get_value()?));
+  }
+  Ok(Some(listeners))
+}
+
+fn set_listeners(
+  env: &Env,
+  res: &mut JsObject,
+  event: &str,
+  listeners: Vec<(JsFunction, bool)>,
+) -> Result<()> {
+  let mut all = match res
+    .get_named_property::<JsUnknown>(LISTENERS_KEY)?
+    .get_type()?
+  {
+    ValueType::Object => res.get_named_property::<JsObject>(LISTENERS_KEY)?,
+    _ => {
+      // Hidden, so that it isn't copied along with `res`.
+      let all = env.create_object()?;
+      res.define_properties(&[Property::new(LISTENERS_KEY)?.with_value(&all)])?;
+      all
+    }
+  };
+  let mut added = env.create_array_with_length(listeners.len())?;
+  for (idx, (listener, once)) in listeners.into_iter().enumerate() {
+    let mut entry = env.create_array_with_length(2)?;
+    entry.set_element(0, listener)?;
+    entry.set_element(1, env.get_boolean(once)?)?;
+    added.set_element(idx as u32, entry)?;
+  }
+  all.set_named_property(event, added)
+}
+
+fn add_listener(
+  env: &Env,
+  res: &mut JsObject,
+  event: &str,
+  listener: JsFunction,
+  once: bool,
+) -> Result<()> {
+  let mut added = listeners(res, event)?.unwrap_or_default();
+  added.push((listener, once));
+  set_listeners(env, res, event, added)
+}
@@ -0,0 +1,424 @@
+// Types napi can't generate from the Rust sources: handler and request
+// shapes, objects built by hand in Rust, and properties set on instances
+// at runtime. `index.d.ts` is generated and refers to them as
+// `import("./types")`.
+
+import type {
+  ActixApp,
+  ExpressResponse,
+  ListenAddress,
+  NodeRequest,
+  NodeResponse,
+  RequestHeaders,
+  ResponseWriter,
+  RouteOptions,
+  Status,
+} from "./index";
+
+declare module "./index" {
+  /** Routes whose handler takes requests in batches. */
+  interface ActixApp {
+    get<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+    post<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+    put<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+    patch<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+    delete<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+    head<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+    options<Req extends AnyActixRequest = ActixRequest>(
+      path: string,
+      handler: BatchHandler<Req>,
+      options: RouteOptions & { batch: true },
+    ): void;
+  }
+
+  interface NodeRequest {
+    readonly headers: RequestHeaders;
+    /** Set when `app.abortSignals(true)` is; see `ActixRequest.signal`. */
+    readonly signal?: AbortSignal;
+  }
+
+  interface ExpressResponse {
+    locals: Record<string, unknown>;
+  }
+
+  interface ResponseWriter {
+    /**
+     * Resolves once the response is over: sent in full, or given up on when
+     * the client went away. Drop the client's subscriptions then.
+     */
+    readonly closed: Promise<void>;
+  }
+}
+
+/** A body streamed from an iterator, as built by `ndjson()` or `csv()`. */
+export interface StreamedBody {
+  readonly __actixStream: unknown;
+}
+
+/** A token, kept apart from strings. */
+export interface StructuredToken {
+  token: string;
+}
+
+export type StructuredBareItem =
+  | number
+  | string
+  | boolean
+  | Buffer
+  | StructuredToken;
+
+export interface StructuredItem {
+  value: StructuredBareItem;
+  params: Record<string, StructuredBareItem>;
+}
+
+export interface StructuredInnerList {
+  value: StructuredItem[];
+  params: Record<string, StructuredBareItem>;
+}
+
+export type StructuredMember = StructuredItem | StructuredInnerList;
+
+export type StructuredFieldType = "item" | "list" | "dictionary";
+
+/** A structured field (RFC 8941), as parsed by `getStructuredHeader()`. */
+export type StructuredField =
+  | StructuredItem
+  | StructuredMember[]
+  | Record<string, StructuredMember>;
+
+export interface ExpressRequest {
+  method: string;
+  /** Path and query, as in Express. */
+  url: string;
+  originalUrl: string;
+  path: string;
+  query: { [key: string]: FormValue };
+  /** Lowercase names; `set-cookie` is an array, other values are joined. */
+  headers: Record<string, string | string[]>;
+  [key: string]: unknown;
+}
+
+export type ExpressMiddleware = (
+  req: ExpressRequest,
+  res: ExpressResponse,
+  next: (err?: unknown) => void,
+) => void;
+
+/** Headers given to `writeHead()`: an object, or names and values in turn. */
+export type OutgoingHeaders =
+  | Record<string, string | number | string[]>
+  | (string | number | string[])[];
+
+export type FormValue = string | FormValue[] | { [key: string]: FormValue };
+
+export interface ActixRequest extends Request {
+  /** Parsed body of `application/x-www-form-urlencoded` requests. */
+  form?: { [key: string]: FormValue };
+  /** Body of JSON requests, pre-parsed when `app.parseJson(true)` is set. */
+  jsonBody?: unknown;
+  /** The tenant the request belongs to, if any. */
+  tenant?: { name: string; labels: Record<string, string> };
+  /** The connection the request came over. */
+  connection?: ConnectionDetails;
+  /**
+   * The client's address: the peer's, or the one forwarded by a proxy
+   * trusted with `app.trustProxy()`. Unset for Unix domain sockets.
+   */
+  ip?: string;
+  /** `http`, or the protocol forwarded by a trusted proxy. */
+  protocol: string;
+  /** The requested host without its port, forwarded or from `Host`. */
+  hostname?: string;
+  /** Body of `application/merge-patch+json` requests (RFC 7386). */
+  mergePatch?: unknown;
+  /** Validated operations of `application/json-patch+json` requests (RFC 6902). */
+  patch?: PatchOperation[];
+  /** W3C trace context of the request's span, when `app.tracing()` is on. */
+  traceparent?: string;
+  /** Hints the request carries, when `app.clientHints()` is on. */
+  clientHints?: ClientHints;
+  /**
+   * Keeps `promise` running after the response is sent. A stopping server
+   * waits for it, up to `app.backgroundTimeout()`.
+   */
+  waitUntil(promise: Promise<unknown>): void;
+  /** Set on the requests synthesized by `app.warmup()`. */
+  warmup?: true;
+  /**
+   * Header `name` parsed as a structured field (RFC 8941), or `null` when
+   * it is missing or malformed. `type` can be left out for registered
+   * fields, such as client hints, `Priority` or `Signature-Input`.
+   */
+  getStructuredHeader(name: string, type?: StructuredFieldType): StructuredField | null;
+  /**
+   * Aborted when the client disconnects before the response is ready, once
+   * `app.abortSignals(true)` is set. Never aborted otherwise.
+   */
+  readonly signal: AbortSignal;
+}
+
+/** A request as handlers see it once `app.lazyHeaders(true)` is set. */
+export type LazyActixRequest = Omit<ActixRequest, "headers"> & {
+  headers: RequestHeaders;
+};
+
+/** `NodeRequest` with the same extras as `ActixRequest`. */
+export type NativeActixRequest = NodeRequest &
+  Omit<ActixRequest, keyof Request>;
+
+export type PatchOperation =
+  | { op: "add" | "replace" | "test"; path: string; value: unknown }
+  | { op: "remove"; path: string }
+  | { op: "move" | "copy"; path: string; from: string };
+
+export interface ConnectionDetails {
+  /** Unset for Unix domain sockets. */
+  remoteAddress?: string;
+  remotePort?: number;
+  /** ALPN-style protocol name, such as `"http/1.1"`. */
+  protocol: string;
+  /** Always `false`: the server does not terminate TLS. */
+  encrypted: boolean;
+}
+
+/** The request a handler gets, depending on `lazyHeaders` and `nativeRequests`. */
+export type AnyActixRequest =
+  | ActixRequest
+  | LazyActixRequest
+  | NativeActixRequest;
+
+/**
+ * What a handler or middleware can return. A status code, a `Status` with a
+ * custom reason phrase, a `StreamedBody`, a `NodeResponse` or a WHATWG
+ * `Response` is sent as the response; from a middleware this also skips the
+ * rest of the chain. Anything else moves on to the next handler.
+ */
+export type HandlerResult =
+  | number
+  | Status
+  | StreamedBody
+  | NodeResponse
+  | Response
+  | void;
+
+/** A route handler or middleware; may return its result as a promise. */
+export type Handler<Req extends AnyActixRequest = ActixRequest> = (
+  req: Req,
+) => HandlerResult | Promise<HandlerResult>;
+
+/** Answers each request of a batch, in order. */
+export type BatchHandler<Req extends AnyActixRequest = ActixRequest> = (
+  reqs: Req[],
+) => (number | Status)[] | Promise<(number | Status)[]>;
+
+/** `err.code` of the errors the addon throws or rejects with. */
+export type ActixErrorCode =
+  | "ROUTE_CONFLICT"
+  | "INVALID_ROUTE"
+  | "INVALID_OPTION"
+  | "BIND_FAILED"
+  | "SERVER_FAILED"
+  | "BODY_READ_FAILED"
+  | "BODY_TOO_LARGE"
+  | "INVALID_JSON"
+  | "INVALID_PATCH"
+  | "INVALID_HEADER"
+  | "INVALID_STATUS"
+  | "INVALID_VALUE"
+  | "WRITE_AFTER_END"
+  | "HEADERS_SENT"
+  | "ALREADY_USED"
+  | "ROUTE_NOT_FOUND"
+  | "NOT_CONFIGURED"
+  | "FETCH_FAILED";
+
+/**
+ * Thrown by the addon when a route, an option, a header or a status is
+ * invalid, or a response is used wrongly, and rejected by `listen()` and
+ * `app.fetch()` when they fail. Errors thrown by handlers and callbacks
+ * reach JS unchanged.
+ */
+export interface ActixError extends Error {
+  name: "ActixError";
+  code: ActixErrorCode;
+  /** The underlying error, such as one with `code: "EADDRINUSE"`. */
+  cause?: Error & { code?: string };
+  /** For route errors, the method and template being registered. */
+  method?: string;
+  path?: string;
+  /** For `ROUTE_CONFLICT`, the template of the route in the way. */
+  existing?: string;
+}
+
+/** A request rejected before reaching a handler. */
+export interface RejectionError {
+  kind: "bodyRead" | "bodyTooLarge" | "json" | "patch";
+  code: "BODY_READ_FAILED" | "BODY_TOO_LARGE" | "INVALID_JSON" | "INVALID_PATCH";
+  /** The status code the response is sent with. */
+  status: number;
+  message: string;
+  /** For `bodyTooLarge`, the size limit in bytes. */
+  limit?: number;
+  /** For `json`, where parsing failed. */
+  line?: number;
+  column?: number;
+}
+
+/**
+ * Renders a rejection: a string, a `Buffer`, a value sent as JSON, or
+ * nothing for an empty body.
+ */
+export type ErrorFormatter = (error: RejectionError) => unknown;
+
+/** A response as compared between a primary and a shadow handler. */
+export interface ShadowAnswer {
+  status: number;
+  /** Without hop-by-hop headers and `date`. */
+  headers: Record<string, string>;
+  /** Hex SHA-256 of the body; `null` when over 16 MiB or not read to its end. */
+  bodySha256: string | null;
+}
+
+/** A shadow handler that answered differently than the primary one. */
+export interface ShadowMismatch {
+  method: string;
+  path: string;
+  primary: ShadowAnswer;
+  shadow: ShadowAnswer;
+}
+
+/** Returns the secret of a signing key, or nothing if the key is unknown. */
+export type KeyLookup = (
+  keyId: string,
+) => string | Buffer | null | undefined | Promise<string | Buffer | null | undefined>;
+
+/** A piece of an archived response, tagged with the response's `id`. */
+export type ArchiveRecord =
+  | {
+      id: number;
+      type: "start";
+      method: string;
+      path: string;
+      status: number;
+      headers: Record<string, string>;
+    }
+  | { id: number; type: "chunk"; data: Buffer }
+  | {
+      id: number;
+      type: "end";
+      aborted: boolean;
+      /** Chunks were dropped because the output fell behind. */
+      truncated: boolean;
+    };
+
+export interface GuardAttempt {
+  method: string;
+  path: string;
+  authorization: string | null;
+  /**
+   * The verified token's claims, or `{ sub }` for an API key. `null` when
+   * neither `jwt` nor `apiKey` is set.
+   */
+  claims: Record<string, unknown> | null;
+}
+
+export interface CompletedUpload {
+  id: string;
+  length: number;
+  /** Decoded `Upload-Metadata`. */
+  metadata: Record<string, string>;
+  /** File holding the upload. */
+  path: string;
+}
+
+export interface ExpectContinueRequest {
+  method: string;
+  path: string;
+  headers: Record<string, string>;
+}
+
+export type Signal = "SIGINT" | "SIGTERM" | "SIGHUP" | "SIGQUIT";
+
+export interface ShutdownEvent {
+  signal: Signal;
+  /** Milliseconds open connections get before being closed. */
+  timeout: number;
+}
+
+export interface UpstreamEjection {
+  prefix: string;
+  upstream: string;
+  reason: "error" | "latency";
+  /** Milliseconds the upstream is left out. */
+  cooldown: number;
+}
+
+export interface ClientHintBrand {
+  brand: string;
+  version?: string;
+}
+
+/** Parsed client hints; missing or malformed hints are left out. */
+export interface ClientHints {
+  brands?: ClientHintBrand[];
+  fullVersionList?: ClientHintBrand[];
+  mobile?: boolean;
+  platform?: string;
+  platformVersion?: string;
+  model?: string;
+  arch?: string;
+  bitness?: string;
+  dpr?: number;
+  viewportWidth?: number;
+  width?: number;
+  deviceMemory?: number;
+  downlink?: number;
+  rtt?: number;
+  ect?: string;
+  saveData?: boolean;
+  prefersColorScheme?: string;
+  prefersReducedMotion?: string;
+}
+
+/** The listeners of `app.on()`, by event. */
+export interface AppEvents {
+  listening: (address: ListenAddress | null) => void;
+  connection: (connection: { remoteAddress?: string; remotePort?: number }) => void;
+  /** A listener failed, or handling a request panicked and got a `500`. */
+  error: (error: Error & { code?: string }) => void;
+  close: () => void;
+  /** A proxy left out an upstream under `outlierDetection`. */
+  ejection: (ejection: UpstreamEjection) => void;
+  /** A signal stopped the server, under `app.handleSignals()`. */
+  shutdown: (shutdown: ShutdownEvent) => void;
+}
+
+export type ListenCallback = (server: ActixApp) => void;
+
+/** Where `app.accessLog()` writes lines. */
+export type LogOutput = ((line: string) => void) | { write(line: string): unknown };